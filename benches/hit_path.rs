@@ -0,0 +1,78 @@
+//! The cache-hit path: `cargo bench --bench hit_path`.
+//!
+//! Serves one repository from a stand-in GitHub and warms the cache with it,
+//! then times asking the router for it in process, one request at a time
+//! and `CONCURRENCY` at once on a multi-threaded runtime.
+
+use axum::{
+    body::{self, Body},
+    extract::connect_info::MockConnectInfo,
+    http::Request,
+    routing::get,
+    Json, Router,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use github_cors_proxy::{build_router, config::Config};
+use serde_json::json;
+use std::net::SocketAddr;
+use tokio::{runtime::Runtime, task::JoinSet};
+use tower::ServiceExt;
+
+const CONCURRENCY: usize = 32;
+
+async fn warmed_proxy() -> Router {
+    // About the size of a real repository's JSON.
+    let repo = json!({ "full_name": "o/r", "description": "x".repeat(4000) });
+    let github = Router::new().route("/api/v3/repos/o/r", get(move || async { Json(repo) }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, github).await.unwrap() });
+
+    std::env::set_var("GITHUB_API_BASE", base);
+    std::env::set_var("GITHUB_TOKEN", "bench");
+    std::env::set_var("CACHE_TTL_SECS", "3600");
+    let config = Config::from_env().unwrap();
+    let client: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+    let proxy = build_router(config).await.unwrap().layer(MockConnectInfo(client));
+    let warmed = proxy.clone().oneshot(request()).await.unwrap();
+    assert_eq!(warmed.headers()["x-cache"], "MISS");
+    proxy
+}
+
+fn request() -> Request<Body> {
+    Request::get("/repos/o/r")
+        .header("origin", "https://prigoana.com")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn hit(proxy: Router) {
+    let response = proxy.oneshot(request()).await.unwrap();
+    assert_eq!(response.headers()["x-cache"], "HIT");
+    body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+}
+
+fn hit_path(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let proxy = runtime.block_on(warmed_proxy());
+
+    let mut group = c.benchmark_group("hit_path");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("one", |b| b.to_async(&runtime).iter(|| hit(proxy.clone())));
+    group.throughput(Throughput::Elements(CONCURRENCY as u64));
+    group.bench_function("concurrent", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut hits = JoinSet::new();
+            for _ in 0..CONCURRENCY {
+                hits.spawn(hit(proxy.clone()));
+            }
+            while let Some(done) = hits.join_next().await {
+                done.unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, hit_path);
+criterion_main!(benches);
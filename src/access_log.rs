@@ -0,0 +1,44 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::{info, info_span, Instrument};
+
+use crate::client_ip::ClientIp;
+
+/// One line per request, once its response is ready, with everything logged
+/// while handling it in a `request` span carrying the method, path, origin
+/// and client. With `LOG_FORMAT=json` each is a JSON object, span fields
+/// included.
+pub async fn log_middleware(request: Request, next: Next) -> Response {
+    let client_ip = request.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip);
+    let origin = request.headers().get(header::ORIGIN).map(printable);
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        origin,
+        client_ip = client_ip.map(tracing::field::display),
+    );
+    let started = Instant::now();
+    async move {
+        let response = next.run(request).await;
+        let cache = response.headers().get("x-cache").map(printable);
+        info!(
+            status = response.status().as_u16(),
+            duration_ms = started.elapsed().as_secs_f64() * 1000.0,
+            cache,
+            "handled request"
+        );
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+fn printable(value: &HeaderValue) -> &str {
+    value.to_str().unwrap_or("<non-ascii>")
+}
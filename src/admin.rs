@@ -0,0 +1,279 @@
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    cache::{self, PurgeMode},
+    error_response,
+    prometheus,
+    repos::RepoPattern,
+    sizes,
+    AppState,
+};
+
+/// Operator-only endpoints, all behind `ADMIN_TOKEN` bearer auth.
+///
+/// Without a configured token the routes answer 404, as if they did not exist.
+/// `/metrics` is the exception: open to scrapers, which rarely carry a token,
+/// unless `METRICS_REQUIRE_ADMIN` is set.
+pub fn router(state: AppState) -> Router<AppState> {
+    let mut metrics = Router::new().route("/metrics", get(prometheus::render));
+    if state.config.metrics_require_admin {
+        metrics = metrics.route_layer(middleware::from_fn_with_state(state.clone(), require_admin));
+    }
+    Router::new()
+        .route("/__stats", get(stats))
+        .route("/__config", get(config))
+        .route("/__ratelimit", get(rate_limit))
+        .route("/__usage", get(usage))
+        .route("/__bans", get(list_bans))
+        .route("/__bans/:client", delete(revoke_ban))
+        .route("/__cache", delete(purge_cache))
+        .route("/__cache/stats", get(cache_stats))
+        .route("/__cache/:owner", delete(purge_owner))
+        .route("/__cache/:owner/:repo", delete(purge_repo))
+        // As first asked for: one entry by its key, with a `?` in it
+        // percent-encoded, rather than an owner.
+        .route("/admin/cache", delete(purge_cache))
+        .route("/admin/cache/stats", get(cache_stats))
+        .route("/admin/cache/*key", delete(purge_entry))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+        .merge(metrics)
+}
+
+pub async fn require_admin(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.config.admin_token.is_none() {
+        return error_response(StatusCode::NOT_FOUND);
+    }
+    if is_admin(&state, request.headers()) {
+        next.run(request).await
+    } else {
+        error_response(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Whether `headers` carry the `ADMIN_TOKEN`.
+pub fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(expected) = state.config.admin_token.as_deref() else {
+        return false;
+    };
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn stats(State(state): State<AppState>) -> Response {
+    Json(json!({
+        "cache": cache_summary(&state).await,
+        "rate_limits": {
+            "origins": state.rate_limiter.origin_stats(),
+        },
+        "upstream_body_sizes": state.upstream.body_sizes.summary(),
+    }))
+    .into_response()
+}
+
+/// Just the `cache` part of `/__stats`.
+async fn cache_stats(State(state): State<AppState>) -> Response {
+    Json(cache_summary(&state).await).into_response()
+}
+
+async fn cache_summary(state: &AppState) -> Value {
+    // Sizes are only up to date once moka has caught up on writes.
+    state.cache.run_pending_tasks().await;
+    let cache = &state.config.cache;
+    json!({
+        "entries": state.cache.entry_count(),
+        "ttl_secs": cache.ttl.as_secs(),
+        "tti_secs": cache.tti.map(|tti| tti.as_secs()),
+        "max_entries": cache.max_bytes.is_none().then_some(cache.max_entries),
+        "bytes": cache::memory_bytes(&state.cache, cache),
+        "max_bytes": cache.max_bytes,
+        "evictions": state.evictions.snapshot(),
+        "hits": state.tiers.snapshot(),
+        "bodies": state.bodies.stats(&state.cache),
+        "body_sizes": sizes::cached(&state.cache),
+        "store": state.store.as_ref().map(|store| store.stats()),
+    })
+}
+
+/// The configuration in effect, secrets shown only as fingerprints. Reloads
+/// (SIGHUP) cover the repository lists, which report their own.
+async fn config(State(state): State<AppState>) -> Response {
+    Json(json!({ "config": &*state.config })).into_response()
+}
+
+#[derive(Deserialize)]
+struct RateLimitParams {
+    refresh: Option<String>,
+}
+
+/// The quota as last seen in upstream rate-limit headers, per token.
+/// `?refresh=1` asks GitHub first instead of relying on passive data.
+async fn rate_limit(
+    Query(params): Query<RateLimitParams>,
+    State(state): State<AppState>,
+) -> Response {
+    let tokens = &state.config.tokens;
+    if params.refresh.as_deref() == Some("1") {
+        for token in tokens.all() {
+            let url = &state.config.github.rate_limit;
+            let fetched = state
+                .upstream
+                .fetch(&state.config, token, url, HeaderMap::new(), false)
+                .await;
+            if fetched.is_err() {
+                warn!(token = token.name, "could not refresh the rate-limit snapshot");
+            }
+        }
+    }
+    Json(json!({ "tokens": state.upstream.quota.snapshot(tokens) })).into_response()
+}
+
+#[derive(Deserialize)]
+struct UsageParams {
+    origin: Option<String>,
+    key: Option<String>,
+}
+
+/// Traffic per origin and per API key over the usage window; `?origin=` and
+/// `?key=` narrow them to one.
+async fn usage(Query(params): Query<UsageParams>, State(state): State<AppState>) -> Response {
+    Json(json!({
+        "window_hours": state.usage.window_hours(),
+        "origins": state.usage.snapshot(params.origin.as_deref()),
+        "keys": state.usage.key_snapshot(params.key.as_deref()),
+        "cache_keys": {
+            "quota": state.key_quota.config().per_origin,
+            "window_secs": state.key_quota.config().window.as_secs(),
+            "origins": state.key_quota.snapshot(params.origin.as_deref()),
+        },
+        "api_keys": state.api_keys.as_ref().map(|api_keys| json!({
+            "keys": api_keys.snapshot(),
+            "redis": api_keys.redis_stats(),
+        })),
+    }))
+    .into_response()
+}
+
+async fn list_bans(State(state): State<AppState>) -> Response {
+    Json(state.bans.list()).into_response()
+}
+
+async fn revoke_ban(Path(client): Path<String>, State(state): State<AppState>) -> Response {
+    if state.bans.revoke(&client) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        error_response(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PurgeParams {
+    #[serde(default)]
+    pub mode: PurgeMode,
+}
+
+#[derive(Deserialize)]
+struct CachePurgeParams {
+    #[serde(default)]
+    mode: PurgeMode,
+    /// A single entry, by the path it was requested at, query included.
+    key: Option<String>,
+}
+
+/// `?key=` purges that entry, 404 if there is none; without it, the whole
+/// cache goes. Soft unless `?mode=hard`, like the other purges.
+async fn purge_cache(
+    Query(params): Query<CachePurgeParams>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(key) = params.key else {
+        if let Some(store) = &state.store {
+            store.clear();
+        }
+        return match cache::purge_all(&state.cache, params.mode).await {
+            Ok(()) => {
+                info!("cache flushed");
+                StatusCode::NO_CONTENT.into_response()
+            }
+            Err(err) => {
+                warn!("cannot flush the cache: {err}");
+                error_response(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    };
+    purge_key(&state, &key, params.mode).await
+}
+
+async fn purge_entry(
+    Path(key): Path<String>,
+    Query(params): Query<PurgeParams>,
+    State(state): State<AppState>,
+) -> Response {
+    purge_key(&state, &key, params.mode).await
+}
+
+/// Invalidates exactly one entry, 404 if there is none under `key`.
+async fn purge_key(state: &AppState, key: &str, mode: PurgeMode) -> Response {
+    // Keyed as the proxy keys it: no leading slash, and no `repos/`.
+    let key = key.trim_start_matches('/');
+    let key = key.strip_prefix("repos/").unwrap_or(key);
+    if let Some(store) = &state.store {
+        store.remove(key);
+    }
+    if cache::purge_key(&state.cache, key, mode).await {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        error_response(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn purge_owner(
+    Path(owner): Path<String>,
+    Query(params): Query<PurgeParams>,
+    State(state): State<AppState>,
+) -> Response {
+    purge(&state, &owner, params.mode).await
+}
+
+async fn purge_repo(
+    Path((owner, repo)): Path<(String, String)>,
+    Query(params): Query<PurgeParams>,
+    State(state): State<AppState>,
+) -> Response {
+    purge(&state, &format!("{owner}/{repo}"), params.mode).await
+}
+
+/// Invalidates what is cached for an owner or one repository; soft unless
+/// `?mode=hard`. The second tier is always purged outright: a soft purge
+/// only has to keep the hot, in-memory copies around.
+async fn purge(state: &AppState, pattern: &str, mode: PurgeMode) -> Response {
+    let Ok(repo) = pattern.parse::<RepoPattern>() else {
+        return error_response(StatusCode::BAD_REQUEST);
+    };
+    if let Some(store) = &state.store {
+        store.purge_repos(std::slice::from_ref(&repo));
+    }
+    match cache::purge_repos(&state.cache, [repo], mode).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => {
+            warn!("cannot purge {pattern}: {err}");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
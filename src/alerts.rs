@@ -0,0 +1,246 @@
+use axum::http::header;
+use metrics::counter;
+use reqwest::Client;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{config::AlertConfig, AppState};
+
+/// How often conditions are evaluated; the error rate is taken per tick.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Fewer upstream requests than this in a minute say nothing about the
+/// error rate.
+const MIN_REQUESTS: u64 = 10;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What `ALERT_CONDITIONS` can switch on.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AlertCondition {
+    /// More than `ALERT_ERROR_RATE_PERCENT` of upstream requests failing for
+    /// `ALERT_ERROR_MINUTES` in a row.
+    ErrorRate,
+    /// A token's core quota below `ALERT_QUOTA_FLOOR`.
+    Quota,
+    /// GitHub answering 401 to our token.
+    Token,
+    /// The circuit breaker open, after `CIRCUIT_BREAKER_FAILURES` upstream
+    /// failures in a row.
+    Breaker,
+}
+
+impl AlertCondition {
+    pub const ALL: [Self; 4] = [Self::ErrorRate, Self::Quota, Self::Token, Self::Breaker];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ErrorRate => "error_rate",
+            Self::Quota => "quota",
+            Self::Token => "token",
+            Self::Breaker => "breaker",
+        }
+    }
+}
+
+impl Serialize for AlertCondition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl FromStr for AlertCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|condition| condition.name() == s)
+            .ok_or_else(|| format!("unknown condition {s:?}"))
+    }
+}
+
+/// Upstream outcomes since the last check, counted by `Upstream`.
+#[derive(Default)]
+pub struct Outcomes {
+    requests: AtomicU64,
+    failures: AtomicU64,
+    unauthorized: AtomicU64,
+}
+
+impl Outcomes {
+    pub fn succeeded(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Unreachable, timed out or a 5xx.
+    pub fn failed(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unauthorized(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.unauthorized.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `(requests, failures, unauthorized)`, resetting them.
+    fn take(&self) -> (u64, u64, u64) {
+        (
+            self.requests.swap(0, Ordering::Relaxed),
+            self.failures.swap(0, Ordering::Relaxed),
+            self.unauthorized.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// Where one condition stands.
+#[derive(Default)]
+struct Alert {
+    /// A firing notification went out and no recovery since.
+    notified: bool,
+    last_sent: Option<Instant>,
+}
+
+/// Notifications for operators without a metrics stack: once a minute, the
+/// `ALERT_CONDITIONS` are checked and `ALERT_WEBHOOK_URL` is sent a JSON
+/// payload (or Slack's `{"text": ...}`, with `ALERT_FORMAT=slack`) when one
+/// starts holding, and again when it stops.
+///
+/// A condition that keeps holding is notified again at most once per
+/// `ALERT_COOLDOWN_SECS`; a recovery is only sent for a condition that was
+/// notified. Everything happens here, away from request handling.
+pub async fn run(state: AppState, shutdown: CancellationToken) {
+    let config = &state.config.alerts;
+    let Some(url) = &config.webhook_url else {
+        return;
+    };
+    let client = Client::builder()
+        .timeout(SEND_TIMEOUT)
+        .build()
+        .expect("alert client builds");
+
+    let mut alerts: Vec<(AlertCondition, Alert)> = config
+        .conditions
+        .iter()
+        .map(|condition| (*condition, Alert::default()))
+        .collect();
+    let mut failing_minutes = 0;
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        let (requests, failures, unauthorized) = state.upstream.outcomes.take();
+        let breaker = &state.upstream.breaker;
+        let (breaker_open, openings) = (breaker.is_open(), breaker.take_openings());
+        let rate = failures as f64 * 100.0 / requests.max(1) as f64;
+        if requests >= MIN_REQUESTS && rate > config.error_rate_percent {
+            failing_minutes += 1;
+        } else {
+            failing_minutes = 0;
+        }
+
+        for (condition, alert) in &mut alerts {
+            let holding = match condition {
+                AlertCondition::ErrorRate => (failing_minutes >= config.error_minutes).then(|| {
+                    format!(
+                        "{rate:.0}% of upstream requests failed in the last minute, \
+                         above {}% for {failing_minutes} minute(s)",
+                        config.error_rate_percent
+                    )
+                }),
+                AlertCondition::Quota => state
+                    .upstream
+                    .quota
+                    .lowest_core_remaining()
+                    .filter(|(_, remaining)| *remaining < config.quota_floor)
+                    .map(|(token, remaining)| {
+                        format!(
+                            "the {token} GitHub token has {remaining} requests left this hour, \
+                             below {}",
+                            config.quota_floor
+                        )
+                    }),
+                // Without traffic there is no news either way.
+                AlertCondition::Token if requests == 0 => continue,
+                AlertCondition::Token => (unauthorized > 0).then(|| {
+                    format!("GitHub rejected our token {unauthorized} time(s) in the last minute")
+                }),
+                AlertCondition::Breaker => (breaker_open || openings > 0).then(|| {
+                    let state = if breaker_open { "is open" } else { "opened and closed again" };
+                    format!(
+                        "the circuit breaker {state}: GitHub failed repeatedly, and requests \
+                         fail at once or are served stale while it is open"
+                    )
+                }),
+            };
+            match holding {
+                Some(summary) => {
+                    let due = alert
+                        .last_sent
+                        .is_none_or(|sent| sent.elapsed() >= config.cooldown);
+                    if due {
+                        notify(&client, url, config, *condition, true, &summary).await;
+                        alert.notified = true;
+                        alert.last_sent = Some(Instant::now());
+                    }
+                }
+                None if alert.notified => {
+                    let summary = format!("{} is back to normal", condition.name());
+                    notify(&client, url, config, *condition, false, &summary).await;
+                    alert.notified = false;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+async fn notify(
+    client: &Client,
+    url: &str,
+    config: &AlertConfig,
+    condition: AlertCondition,
+    firing: bool,
+    summary: &str,
+) {
+    let status = if firing { "firing" } else { "resolved" };
+    let payload: Value = if config.slack {
+        json!({ "text": format!("[{status}] github-cors-proxy: {summary}") })
+    } else {
+        json!({
+            "source": "github-cors-proxy",
+            "condition": condition.name(),
+            "status": status,
+            "summary": summary,
+        })
+    };
+    let sent = client
+        .post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(payload.to_string())
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    match sent {
+        Ok(_) => {
+            counter!("proxy_alerts_sent_total", "condition" => condition.name(), "status" => status)
+                .increment(1);
+            info!(condition = condition.name(), status, "sent alert: {summary}");
+        }
+        Err(err) => {
+            counter!("proxy_alert_failures_total").increment(1);
+            warn!(condition = condition.name(), "cannot send alert: {err}");
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use moka::sync::Cache;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Renamed and transferred repositories, `old/name` → `new/name`, learned
+/// from GitHub's 301s so requests for the old path go straight to the new
+/// one and share its cache entries.
+pub struct Aliases {
+    moved: Cache<String, String>,
+    /// Skips the lookup, and its allocation, until anything has moved.
+    any: AtomicBool,
+}
+
+impl Aliases {
+    pub fn new() -> Self {
+        Self {
+            moved: Cache::builder()
+                .max_capacity(1_000)
+                // A name can be taken over by a new repository after a rename.
+                .time_to_live(Duration::from_secs(24 * 60 * 60))
+                .build(),
+            any: AtomicBool::new(false),
+        }
+    }
+
+    /// The path under the repository's current name, if it has moved.
+    pub fn resolve(&self, path: &str) -> Option<String> {
+        if !self.any.load(Ordering::Relaxed) {
+            return None;
+        }
+        let (repo, rest) = split_repo(path)?;
+        let target = self.moved.get(&repo.to_ascii_lowercase())?;
+        Some(format!("{target}{rest}"))
+    }
+
+    pub fn record(&self, from: &str, to: &str) {
+        self.moved.insert(from.to_ascii_lowercase(), to.to_owned());
+        self.any.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Splits a proxied path into `owner/repo` and whatever follows it.
+pub fn split_repo(path: &str) -> Option<(&str, &str)> {
+    let owner_end = path.find('/')?;
+    let repo_end = path[owner_end + 1..]
+        .find('/')
+        .map_or(path.len(), |i| owner_end + 1 + i);
+    (repo_end > owner_end + 1).then(|| path.split_at(repo_end))
+}
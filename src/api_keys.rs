@@ -0,0 +1,313 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+use crate::{
+    admin, batch,
+    config::ApiKeysConfig,
+    json_error,
+    origin::OriginPattern,
+    peers::FromPeer,
+    ratelimit::too_many_requests,
+    redis::{Redis, Reply},
+    shadow::FromShadow,
+    AppState,
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// On every answer to a request with a key that has a quota.
+pub const QUOTA_HEADERS: [&str; 3] =
+    ["x-api-quota-limit", "x-api-quota-remaining", "x-api-quota-reset"];
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// One key as `API_KEYS_FILE` lists it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    name: String,
+    key: String,
+    /// Origins the key may be used from; any, if none are given.
+    #[serde(default)]
+    origins: Vec<String>,
+    /// Requests a UTC day; unlimited if unset.
+    daily_quota: Option<u64>,
+}
+
+/// On the response to a request made with a key, for usage to count it by.
+#[derive(Clone)]
+pub struct KeyName(pub Arc<str>);
+
+pub struct ApiKey {
+    name: Arc<str>,
+    origins: Vec<OriginPattern>,
+    daily_quota: Option<u64>,
+}
+
+impl ApiKey {
+    fn allows(&self, origin: Option<&str>) -> bool {
+        self.origins.is_empty()
+            || origin.is_some_and(|origin| self.origins.iter().any(|p| p.matches(origin)))
+    }
+}
+
+#[derive(Serialize)]
+pub struct KeyUsage {
+    requests_today: u64,
+    daily_quota: Option<u64>,
+}
+
+/// The keys in `API_KEYS_FILE`, by the SHA-256 of the secret, and how many
+/// requests each has made today. Counts are kept in memory, and in Redis
+/// too with `API_KEYS_REDIS_URL`, which is then what quotas go by; while
+/// Redis can't be reached, this replica's own counts stand in.
+pub struct ApiKeys {
+    config: ApiKeysConfig,
+    keys: RwLock<Arc<HashMap<[u8; 32], Arc<ApiKey>>>>,
+    /// The UTC day, as days since the epoch, and requests on it, by name.
+    counts: Mutex<HashMap<Arc<str>, (u64, u64)>>,
+    redis: Option<Redis>,
+}
+
+impl ApiKeys {
+    pub fn open(config: &ApiKeysConfig) -> Result<Self, String> {
+        let keys = Self {
+            config: config.clone(),
+            keys: RwLock::default(),
+            counts: Mutex::default(),
+            redis: config.redis.as_ref().map(|redis| Redis::new(redis, "api_keys")),
+        };
+        keys.reload()?;
+        Ok(keys)
+    }
+
+    /// Re-reads the file, keeping the previous keys if it can't be used.
+    /// Today's counts carry over for keys that keep their name.
+    pub fn reload(&self) -> Result<usize, String> {
+        let file = &self.config.file;
+        let invalid = |why: String| format!("API_KEYS_FILE: {}: {why}", file.display());
+        let contents = fs::read(file).map_err(|e| invalid(format!("cannot read it: {e}")))?;
+        let entries: Vec<KeyEntry> =
+            serde_json::from_slice(&contents).map_err(|e| invalid(e.to_string()))?;
+        let mut keys = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            if entry.key.is_empty() || entry.name.is_empty() {
+                return Err(invalid("every key needs a name and a secret".into()));
+            }
+            let origins = entry
+                .origins
+                .iter()
+                .map(|origin| origin.parse())
+                .collect::<Result<_, String>>()
+                .map_err(&invalid)?;
+            let key = ApiKey {
+                name: entry.name.into(),
+                origins,
+                daily_quota: entry.daily_quota,
+            };
+            if keys.insert(digest(entry.key.as_bytes()), Arc::new(key)).is_some() {
+                return Err(invalid("the same secret is listed twice".into()));
+            }
+        }
+        let count = keys.len();
+        *self.keys.write().unwrap() = Arc::new(keys);
+        Ok(count)
+    }
+
+    fn find(&self, secret: &[u8]) -> Option<Arc<ApiKey>> {
+        self.keys.read().unwrap().get(&digest(secret)).cloned()
+    }
+
+    /// The name of the key `secret` is, if it is one.
+    pub fn name_of(&self, secret: &[u8]) -> Option<Arc<str>> {
+        self.find(secret).map(|key| key.name.clone())
+    }
+
+    /// Counts one request against `key`, returning how many it has made
+    /// today, this one included.
+    async fn charge(&self, key: &ApiKey) -> u64 {
+        let day = today();
+        let local = {
+            let mut counts = self.counts.lock().unwrap();
+            let count = counts.entry(key.name.clone()).or_default();
+            if count.0 != day {
+                *count = (day, 0);
+            }
+            count.1 += 1;
+            count.1
+        };
+        let Some(redis) = &self.redis else {
+            return local;
+        };
+        let counter = redis.key(&format!("{}:{day}", key.name));
+        // Created with its expiry in one step, so no count outlives its day
+        // by much, whatever fails in between.
+        let expiry = (2 * DAY_SECS).to_string();
+        let create = [b"SET", counter.as_bytes(), b"0", b"EX", expiry.as_bytes(), b"NX"];
+        let created = redis.command(&create).await;
+        let counted = match created {
+            Ok(_) => redis.command(&[b"INCR", counter.as_bytes()]).await,
+            Err(err) => Err(err),
+        };
+        match counted {
+            Ok(Reply::Integer(shared)) => shared.max(0) as u64,
+            Ok(_) => local,
+            Err(err) => {
+                debug!(key = %key.name, "cannot count usage in Redis: {err}");
+                local
+            }
+        }
+    }
+
+    /// Today's requests per key, as this replica counted them.
+    pub fn snapshot(&self) -> BTreeMap<String, KeyUsage> {
+        let day = today();
+        let counts = self.counts.lock().unwrap();
+        self.keys
+            .read()
+            .unwrap()
+            .values()
+            .map(|key| {
+                let requests_today = counts
+                    .get(&key.name)
+                    .filter(|(counted, _)| *counted == day)
+                    .map_or(0, |(_, count)| *count);
+                let usage = KeyUsage {
+                    requests_today,
+                    daily_quota: key.daily_quota,
+                };
+                (key.name.to_string(), usage)
+            })
+            .collect()
+    }
+
+    pub fn redis_stats(&self) -> Option<serde_json::Value> {
+        self.redis.as_ref().map(Redis::stats)
+    }
+}
+
+/// Identifies the client by its `X-Api-Key`, holding it to the key's origins
+/// and daily quota. Without `API_KEYS_REQUIRED`, requests without a key are
+/// served as before. A batch is charged for each path it asks for.
+pub async fn api_key_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(api_keys) = &state.api_keys else {
+        return next.run(request).await;
+    };
+    // Charged on the replica the client came to, or not at all; operators
+    // prove who they are otherwise.
+    let extensions = request.extensions();
+    if extensions.get::<FromPeer>().is_some()
+        || extensions.get::<FromShadow>().is_some()
+        || admin::is_admin(&state, request.headers())
+    {
+        return next.run(request).await;
+    }
+    let Some(secret) = request.headers().get(API_KEY_HEADER) else {
+        if api_keys.config.required {
+            counter!("proxy_api_key_rejected_total", "reason" => "missing").increment(1);
+            return json_error(StatusCode::UNAUTHORIZED, "an X-Api-Key header is required");
+        }
+        return next.run(request).await;
+    };
+    let Some(key) = api_keys.find(secret.as_bytes()) else {
+        counter!("proxy_api_key_rejected_total", "reason" => "unknown").increment(1);
+        return json_error(StatusCode::UNAUTHORIZED, "unknown API key");
+    };
+    let origin = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok());
+    if !key.allows(origin) {
+        counter!("proxy_api_key_rejected_total", "reason" => "origin").increment(1);
+        return json_error(StatusCode::FORBIDDEN, "this API key can't be used from this origin");
+    }
+    let name = KeyName(key.name.clone());
+    if request.uri().path() == batch::PATH {
+        request.extensions_mut().insert(key);
+        let mut response = next.run(request).await;
+        response.extensions_mut().insert(name);
+        return response;
+    }
+
+    let used = api_keys.charge(&key).await;
+    counter!("proxy_api_key_requests_total", "key" => key.name.to_string()).increment(1);
+    if let Some(quota) = key.daily_quota.filter(|quota| used > *quota) {
+        counter!("proxy_api_key_rejected_total", "reason" => "quota").increment(1);
+        let reset = (today() + 1) * DAY_SECS;
+        let mut response = too_many_requests(Duration::from_secs(reset.saturating_sub(now())));
+        quota_headers(response.headers_mut(), quota, used);
+        response.headers_mut().insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(
+                "retry-after, x-api-quota-limit, x-api-quota-remaining, x-api-quota-reset",
+            ),
+        );
+        response.extensions_mut().insert(name);
+        return response;
+    }
+    let mut response = next.run(request).await;
+    if let Some(quota) = key.daily_quota {
+        quota_headers(response.headers_mut(), quota, used);
+    }
+    response.extensions_mut().insert(name);
+    response
+}
+
+/// Charges one path of a batch to the key it came with, if any: `Err` with
+/// the quota when it's used up.
+pub async fn charge_batch_path(state: &AppState, key: Option<&ApiKey>) -> Result<(), u64> {
+    let (Some(api_keys), Some(key)) = (&state.api_keys, key) else {
+        return Ok(());
+    };
+    let used = api_keys.charge(key).await;
+    counter!("proxy_api_key_requests_total", "key" => key.name.to_string()).increment(1);
+    match key.daily_quota {
+        Some(quota) if used > quota => {
+            counter!("proxy_api_key_rejected_total", "reason" => "quota").increment(1);
+            Err(quota)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `X-Api-Quota-*`: the quota, what is left of it, and the Unix time it
+/// resets at, midnight UTC.
+fn quota_headers(headers: &mut HeaderMap, quota: u64, used: u64) {
+    let [limit, remaining, reset] = QUOTA_HEADERS;
+    headers.insert(limit, HeaderValue::from(quota));
+    headers.insert(remaining, HeaderValue::from(quota.saturating_sub(used)));
+    headers.insert(reset, HeaderValue::from((today() + 1) * DAY_SECS));
+}
+
+fn digest(secret: &[u8]) -> [u8; 32] {
+    Sha256::digest(secret).into()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Days since the epoch, in UTC.
+fn today() -> u64 {
+    now() / DAY_SECS
+}
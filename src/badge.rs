@@ -0,0 +1,238 @@
+use axum::{
+    body,
+    extract::{Path, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+use metrics::counter;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::{
+    client_ip::ClientIp, config::BadgeConfig, json_error, proxy_handler, tokens::GithubToken,
+    AppState,
+};
+
+pub const PATH: &str = "/badge/:owner/:repo/:kind";
+
+/// Shields-style SVG badges for READMEs: `GET /badge/owner/repo/stars`, or
+/// `forks`, `issues` or `release` for the latest release's tag. The numbers
+/// come from the repository's JSON through the cache like any request for
+/// it; the badge itself may be kept by browsers and image proxies for
+/// `BADGE_MAX_AGE_SECS`.
+pub fn router(config: &BadgeConfig) -> Router<AppState> {
+    if !config.enabled {
+        return Router::new();
+    }
+    Router::new().route(PATH, get(badge))
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Stars,
+    Forks,
+    Issues,
+    Release,
+}
+
+impl Kind {
+    fn parse(kind: &str) -> Option<Self> {
+        Some(match kind.strip_suffix(".svg").unwrap_or(kind) {
+            "stars" => Self::Stars,
+            "forks" => Self::Forks,
+            "issues" => Self::Issues,
+            "release" => Self::Release,
+            _ => return None,
+        })
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Stars => "stars",
+            Self::Forks => "forks",
+            Self::Issues => "issues",
+            Self::Release => "release",
+        }
+    }
+}
+
+async fn badge(
+    Path((owner, repo, kind)): Path<(String, String, String)>,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<Arc<GithubToken>>>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(kind) = Kind::parse(&kind) else {
+        return json_error(StatusCode::NOT_FOUND, "badges are stars, forks, issues or release");
+    };
+    let path = match kind {
+        Kind::Release => format!("{owner}/{repo}/releases/latest"),
+        _ => format!("{owner}/{repo}"),
+    };
+    // As in `/__batch`: only the origin is passed on, so the badge shares
+    // cache entries and coalesced fetches with plain requests for the path.
+    let mut sub_headers = HeaderMap::new();
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        sub_headers.insert(header::ORIGIN, origin.clone());
+    }
+    let response = proxy_handler(
+        Path(path),
+        RawQuery(None),
+        sub_headers,
+        client_ip,
+        token,
+        State(state.clone()),
+    )
+    .await;
+    let status = response.status();
+    let body = body::to_bytes(response.into_body(), usize::MAX).await;
+    let document: Option<Value> = body.ok().and_then(|b| serde_json::from_slice(&b).ok());
+    let value = document.as_ref().filter(|_| status == StatusCode::OK).and_then(|d| {
+        match kind {
+            Kind::Stars => d["stargazers_count"].as_u64().map(count),
+            Kind::Forks => d["forks_count"].as_u64().map(count),
+            Kind::Issues => d["open_issues_count"].as_u64().map(count),
+            Kind::Release => d["tag_name"].as_str().map(str::to_owned),
+        }
+    });
+    counter!("proxy_badges_total", "kind" => kind.label()).increment(1);
+
+    let (value, color, cache_control) = match value {
+        Some(value) => {
+            let max_age = state.config.badge.max_age.as_secs();
+            (value, "#007ec6", format!("public, max-age={max_age}"))
+        }
+        None if status == StatusCode::NOT_FOUND => {
+            let value = match kind {
+                Kind::Release => "none",
+                _ => "not found",
+            };
+            (value.to_owned(), "#9f9f9f", "no-cache".to_owned())
+        }
+        None => ("unavailable".to_owned(), "#9f9f9f", "no-cache".to_owned()),
+    };
+    let mut response = render(kind.label(), &value, color).into_response();
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/svg+xml"));
+    if let Ok(cache_control) = HeaderValue::try_from(cache_control) {
+        response_headers.insert(header::CACHE_CONTROL, cache_control);
+    }
+    // Opened on its own, the image must not be able to run anything.
+    response_headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'"),
+    );
+    response
+}
+
+/// `1234` as `1.2k`, the way badges show counts. The suffix is picked once
+/// rounded, so `999_999` is `1M` rather than `1000k`.
+fn count(n: u64) -> String {
+    if n < 1_000 {
+        return n.to_string();
+    }
+    let thousands = rounded(n as f64 / 1_000.0);
+    if thousands < 1_000.0 {
+        return format!("{thousands}k");
+    }
+    format!("{}M", rounded(n as f64 / 1_000_000.0))
+}
+
+/// To one decimal below 10, to a whole number above.
+fn rounded(n: f64) -> f64 {
+    if n < 10.0 {
+        (n * 10.0).round() / 10.0
+    } else {
+        n.round()
+    }
+}
+
+/// A flat two-part badge, the label on grey and the value on `color`.
+fn render(label: &str, value: &str, color: &str) -> String {
+    let (label_width, value_width) = (text_width(label) + 10, text_width(value) + 10);
+    let width = label_width + value_width;
+    let (label, value) = (escape(label), escape(value));
+    let (label_x, value_x) = (label_width * 5, label_width * 10 + value_width * 5);
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img""#,
+            r#" aria-label="{label}: {value}"><title>{label}: {value}</title>"#,
+            r#"<linearGradient id="s" x2="0" y2="100%">"#,
+            r##"<stop offset="0" stop-color="#bbb" stop-opacity=".1"/>"##,
+            r#"<stop offset="1" stop-opacity=".1"/></linearGradient>"#,
+            r##"<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/>"##,
+            r#"</clipPath>"#,
+            r##"<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/>"##,
+            r#"<rect x="{label_width}" width="{value_width}" height="20" fill="{color}"/>"#,
+            r##"<rect width="{width}" height="20" fill="url(#s)"/></g>"##,
+            r##"<g fill="#fff" text-anchor="middle""##,
+            r#" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="110">"#,
+            r##"<text x="{label_x}" y="150" fill="#010101" fill-opacity=".3""##,
+            r#" transform="scale(.1)">{label}</text>"#,
+            r#"<text x="{label_x}" y="140" transform="scale(.1)">{label}</text>"#,
+            r##"<text x="{value_x}" y="150" fill="#010101" fill-opacity=".3""##,
+            r#" transform="scale(.1)">{value}</text>"#,
+            r#"<text x="{value_x}" y="140" transform="scale(.1)">{value}</text></g></svg>"#,
+        ),
+        width = width,
+        label = label,
+        value = value,
+        color = color,
+        label_width = label_width,
+        value_width = value_width,
+        label_x = label_x,
+        value_x = value_x,
+    )
+}
+
+/// Roughly how wide `text` is in 11px Verdana, which badges are set in.
+fn text_width(text: &str) -> u32 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'l' | 'j' | '.' | ',' | ':' | ';' | '|' | '!' | '\'' => 3,
+            'f' | 'r' | 't' | 'I' | ' ' | '(' | ')' | '-' | '/' => 5,
+            'm' | 'w' | 'M' | 'W' => 10,
+            c if c.is_ascii_uppercase() => 8,
+            _ => 7,
+        })
+        .sum()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::count;
+
+    #[test]
+    fn counts_are_shortened() {
+        assert_eq!(count(999), "999");
+        assert_eq!(count(1_000), "1k");
+        assert_eq!(count(1_234), "1.2k");
+        assert_eq!(count(9_960), "10k");
+        assert_eq!(count(12_345_678), "12M");
+    }
+
+    #[test]
+    fn the_suffix_follows_the_rounding() {
+        assert_eq!(count(999_499), "999k");
+        assert_eq!(count(999_500), "1M");
+        assert_eq!(count(999_999), "1M");
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use metrics::{counter, gauge};
+use moka::sync::Cache;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+use crate::{
+    api_keys::API_KEY_HEADER, client_ip::ClientIp, config::BanConfig, error_response, AppState,
+};
+
+/// Temporary, in-memory bans for clients that keep tripping limits.
+///
+/// Clients accrue strikes from the responses they receive; crossing either
+/// threshold within the sliding window earns a ban, during which every
+/// request is turned away before any real work is done.
+pub struct Bans {
+    config: BanConfig,
+    strikes: Cache<String, Arc<Mutex<Strikes>>>,
+    active: Cache<String, Arc<Ban>>,
+}
+
+#[derive(Default)]
+struct Strikes {
+    rate_limited: VecDeque<Instant>,
+    invalid: VecDeque<Instant>,
+}
+
+struct Ban {
+    reason: &'static str,
+    issued_at: SystemTime,
+    expires_at: Instant,
+    blocked: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct BanInfo {
+    client: String,
+    reason: &'static str,
+    issued_at: u64,
+    expires_in_secs: u64,
+    blocked_requests: u64,
+}
+
+impl Bans {
+    pub fn new(config: BanConfig) -> Self {
+        let strikes = Cache::builder()
+            .time_to_idle(config.window)
+            .max_capacity(100_000)
+            .build();
+
+        let active = Cache::builder()
+            .time_to_live(config.duration)
+            .max_capacity(100_000)
+            .eviction_listener(|_, _, _| gauge!("proxy_bans_active").decrement(1.0))
+            .build();
+
+        Self {
+            config,
+            strikes,
+            active,
+        }
+    }
+
+    /// Returns true (and counts the attempt) if `client` is currently banned.
+    pub fn is_banned(&self, client: &str) -> bool {
+        match self.active.get(client) {
+            Some(ban) => {
+                ban.blocked.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Feeds a response status into the client's strike record, issuing a
+    /// ban if it pushes them over a threshold.
+    pub fn record(&self, client: &str, status: StatusCode) {
+        let (limit, reason) = match status {
+            StatusCode::TOO_MANY_REQUESTS => (self.config.max_rate_limited, "rate_limited"),
+            StatusCode::BAD_REQUEST => (self.config.max_invalid, "invalid_requests"),
+            _ => return,
+        };
+        if limit == 0 || self.config.duration.is_zero() {
+            return;
+        }
+
+        let entry = self.strikes.get_with_by_ref(client, Default::default);
+        let tripped = {
+            let mut strikes = entry.lock().unwrap();
+            let times = if status == StatusCode::TOO_MANY_REQUESTS {
+                &mut strikes.rate_limited
+            } else {
+                &mut strikes.invalid
+            };
+            let now = Instant::now();
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > self.config.window)
+            {
+                times.pop_front();
+            }
+            times.push_back(now);
+            times.len() > limit
+        };
+
+        if tripped {
+            self.strikes.invalidate(client);
+            self.ban(client, reason);
+        }
+    }
+
+    fn ban(&self, client: &str, reason: &'static str) {
+        warn!(
+            client,
+            reason,
+            duration_secs = self.config.duration.as_secs(),
+            "banning client"
+        );
+        counter!("proxy_bans_issued_total", "reason" => reason).increment(1);
+        gauge!("proxy_bans_active").increment(1.0);
+        self.active.insert(
+            client.to_owned(),
+            Arc::new(Ban {
+                reason,
+                issued_at: SystemTime::now(),
+                expires_at: Instant::now() + self.config.duration,
+                blocked: AtomicU64::new(0),
+            }),
+        );
+    }
+
+    pub fn list(&self) -> Vec<BanInfo> {
+        let now = Instant::now();
+        self.active
+            .iter()
+            .map(|(client, ban)| BanInfo {
+                client: client.as_ref().clone(),
+                reason: ban.reason,
+                issued_at: ban
+                    .issued_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or(Duration::ZERO)
+                    .as_secs(),
+                expires_in_secs: ban.expires_at.saturating_duration_since(now).as_secs(),
+                blocked_requests: ban.blocked.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Lifts a ban early; returns whether one existed.
+    pub fn revoke(&self, client: &str) -> bool {
+        self.strikes.invalidate(client);
+        self.active.remove(client).is_some()
+    }
+}
+
+/// Bans clients by their API key, as `key:<name>`, when they present a
+/// known one: many of a key's users may share an address, and one user may
+/// have many. Anything else is banned by address.
+pub async fn ban_middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let key = state.api_keys.as_ref().and_then(|api_keys| {
+        let secret = request.headers().get(API_KEY_HEADER)?;
+        api_keys.name_of(secret.as_bytes())
+    });
+    let client = match (key, request.extensions().get::<ClientIp>()) {
+        (Some(name), _) => format!("key:{name}"),
+        (None, Some(ClientIp(ip))) => ip.to_string(),
+        (None, None) => return next.run(request).await,
+    };
+
+    if state.bans.is_banned(&client) {
+        return error_response(StatusCode::FORBIDDEN);
+    }
+
+    let response = next.run(request).await;
+    state.bans.record(&client, response.status());
+    response
+}
@@ -0,0 +1,226 @@
+use axum::{
+    body::{self, Bytes},
+    extract::{rejection::BytesRejection, DefaultBodyLimit, Path, Query, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use metrics::{counter, histogram};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    api_keys::{self, ApiKey},
+    client_ip::ClientIp,
+    config::BatchConfig,
+    content::BodyKind,
+    json_body, json_error, paths, preflight, proxy_handler,
+    tokens::GithubToken,
+    AppState,
+};
+
+/// Not charged to the origin's or the client's rate limit as one request:
+/// each path is.
+pub const PATH: &str = "/__batch";
+
+/// Several proxied paths in one round trip, for pages that would otherwise
+/// fan out into dozens of requests. `POST {"paths": [...]}`, or just the
+/// array, or `GET ?paths=a/b,c/d`; each path goes through the same cache and upstream
+/// as it would on its own, and answers `{status, body}` under its own key, so
+/// one failing path doesn't fail the rest.
+pub fn router(config: &BatchConfig) -> Router<AppState> {
+    Router::new()
+        .route(PATH, get(from_query).post(from_body).options(batch_preflight))
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PathList {
+    Object { paths: Vec<String> },
+    Array(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct PathQuery {
+    paths: String,
+}
+
+async fn from_query(
+    query: Option<Query<PathQuery>>,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<Arc<GithubToken>>>,
+    api_key: Option<Extension<Arc<ApiKey>>>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(Query(query)) = query else {
+        return json_error(StatusCode::BAD_REQUEST, "expected ?paths=owner/repo,...");
+    };
+    let paths = query.paths.split(',').map(str::to_owned).collect();
+    batch(state, paths, &headers, client_ip, token, api_key).await
+}
+
+async fn from_body(
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<Arc<GithubToken>>>,
+    api_key: Option<Extension<Arc<ApiKey>>>,
+    State(state): State<AppState>,
+    body: Result<Bytes, BytesRejection>,
+) -> Response {
+    let body = match body {
+        Ok(body) => body,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text()),
+    };
+    let paths = match serde_json::from_slice(&body) {
+        Ok(PathList::Object { paths } | PathList::Array(paths)) => paths,
+        Err(_) => return json_error(StatusCode::BAD_REQUEST, r#"expected {"paths": [...]}"#),
+    };
+    batch(state, paths, &headers, client_ip, token, api_key).await
+}
+
+/// The usual preflight, plus `POST`.
+async fn batch_preflight(state: State<AppState>, headers: HeaderMap) -> Response {
+    let mut response = preflight(state, headers).await;
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("GET, POST, OPTIONS"),
+    );
+    response
+}
+
+async fn batch(
+    state: AppState,
+    paths: Vec<String>,
+    headers: &HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<Arc<GithubToken>>>,
+    api_key: Option<Extension<Arc<ApiKey>>>,
+) -> Response {
+    let mut requested: Vec<String> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = path.trim().trim_start_matches('/');
+        if !path.is_empty() && !requested.iter().any(|p| p == path) {
+            requested.push(path.to_owned());
+        }
+    }
+    let max_paths = state.config.batch.max_paths;
+    if requested.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "no paths given");
+    }
+    if requested.len() > max_paths {
+        let message = format!("at most {max_paths} paths per batch");
+        return json_error(StatusCode::BAD_REQUEST, &message);
+    }
+    counter!("proxy_batch_requests_total").increment(1);
+    histogram!("proxy_batch_paths").record(requested.len() as f64);
+
+    // Only the origin is passed on: without the client's validators or
+    // cache-control, every path shares cache entries and coalesced fetches
+    // with plain requests for it.
+    let origin = headers.get(header::ORIGIN).cloned();
+    let mut sub_headers = HeaderMap::new();
+    if let Some(origin) = &origin {
+        sub_headers.insert(header::ORIGIN, origin.clone());
+    }
+    let origin = origin.as_ref().and_then(|v| v.to_str().ok());
+
+    let mut results: Vec<Option<Value>> = vec![None; requested.len()];
+    let permits = Arc::new(Semaphore::new(state.config.batch.concurrency));
+    let mut tasks = JoinSet::new();
+    for (i, path) in requested.iter().enumerate() {
+        if paths::is_download(path) {
+            let body = json!({ "error": "downloads can't be batched" });
+            results[i] = Some(json!({ "status": 400, "body": body }));
+            continue;
+        }
+        if let Some(Err(retry_after)) = origin.and_then(|o| state.rate_limiter.check_origin(o)) {
+            counter!("proxy_rate_limited_total", "scope" => "origin").increment(1);
+            results[i] = Some(rate_limited(retry_after));
+            continue;
+        }
+        if let Some(Extension(ClientIp(ip))) = client_ip {
+            if let Err(retry_after) = state.rate_limiter.check_client(ip) {
+                counter!("proxy_rate_limited_total", "scope" => "client").increment(1);
+                results[i] = Some(rate_limited(retry_after));
+                continue;
+            }
+        }
+        let api_key = api_key.as_ref().map(|Extension(key)| key.as_ref());
+        if let Err(quota) = api_keys::charge_batch_path(&state, api_key).await {
+            let body = json!({ "error": "API key quota used up", "daily_quota": quota });
+            results[i] = Some(json!({ "status": 429, "body": body }));
+            continue;
+        }
+
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path.to_owned(), Some(query.to_owned())),
+            None => (path.clone(), None),
+        };
+        let (state, headers, permits) = (state.clone(), sub_headers.clone(), permits.clone());
+        let token = token.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire().await;
+            let response = proxy_handler(
+                Path(path),
+                RawQuery(query),
+                headers,
+                client_ip,
+                token,
+                State(state),
+            )
+            .await;
+            (i, outcome(response).await)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((i, outcome)) = joined {
+            results[i] = Some(outcome);
+        }
+    }
+
+    let results = requested
+        .into_iter()
+        .zip(results)
+        .map(|(path, result)| {
+            let result = result.unwrap_or_else(|| {
+                json!({ "status": 500, "body": { "error": "internal error" } })
+            });
+            (path, result)
+        })
+        .collect::<Map<_, _>>();
+    json_body(StatusCode::OK, Value::Object(results))
+}
+
+fn rate_limited(retry_after: Duration) -> Value {
+    let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let body = json!({ "error": "rate limited", "retry_after": retry_after });
+    json!({ "status": 429, "body": body })
+}
+
+/// A sub-request's response as `{status, body}`, the body inlined as JSON
+/// when it is JSON and as a string when it is UTF-8 text. Anything else
+/// can't be carried faithfully and is left out, marked `"binary": true`.
+async fn outcome(response: Response) -> Value {
+    let status = response.status().as_u16();
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let body = match body::to_bytes(response.into_body(), usize::MAX).await {
+        Ok(body) if body.is_empty() => Value::Null,
+        Ok(body) => {
+            let json = BodyKind::classify(content_type.as_ref(), &body) == BodyKind::Json;
+            match json.then(|| serde_json::from_slice(&body).ok()).flatten() {
+                Some(document) => document,
+                None => match String::from_utf8(body.to_vec()) {
+                    Ok(text) => text.into(),
+                    Err(_) => return json!({ "status": status, "body": null, "binary": true }),
+                },
+            }
+        }
+        Err(_) => return json!({ "status": 502, "body": { "error": "response was cut short" } }),
+    };
+    json!({ "status": status, "body": body })
+}
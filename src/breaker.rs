@@ -0,0 +1,97 @@
+use metrics::{counter, gauge};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Stops sending GitHub requests while it is down. After
+/// `CIRCUIT_BREAKER_FAILURES` failures in a row (unreachable, timed out or a
+/// 5xx) the circuit opens: requests fail at once, or are served stale, for
+/// `CIRCUIT_BREAKER_COOLDOWN_SECS`. Then one request at a time goes through
+/// as a probe; the first to succeed closes the circuit again, a failure
+/// reopens it for another cooldown.
+pub struct CircuitBreaker {
+    /// 0 never opens.
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    opened_at: Option<Instant>,
+    /// When the last probe was let through. A probe that never reports
+    /// back, because its client went away, is replaced after a cooldown.
+    probe_at: Option<Instant>,
+    /// Times it opened since `take_openings` last asked.
+    openings: u32,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether a request may be sent now; if not, how long until one may.
+    pub fn allow(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let Some(opened_at) = state.opened_at else {
+            return Ok(());
+        };
+        let open_for = opened_at.elapsed();
+        let probing = state.probe_at.is_some_and(|at| at.elapsed() < self.cooldown);
+        if open_for < self.cooldown || probing {
+            counter!("proxy_upstream_circuit_rejected_total").increment(1);
+            return Err(self.cooldown.saturating_sub(open_for).max(Duration::from_secs(1)));
+        }
+        state.probe_at = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        if state.opened_at.take().is_some() {
+            state.probe_at = None;
+            gauge!("proxy_upstream_circuit_open").set(0.0);
+            info!("GitHub answered again, closing the circuit");
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().opened_at.is_some()
+    }
+
+    /// Times the circuit opened since the last call, resetting the count,
+    /// so an opening that closed again in between isn't missed.
+    pub fn take_openings(&self) -> u32 {
+        std::mem::take(&mut self.state.lock().unwrap().openings)
+    }
+
+    pub fn failed(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        if state.opened_at.is_some() {
+            // The probe failed: another full cooldown.
+            state.opened_at = Some(Instant::now());
+            state.probe_at = None;
+        } else if state.failures >= self.threshold {
+            state.opened_at = Some(Instant::now());
+            state.openings = state.openings.saturating_add(1);
+            gauge!("proxy_upstream_circuit_open").set(1.0);
+            warn!(
+                "{} upstream failures in a row, opening the circuit for {:?}",
+                state.failures, self.cooldown
+            );
+        }
+    }
+}
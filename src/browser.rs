@@ -0,0 +1,183 @@
+use axum::{
+    body::{self, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use std::fmt::Write;
+
+use crate::{admin::constant_time_eq, content::BodyKind, error_response, AppState};
+
+/// Bodies larger than this are served as they are, even to browsers.
+const MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+/// The cookie holding `ADMIN_TOKEN`, which unlocks the refresh link.
+const ADMIN_COOKIE: &str = "proxy_admin_token";
+/// The query parameter the refresh link adds; never sent to GitHub.
+const REFRESH_PARAM: &str = "__refresh";
+
+/// With `BROWSER_PAGE`, someone opening a proxied URL in a browser tab gets
+/// the JSON pretty-printed in a small page, with the cache status, status
+/// code and entry age, rather than a wall of text. Only requests whose
+/// `Accept` asks for HTML are affected; every other client sees exactly
+/// what it saw before, bar a `Vary: accept`.
+///
+/// With the `proxy_admin_token` cookie set to `ADMIN_TOKEN`, the page also
+/// links to a refresh that bypasses the cache, like a hard reload.
+pub async fn browser_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let eligible = state.config.browser_page
+        && request.method() == Method::GET
+        && !request.uri().path().starts_with("/__")
+        && !request.uri().path().starts_with("/watch/");
+    if !eligible {
+        return next.run(request).await;
+    }
+    let wants_html = request
+        .headers()
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains("text/html"));
+    let admin = state.config.admin_token.as_deref().is_some_and(|expected| {
+        cookie(request.headers(), ADMIN_COOKIE)
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+    });
+    if let Some(query) = request.uri().query() {
+        let refresh = query.split('&').any(|p| p == REFRESH_PARAM);
+        if refresh {
+            let rest: Vec<&str> = query.split('&').filter(|p| *p != REFRESH_PARAM).collect();
+            let mut uri = request.uri().path().to_owned();
+            if !rest.is_empty() {
+                uri.push('?');
+                uri.push_str(&rest.join("&"));
+            }
+            match uri.parse() {
+                Ok(uri) => *request.uri_mut() = uri,
+                Err(_) => return error_response(StatusCode::BAD_REQUEST),
+            }
+            if admin {
+                request
+                    .headers_mut()
+                    .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            }
+        }
+    }
+    let path = request.uri().to_string();
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    if !wants_html {
+        return response;
+    }
+
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let is_json = content_type
+        .as_ref()
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let small = body::HttpBody::size_hint(response.body())
+        .upper()
+        .is_some_and(|len| len <= MAX_PAGE_BYTES as u64);
+    if !is_json || !small {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let Ok(body) = body::to_bytes(body, MAX_PAGE_BYTES).await else {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    if BodyKind::classify(content_type.as_ref(), &body) != BodyKind::Json {
+        return Response::from_parts(parts, Body::from(body));
+    }
+    let pretty = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|document| serde_json::to_string_pretty(&document).ok())
+        .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("-")
+    };
+    let mut page = String::with_capacity(pretty.len() + 1024);
+    page.push_str(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"robots\" content=\"noindex\"><title>",
+    );
+    page.push_str(&escape(&path));
+    page.push_str(
+        "</title><style>body{font-family:sans-serif;margin:1.5em}\
+         dl{display:grid;grid-template-columns:max-content auto;gap:.2em 1em}\
+         dd{margin:0}pre{background:#f6f8fa;padding:1em;overflow:auto}</style>\
+         </head><body>\n<h1>",
+    );
+    page.push_str(&escape(&path));
+    let _ = write!(
+        page,
+        "</h1>\n<dl><dt>Status</dt><dd>{}</dd><dt>Cache</dt><dd>{}</dd>\
+         <dt>Age</dt><dd>{}s</dd></dl>\n",
+        parts.status,
+        escape(header("x-cache")),
+        escape(header("age")),
+    );
+    if admin {
+        let separator = if path.contains('?') { "&amp;" } else { "?" };
+        let _ = writeln!(
+            page,
+            "<p><a href=\"{}{separator}{REFRESH_PARAM}\">Refresh (bypass cache)</a></p>",
+            escape(&path)
+        );
+    }
+    page.push_str("<pre>");
+    page.push_str(&escape(&pretty));
+    page.push_str("</pre>\n</body></html>\n");
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'"),
+    );
+    headers.insert(header::VARY, HeaderValue::from_static("accept"));
+    (parts.status, headers, page).into_response()
+}
+
+/// A cookie's value from the request's `Cookie` headers.
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+}
+
+/// Escapes text for HTML element content and double-quoted attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,610 @@
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use bytes::Bytes;
+use metrics::{counter, histogram};
+use moka::{future::Cache, notification::RemovalCause, Expiry};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::{
+    config::CacheConfig, content::BodyKind, freshness::IMMUTABLE_TTL, repos::RepoPattern,
+    upstream::FetchError,
+};
+
+pub type ResponseCache = Cache<Arc<str>, Arc<CachedResponse>>;
+
+/// What the cache holds for a path: the body plus the allowlisted upstream
+/// headers, so a hit can reproduce the original response.
+pub struct CachedResponse {
+    /// GitHub's status: its 404s and 403s are cached too, and must not be
+    /// served as successes.
+    pub status: StatusCode,
+    pub body: Bytes,
+    pub headers: HeaderMap,
+    pub stored_at: Instant,
+    /// This entry's own freshness lifetime, usually from upstream `Cache-Control`.
+    pub ttl: Duration,
+    /// Soft-purged: stale whatever its TTL says, until refreshed.
+    pub purged: bool,
+    /// Pinned to a commit SHA, so it can never change.
+    pub immutable: bool,
+    /// Whether the body may be rewritten as JSON.
+    pub kind: BodyKind,
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self) -> bool {
+        !self.purged && self.stored_at.elapsed() < self.ttl
+    }
+
+    /// No longer fresh, but by less than `window`.
+    pub fn is_stale_within(&self, window: Duration) -> bool {
+        let age = self.stored_at.elapsed();
+        !self.purged && age >= self.ttl && age < self.ttl + window
+    }
+
+    /// A copy of this entry that is no longer fresh but still there to be
+    /// revalidated and, meanwhile, served as stale.
+    fn purged(&self) -> Self {
+        Self {
+            status: self.status,
+            body: self.body.clone(),
+            headers: self.headers.clone(),
+            stored_at: self.stored_at,
+            ttl: self.ttl,
+            purged: true,
+            immutable: self.immutable,
+            kind: self.kind,
+        }
+    }
+
+    /// This entry renewed by an upstream 304: same body, a fresh clock, and
+    /// any relayable headers the 304 carried replacing the old ones.
+    pub fn revalidated(&self, not_modified: &HeaderMap, ttl: Duration) -> Self {
+        let mut headers = self.headers.clone();
+        for name in not_modified.keys() {
+            headers.remove(name);
+            for value in not_modified.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        Self {
+            status: self.status,
+            body: self.body.clone(),
+            headers,
+            stored_at: Instant::now(),
+            ttl: if self.immutable { IMMUTABLE_TTL } else { ttl },
+            purged: false,
+            immutable: self.immutable,
+            kind: self.kind,
+        }
+    }
+}
+
+/// Bodies by SHA-256, so entries whose bodies are byte-for-byte the same
+/// (equivalent query strings, list pages that haven't changed) share one
+/// allocation through cheap `Bytes` clones instead of each holding a copy.
+#[derive(Default)]
+pub struct BodyPool {
+    bodies: Mutex<HashMap<[u8; 32], Bytes>>,
+}
+
+#[derive(Serialize)]
+pub struct BodyStats {
+    /// What the cached bodies would take up stored separately.
+    logical_bytes: u64,
+    /// What they do take up.
+    stored_bytes: u64,
+    pooled: usize,
+}
+
+impl BodyPool {
+    /// `entry`, with its body swapped for the pooled copy if there is one.
+    pub fn intern(&self, entry: Arc<CachedResponse>) -> Arc<CachedResponse> {
+        histogram!("proxy_cache_entry_bytes").record(entry.body.len() as f64);
+        if entry.body.is_empty() {
+            return entry;
+        }
+        let hash: [u8; 32] = Sha256::digest(&entry.body).into();
+        let pooled = {
+            let mut bodies = self.bodies.lock().unwrap();
+            bodies.entry(hash).or_insert_with(|| entry.body.clone()).clone()
+        };
+        if pooled.as_ptr() == entry.body.as_ptr() {
+            return entry;
+        }
+        counter!("proxy_cache_deduplicated_bytes_total").increment(pooled.len() as u64);
+        Arc::new(CachedResponse {
+            status: entry.status,
+            body: pooled,
+            headers: entry.headers.clone(),
+            stored_at: entry.stored_at,
+            ttl: entry.ttl,
+            purged: entry.purged,
+            immutable: entry.immutable,
+            kind: entry.kind,
+        })
+    }
+
+    /// Frees the bodies no entry refers to any more.
+    pub fn sweep(&self) {
+        self.bodies.lock().unwrap().retain(|_, body| !body.is_unique());
+    }
+
+    /// Logical against actual body bytes across `cache`.
+    pub fn stats(&self, cache: &ResponseCache) -> BodyStats {
+        let mut seen = HashSet::new();
+        let (mut logical_bytes, mut stored_bytes) = (0, 0);
+        for (_, entry) in cache.iter() {
+            let len = entry.body.len() as u64;
+            logical_bytes += len;
+            if seen.insert(entry.body.as_ptr()) {
+                stored_bytes += len;
+            }
+        }
+        BodyStats {
+            logical_bytes,
+            stored_bytes,
+            pooled: self.bodies.lock().unwrap().len(),
+        }
+    }
+}
+
+/// Sweeps `pool` every minute, for as long as the process runs.
+pub async fn sweep_bodies(pool: Arc<BodyPool>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        ticker.tick().await;
+        pool.sweep();
+    }
+}
+
+/// How invalidation treats what is cached, from `?mode=`.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PurgeMode {
+    /// Drop the entries: the next requests all miss.
+    Hard,
+    /// Mark them stale: the next request revalidates, and until that is
+    /// done everyone else is served the old body as `STALE`. Spares GitHub
+    /// a thundering herd when a hot repository is purged.
+    #[default]
+    Soft,
+}
+
+/// Purges every entry for a repository matching one of `repos`.
+///
+/// A hard purge is applied lazily by moka, so it returns at once; matching
+/// entries are never served again. A soft one re-inserts each match marked
+/// as purged, expiring when it would have.
+pub async fn purge_repos(
+    cache: &ResponseCache,
+    repos: impl AsRef<[RepoPattern]> + Send + Sync + 'static,
+    mode: PurgeMode,
+) -> Result<(), String> {
+    let matches = move |key: &str| {
+        let path = key.split(['?', '#']).next().unwrap_or_default();
+        repos.as_ref().iter().any(|p| p.matches(path))
+    };
+    purge_matching(cache, matches, mode).await
+}
+
+/// Purges every entry, like `purge_repos` does some.
+pub async fn purge_all(cache: &ResponseCache, mode: PurgeMode) -> Result<(), String> {
+    purge_matching(cache, |_| true, mode).await
+}
+
+/// Purges the entry under `key`; whether there was one.
+pub async fn purge_key(cache: &ResponseCache, key: &str, mode: PurgeMode) -> bool {
+    match mode {
+        PurgeMode::Hard => cache.remove(key).await.is_some(),
+        PurgeMode::Soft => {
+            let Some(entry) = cache.get(key).await else {
+                return false;
+            };
+            if !entry.purged {
+                cache.insert(key.into(), Arc::new(entry.purged())).await;
+            }
+            true
+        }
+    }
+}
+
+async fn purge_matching(
+    cache: &ResponseCache,
+    matches: impl Fn(&str) -> bool + Send + Sync + 'static,
+    mode: PurgeMode,
+) -> Result<(), String> {
+    if mode == PurgeMode::Hard {
+        return cache
+            .invalidate_entries_if(move |key, _| matches(key))
+            .map(drop)
+            .map_err(|err| err.to_string());
+    }
+    let matching: Vec<_> = cache
+        .iter()
+        .filter(|(key, entry)| !entry.purged && matches(key))
+        .collect();
+    for (key, entry) in matching {
+        cache.insert(Arc::unwrap_or_clone(key), Arc::new(entry.purged())).await;
+    }
+    Ok(())
+}
+
+/// Keys being refreshed on behalf of a soft-purged entry, so only the first
+/// request for one waits on GitHub.
+#[derive(Default)]
+pub struct Revalidating {
+    keys: Mutex<HashSet<Arc<str>>>,
+}
+
+/// Held by the one request refreshing a key.
+pub struct RevalidationClaim {
+    revalidating: Arc<Revalidating>,
+    key: Arc<str>,
+}
+
+impl Revalidating {
+    /// Claims `key`, unless another request already has.
+    pub fn claim(self: &Arc<Self>, key: &Arc<str>) -> Option<RevalidationClaim> {
+        self.keys.lock().unwrap().insert(key.clone()).then(|| RevalidationClaim {
+            revalidating: self.clone(),
+            key: key.clone(),
+        })
+    }
+}
+
+impl Drop for RevalidationClaim {
+    fn drop(&mut self) {
+        self.revalidating.keys.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// How a refresh that left nothing in the cache ended: GitHub failed, or
+/// answered with something that can't be kept.
+type Outcome = Result<Arc<CachedResponse>, FetchError>;
+
+/// The ends of refreshes that put nothing in the cache, briefly. Requests
+/// that queued behind one would otherwise each ask GitHub again in turn, so
+/// an outage met by a burst of misses cost as many upstream requests.
+pub struct Settled {
+    outcomes: moka::sync::Cache<Arc<str>, (Instant, Outcome)>,
+}
+
+impl Default for Settled {
+    fn default() -> Self {
+        Self {
+            outcomes: moka::sync::Cache::builder()
+                .max_capacity(10_000)
+                // Queued requests look as soon as the refresh is done.
+                .time_to_live(Duration::from_secs(5))
+                .build(),
+        }
+    }
+}
+
+impl Settled {
+    pub fn record(&self, key: &Arc<str>, outcome: Outcome) {
+        self.outcomes.insert(key.clone(), (Instant::now(), outcome));
+    }
+
+    /// How the last refresh of `key` ended, if it did after `since`.
+    pub fn since(&self, key: &Arc<str>, since: Instant) -> Option<Outcome> {
+        self.outcomes
+            .get(key)
+            .filter(|(at, _)| *at >= since)
+            .map(|(_, outcome)| outcome)
+    }
+}
+
+/// Expires each entry at the end of its own TTL plus the stale window,
+/// counted from when it was fetched rather than put in: one read back from
+/// the second tier, or re-inserted as purged, keeps the time it had left.
+pub struct EntryExpiry {
+    pub stale: Duration,
+}
+
+impl Expiry<Arc<str>, Arc<CachedResponse>> for EntryExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &Arc<str>,
+        entry: &Arc<CachedResponse>,
+        created_at: Instant,
+    ) -> Option<Duration> {
+        let expires_at = entry.stored_at + entry.ttl + self.stale;
+        Some(expires_at.saturating_duration_since(created_at))
+    }
+
+    fn expire_after_update(
+        &self,
+        key: &Arc<str>,
+        entry: &Arc<CachedResponse>,
+        updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.expire_after_create(key, entry, updated_at)
+    }
+}
+
+/// How a response was produced, reported to clients as `X-Cache`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// Served fresh from the cache.
+    Hit,
+    /// Fetched from GitHub and stored.
+    Miss,
+    /// Served from the cache past its TTL because GitHub couldn't be asked.
+    Stale,
+    /// GitHub answered 304: nothing changed.
+    Revalidated,
+    /// Fetched from GitHub because the client skipped the cache: with its
+    /// own token, or with a hard refresh or `?max_age=` that was honoured.
+    Bypass,
+    /// Fetched from GitHub and not stored, because the proxy keeps none of
+    /// its kind (excluded paths, downloads, an origin over its key quota) or
+    /// GitHub said not to.
+    Pass,
+}
+
+impl CacheStatus {
+    pub fn header_value(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Revalidated => "REVALIDATED",
+            CacheStatus::Bypass => "BYPASS",
+            CacheStatus::Pass => "PASS",
+        })
+    }
+}
+
+/// Which tier lookups were answered from.
+#[derive(Default)]
+pub struct TierHits {
+    memory: AtomicU64,
+    store: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct TierStats {
+    memory: u64,
+    store: u64,
+    misses: u64,
+}
+
+impl TierHits {
+    pub fn memory_hit(&self) {
+        self.memory.fetch_add(1, Ordering::Relaxed);
+        counter!("proxy_cache_lookups_total", "tier" => "memory").increment(1);
+    }
+
+    /// Labelled with the store's name, `disk` or `redis`.
+    pub fn store_hit(&self, store: &'static str) {
+        self.store.fetch_add(1, Ordering::Relaxed);
+        counter!("proxy_cache_lookups_total", "tier" => store).increment(1);
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        counter!("proxy_cache_lookups_total", "tier" => "none").increment(1);
+    }
+
+    pub fn snapshot(&self) -> TierStats {
+        TierStats {
+            memory: self.memory.load(Ordering::Relaxed),
+            store: self.store.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Why entries left the cache, for tuning TTL/TTI/capacity.
+#[derive(Default)]
+pub struct EvictionCounters {
+    expired_ttl: AtomicU64,
+    expired_idle: AtomicU64,
+    evicted_size: AtomicU64,
+    removed_explicit: AtomicU64,
+    /// Events lost because the queue to `record_evictions` was full.
+    dropped: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct EvictionStats {
+    expired_ttl: u64,
+    expired_idle: u64,
+    evicted_size: u64,
+    removed_explicit: u64,
+    dropped: u64,
+}
+
+impl EvictionCounters {
+    pub fn snapshot(&self) -> EvictionStats {
+        EvictionStats {
+            expired_ttl: self.expired_ttl.load(Ordering::Relaxed),
+            expired_idle: self.expired_idle.load(Ordering::Relaxed),
+            evicted_size: self.evicted_size.load(Ordering::Relaxed),
+            removed_explicit: self.removed_explicit.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// How many evictions may wait for `record_evictions` before more are dropped.
+const EVICTION_QUEUE: usize = 1024;
+/// An entry's own size and moka's per-entry bookkeeping, by estimate.
+const ENTRY_OVERHEAD_BYTES: usize = 256;
+
+#[derive(Clone, Copy)]
+enum EvictionCause {
+    Ttl,
+    Idle,
+    Size,
+    Explicit,
+}
+
+/// An entry that left the cache, on its way to `record_evictions`.
+pub struct Eviction {
+    key: Arc<str>,
+    cause: EvictionCause,
+    bytes: usize,
+    age: Duration,
+}
+
+pub fn build(
+    config: &CacheConfig,
+    counters: Arc<EvictionCounters>,
+) -> (ResponseCache, mpsc::Receiver<Eviction>) {
+    // Entries outlive their freshness by the stale window so there is
+    // something to fall back on when upstream can't be asked.
+    let stale = config.stale;
+    // moka may run the listener on whichever thread touched the cache, so
+    // it only classifies the removal and hands it off.
+    let (events, received) = mpsc::channel(EVICTION_QUEUE);
+    let mut builder = Cache::builder()
+        .expire_after(EntryExpiry { stale })
+        .support_invalidation_closures()
+        .eviction_listener(move |key, entry: Arc<CachedResponse>, cause| {
+            let age = entry.stored_at.elapsed();
+            let cause = match cause {
+                // moka reports TTL and TTI expiry alike; an entry that goes
+                // before its lifetime was up can only have idled out.
+                RemovalCause::Expired if age < entry.ttl + stale => EvictionCause::Idle,
+                RemovalCause::Expired => EvictionCause::Ttl,
+                RemovalCause::Size => EvictionCause::Size,
+                RemovalCause::Explicit => EvictionCause::Explicit,
+                RemovalCause::Replaced => return,
+            };
+            let eviction = Eviction {
+                key: Arc::unwrap_or_clone(key),
+                cause,
+                bytes: entry.body.len(),
+                age,
+            };
+            if events.try_send(eviction).is_err() {
+                counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    if let Some(tti) = config.tti {
+        builder = builder.time_to_idle(tti);
+    }
+    builder = match config.max_bytes {
+        Some(max_bytes) => builder.weigher(weight).max_capacity(max_bytes),
+        None => builder.max_capacity(config.max_entries),
+    };
+    (builder.build(), received)
+}
+
+/// What an entry takes up, roughly: key, headers and body, plus the entry
+/// and moka's bookkeeping. Bodies shared through the `BodyPool` are counted
+/// in full for each entry, so the budget errs on the safe side.
+fn weight(key: &Arc<str>, entry: &Arc<CachedResponse>) -> u32 {
+    let headers: usize = entry
+        .headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum();
+    let bytes = ENTRY_OVERHEAD_BYTES + key.len() + headers + entry.body.len();
+    u32::try_from(bytes).unwrap_or(u32::MAX)
+}
+
+/// Roughly what the cache takes up in memory.
+pub fn memory_bytes(cache: &ResponseCache, config: &CacheConfig) -> u64 {
+    if config.max_bytes.is_some() {
+        return cache.weighted_size();
+    }
+    cache
+        .iter()
+        .map(|(key, entry)| u64::from(weight(&key, &entry)))
+        .sum()
+}
+
+/// Counts each eviction by cause and records how big and how old the entry
+/// was, for as long as the cache exists.
+pub async fn record_evictions(
+    mut evictions: mpsc::Receiver<Eviction>,
+    counters: Arc<EvictionCounters>,
+) {
+    while let Some(eviction) = evictions.recv().await {
+        let (counter, label) = match eviction.cause {
+            EvictionCause::Ttl => (&counters.expired_ttl, "ttl"),
+            EvictionCause::Idle => (&counters.expired_idle, "idle"),
+            EvictionCause::Size => (&counters.evicted_size, "size"),
+            EvictionCause::Explicit => {
+                debug!(key = %eviction.key, "removed from the cache");
+                (&counters.removed_explicit, "explicit")
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        counter!("proxy_cache_evictions_total", "cause" => label).increment(1);
+        histogram!("proxy_cache_evicted_bytes", "cause" => label).record(eviction.bytes as f64);
+        histogram!("proxy_cache_evicted_age_seconds", "cause" => label)
+            .record(eviction.age.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(age: Duration, ttl: Duration) -> Arc<CachedResponse> {
+        Arc::new(CachedResponse {
+            status: StatusCode::OK,
+            body: Bytes::from_static(b"{}"),
+            headers: HeaderMap::new(),
+            stored_at: Instant::now().checked_sub(age).unwrap(),
+            ttl,
+            purged: false,
+            immutable: false,
+            kind: BodyKind::Json,
+        })
+    }
+
+    #[test]
+    fn expiry_counts_from_when_the_entry_was_fetched() {
+        let expiry = EntryExpiry {
+            stale: Duration::from_secs(10),
+        };
+        let key: Arc<str> = "o/r".into();
+        let old = entry(Duration::from_secs(50), Duration::from_secs(60));
+        let gone = entry(Duration::from_secs(90), Duration::from_secs(60));
+        let now = Instant::now();
+        let left = expiry.expire_after_create(&key, &old, now).unwrap();
+        assert!(left <= Duration::from_secs(20) && left > Duration::from_secs(19));
+        let left = expiry.expire_after_update(&key, &old, now, None).unwrap();
+        assert!(left <= Duration::from_secs(20) && left > Duration::from_secs(19));
+        assert_eq!(expiry.expire_after_create(&key, &gone, now), Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn a_soft_purge_keeps_the_entry_expiring_when_it_would_have() {
+        let cache: ResponseCache = Cache::builder()
+            .expire_after(EntryExpiry {
+                stale: Duration::ZERO,
+            })
+            .build();
+        let key: Arc<str> = "o/r".into();
+        let ttl = Duration::from_secs(60);
+        cache.insert(key.clone(), entry(ttl - Duration::from_millis(500), ttl)).await;
+
+        assert!(purge_key(&cache, &key, PurgeMode::Soft).await);
+        let purged = cache.get(&key).await.unwrap();
+        assert!(purged.purged && !purged.is_fresh());
+
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        cache.run_pending_tasks().await;
+        assert!(cache.get(&key).await.is_none());
+    }
+}
@@ -0,0 +1,224 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use serde::{Serialize, Serializer};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use crate::AppState;
+
+/// The address of the end user, as opposed to the load balancer in front of us.
+///
+/// Inserted as a request extension by [`client_ip_middleware`]; anything that
+/// keys on the client (rate limits, bans, logs) should read this rather than
+/// the socket address.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+pub async fn client_ip_middleware(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config;
+    let ip = resolve(
+        peer.ip(),
+        request.headers(),
+        &config.trusted_proxies,
+        config.client_ip_header,
+    );
+    request.extensions_mut().insert(ClientIp(ip));
+    next.run(request).await
+}
+
+/// `CLIENT_IP_HEADER`: the one forwarding header our proxies set. Any other
+/// is the client's own, passed through untouched, and never read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    XForwardedFor,
+    Forwarded,
+    XRealIp,
+}
+
+impl ForwardedHeader {
+    pub const ALL: [Self; 3] = [Self::XForwardedFor, Self::Forwarded, Self::XRealIp];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::XForwardedFor => "x-forwarded-for",
+            Self::Forwarded => "forwarded",
+            Self::XRealIp => "x-real-ip",
+        }
+    }
+}
+
+impl Serialize for ForwardedHeader {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|header| header.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("unknown forwarding header {s:?}"))
+    }
+}
+
+/// Works out the real client address for a request received from `peer`.
+///
+/// Forwarding headers are only believed when the peer itself is a trusted
+/// proxy; otherwise anyone could claim any address. When it is, the hop chain
+/// in `header` is walked right-to-left (nearest hop first) and the first
+/// address that is not one of our own proxies wins.
+pub fn resolve(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    trusted: &[IpNet],
+    header: ForwardedHeader,
+) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !is_trusted(peer, trusted) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_chain(headers, header).iter().rev() {
+        match hop {
+            Some(ip) => {
+                client = *ip;
+                if !is_trusted(client, trusted) {
+                    break;
+                }
+            }
+            // An obfuscated or garbled hop: nothing left of it can be trusted,
+            // so settle for the last address we could vouch for.
+            None => break,
+        }
+    }
+    client
+}
+
+fn is_trusted(ip: IpAddr, trusted: &[IpNet]) -> bool {
+    trusted.iter().any(|net| net.contains(&ip))
+}
+
+/// The hop list in `header`, in order from the original client to the
+/// nearest proxy.
+fn forwarded_chain(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    let values = joined(headers, header.name());
+    match header {
+        ForwardedHeader::Forwarded => values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor => values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(parse_node)
+            .collect(),
+        ForwardedHeader::XRealIp => values
+            .last()
+            .map(|value| vec![parse_node(value)])
+            .unwrap_or_default(),
+    }
+}
+
+fn joined<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect()
+}
+
+/// Parses a node as it appears in `X-Forwarded-For` or a `Forwarded` `for=`
+/// parameter: a bare address, `v4:port`, or `[v6]:port`, optionally quoted.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(ip, _)| ip.parse::<IpAddr>().ok())
+        .map(|ip| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn a_spoofed_forwarded_header_is_ignored() {
+        let headers = headers(&[
+            ("forwarded", "for=198.51.100.7"),
+            ("x-forwarded-for", "203.0.113.9"),
+        ]);
+        let peer = "10.0.0.1".parse().unwrap();
+        let ip = resolve(peer, &headers, &trusted(), ForwardedHeader::XForwardedFor);
+        assert_eq!(ip, "203.0.113.9".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn trusted_hops_are_walked_past_from_the_right() {
+        let chain = "198.51.100.7, 203.0.113.9, 10.0.0.3, 10.0.0.2";
+        let headers = headers(&[("x-forwarded-for", chain)]);
+        let peer = "10.0.0.1".parse().unwrap();
+        let ip = resolve(peer, &headers, &trusted(), ForwardedHeader::XForwardedFor);
+        assert_eq!(ip, "203.0.113.9".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn the_chosen_header_is_the_only_one_read() {
+        let headers = headers(&[
+            ("forwarded", "for=203.0.113.9"),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+        let peer = "10.0.0.1".parse().unwrap();
+        let ip = resolve(peer, &headers, &trusted(), ForwardedHeader::Forwarded);
+        assert_eq!(ip, "203.0.113.9".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn headers_from_an_untrusted_peer_are_ignored() {
+        let headers = headers(&[("x-forwarded-for", "203.0.113.9")]);
+        let peer = "192.0.2.1".parse().unwrap();
+        let ip = resolve(peer, &headers, &trusted(), ForwardedHeader::XForwardedFor);
+        assert_eq!(ip, peer);
+    }
+}
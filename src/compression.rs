@@ -0,0 +1,147 @@
+use axum::{
+    body::{self, Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use metrics::counter;
+use moka::sync::Cache;
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+use crate::{config::CompressionConfig, error_response, AppState};
+
+/// Anything larger is a download or a stream, passed on as it comes.
+const MAX_COMPRESSED_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Gzipped bodies by the SHA-256 of what they were made from, so a hot
+/// entry is compressed once rather than on every request for it.
+pub struct Compressed {
+    bodies: Cache<[u8; 32], Bytes>,
+}
+
+impl Compressed {
+    pub fn new(config: &CompressionConfig) -> Self {
+        Self {
+            bodies: Cache::builder()
+                .max_capacity(config.cache_bytes)
+                .weigher(|_, body: &Bytes| body.len().try_into().unwrap_or(u32::MAX))
+                .build(),
+        }
+    }
+}
+
+/// Gzips text answers, JSON above all, for clients that take it: with
+/// `COMPRESSION_ENABLED`, anything from `COMPRESSION_MIN_BYTES` up.
+pub async fn compress_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config.compression;
+    if !config.enabled {
+        return next.run(request).await;
+    }
+    let gzip = accepts_gzip(request.headers());
+    let mut response = next.run(request).await;
+    let size = response.body().size_hint().exact();
+    let compressible = response.status() == StatusCode::OK
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+        && is_text(response.headers())
+        && size.is_some_and(|size| (config.min_bytes..=MAX_COMPRESSED_BYTES).contains(&size));
+    if !compressible {
+        return response;
+    }
+    // Shared caches must keep both forms apart.
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if !gzip {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(plain) = body::to_bytes(body, usize::MAX).await else {
+        return error_response(StatusCode::BAD_GATEWAY);
+    };
+    let hash: [u8; 32] = Sha256::digest(&plain).into();
+    let compressed = match state.compressed.bodies.get(&hash) {
+        Some(compressed) => compressed,
+        None => {
+            let level = config.level;
+            let compressed = tokio::task::spawn_blocking(move || gzip_bytes(&plain, level))
+                .await
+                .expect("compression doesn't panic");
+            state.compressed.bodies.insert(hash, compressed.clone());
+            compressed
+        }
+    };
+    counter!("proxy_compressed_responses_total").increment(1);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    Response::from_parts(parts, Body::from(compressed))
+}
+
+/// Whether `Accept-Encoding` takes gzip, or anything, at a non-zero `q`.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let refused = params.any(|p| {
+                p.trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+fn is_text(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let media = content_type.split(';').next().unwrap_or_default().trim();
+    media.starts_with("text/")
+        || media.ends_with("json")
+        || media.ends_with("+xml")
+        || matches!(media, "application/javascript" | "application/xml" | "image/svg+xml")
+}
+
+/// `data` as a gzip member: a minimal header, the deflated data, then the
+/// CRC-32 and length of what went in.
+fn gzip_bytes(data: &[u8], level: u8) -> Bytes {
+    let deflated = miniz_oxide::deflate::compress_to_vec(data, level);
+    let mut gzip = Vec::with_capacity(deflated.len() + 18);
+    gzip.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+    gzip.extend_from_slice(&deflated);
+    gzip.extend_from_slice(&crc32(data).to_le_bytes());
+    gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    gzip.into()
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    });
+    !data.iter().fold(!0u32, |crc, &b| table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
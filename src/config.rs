@@ -0,0 +1,1601 @@
+use axum::http::{HeaderName, HeaderValue};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    alerts::AlertCondition,
+    api_keys,
+    client_ip::ForwardedHeader,
+    headers::{HeaderAllowlist, DEFAULT_FORWARD, DEFAULT_PASSTHROUGH},
+    namespaces::Namespaces,
+    origin::{OriginAllowlist, OriginPattern},
+    paths::PathPattern,
+    redact::RedactRule,
+    repos::RepoAccess,
+    schema::Schemas,
+    signing::{Signer, SIGNATURE_HEADER, SIGNED_HEADERS_HEADER},
+    tokens::Tokens,
+    upstream::POOL_IDLE_TIMEOUT,
+};
+
+/// What `ALLOWED_ORIGINS` is when unset, as it was before it could be set.
+const DEFAULT_ALLOWED_ORIGINS: &[&str] = &["prigoana.com", "*.prigoana.com"];
+
+#[derive(Serialize)]
+pub struct Config {
+    pub tokens: Tokens,
+    pub fixtures: FixtureConfig,
+    /// Don't verify the tokens with GitHub at startup.
+    pub skip_token_check: bool,
+    /// With `CLIENT_TOKENS`, a request carrying its own token in
+    /// `Authorization` is sent to GitHub with it instead of ours, and its
+    /// answer is never cached.
+    pub client_tokens: bool,
+    /// How far ahead a token's expiry is warned about.
+    #[serde(serialize_with = "secs")]
+    pub token_expiry_warning: Duration,
+    /// With `READYZ_UPSTREAM_CHECK_SECS`, `/readyz` also checks that GitHub
+    /// answers and takes our tokens, at most this often.
+    #[serde(serialize_with = "optional_secs_value")]
+    pub readiness_upstream_check: Option<Duration>,
+    /// Repositories the proxy serves; reloadable on SIGHUP.
+    pub repos: RepoAccess,
+    /// Where GitHub is, from `GITHUB_API_BASE` and `GITHUB_RAW_BASE`.
+    pub github: GithubUrls,
+    /// Sent on every upstream request; GitHub asks integrations to be contactable.
+    #[serde(serialize_with = "header_value")]
+    pub user_agent: HeaderValue,
+    #[serde(serialize_with = "display_list")]
+    pub trusted_proxies: Vec<IpNet>,
+    /// `CLIENT_IP_HEADER`: the forwarding header `TRUSTED_PROXIES` set,
+    /// `x-forwarded-for` unless told otherwise.
+    pub client_ip_header: ForwardedHeader,
+    #[serde(serialize_with = "optional_fingerprint")]
+    pub admin_token: Option<String>,
+    /// `METRICS_REQUIRE_ADMIN`: put `/metrics` behind `ADMIN_TOKEN` too;
+    /// otherwise any scraper that can reach the listener reads it.
+    pub metrics_require_admin: bool,
+    /// Shared secret for `X-Hub-Signature-256` on GitHub webhook deliveries.
+    #[serde(serialize_with = "optional_fingerprint")]
+    pub webhook_secret: Option<String>,
+    pub cache: CacheConfig,
+    pub client_cache: ClientCacheConfig,
+    pub upstream: UpstreamConfig,
+    /// Where to accept connections; at least one of them, or the Unix
+    /// socket, isn't admin-only.
+    pub listeners: Vec<Listener>,
+    pub unix_socket: Option<UnixSocketConfig>,
+    /// HTTPS on the public TCP listeners, from `TLS_CERT` and `TLS_KEY`.
+    pub tls: Option<TlsConfig>,
+    pub server: ServerConfig,
+    pub bans: BanConfig,
+    pub refresh: RefreshConfig,
+    pub usage: UsageConfig,
+    pub peers: Option<PeerConfig>,
+    /// `PEER_WARM_FROM`: the admin listener of a replica whose cache is
+    /// imported at startup.
+    pub warm_from: Option<String>,
+    pub shadow: ShadowConfig,
+    pub alerts: AlertConfig,
+    pub key_quota: KeyQuotaConfig,
+    pub api_keys: Option<ApiKeysConfig>,
+    pub batch: BatchConfig,
+    pub paginate: PaginateConfig,
+    pub graphql: GraphqlConfig,
+    pub raw: RawConfig,
+    pub badge: BadgeConfig,
+    pub compression: CompressionConfig,
+    /// Trace export, with `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    pub otel: Option<OtelConfig>,
+    pub watch: WatchConfig,
+    /// Origins allowed to use the proxy, from `ALLOWED_ORIGINS`.
+    pub allowed_origins: OriginAllowlist,
+    /// Parts of the API proxied besides repositories, from `API_NAMESPACES`.
+    pub api_namespaces: Namespaces,
+    /// Requests-per-minute budgets, first matching pattern wins.
+    pub origin_rate_limits: Vec<(OriginPattern, u32)>,
+    /// Requests-per-minute budget of each client address; 0 for none.
+    pub client_rate_limit: u32,
+    /// Cache-bypassing refreshes (`Cache-Control: no-cache`) each client may
+    /// force per minute; 0 ignores such requests entirely.
+    pub forced_refresh_per_minute: u32,
+    /// Upstream response headers relayed to clients and kept in the cache.
+    pub passthrough_headers: HeaderAllowlist,
+    /// Prebuilt `access-control-expose-headers` for proxied responses.
+    #[serde(skip)]
+    pub expose_headers: HeaderValue,
+    /// What preflights allow, and whether credentials are.
+    pub cors: CorsConfig,
+    /// Turn away requests without an `Origin`, which browsers always send
+    /// cross-origin.
+    pub require_origin: bool,
+    /// Longest `Origin` we accept, and so reflect back.
+    pub max_origin_len: usize,
+    /// Send `Timing-Allow-Origin` alongside every allow-origin header.
+    pub timing_allow_origin: bool,
+    /// JSON fields stripped from upstream bodies before caching and serving.
+    pub redact_fields: Vec<RedactRule>,
+    /// Contract checks on upstream bodies, from `SCHEMA_DIR`.
+    pub schemas: Option<Schemas>,
+    /// Signs proxied responses, with `RESPONSE_SIGNING_KEY`.
+    pub signer: Option<Signer>,
+    /// Client request headers copied onto the upstream request.
+    pub forward_headers: HeaderAllowlist,
+    /// Show browsers asking for HTML a page around the JSON.
+    pub browser_page: bool,
+    /// Log every upstream exchange at debug level (secrets redacted).
+    pub debug_dump: bool,
+    /// How much of each upstream body a debug dump shows.
+    pub debug_dump_bytes: usize,
+    /// Unix time the configuration was read.
+    pub loaded_at: u64,
+}
+
+/// Offline development: upstream replaced by, or recorded to, fixture files.
+#[derive(Serialize)]
+pub struct FixtureConfig {
+    /// `FIXTURE_MODE`: answer from here and never call GitHub.
+    pub serve: Option<PathBuf>,
+    /// `RECORD_FIXTURES`: save real responses here.
+    pub record: Option<PathBuf>,
+}
+
+/// A `pattern=secs` entry of `CACHE_TTL_RULES`, or a `[[rule]]` of
+/// `CACHE_TTL_RULES_FILE`.
+#[derive(Serialize)]
+pub struct TtlRule {
+    pub pattern: PathPattern,
+    #[serde(serialize_with = "secs")]
+    pub ttl: Duration,
+}
+
+#[derive(Serialize)]
+pub struct CacheConfig {
+    /// How long an entry is served as fresh when GitHub doesn't say.
+    #[serde(serialize_with = "secs")]
+    pub ttl: Duration,
+    /// Bounds applied to TTLs derived from upstream `Cache-Control`.
+    #[serde(serialize_with = "secs")]
+    pub min_ttl: Duration,
+    #[serde(serialize_with = "secs")]
+    pub max_ttl: Duration,
+    /// How much longer it is kept around as a fallback once no longer fresh.
+    #[serde(serialize_with = "secs")]
+    pub stale: Duration,
+    /// How far into that window an entry is still served straight away,
+    /// while it is refreshed in the background.
+    #[serde(serialize_with = "optional_secs_value")]
+    pub stale_while_revalidate: Option<Duration>,
+    /// Entries not read for this long expire early; never longer than `ttl`.
+    #[serde(serialize_with = "optional_secs_value")]
+    pub tti: Option<Duration>,
+    pub max_entries: u64,
+    /// `CACHE_MAX_BYTES`: a memory budget, held against the size of each
+    /// entry rather than their number, which `max_entries` then isn't.
+    pub max_bytes: Option<u64>,
+    /// Paths that are always fetched live and never stored.
+    pub no_cache_paths: Vec<PathPattern>,
+    /// Paths whose answers are passed on as they arrive, never stored.
+    pub stream_paths: Vec<PathPattern>,
+    /// Answers declaring a larger body are streamed where they can be, and
+    /// never stored.
+    pub stream_threshold: u64,
+    /// Paths that answer `?diff_from=` with a JSON Patch.
+    pub diff_paths: Vec<PathPattern>,
+    /// `CACHE_TTL_RULES`, then `CACHE_TTL_RULES_FILE`: how long answers for
+    /// some paths are kept, whatever GitHub says, e.g.
+    /// `*/*/releases=300,search/*=5`; 0 never keeps them.
+    pub ttl_rules: Vec<TtlRule>,
+    /// How long repository statistics are kept once GitHub has them ready,
+    /// whatever it says.
+    #[serde(serialize_with = "secs")]
+    pub stats_ttl: Duration,
+    /// The most a 4xx such as a 404 for a mistyped repository is kept; 0
+    /// fetches those every time.
+    #[serde(serialize_with = "secs")]
+    pub error_ttl: Duration,
+    /// Bounds on the `?max_age=` clients may ask for.
+    #[serde(serialize_with = "secs")]
+    pub max_age_param_min: Duration,
+    #[serde(serialize_with = "secs")]
+    pub max_age_param_max: Duration,
+    /// A second, larger tier on disk, from `CACHE_DISK_PATH`.
+    pub disk: Option<DiskCacheConfig>,
+    /// Or in Redis, shared by every replica, from `CACHE_REDIS_URL`.
+    pub redis: Option<RedisCacheConfig>,
+}
+
+#[derive(Serialize)]
+pub struct DiskCacheConfig {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    /// How long an entry may stay on disk, fresh or not; stale ones are
+    /// still worth having for revalidation.
+    #[serde(serialize_with = "secs")]
+    pub ttl: Duration,
+}
+
+/// A Redis server, from a `redis://[user[:password]@]host[:port][/db]` URL.
+#[derive(Clone, Serialize)]
+pub struct RedisConfig {
+    /// `host:port`.
+    pub address: String,
+    pub username: Option<String>,
+    #[serde(serialize_with = "optional_fingerprint")]
+    pub password: Option<String>,
+    pub database: u32,
+    /// Put before every key, so several deployments can share a database.
+    pub key_prefix: String,
+    /// How long a command may take before it counts as failed.
+    #[serde(serialize_with = "secs")]
+    pub timeout: Duration,
+}
+
+impl RedisConfig {
+    fn from_url(
+        name: &str,
+        url: &str,
+        key_prefix: String,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let invalid = |why: &str| format!("{name}: {why}");
+        let parsed = reqwest::Url::parse(url).map_err(|_| invalid("not a URL"))?;
+        match parsed.scheme() {
+            "redis" => {}
+            "rediss" => return Err(invalid("TLS isn't supported; use redis://")),
+            _ => return Err(invalid("expected redis://")),
+        }
+        let host = parsed.host_str().ok_or_else(|| invalid("no host"))?;
+        let database = match parsed.path().trim_start_matches('/') {
+            "" => 0,
+            db => db.parse().map_err(|_| invalid("the path must be a database number"))?,
+        };
+        Ok(Self {
+            address: format!("{host}:{}", parsed.port().unwrap_or(6379)),
+            username: Some(percent_decoded(parsed.username())).filter(|u| !u.is_empty()),
+            password: parsed.password().map(percent_decoded),
+            database,
+            key_prefix,
+            timeout,
+        })
+    }
+}
+
+/// `CACHE_REDIS_URL`, where a command outlasting `timeout` is a miss.
+#[derive(Clone, Serialize)]
+pub struct RedisCacheConfig {
+    #[serde(flatten)]
+    pub server: RedisConfig,
+    /// How long an entry stays in Redis, fresh or not.
+    #[serde(serialize_with = "secs")]
+    pub ttl: Duration,
+}
+
+impl RedisCacheConfig {
+    fn from_env(url: &str) -> Result<Self, String> {
+        let key_prefix = var("CACHE_REDIS_PREFIX").unwrap_or_else(|| "github-cors-proxy:".into());
+        // Purges list keys with `SCAN MATCH <prefix>*`.
+        if key_prefix.contains(['*', '?', '[', ']', '\\']) {
+            return Err("CACHE_REDIS_PREFIX can't contain glob characters".into());
+        }
+        let timeout = Duration::from_millis(parse("CACHE_REDIS_TIMEOUT_MS", 250)?);
+        Ok(Self {
+            server: RedisConfig::from_url("CACHE_REDIS_URL", url, key_prefix, timeout)?,
+            ttl: Duration::from_secs(parse("CACHE_REDIS_TTL_SECS", 24 * 60 * 60)?),
+        })
+    }
+}
+
+/// The userinfo of a URL as it was before escaping.
+fn percent_decoded(escaped: &str) -> String {
+    let bytes = escaped.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// What browsers and CDNs in front of us are told about caching. Nothing is
+/// ever advertised as fresh for longer than the entry itself is.
+#[derive(Serialize)]
+pub struct ClientCacheConfig {
+    /// Cap on the browser `max-age`, which otherwise is whatever freshness
+    /// the entry has left.
+    #[serde(serialize_with = "optional_secs_value")]
+    pub max_age: Option<Duration>,
+    /// Shared-cache lifetime, sent as `s-maxage`; capped by the entry's
+    /// remaining freshness.
+    #[serde(serialize_with = "optional_secs_value")]
+    pub cdn_max_age: Option<Duration>,
+    /// CDN-specific headers that repeat the shared-cache lifetime.
+    #[serde(serialize_with = "display_list")]
+    pub cdn_headers: Vec<HeaderName>,
+    #[serde(serialize_with = "optional_secs_value")]
+    pub stale_while_revalidate: Option<Duration>,
+}
+
+/// One address to listen on, from `BIND_ADDRS`. Once any listener is
+/// admin-only (`admin=127.0.0.1:9100`), the operator endpoints are served there
+/// and nowhere else.
+#[derive(Clone, Copy, Serialize)]
+pub struct Listener {
+    pub addr: SocketAddr,
+    pub admin_only: bool,
+}
+
+impl FromStr for Listener {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, admin_only) = match s.strip_prefix("admin=") {
+            Some(addr) => (addr, true),
+            None => (s, false),
+        };
+        let addr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid listen address {s:?}"))?;
+        Ok(Self { addr, admin_only })
+    }
+}
+
+/// `LISTEN_UNIX`: a socket to take requests on as well, or instead, for a
+/// reverse proxy on the same machine.
+#[derive(Clone, Serialize)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    /// `LISTEN_UNIX_MODE`, in octal: who may connect.
+    #[serde(serialize_with = "octal")]
+    pub mode: u32,
+}
+
+/// Files re-read on SIGHUP, so a renewed certificate is picked up without a
+/// restart.
+#[derive(Clone, Serialize)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// `TLS_REDIRECT_ADDR`: where plain HTTP is answered with a redirect to
+    /// HTTPS.
+    pub redirect: Option<SocketAddr>,
+}
+
+/// Limits on client connections, so slow or stuck clients can't hold them.
+#[derive(Clone, Serialize)]
+pub struct ServerConfig {
+    /// How long a client may take to send a request's headers.
+    #[serde(serialize_with = "secs")]
+    pub header_read_timeout: Duration,
+    /// How long a kept-alive connection may sit without a request.
+    #[serde(serialize_with = "secs")]
+    pub idle_timeout: Duration,
+    /// Requests served on one connection before it is closed; 0 for no limit.
+    pub max_requests_per_connection: u64,
+    /// Bound on a request's header block; larger ones are answered with 431.
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    /// Longest path and query taken on public routes; longer is a 400.
+    pub max_url_bytes: usize,
+    /// Largest request body taken on public routes, unless a route sets a
+    /// smaller one of its own.
+    pub max_body_bytes: usize,
+    /// Accept cleartext HTTP/2 with prior knowledge (h2c), for load balancers
+    /// that terminate TLS and forward HTTP/2 as is.
+    pub h2c: bool,
+    pub http2_max_concurrent_streams: u32,
+    /// `MAX_IN_FLIGHT_REQUESTS`: proxied requests handled at once, cache
+    /// hits included; more are shed with a 503. 0 for no limit.
+    pub max_in_flight: usize,
+    /// How long requests in flight at shutdown get to finish before their
+    /// connections are dropped.
+    #[serde(serialize_with = "secs")]
+    pub drain_timeout: Duration,
+}
+
+#[derive(Clone, Serialize)]
+pub struct UpstreamConfig {
+    pub max_concurrency: usize,
+    /// How long a request may queue for an upstream slot before it is shed.
+    #[serde(serialize_with = "secs")]
+    pub permit_timeout: Duration,
+    /// `Retry-After` sent with 503s when shedding load.
+    #[serde(serialize_with = "secs")]
+    pub shed_retry_after: Duration,
+    /// How long GitHub has to answer a request, unless the client's
+    /// `X-Request-Deadline-Ms` leaves it less.
+    #[serde(serialize_with = "secs")]
+    pub timeout: Duration,
+    /// How often a 202 from the statistics endpoints is asked again before
+    /// the client is told to come back later, and how long apart.
+    pub stats_retries: u32,
+    #[serde(serialize_with = "secs")]
+    pub stats_retry_delay: Duration,
+    /// How often a request that failed on the way, with a 5xx, or with a
+    /// short `Retry-After` is sent again, waiting twice as long each time
+    /// from `retry_base_delay` up to `retry_max_delay`, give or take half.
+    pub retries: u32,
+    #[serde(serialize_with = "secs")]
+    pub retry_base_delay: Duration,
+    #[serde(serialize_with = "secs")]
+    pub retry_max_delay: Duration,
+    /// Failures in a row that open the circuit breaker; 0 for none.
+    pub circuit_breaker_failures: u32,
+    #[serde(serialize_with = "secs")]
+    pub circuit_breaker_cooldown: Duration,
+    /// How long DNS answers are reused; zero looks up every connection.
+    #[serde(serialize_with = "secs")]
+    pub dns_cache_ttl: Duration,
+    /// `RESOLVE_OVERRIDES`: hosts pinned to fixed addresses, skipping DNS.
+    pub resolve_overrides: Vec<(String, SocketAddr)>,
+    /// `UPSTREAM_KEEPALIVE_SECS`: how long GitHub may go unasked before a
+    /// ping keeps the connection open; off when unset.
+    #[serde(serialize_with = "optional_secs_value")]
+    pub keepalive: Option<Duration>,
+}
+
+/// The CORS policy, beyond which origins are allowed.
+#[derive(Serialize)]
+pub struct CorsConfig {
+    /// `CORS_ALLOW_CREDENTIALS`: pages may send cookies and client
+    /// certificates. The allow-origin then always names the origin: a
+    /// request without one gets none rather than `*`.
+    pub allow_credentials: bool,
+    #[serde(serialize_with = "header_value")]
+    pub allow_methods: HeaderValue,
+    #[serde(serialize_with = "header_value")]
+    pub allow_headers: HeaderValue,
+    /// How long browsers may reuse a preflight.
+    #[serde(serialize_with = "secs")]
+    pub max_age: Duration,
+}
+
+/// What browsers may send when `*` can't stand for everything, as it can't
+/// with credentials.
+const CREDENTIALED_ALLOW_HEADERS: &str =
+    "authorization, content-type, if-none-match, if-modified-since, x-request-deadline-ms, \
+     traceparent, x-api-key";
+
+/// The GitHub the proxy stands in front of: github.com, or an Enterprise
+/// Server named by `GITHUB_API_BASE`.
+#[derive(Serialize)]
+pub struct GithubUrls {
+    /// The REST API, ending in a slash: `https://<host>/api/v3/` on GHES.
+    pub api: String,
+    #[serde(skip)]
+    pub repos: String,
+    /// Free to call: it doesn't count against the quota it reports.
+    #[serde(skip)]
+    pub rate_limit: String,
+    #[serde(skip)]
+    pub user: String,
+    pub graphql: String,
+    /// Raw file contents, ending in a slash: `https://<host>/raw/` on GHES.
+    pub raw: String,
+    /// An Enterprise Server, where rate limits may be turned off: it then
+    /// sends no `X-RateLimit-*` headers and answers `/rate_limit` with 404.
+    pub enterprise: bool,
+}
+
+impl GithubUrls {
+    const API: &'static str = "https://api.github.com/";
+    const RAW: &'static str = "https://raw.githubusercontent.com/";
+
+    /// A bare GHES address (`https://github.example.com`) gets the `/api/v3`
+    /// its REST API lives under.
+    fn from_env() -> Result<Self, String> {
+        let api = match var("GITHUB_API_BASE") {
+            Some(base) => base_url("GITHUB_API_BASE", &base)?,
+            None => Self::API.to_owned(),
+        };
+        let enterprise = api != Self::API;
+        let api = match reqwest::Url::parse(&api) {
+            Ok(url) if enterprise && url.path() == "/" => format!("{api}api/v3/"),
+            _ => api,
+        };
+        let graphql = match api.strip_suffix("/v3/") {
+            // GHES serves GraphQL beside the REST API, not under it.
+            Some(root) if enterprise => format!("{root}/graphql"),
+            _ => format!("{api}graphql"),
+        };
+        let raw = match var("GITHUB_RAW_BASE") {
+            Some(base) => base_url("GITHUB_RAW_BASE", &base)?,
+            None if enterprise => {
+                let url = reqwest::Url::parse(&api).expect("checked by base_url");
+                format!("{}/raw/", url.origin().ascii_serialization())
+            }
+            None => Self::RAW.to_owned(),
+        };
+        Ok(Self {
+            repos: format!("{api}repos/"),
+            rate_limit: format!("{api}rate_limit"),
+            user: format!("{api}user"),
+            api,
+            graphql,
+            raw,
+            enterprise,
+        })
+    }
+
+    /// The host of the API, which on GHES also serves downloads.
+    pub fn host(&self) -> Option<String> {
+        reqwest::Url::parse(&self.api).ok()?.host_str().map(str::to_owned)
+    }
+}
+
+/// `base` as an http(s) URL without a query, ending in a slash.
+fn base_url(name: &str, base: &str) -> Result<String, String> {
+    let invalid = || format!("{name}: {base:?} is not an http(s) URL");
+    let url = reqwest::Url::parse(base).map_err(|_| invalid())?;
+    if !matches!(url.scheme(), "http" | "https") || url.query().is_some() {
+        return Err(invalid());
+    }
+    Ok(format!("{}/", url.as_str().trim_end_matches('/')))
+}
+
+/// Per-origin traffic accounting, reported by `GET /__usage`.
+#[derive(Clone, Serialize)]
+pub struct UsageConfig {
+    pub window_hours: u64,
+    /// Origins counted separately; the rest share one "other" entry.
+    pub max_origins: usize,
+}
+
+/// Replicas that split the cache between them, from `PEERS`.
+#[derive(Serialize)]
+pub struct PeerConfig {
+    /// Every replica's base URL, this one's included.
+    pub urls: Vec<String>,
+    /// This replica's own entry in `urls`, from `PEER_SELF`.
+    pub own_url: String,
+    /// Authenticates requests between replicas.
+    #[serde(serialize_with = "fingerprinted")]
+    pub secret: String,
+    /// How long to wait on a peer before asking GitHub directly.
+    #[serde(serialize_with = "secs")]
+    pub timeout: Duration,
+}
+
+/// Mirroring of live traffic to another instance, and recognising it there.
+#[derive(Serialize)]
+pub struct ShadowConfig {
+    /// `SHADOW_TARGET_URL`: where to mirror requests to.
+    pub target: Option<String>,
+    /// Shared by both instances; required to mirror, and to be mirrored to.
+    #[serde(serialize_with = "optional_fingerprint")]
+    pub secret: Option<String>,
+    /// Share of proxied requests mirrored, 0-100.
+    pub percent: u64,
+    pub concurrency: usize,
+    /// One in this many mirrored requests is compared with our response.
+    pub compare_every: u64,
+}
+
+/// Webhook notifications on sustained trouble.
+#[derive(Serialize)]
+pub struct AlertConfig {
+    /// `ALERT_WEBHOOK_URL`; no alerts without one. Slack's embed a secret.
+    #[serde(serialize_with = "optional_fingerprint")]
+    pub webhook_url: Option<String>,
+    /// Send Slack's `{"text": ...}` rather than the generic payload.
+    pub slack: bool,
+    pub conditions: Vec<AlertCondition>,
+    pub error_rate_percent: f64,
+    /// Minutes in a row the error rate must stay above the threshold.
+    pub error_minutes: u32,
+    pub quota_floor: u64,
+    /// How often a condition that keeps holding is notified again.
+    #[serde(serialize_with = "secs")]
+    pub cooldown: Duration,
+}
+
+/// How many distinct new cache keys one origin may add per window before
+/// its misses are no longer stored.
+#[derive(Clone, Serialize)]
+pub struct KeyQuotaConfig {
+    /// 0 for no quota.
+    pub per_origin: usize,
+    #[serde(serialize_with = "secs")]
+    pub window: Duration,
+}
+
+/// Clients that name themselves with `X-Api-Key`, each allowed its own
+/// origins and number of requests a day, from `API_KEYS_FILE`.
+#[derive(Clone, Serialize)]
+pub struct ApiKeysConfig {
+    /// JSON, re-read on SIGHUP: `[{"name", "key", "origins", "daily_quota"}]`.
+    pub file: PathBuf,
+    /// `API_KEYS_REQUIRED`: requests without a key are refused rather than
+    /// served as before.
+    pub required: bool,
+    /// `API_KEYS_REDIS_URL`: daily counts shared by every replica, rather
+    /// than kept by each.
+    pub redis: Option<RedisConfig>,
+}
+
+/// Limits on `/__batch`.
+#[derive(Serialize)]
+pub struct BatchConfig {
+    pub max_paths: usize,
+    /// Paths of one batch resolved at once.
+    pub concurrency: usize,
+    pub max_body_bytes: usize,
+}
+
+/// `GET /__all/...`, off with `PAGINATE_MAX_PAGES=0`.
+#[derive(Serialize)]
+pub struct PaginateConfig {
+    /// Pages fetched for one request at most.
+    pub max_pages: usize,
+}
+
+/// `POST /graphql`, off unless `GRAPHQL_ENABLED`.
+#[derive(Serialize)]
+pub struct GraphqlConfig {
+    pub enabled: bool,
+    /// The longest query document accepted, in bytes.
+    pub max_query_bytes: usize,
+    /// How long a result is kept; GitHub's GraphQL answers say nothing about it.
+    #[serde(serialize_with = "secs")]
+    pub ttl: Duration,
+    pub max_entries: u64,
+}
+
+/// `GET /badge/...`, off unless `BADGES_ENABLED`.
+#[derive(Serialize)]
+pub struct BadgeConfig {
+    pub enabled: bool,
+    /// How long browsers and image proxies may keep a badge.
+    #[serde(serialize_with = "secs")]
+    pub max_age: Duration,
+}
+
+/// Where spans go, from the standard `OTEL_*` variables. Only OTLP over
+/// HTTP with JSON is spoken.
+#[derive(Clone, Serialize)]
+pub struct OtelConfig {
+    /// Where spans are posted: `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, or
+    /// `/v1/traces` under `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    pub endpoint: String,
+    /// Sent with every export, usually credentials: only names are shown.
+    #[serde(serialize_with = "header_names")]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub service_name: String,
+    /// The share of new traces kept; traces continued from a `traceparent`
+    /// are kept as its sender decided.
+    pub sample_ratio: f64,
+    #[serde(serialize_with = "secs")]
+    pub schedule_delay: Duration,
+}
+
+/// Gzip for clients that accept it, off unless `COMPRESSION_ENABLED`.
+#[derive(Serialize)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Smaller bodies aren't worth the trouble.
+    pub min_bytes: u64,
+    /// 1, fastest, to 9, smallest.
+    pub level: u8,
+    /// Compressed bodies kept to serve again.
+    pub cache_bytes: u64,
+}
+
+/// `GET /raw/...`, off unless `RAW_ENABLED`.
+#[derive(Serialize)]
+pub struct RawConfig {
+    pub enabled: bool,
+    /// How long a file is kept at a branch or tag, which can move.
+    #[serde(serialize_with = "secs")]
+    pub ttl: Duration,
+    /// How long at a full commit SHA, where it can't.
+    #[serde(serialize_with = "secs")]
+    pub immutable_ttl: Duration,
+    pub max_entries: u64,
+}
+
+/// Limits on long-polling `/watch` requests.
+#[derive(Serialize)]
+pub struct WatchConfig {
+    /// Requests that may be held open at once; more are answered with 503.
+    pub max_watchers: usize,
+    /// The longest a request is held, and the default `?timeout=`.
+    #[serde(serialize_with = "secs")]
+    pub max_timeout: Duration,
+}
+
+/// Paths kept warm in the background.
+#[derive(Serialize)]
+pub struct RefreshConfig {
+    /// Cache keys: the proxied path without its leading slash, plus query.
+    #[serde(serialize_with = "display_list")]
+    pub paths: Vec<Arc<str>>,
+    #[serde(serialize_with = "secs")]
+    pub interval: Duration,
+    /// How many background refreshes may run at once.
+    pub concurrency: usize,
+    /// Share of a token's quota for a resource, in percent, kept back for
+    /// visitors: below it, paths needing that resource aren't refreshed.
+    pub min_quota_percent: u64,
+}
+
+/// Thresholds for escalating repeat offenders from 4xx responses to a ban.
+#[derive(Clone, Serialize)]
+pub struct BanConfig {
+    /// 429s within `window` that trigger a ban; 0 disables this trigger.
+    pub max_rate_limited: usize,
+    /// 400s within `window` that trigger a ban; 0 disables this trigger.
+    pub max_invalid: usize,
+    #[serde(serialize_with = "secs")]
+    pub window: Duration,
+    #[serde(serialize_with = "secs")]
+    pub duration: Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        let fixtures = FixtureConfig {
+            serve: var("FIXTURE_MODE").map(PathBuf::from),
+            record: var("RECORD_FIXTURES").map(PathBuf::from),
+        };
+        let real_token = ["GITHUB_TOKEN", "GITHUB_TOKENS", "GITHUB_APP_ID"]
+            .into_iter()
+            .any(|name| var(name).is_some());
+        let tokens = match &fixtures.serve {
+            Some(_) if fixtures.record.is_some() => {
+                return Err("FIXTURE_MODE and RECORD_FIXTURES can't be used together".into());
+            }
+            Some(dir) if !dir.is_dir() => {
+                return Err(format!("FIXTURE_MODE: {} is not a directory", dir.display()));
+            }
+            // A real token means this is somewhere real: not where canned
+            // responses should stand in for GitHub.
+            Some(_) if real_token && !flag("FIXTURE_MODE_FORCE")? => {
+                return Err("FIXTURE_MODE is for development and refuses to start with \
+                     GitHub credentials set; set FIXTURE_MODE_FORCE=1 if this is \
+                     intended"
+                    .into());
+            }
+            Some(_) if !real_token => Tokens::offline(),
+            _ => Tokens::from_env()?,
+        };
+
+        let github = GithubUrls::from_env()?;
+        let user_agent = var("UPSTREAM_USER_AGENT").unwrap_or_else(|| {
+            format!(
+                "repos-proxy/{} (+https://github.com/EduardPrigoana/repos)",
+                env!("CARGO_PKG_VERSION")
+            )
+        });
+        let user_agent = HeaderValue::from_str(&user_agent)
+            .map_err(|_| format!("UPSTREAM_USER_AGENT: not a valid header value: {user_agent:?}"))?;
+
+        let trusted_proxies = list("TRUSTED_PROXIES")
+            .iter()
+            .map(|entry| parse_net(entry))
+            .collect::<Result<_, _>>()?;
+        let client_ip_header = parse("CLIENT_IP_HEADER", ForwardedHeader::XForwardedFor)?;
+
+        let ttl_secs = parse("CACHE_TTL_SECS", 10)?;
+        let cache = CacheConfig {
+            ttl: Duration::from_secs(ttl_secs),
+            min_ttl: Duration::from_secs(parse("CACHE_TTL_MIN_SECS", 5)?),
+            max_ttl: Duration::from_secs(parse("CACHE_TTL_MAX_SECS", 300)?),
+            stale: Duration::from_secs(parse("CACHE_STALE_SECS", 60)?),
+            stale_while_revalidate: optional_secs("CACHE_STALE_WHILE_REVALIDATE_SECS")?,
+            tti: optional_secs("CACHE_TTI_SECS")?,
+            max_entries: parse("CACHE_MAX_ENTRIES", 10_000)?,
+            max_bytes: var("CACHE_MAX_BYTES")
+                .map(|_| parse("CACHE_MAX_BYTES", 0))
+                .transpose()?,
+            no_cache_paths: parse_list("NO_CACHE_PATHS")?,
+            stream_paths: parse_list("STREAM_PATHS")?,
+            stream_threshold: parse("STREAM_THRESHOLD_BYTES", 8 * 1024 * 1024)?,
+            diff_paths: parse_list("DIFF_PATHS")?,
+            ttl_rules: ttl_rules()?,
+            stats_ttl: Duration::from_secs(parse("STATS_TTL_SECS", 3600)?),
+            error_ttl: Duration::from_secs(parse("CACHE_ERROR_TTL_SECS", 30)?),
+            max_age_param_min: Duration::from_secs(parse("MAX_AGE_PARAM_MIN_SECS", 10)?),
+            max_age_param_max: Duration::from_secs(parse("MAX_AGE_PARAM_MAX_SECS", 3600)?),
+            disk: match var("CACHE_DISK_PATH") {
+                Some(path) => Some(DiskCacheConfig {
+                    path: path.into(),
+                    max_bytes: parse("CACHE_DISK_MAX_BYTES", 1024 * 1024 * 1024)?,
+                    ttl: Duration::from_secs(parse("CACHE_DISK_TTL_SECS", 24 * 60 * 60)?),
+                }),
+                None => None,
+            },
+            redis: var("CACHE_REDIS_URL")
+                .map(|url| RedisCacheConfig::from_env(&url))
+                .transpose()?,
+        };
+        if cache.disk.is_some() && cache.redis.is_some() {
+            return Err("set either CACHE_DISK_PATH or CACHE_REDIS_URL, not both".into());
+        }
+        if cache.max_bytes.is_some() && var("CACHE_MAX_ENTRIES").is_some() {
+            return Err("set either CACHE_MAX_ENTRIES or CACHE_MAX_BYTES, not both".into());
+        }
+        if cache.tti.is_some_and(|tti| tti > cache.ttl) {
+            return Err("CACHE_TTI_SECS must not exceed CACHE_TTL_SECS".into());
+        }
+        if cache.stale_while_revalidate.is_some_and(|swr| swr > cache.stale) {
+            return Err("CACHE_STALE_WHILE_REVALIDATE_SECS must not exceed CACHE_STALE_SECS".into());
+        }
+        if cache.min_ttl > cache.max_ttl {
+            return Err("CACHE_TTL_MIN_SECS must not exceed CACHE_TTL_MAX_SECS".into());
+        }
+        if cache.max_age_param_min > cache.max_age_param_max {
+            return Err("MAX_AGE_PARAM_MIN_SECS must not exceed MAX_AGE_PARAM_MAX_SECS".into());
+        }
+
+        let client_cache = ClientCacheConfig {
+            max_age: optional_secs("CLIENT_MAX_AGE")?,
+            cdn_max_age: optional_secs("CDN_S_MAXAGE")?,
+            cdn_headers: list("CDN_CACHE_HEADERS")
+                .iter()
+                .map(|name| match name.to_ascii_lowercase().as_str() {
+                    "surrogate-control" => Ok(HeaderName::from_static("surrogate-control")),
+                    "cdn-cache-control" => Ok(HeaderName::from_static("cdn-cache-control")),
+                    _ => Err(format!("CDN_CACHE_HEADERS: unsupported header {name:?}")),
+                })
+                .collect::<Result<_, _>>()?,
+            stale_while_revalidate: optional_secs("STALE_WHILE_REVALIDATE_SECS")?,
+        };
+        if !client_cache.cdn_headers.is_empty() && client_cache.cdn_max_age.is_none() {
+            return Err("CDN_CACHE_HEADERS requires CDN_S_MAXAGE".into());
+        }
+
+        let upstream = UpstreamConfig {
+            max_concurrency: parse("MAX_UPSTREAM_CONCURRENCY", 32)?,
+            permit_timeout: Duration::from_millis(parse("UPSTREAM_PERMIT_TIMEOUT_MS", 2000)?),
+            shed_retry_after: Duration::from_secs(parse("LOAD_SHED_RETRY_AFTER_SECS", 2)?),
+            timeout: Duration::from_secs(parse("UPSTREAM_TIMEOUT_SECS", 30)?),
+            stats_retries: parse("STATS_RETRIES", 3)?,
+            stats_retry_delay: Duration::from_millis(parse("STATS_RETRY_DELAY_MS", 1000)?),
+            retries: parse("UPSTREAM_RETRIES", 2)?,
+            retry_base_delay: Duration::from_millis(parse("UPSTREAM_RETRY_BASE_DELAY_MS", 200)?),
+            retry_max_delay: Duration::from_millis(parse("UPSTREAM_RETRY_MAX_DELAY_MS", 5000)?),
+            circuit_breaker_failures: parse("CIRCUIT_BREAKER_FAILURES", 5)?,
+            circuit_breaker_cooldown: Duration::from_secs(parse(
+                "CIRCUIT_BREAKER_COOLDOWN_SECS",
+                30,
+            )?),
+            dns_cache_ttl: Duration::from_secs(parse("DNS_CACHE_TTL_SECS", 60)?),
+            resolve_overrides: list("RESOLVE_OVERRIDES")
+                .iter()
+                .map(|entry| parse_override(entry))
+                .collect::<Result<_, _>>()?,
+            keepalive: optional_secs("UPSTREAM_KEEPALIVE_SECS")?,
+        };
+        if upstream.max_concurrency == 0 {
+            return Err("MAX_UPSTREAM_CONCURRENCY must be at least 1".into());
+        }
+        if upstream.retry_base_delay > upstream.retry_max_delay {
+            return Err(
+                "UPSTREAM_RETRY_BASE_DELAY_MS can't be above UPSTREAM_RETRY_MAX_DELAY_MS".into(),
+            );
+        }
+        if upstream.timeout.is_zero() {
+            return Err("UPSTREAM_TIMEOUT_SECS must be at least 1".into());
+        }
+        if upstream
+            .keepalive
+            .is_some_and(|every| every.is_zero() || every >= POOL_IDLE_TIMEOUT)
+        {
+            return Err(format!(
+                "UPSTREAM_KEEPALIVE_SECS must be between 1 and {}, the pool's idle timeout",
+                POOL_IDLE_TIMEOUT.as_secs() - 1
+            ));
+        }
+
+        let unix_socket = match var("LISTEN_UNIX") {
+            Some(path) => {
+                let mode = var("LISTEN_UNIX_MODE").unwrap_or_else(|| "660".into());
+                let mode = u32::from_str_radix(&mode, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| format!("LISTEN_UNIX_MODE: invalid mode {mode:?}"))?;
+                Some(UnixSocketConfig {
+                    path: path.into(),
+                    mode,
+                })
+            }
+            None => None,
+        };
+        let mut listeners: Vec<Listener> = parse_list("BIND_ADDRS")?;
+        let single = var("BIND_ADDR").is_some() || var("PORT").is_some();
+        if single && !listeners.is_empty() {
+            return Err("set either BIND_ADDRS or BIND_ADDR and PORT, not both".into());
+        }
+        // With only a socket asked for, nothing listens on TCP.
+        if listeners.is_empty() && (single || unix_socket.is_none()) {
+            let ip = parse("BIND_ADDR", IpAddr::from([0, 0, 0, 0]))?;
+            listeners.push(Listener {
+                addr: SocketAddr::new(ip, parse("PORT", 3000)?),
+                admin_only: false,
+            });
+        }
+        if unix_socket.is_none() && listeners.iter().all(|l| l.admin_only) {
+            return Err("BIND_ADDRS needs at least one listener that isn't admin-only".into());
+        }
+
+        let tls = match (var("TLS_CERT"), var("TLS_KEY")) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert: cert.into(),
+                key: key.into(),
+                redirect: var("TLS_REDIRECT_ADDR")
+                    .map(|_| parse("TLS_REDIRECT_ADDR", SocketAddr::from(([0, 0, 0, 0], 80))))
+                    .transpose()?,
+            }),
+            (None, None) if var("TLS_REDIRECT_ADDR").is_some() => {
+                return Err("TLS_REDIRECT_ADDR needs TLS_CERT and TLS_KEY".into());
+            }
+            (None, None) => None,
+            _ => return Err("set both TLS_CERT and TLS_KEY, or neither".into()),
+        };
+
+        let server = ServerConfig {
+            header_read_timeout: Duration::from_secs(parse("HTTP_HEADER_TIMEOUT_SECS", 10)?),
+            idle_timeout: Duration::from_secs(parse("HTTP_IDLE_TIMEOUT_SECS", 60)?),
+            max_requests_per_connection: parse("HTTP_MAX_REQUESTS_PER_CONNECTION", 1000)?,
+            max_header_bytes: parse("HTTP_MAX_HEADER_BYTES", 16 * 1024)?,
+            max_headers: parse("HTTP_MAX_HEADERS", 100)?,
+            max_url_bytes: parse("MAX_URL_BYTES", 4096)?,
+            max_body_bytes: parse("MAX_REQUEST_BODY_BYTES", 2 * 1024 * 1024)?,
+            h2c: flag("HTTP2_PRIOR_KNOWLEDGE")?,
+            http2_max_concurrent_streams: parse("HTTP2_MAX_CONCURRENT_STREAMS", 100)?,
+            max_in_flight: parse("MAX_IN_FLIGHT_REQUESTS", 0)?,
+            drain_timeout: Duration::from_secs(parse("SHUTDOWN_DRAIN_SECS", 30)?),
+        };
+        // hyper's own floor for the HTTP/1 read buffer.
+        if server.max_header_bytes < 8192 {
+            return Err("HTTP_MAX_HEADER_BYTES must be at least 8192".into());
+        }
+        if server.header_read_timeout.is_zero() || server.idle_timeout.is_zero() {
+            return Err(
+                "HTTP_HEADER_TIMEOUT_SECS and HTTP_IDLE_TIMEOUT_SECS must be at least 1".into(),
+            );
+        }
+
+        let bans = BanConfig {
+            max_rate_limited: parse("BAN_MAX_RATE_LIMITED", 30)?,
+            max_invalid: parse("BAN_MAX_INVALID", 20)?,
+            window: Duration::from_secs(parse("BAN_WINDOW_SECS", 60)?),
+            duration: Duration::from_secs(parse("BAN_DURATION_SECS", 600)?),
+        };
+
+        let refresh = RefreshConfig {
+            paths: list("REFRESH_PATHS")
+                .iter()
+                .map(|path| {
+                    // As requested, `repos/owner/repo`, or already as keyed.
+                    let path = path.trim_start_matches('/');
+                    path.strip_prefix("repos/").unwrap_or(path).into()
+                })
+                .collect(),
+            interval: Duration::from_secs(parse("REFRESH_INTERVAL_SECS", 5)?),
+            concurrency: parse("REFRESH_CONCURRENCY", 4)?,
+            min_quota_percent: parse("REFRESH_MIN_QUOTA_PERCENT", 10)?,
+        };
+        if refresh.interval.is_zero() || refresh.concurrency == 0 {
+            return Err(
+                "REFRESH_INTERVAL_SECS and REFRESH_CONCURRENCY must be at least 1".into(),
+            );
+        }
+        if refresh.min_quota_percent > 100 {
+            return Err("REFRESH_MIN_QUOTA_PERCENT is a percentage, at most 100".into());
+        }
+
+        let usage = UsageConfig {
+            window_hours: parse("USAGE_WINDOW_HOURS", 24)?,
+            max_origins: parse("USAGE_MAX_ORIGINS", 1000)?,
+        };
+        if usage.window_hours == 0 {
+            return Err("USAGE_WINDOW_HOURS must be at least 1".into());
+        }
+
+        let peer_urls: Vec<String> = list("PEERS")
+            .iter()
+            .map(|url| url.trim_end_matches('/').to_owned())
+            .collect();
+        let peers = if peer_urls.is_empty() {
+            None
+        } else {
+            if let Some(url) = peer_urls.iter().find(|url| reqwest::Url::parse(url).is_err()) {
+                return Err(format!("PEERS: invalid URL {url:?}"));
+            }
+            let own_url = var("PEER_SELF")
+                .map(|url| url.trim_end_matches('/').to_owned())
+                .filter(|url| peer_urls.contains(url))
+                .ok_or("PEERS requires PEER_SELF, set to this replica's own entry in it")?;
+            let secret = var("PEER_SECRET").ok_or("PEERS requires PEER_SECRET")?;
+            if HeaderValue::from_str(&secret).is_err() {
+                return Err("PEER_SECRET contains characters not allowed in a header".into());
+            }
+            Some(PeerConfig {
+                urls: peer_urls,
+                own_url,
+                secret,
+                timeout: Duration::from_millis(parse("PEER_TIMEOUT_MS", 2000)?),
+            })
+        };
+
+        let warm_from = var("PEER_WARM_FROM").map(|url| url.trim_end_matches('/').to_owned());
+        if let Some(url) = &warm_from {
+            if reqwest::Url::parse(url).is_err() {
+                return Err(format!("PEER_WARM_FROM: invalid URL {url:?}"));
+            }
+            if var("ADMIN_TOKEN").is_none() {
+                return Err("PEER_WARM_FROM requires ADMIN_TOKEN, shared with that replica".into());
+            }
+        }
+
+        let shadow = ShadowConfig {
+            target: var("SHADOW_TARGET_URL").map(|url| url.trim_end_matches('/').to_owned()),
+            secret: var("SHADOW_SECRET"),
+            percent: parse("SHADOW_PERCENT", 10)?,
+            concurrency: parse("SHADOW_CONCURRENCY", 8)?,
+            compare_every: parse("SHADOW_COMPARE_EVERY", 10)?,
+        };
+        if let Some(target) = &shadow.target {
+            if reqwest::Url::parse(target).is_err() {
+                return Err(format!("SHADOW_TARGET_URL: invalid URL {target:?}"));
+            }
+            if shadow.secret.is_none() {
+                return Err("SHADOW_TARGET_URL requires SHADOW_SECRET".into());
+            }
+        }
+        if shadow.secret.as_deref().is_some_and(|s| HeaderValue::from_str(s).is_err()) {
+            return Err("SHADOW_SECRET contains characters not allowed in a header".into());
+        }
+        if shadow.percent > 100 {
+            return Err("SHADOW_PERCENT must be between 0 and 100".into());
+        }
+        if shadow.concurrency == 0 || shadow.compare_every == 0 {
+            return Err("SHADOW_CONCURRENCY and SHADOW_COMPARE_EVERY must be at least 1".into());
+        }
+
+        let alerts = AlertConfig {
+            webhook_url: var("ALERT_WEBHOOK_URL"),
+            slack: match var("ALERT_FORMAT").as_deref() {
+                None | Some("generic") => false,
+                Some("slack") => true,
+                Some(other) => {
+                    return Err(format!("ALERT_FORMAT: expected generic or slack, got {other:?}"))
+                }
+            },
+            conditions: match var("ALERT_CONDITIONS") {
+                Some(_) => parse_list("ALERT_CONDITIONS")?,
+                None => AlertCondition::ALL.to_vec(),
+            },
+            error_rate_percent: parse("ALERT_ERROR_RATE_PERCENT", 25.0)?,
+            error_minutes: parse("ALERT_ERROR_MINUTES", 5)?,
+            quota_floor: parse("ALERT_QUOTA_FLOOR", 500)?,
+            cooldown: Duration::from_secs(parse("ALERT_COOLDOWN_SECS", 3600)?),
+        };
+        if let Some(url) = &alerts.webhook_url {
+            if reqwest::Url::parse(url).is_err() {
+                return Err(format!("ALERT_WEBHOOK_URL: invalid URL {url:?}"));
+            }
+        }
+        if alerts.error_minutes == 0 {
+            return Err("ALERT_ERROR_MINUTES must be at least 1".into());
+        }
+
+        let key_quota = KeyQuotaConfig {
+            per_origin: parse("KEY_QUOTA_PER_ORIGIN", 5000)?,
+            window: Duration::from_secs(parse("KEY_QUOTA_WINDOW_SECS", 3600)?),
+        };
+
+        let api_keys = match var("API_KEYS_FILE") {
+            Some(file) => Some(ApiKeysConfig {
+                file: file.into(),
+                required: flag("API_KEYS_REQUIRED")?,
+                redis: match var("API_KEYS_REDIS_URL") {
+                    Some(url) => Some(RedisConfig::from_url(
+                        "API_KEYS_REDIS_URL",
+                        &url,
+                        var("API_KEYS_REDIS_PREFIX")
+                            .unwrap_or_else(|| "github-cors-proxy-api-keys:".into()),
+                        Duration::from_millis(parse("API_KEYS_REDIS_TIMEOUT_MS", 250)?),
+                    )?),
+                    None => None,
+                },
+            }),
+            None if flag("API_KEYS_REQUIRED")? => {
+                return Err("API_KEYS_REQUIRED needs API_KEYS_FILE".into());
+            }
+            None => None,
+        };
+
+        let batch = BatchConfig {
+            max_paths: parse("BATCH_MAX_PATHS", 50)?,
+            concurrency: parse("BATCH_CONCURRENCY", 8)?,
+            max_body_bytes: parse("BATCH_MAX_BODY_BYTES", 16 * 1024)?,
+        };
+        if batch.max_paths == 0 || batch.concurrency == 0 {
+            return Err("BATCH_MAX_PATHS and BATCH_CONCURRENCY must be at least 1".into());
+        }
+
+        let paginate = PaginateConfig {
+            max_pages: parse("PAGINATE_MAX_PAGES", 10)?,
+        };
+
+        let graphql = GraphqlConfig {
+            enabled: flag("GRAPHQL_ENABLED")?,
+            max_query_bytes: parse("GRAPHQL_MAX_QUERY_BYTES", 8 * 1024)?,
+            ttl: Duration::from_secs(parse("GRAPHQL_TTL_SECS", 60)?),
+            max_entries: parse("GRAPHQL_MAX_ENTRIES", 1000)?,
+        };
+        // Any repository links to others, which no allowlist check can follow.
+        let allowlist = var("ALLOWED_REPOS").is_some() || var("ALLOWED_REPOS_FILE").is_some();
+        if graphql.enabled && allowlist {
+            return Err("GRAPHQL_ENABLED can't be combined with ALLOWED_REPOS".into());
+        }
+
+        let raw = RawConfig {
+            enabled: flag("RAW_ENABLED")?,
+            ttl: Duration::from_secs(parse("RAW_TTL_SECS", 300)?),
+            immutable_ttl: Duration::from_secs(parse("RAW_IMMUTABLE_TTL_SECS", 7 * 24 * 60 * 60)?),
+            max_entries: parse("RAW_MAX_ENTRIES", 1000)?,
+        };
+
+        let badge = BadgeConfig {
+            enabled: flag("BADGES_ENABLED")?,
+            max_age: Duration::from_secs(parse("BADGE_MAX_AGE_SECS", 3600)?),
+        };
+
+        let compression = CompressionConfig {
+            enabled: flag("COMPRESSION_ENABLED")?,
+            min_bytes: parse("COMPRESSION_MIN_BYTES", 1024)?,
+            level: parse("COMPRESSION_LEVEL", 6)?,
+            cache_bytes: parse("COMPRESSION_CACHE_BYTES", 64 * 1024 * 1024)?,
+        };
+        if !(1..=9).contains(&compression.level) {
+            return Err("COMPRESSION_LEVEL must be between 1 and 9".into());
+        }
+
+        let otel_endpoint = match var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+            Some(endpoint) => Some(endpoint),
+            None => var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .map(|base| format!("{}/v1/traces", base.trim_end_matches('/'))),
+        };
+        let otel_disabled = flag("OTEL_SDK_DISABLED")?;
+        let otel = match otel_endpoint.filter(|_| !otel_disabled) {
+            Some(endpoint) => {
+                let protocol = var("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL")
+                    .or_else(|| var("OTEL_EXPORTER_OTLP_PROTOCOL"));
+                if protocol.is_some_and(|p| p != "http/json") {
+                    return Err("OTEL_EXPORTER_OTLP_PROTOCOL: only http/json is supported".into());
+                }
+                let headers = match var("OTEL_EXPORTER_OTLP_TRACES_HEADERS") {
+                    Some(_) => list("OTEL_EXPORTER_OTLP_TRACES_HEADERS"),
+                    None => list("OTEL_EXPORTER_OTLP_HEADERS"),
+                };
+                let headers = headers
+                    .iter()
+                    .map(|entry| {
+                        let invalid = || format!("OTEL_EXPORTER_OTLP_HEADERS: invalid {entry:?}");
+                        let (name, value) = entry.split_once('=').ok_or_else(invalid)?;
+                        let name = HeaderName::try_from(name.trim()).map_err(|_| invalid())?;
+                        let value = HeaderValue::try_from(value.trim()).map_err(|_| invalid())?;
+                        Ok((name, value))
+                    })
+                    .collect::<Result<_, String>>()?;
+                let sample_ratio = parse("OTEL_TRACES_SAMPLER_ARG", 1.0)?;
+                if !(0.0..=1.0).contains(&sample_ratio) {
+                    return Err("OTEL_TRACES_SAMPLER_ARG must be between 0 and 1".into());
+                }
+                Some(OtelConfig {
+                    endpoint,
+                    headers,
+                    service_name: var("OTEL_SERVICE_NAME")
+                        .unwrap_or_else(|| env!("CARGO_PKG_NAME").into()),
+                    sample_ratio,
+                    schedule_delay: Duration::from_millis(parse("OTEL_BSP_SCHEDULE_DELAY", 5000)?),
+                })
+            }
+            None => None,
+        };
+
+        let watch = WatchConfig {
+            max_watchers: parse("WATCH_MAX_WATCHERS", 1000)?,
+            max_timeout: Duration::from_secs(parse("WATCH_MAX_TIMEOUT_SECS", 30)?),
+        };
+
+        let allowed_origins = match var("ALLOWED_ORIGINS") {
+            Some(_) => parse_list("ALLOWED_ORIGINS")?,
+            None => DEFAULT_ALLOWED_ORIGINS
+                .iter()
+                .map(|pattern| pattern.parse())
+                .collect::<Result<_, _>>()?,
+        };
+        let allowed_origins = OriginAllowlist::new(allowed_origins);
+        if allowed_origins.patterns().is_empty() {
+            return Err("ALLOWED_ORIGINS must list at least one origin".into());
+        }
+        let origin_rate_limits = list("ORIGIN_RATE_LIMITS")
+            .iter()
+            .map(|entry| {
+                let (pattern, rpm) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("ORIGIN_RATE_LIMITS: expected origin=rpm, got {entry:?}"))?;
+                let rpm = rpm
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|rpm| *rpm > 0)
+                    .ok_or_else(|| format!("ORIGIN_RATE_LIMITS: invalid budget in {entry:?}"))?;
+                Ok((pattern.trim().parse()?, rpm))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let passthrough_headers = match var("PASSTHROUGH_HEADERS") {
+            Some(_) => HeaderAllowlist::response(&list("PASSTHROUGH_HEADERS")),
+            None => HeaderAllowlist::response(DEFAULT_PASSTHROUGH),
+        }
+        .map_err(|e| format!("PASSTHROUGH_HEADERS: {e}"))?;
+
+        let forward_headers = match var("FORWARD_HEADERS") {
+            Some(_) => HeaderAllowlist::request(&list("FORWARD_HEADERS")),
+            None => HeaderAllowlist::request(DEFAULT_FORWARD),
+        }
+        .map_err(|e| format!("FORWARD_HEADERS: {e}"))?;
+
+        let allow_credentials = flag("CORS_ALLOW_CREDENTIALS")?;
+        let header_list = |name: &str| -> Result<Vec<String>, String> {
+            let names = list(name);
+            for entry in &names {
+                if entry == "*" && allow_credentials {
+                    return Err(format!("{name}: `*` is taken literally with credentials"));
+                }
+                if entry != "*" && HeaderName::try_from(entry.as_str()).is_err() {
+                    return Err(format!("{name}: invalid header name {entry:?}"));
+                }
+            }
+            Ok(names)
+        };
+        let allow_methods = match var("CORS_ALLOW_METHODS") {
+            Some(_) => list("CORS_ALLOW_METHODS")
+                .iter()
+                .map(|m| {
+                    let method = m.to_ascii_uppercase();
+                    match method.as_str() {
+                        "GET" | "HEAD" | "POST" | "OPTIONS" => Ok(method),
+                        _ => Err(format!("CORS_ALLOW_METHODS: {m:?} isn't served here")),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .join(", "),
+            None => "GET, OPTIONS".into(),
+        };
+        let allow_headers = match header_list("CORS_ALLOW_HEADERS")? {
+            names if !names.is_empty() => names.join(", "),
+            _ if allow_credentials => CREDENTIALED_ALLOW_HEADERS.into(),
+            // A wildcard never covers `Authorization`, for `CLIENT_TOKENS`.
+            _ => "*, authorization".into(),
+        };
+        let cors = CorsConfig {
+            allow_credentials,
+            allow_methods: HeaderValue::try_from(allow_methods).expect("checked methods"),
+            allow_headers: HeaderValue::try_from(allow_headers).expect("checked header names"),
+            max_age: Duration::from_secs(parse("CORS_MAX_AGE_SECS", 3600)?),
+        };
+        let expose_extra = header_list("CORS_EXPOSE_HEADERS")?;
+
+        let signer = Signer::from_env()?;
+        let signed: &[&str] = match signer {
+            Some(_) => &[SIGNATURE_HEADER, SIGNED_HEADERS_HEADER],
+            None => &[],
+        };
+        let quota: &[&str] = match api_keys {
+            Some(_) => &api_keys::QUOTA_HEADERS,
+            None => &[],
+        };
+
+        Ok(Self {
+            tokens,
+            fixtures,
+            skip_token_check: flag("SKIP_TOKEN_CHECK")?,
+            client_tokens: flag("CLIENT_TOKENS")?,
+            token_expiry_warning: Duration::from_secs(
+                parse("TOKEN_EXPIRY_WARN_DAYS", 7)? * 24 * 60 * 60,
+            ),
+            readiness_upstream_check: optional_secs("READYZ_UPSTREAM_CHECK_SECS")?,
+            repos: RepoAccess::from_env()?,
+            github,
+            user_agent,
+            trusted_proxies,
+            client_ip_header,
+            admin_token: var("ADMIN_TOKEN"),
+            metrics_require_admin: flag("METRICS_REQUIRE_ADMIN")?,
+            webhook_secret: var("WEBHOOK_SECRET"),
+            cache,
+            client_cache,
+            upstream,
+            listeners,
+            unix_socket,
+            tls,
+            server,
+            bans,
+            refresh,
+            usage,
+            peers,
+            warm_from,
+            shadow,
+            alerts,
+            key_quota,
+            api_keys,
+            batch,
+            paginate,
+            graphql,
+            raw,
+            badge,
+            compression,
+            otel,
+            watch,
+            allowed_origins,
+            origin_rate_limits,
+            api_namespaces: Namespaces::parse(&list("API_NAMESPACES"))?,
+            client_rate_limit: parse("CLIENT_RATE_LIMIT_RPM", 0)?,
+            forced_refresh_per_minute: parse("FORCED_REFRESH_PER_MINUTE", 6)?,
+            expose_headers: passthrough_headers.expose_value(
+                &signed
+                    .iter()
+                    .chain(quota)
+                    .copied()
+                    .chain(expose_extra.iter().map(String::as_str))
+                    .collect::<Vec<_>>(),
+            ),
+            cors,
+            passthrough_headers,
+            require_origin: flag("REQUIRE_ORIGIN")?,
+            max_origin_len: parse("MAX_ORIGIN_LENGTH", 256)?,
+            timing_allow_origin: flag("TIMING_ALLOW_ORIGIN")?,
+            redact_fields: parse_list("REDACT_FIELDS")?,
+            schemas: Schemas::from_env()?,
+            signer,
+            forward_headers,
+            browser_page: flag("BROWSER_PAGE")?,
+            debug_dump: flag("DEBUG_DUMP")?,
+            debug_dump_bytes: parse("DEBUG_DUMP_BYTES", 2048)?,
+            loaded_at: unix_now(),
+        })
+    }
+
+    /// Every secret configured, for scrubbing out of dumps and reports.
+    pub fn secrets(&self) -> Vec<String> {
+        let mut secrets: Vec<String> = self.tokens.all().iter().map(|t| t.secret()).collect();
+        secrets.extend(self.admin_token.clone());
+        secrets.extend(self.peers.as_ref().map(|peers| peers.secret.clone()));
+        secrets.extend(self.shadow.secret.clone());
+        secrets.extend(self.webhook_secret.clone());
+        secrets
+    }
+}
+
+/// Whether logs are JSON (`LOG_FORMAT=json`) rather than text. Read apart
+/// from the rest, before there is a logger to report config errors to.
+pub fn log_format() -> Result<bool, String> {
+    match var("LOG_FORMAT").as_deref() {
+        None | Some("text") => Ok(false),
+        Some("json") => Ok(true),
+        Some(other) => Err(format!("LOG_FORMAT: expected text or json, got {other:?}")),
+    }
+}
+
+/// Reads a variable, treating unset and blank values the same.
+pub fn var(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
+/// Reads a comma-separated list, skipping empty items.
+pub fn list(name: &str) -> Vec<String> {
+    var(name)
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads a boolean switch; unset means off.
+pub fn flag(name: &str) -> Result<bool, String> {
+    match var(name).as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("0" | "false" | "no" | "off") => Ok(false),
+        Some("1" | "true" | "yes" | "on") => Ok(true),
+        Some(other) => Err(format!("{name}: expected a boolean, got {other:?}")),
+    }
+}
+
+/// Parses every item of a comma-separated list.
+pub fn parse_list<T: FromStr<Err = String>>(name: &str) -> Result<Vec<T>, String> {
+    list(name)
+        .iter()
+        .map(|item| item.parse().map_err(|e| format!("{name}: {e}")))
+        .collect()
+}
+
+/// Parses a variable with `FromStr`, falling back to `default` when unset.
+pub fn parse<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match var(name) {
+        Some(v) => v
+            .parse()
+            .map_err(|_| format!("{name}: invalid value {v:?}")),
+        None => Ok(default),
+    }
+}
+
+/// Reads a number of seconds that may be left unset.
+pub fn optional_secs(name: &str) -> Result<Option<Duration>, String> {
+    var(name)
+        .map(|_| parse(name, 0).map(Duration::from_secs))
+        .transpose()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Stands in for a secret wherever it is shown: enough to tell two apart,
+/// or to check one against what was deployed, but not to recover it.
+pub fn fingerprint(secret: &str) -> String {
+    let digest = Sha256::digest(secret.as_bytes());
+    let hex: String = digest[..4].iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256:{hex}")
+}
+
+fn fingerprinted<S: Serializer>(secret: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&fingerprint(secret))
+}
+
+fn optional_fingerprint<S: Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    secret.as_deref().map(fingerprint).serialize(serializer)
+}
+
+/// Durations are shown in seconds.
+fn secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn octal<S: Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{mode:o}"))
+}
+
+fn optional_secs_value<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration.map(|d| d.as_secs_f64()).serialize(serializer)
+}
+
+fn header_value<S: Serializer>(value: &HeaderValue, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(value.as_bytes()))
+}
+
+fn header_names<S: Serializer>(
+    headers: &[(HeaderName, HeaderValue)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(headers.iter().map(|(name, _)| name.as_str()))
+}
+
+fn display_list<S: Serializer, T: Display>(items: &[T], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(items.iter().map(ToString::to_string))
+}
+
+/// `CACHE_TTL_RULES_FILE`, in TOML, its rules checked in the order given:
+///
+/// ```toml
+/// [[rule]]
+/// path = "*/*/releases"
+/// ttl_secs = 300
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TtlRulesFile {
+    #[serde(default)]
+    rule: Vec<TtlRuleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TtlRuleEntry {
+    path: String,
+    ttl_secs: u64,
+}
+
+/// `CACHE_TTL_RULES`, then those in `CACHE_TTL_RULES_FILE`.
+fn ttl_rules() -> Result<Vec<TtlRule>, String> {
+    let mut rules = list("CACHE_TTL_RULES")
+        .iter()
+        .map(|entry| parse_ttl_rule("CACHE_TTL_RULES", entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(file) = var("CACHE_TTL_RULES_FILE") {
+        let invalid = |why: String| format!("CACHE_TTL_RULES_FILE: {file}: {why}");
+        let contents = fs::read_to_string(&file)
+            .map_err(|e| invalid(format!("cannot read it: {e}")))?;
+        let parsed: TtlRulesFile = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        for entry in parsed.rule {
+            rules.push(TtlRule {
+                pattern: entry.path.parse().map_err(&invalid)?,
+                ttl: Duration::from_secs(entry.ttl_secs),
+            });
+        }
+    }
+    Ok(rules)
+}
+
+/// `pattern=secs`.
+fn parse_ttl_rule(name: &str, entry: &str) -> Result<TtlRule, String> {
+    let (pattern, secs) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("{name}: expected pattern=secs, got {entry:?}"))?;
+    let secs = secs
+        .trim()
+        .parse()
+        .map_err(|_| format!("{name}: invalid seconds in {entry:?}"))?;
+    Ok(TtlRule {
+        pattern: pattern.parse().map_err(|e| format!("{name}: {e}"))?,
+        ttl: Duration::from_secs(secs),
+    })
+}
+
+/// `host=ip:port`, or `host=ip` for HTTPS's port.
+fn parse_override(entry: &str) -> Result<(String, SocketAddr), String> {
+    let invalid = || format!("RESOLVE_OVERRIDES: expected host=ip:port, got {entry:?}");
+    let (host, addr) = entry.split_once('=').ok_or_else(invalid)?;
+    let host = host.trim().to_ascii_lowercase();
+    let addr = addr.trim();
+    let addr = match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => SocketAddr::new(addr.parse::<IpAddr>().map_err(|_| invalid())?, 443),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host, addr))
+}
+
+/// Accepts either CIDR notation or a bare address (a single-host network).
+fn parse_net(entry: &str) -> Result<IpNet, String> {
+    if let Ok(net) = entry.parse::<IpNet>() {
+        return Ok(net.trunc());
+    }
+    entry
+        .parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|_| format!("TRUSTED_PROXIES: invalid network {entry:?}"))
+}
@@ -0,0 +1,51 @@
+use axum::http::HeaderValue;
+
+/// What an upstream body is, as far as rewriting it goes. Only `Json` is
+/// ever parsed, redacted, checked or diffed; the others are relayed byte for
+/// byte, whatever their encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyKind {
+    Json,
+    Text,
+    Binary,
+}
+
+impl BodyKind {
+    /// Goes by `Content-Type`, confirmed by a look at the body: GitHub's raw
+    /// and HTML media types come labelled `+json` too, and a body without a
+    /// type is only taken for JSON if it starts like a document.
+    pub fn classify(content_type: Option<&HeaderValue>, body: &[u8]) -> Self {
+        let content_type = content_type
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        let looks_json = matches!(
+            body.iter().find(|b| !b.is_ascii_whitespace()),
+            Some(b'{' | b'[')
+        );
+        let utf8 = || std::str::from_utf8(body).is_ok();
+
+        let json_type = essence == "application/json" || essence.ends_with("+json");
+        if essence.is_empty() || json_type {
+            if looks_json && !essence.contains(".raw") && utf8() {
+                return Self::Json;
+            }
+            return if utf8() { Self::Text } else { Self::Binary };
+        }
+        if essence.starts_with("text/") {
+            Self::Text
+        } else {
+            Self::Binary
+        }
+    }
+
+    /// The type to label a body with when upstream gave none.
+    pub fn default_content_type(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Self::Json => "application/json",
+            Self::Text => "text/plain",
+            Self::Binary => "application/octet-stream",
+        })
+    }
+}
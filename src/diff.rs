@@ -0,0 +1,287 @@
+use axum::http::{header, HeaderValue};
+use bytes::Bytes;
+use metrics::counter;
+use moka::sync::Cache;
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+
+use crate::{cache::CachedResponse, content::BodyKind, paths::PathPattern};
+
+/// How many superseded versions are kept to diff against.
+const MAX_BASES: u64 = 1000;
+/// How long a superseded version is kept.
+const BASE_TTL: Duration = Duration::from_secs(3600);
+
+/// The query parameter naming the version a client already has.
+const PARAM: &str = "diff_from=";
+
+/// Differential responses for `DIFF_PATHS`: a client that sends
+/// `?diff_from=<etag>` with the ETag of the version it holds gets a JSON
+/// Patch (RFC 6902) from it to the current one, plus `X-Diff-Base`, instead
+/// of the whole body. Only the version before the current one is kept to
+/// diff against; for anything else, or when the patch wouldn't be smaller
+/// than the document, the full body is served as usual.
+pub struct DiffBases {
+    paths: Vec<PathPattern>,
+    /// The version each entry replaced, by cache key.
+    previous: Cache<Arc<str>, Arc<CachedResponse>>,
+}
+
+impl DiffBases {
+    pub fn new(paths: Vec<PathPattern>) -> Self {
+        Self {
+            paths,
+            previous: Cache::builder()
+                .max_capacity(MAX_BASES)
+                .time_to_live(BASE_TTL)
+                .build(),
+        }
+    }
+
+    pub fn enabled(&self, key: &str) -> bool {
+        let path = key.split(['?', '#']).next().unwrap_or_default();
+        self.paths.iter().any(|p| p.matches(path))
+    }
+
+    /// Keeps `replaced` to diff against, if `key` is a diff path and the
+    /// body actually changed.
+    pub fn remember(
+        &self,
+        key: &Arc<str>,
+        replaced: &Arc<CachedResponse>,
+        current: &CachedResponse,
+    ) {
+        let json = replaced.kind == BodyKind::Json && current.kind == BodyKind::Json;
+        if json && replaced.body != current.body && self.enabled(key) {
+            self.previous.insert(key.clone(), replaced.clone());
+        }
+    }
+
+    /// The patch from the version tagged `base` to `current`, serialized, if
+    /// that version is known and the patch is smaller than `current`.
+    pub fn patch(
+        &self,
+        key: &Arc<str>,
+        base: &HeaderValue,
+        current: &CachedResponse,
+    ) -> Option<Bytes> {
+        if current.kind != BodyKind::Json || !self.enabled(key) {
+            return None;
+        }
+        let tag = |entry: &CachedResponse| entry.headers.get(header::ETAG).cloned();
+        if tag(current).is_some_and(|etag| etag == base) {
+            // Nothing changed since: the empty patch.
+            return Some(Bytes::from_static(b"[]"));
+        }
+        let from = self
+            .previous
+            .get(key)
+            .filter(|previous| tag(previous).is_some_and(|etag| etag == base))?;
+        let (Ok(from), Ok(to)) = (
+            serde_json::from_slice::<Value>(&from.body),
+            serde_json::from_slice::<Value>(&current.body),
+        ) else {
+            return None;
+        };
+        let mut operations = Vec::new();
+        diff(&from, &to, &mut String::new(), &mut operations);
+        let patch = serde_json::to_vec(&operations).ok()?;
+        if patch.len() >= current.body.len() {
+            counter!("proxy_diffs_total", "outcome" => "too_large").increment(1);
+            return None;
+        }
+        counter!("proxy_diffs_total", "outcome" => "served").increment(1);
+        Some(Bytes::from(patch))
+    }
+}
+
+/// Takes `diff_from` out of a query string, since it means nothing to
+/// GitHub and mustn't split the cache key.
+pub fn split_query(query: Option<String>) -> (Option<String>, Option<HeaderValue>) {
+    let Some(query) = query else {
+        return (None, None);
+    };
+    if !query.split('&').any(|p| p.starts_with(PARAM)) {
+        return (Some(query), None);
+    }
+    let mut base = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|p| match p.strip_prefix(PARAM) {
+            Some(value) => {
+                base = decode(value).and_then(|v| HeaderValue::from_str(&v).ok());
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let rest = (!rest.is_empty()).then(|| rest.join("&"));
+    (rest, base)
+}
+
+/// Percent-decodes a query value; ETags come quoted, so `%22` is expected.
+fn decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = value.get(i + 1..i + 3)?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Appends the operations turning `from` into `to` at `pointer`.
+///
+/// Arrays are matched up by their common prefix and suffix, so the usual
+/// change to a list endpoint (a few items added at the front) becomes a few
+/// `add`s rather than a rewrite of every index.
+fn diff(from: &Value, to: &Value, pointer: &mut String, operations: &mut Vec<Value>) {
+    if from == to {
+        return;
+    }
+    match (from, to) {
+        (Value::Object(from), Value::Object(to)) => {
+            for (key, old) in from {
+                let len = pointer.len();
+                push_token(pointer, key);
+                match to.get(key) {
+                    Some(new) => diff(old, new, pointer, operations),
+                    None => operations.push(json!({ "op": "remove", "path": pointer })),
+                }
+                pointer.truncate(len);
+            }
+            for (key, new) in to.iter().filter(|(key, _)| !from.contains_key(*key)) {
+                let len = pointer.len();
+                push_token(pointer, key);
+                operations.push(json!({ "op": "add", "path": pointer, "value": new }));
+                pointer.truncate(len);
+            }
+        }
+        (Value::Array(from), Value::Array(to)) => {
+            let prefix = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+            let suffix = from[prefix..]
+                .iter()
+                .rev()
+                .zip(to[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let (old, new) = (&from[prefix..from.len() - suffix], &to[prefix..to.len() - suffix]);
+            if old.len() == new.len() {
+                for (i, (a, b)) in old.iter().zip(new).enumerate() {
+                    let len = pointer.len();
+                    pointer.push('/');
+                    pointer.push_str(&(prefix + i).to_string());
+                    diff(a, b, pointer, operations);
+                    pointer.truncate(len);
+                }
+                return;
+            }
+            let at = |i: usize| format!("{pointer}/{i}");
+            for _ in old {
+                operations.push(json!({ "op": "remove", "path": at(prefix) }));
+            }
+            for (i, value) in new.iter().enumerate() {
+                operations.push(json!({ "op": "add", "path": at(prefix + i), "value": value }));
+            }
+        }
+        _ => operations.push(json!({ "op": "replace", "path": pointer, "value": to })),
+    }
+}
+
+/// RFC 6901 escaping for one reference token.
+fn push_token(pointer: &mut String, key: &str) {
+    pointer.push('/');
+    pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just enough RFC 6902 to replay what `diff` emits.
+    fn apply(mut document: Value, operations: &[Value]) -> Value {
+        for operation in operations {
+            let path = operation["path"].as_str().unwrap();
+            if path.is_empty() {
+                document = operation["value"].clone();
+                continue;
+            }
+            let (parent, last) = path.rsplit_once('/').unwrap();
+            let last = last.replace("~1", "/").replace("~0", "~");
+            let target = document.pointer_mut(parent).unwrap();
+            match (operation["op"].as_str().unwrap(), target) {
+                ("add", Value::Array(items)) => {
+                    items.insert(last.parse().unwrap(), operation["value"].clone())
+                }
+                ("remove", Value::Array(items)) => {
+                    items.remove(last.parse().unwrap());
+                }
+                ("replace", Value::Array(items)) => {
+                    items[last.parse::<usize>().unwrap()] = operation["value"].clone()
+                }
+                ("add" | "replace", Value::Object(map)) => {
+                    map.insert(last, operation["value"].clone());
+                }
+                ("remove", Value::Object(map)) => {
+                    map.remove(&last);
+                }
+                (op, target) => panic!("cannot {op} at {path} in {target}"),
+            }
+        }
+        document
+    }
+
+    fn round_trip(from: Value, to: Value) -> Vec<Value> {
+        let mut operations = Vec::new();
+        diff(&from, &to, &mut String::new(), &mut operations);
+        assert_eq!(apply(from, &operations), to);
+        operations
+    }
+
+    #[test]
+    fn object_changes_round_trip() {
+        let operations = round_trip(
+            json!({ "name": "r", "stars": 1, "owner": { "login": "o", "id": 7 }, "gone": true }),
+            json!({ "name": "r", "stars": 2, "owner": { "login": "o" }, "a/b~c": null }),
+        );
+        assert_eq!(operations.len(), 4);
+        assert!(operations.contains(&json!({ "op": "remove", "path": "/gone" })));
+        assert!(operations.contains(&json!({ "op": "remove", "path": "/owner/id" })));
+        assert!(operations.contains(&json!({ "op": "add", "path": "/a~1b~0c", "value": null })));
+    }
+
+    #[test]
+    fn arrays_round_trip() {
+        let operations = round_trip(json!([3, 4, 5]), json!([1, 2, 3, 4, 5]));
+        assert_eq!(operations.len(), 2);
+        round_trip(json!([1, 2, 3, 4, 5]), json!([1, 5]));
+        round_trip(json!([1, 2, 3]), json!([]));
+        round_trip(json!([]), json!([1, 2]));
+        round_trip(json!([1, 2, 3]), json!([1, 9, 8, 7, 3]));
+        round_trip(json!([{ "id": 1, "x": 1 }, 2]), json!([{ "id": 1, "x": 2 }, 2]));
+        round_trip(json!([[1, 2], [3]]), json!([[1], [3, 4], [5]]));
+    }
+
+    #[test]
+    fn type_changes_round_trip() {
+        round_trip(json!({ "a": [1] }), json!({ "a": { "b": 1 } }));
+        round_trip(json!([1]), json!({ "a": 1 }));
+        round_trip(json!("old"), json!(null));
+        assert!(round_trip(json!({ "a": [1, { "b": 2 }] }), json!({ "a": [1, { "b": 2 }] }))
+            .is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+use futures_util::future::BoxFuture;
+use metrics::counter;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    cache::CachedResponse,
+    config::DiskCacheConfig,
+    repos::RepoPattern,
+    store::{self, unix_millis, CacheStore, Meta},
+};
+
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// What the index knows of a file, enough to enforce the budget and TTL and
+/// to purge by repository without opening it.
+struct Indexed {
+    key: Arc<str>,
+    stored_at: u64,
+    size: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    files: HashMap<String, Indexed>,
+    /// `(stored_at, file)`, oldest first, for eviction.
+    by_age: BTreeSet<(u64, String)>,
+    bytes: u64,
+}
+
+impl Index {
+    fn insert(&mut self, file: String, entry: Indexed) {
+        self.remove(&file);
+        self.bytes += entry.size;
+        self.by_age.insert((entry.stored_at, file.clone()));
+        self.files.insert(file, entry);
+    }
+
+    fn remove(&mut self, file: &str) -> bool {
+        let Some(old) = self.files.remove(file) else {
+            return false;
+        };
+        self.bytes -= old.size;
+        self.by_age.remove(&(old.stored_at, file.to_owned()));
+        true
+    }
+
+    fn oldest(&self) -> Option<(u64, String)> {
+        self.by_age.first().cloned()
+    }
+}
+
+/// Entries as plain files under `CACHE_DISK_PATH`, each its metadata line
+/// and body. A file that can't be read back for any reason is deleted and
+/// counts as a miss.
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    ttl: Duration,
+    index: Mutex<Index>,
+}
+
+impl DiskCache {
+    /// Opens the directory, creating it if needed, and indexes what a
+    /// previous run left there in the background.
+    pub fn open(config: &DiskCacheConfig) -> Result<Arc<Self>, String> {
+        fs::create_dir_all(&config.path).map_err(|e| {
+            format!(
+                "CACHE_DISK_PATH: cannot create {}: {e}",
+                config.path.display()
+            )
+        })?;
+        let disk = Arc::new(Self {
+            dir: config.path.clone(),
+            max_bytes: config.max_bytes,
+            ttl: config.ttl,
+            index: Mutex::new(Index::default()),
+        });
+        let scanning = disk.clone();
+        tokio::task::spawn_blocking(move || scanning.scan());
+        Ok(disk)
+    }
+
+    fn scan(&self) {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut indexed = 0;
+        for file in dir.flatten() {
+            let path = file.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()).map(str::to_owned) else {
+                continue;
+            };
+            // Left over from a write that never finished.
+            if name.ends_with(".tmp") {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+            let meta = fs::File::open(&path)
+                .ok()
+                .and_then(|file| read_meta(&mut BufReader::new(file)));
+            match meta {
+                Some(meta) if file_name(&meta.key) == name => {
+                    let entry = Indexed {
+                        key: meta.key.into(),
+                        stored_at: meta.stored_at,
+                        size,
+                    };
+                    self.index.lock().unwrap().insert(name, entry);
+                    indexed += 1;
+                }
+                _ => {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        self.enforce_limits();
+        info!(
+            "Disk cache: indexed {indexed} entries in {}",
+            self.dir.display()
+        );
+    }
+
+    fn write(
+        &self,
+        name: &str,
+        key: &str,
+        entry: &CachedResponse,
+        stored_at: u64,
+    ) -> std::io::Result<u64> {
+        let contents = store::encode(key, entry, stored_at);
+
+        // Written aside and renamed into place, so readers never see half a
+        // file; each write has its own temporary in case two race.
+        let unique = WRITES.fetch_add(1, Ordering::Relaxed);
+        let temporary = self.dir.join(format!("{name}.{unique}.tmp"));
+        fs::write(&temporary, &contents)?;
+        fs::rename(&temporary, self.dir.join(name))?;
+        Ok(contents.len() as u64)
+    }
+
+    /// Deletes the oldest entries until the disk TTL and size budget hold.
+    fn enforce_limits(&self) {
+        let expired_before = unix_millis(SystemTime::now()).saturating_sub(self.ttl_ms());
+        loop {
+            let oldest = {
+                let index = self.index.lock().unwrap();
+                match index.oldest() {
+                    Some((stored_at, name))
+                        if stored_at < expired_before || index.bytes > self.max_bytes =>
+                    {
+                        name
+                    }
+                    _ => return,
+                }
+            };
+            self.delete(&oldest);
+        }
+    }
+
+    fn delete(&self, name: &str) {
+        self.index.lock().unwrap().remove(name);
+        let _ = fs::remove_file(self.dir.join(name));
+    }
+
+    fn ttl_ms(&self) -> u64 {
+        self.ttl.as_millis() as u64
+    }
+}
+
+/// A key's file name: its SHA-256, so any path maps to a safe, fixed-length name.
+fn file_name(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn read_meta(reader: &mut impl BufRead) -> Option<Meta> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).ok()?;
+    serde_json::from_slice(&line).ok()
+}
+
+impl CacheStore for Arc<DiskCache> {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    /// The stored entry for `key`, unless it's missing, past the disk TTL or
+    /// unreadable.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<Arc<CachedResponse>>> {
+        let name = file_name(key);
+        let path = self.dir.join(&name);
+        let disk = self.clone();
+        let key = key.to_owned();
+        let read = tokio::task::spawn_blocking(move || {
+            let read = fs::read(&path).ok();
+            match read.as_deref().and_then(|contents| store::decode(contents, &key)) {
+                Some((entry, stored_at))
+                    if unix_millis(SystemTime::now()) < stored_at + disk.ttl_ms() =>
+                {
+                    Some(Arc::new(entry))
+                }
+                Some(_) => {
+                    disk.delete(&name);
+                    None
+                }
+                None => {
+                    if read.is_some() {
+                        warn!(key, "discarding unreadable disk cache entry");
+                        counter!("proxy_disk_cache_corrupt_total").increment(1);
+                        disk.delete(&name);
+                    }
+                    None
+                }
+            }
+        });
+        Box::pin(async move { read.await.ok().flatten() })
+    }
+
+    /// Writes `entry` in the background, replacing any older copy.
+    fn store(&self, key: Arc<str>, entry: Arc<CachedResponse>) {
+        let disk = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let name = file_name(&key);
+            let stored_at = store::fetched_at(&entry);
+            match disk.write(&name, &key, &entry, stored_at) {
+                Ok(size) => {
+                    disk.index.lock().unwrap().insert(
+                        name,
+                        Indexed {
+                            key,
+                            stored_at,
+                            size,
+                        },
+                    );
+                    disk.enforce_limits();
+                }
+                Err(err) => warn!(%key, "cannot write disk cache entry: {err}"),
+            }
+        });
+    }
+
+    fn remove(&self, key: &str) {
+        let (disk, name) = (self.clone(), file_name(key));
+        tokio::task::spawn_blocking(move || disk.delete(&name));
+    }
+
+    fn purge_repos(&self, repos: &[RepoPattern]) {
+        let doomed: Vec<String> = {
+            let index = self.index.lock().unwrap();
+            index
+                .files
+                .iter()
+                .filter(|(_, entry)| store::in_repos(&entry.key, repos))
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+        debug!(count = doomed.len(), "purging disk cache entries");
+        let disk = self.clone();
+        tokio::task::spawn_blocking(move || doomed.iter().for_each(|name| disk.delete(name)));
+    }
+
+    fn clear(&self) {
+        let doomed: Vec<String> = self.index.lock().unwrap().files.keys().cloned().collect();
+        debug!(count = doomed.len(), "clearing the disk cache");
+        let disk = self.clone();
+        tokio::task::spawn_blocking(move || doomed.iter().for_each(|name| disk.delete(name)));
+    }
+
+    fn stats(&self) -> Value {
+        let index = self.index.lock().unwrap();
+        json!({
+            "backend": "disk",
+            "entries": index.files.len(),
+            "bytes": index.bytes,
+        })
+    }
+}
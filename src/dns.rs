@@ -0,0 +1,91 @@
+use metrics::{counter, histogram};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Lookups slower than this are logged as a warning.
+const SLOW_LOOKUP: Duration = Duration::from_secs(1);
+
+/// When each host was looked up, and the answer.
+type Answers = HashMap<String, (Instant, Arc<[SocketAddr]>)>;
+
+/// The upstream client's resolver: the system's, with every lookup timed
+/// and its answers kept for `DNS_CACHE_TTL_SECS` (0 to ask every time).
+/// Should a lookup fail, an expired answer is used if there is one.
+///
+/// `RESOLVE_OVERRIDES` are handed to reqwest instead and never get here.
+#[derive(Clone)]
+pub struct CachingResolver {
+    ttl: Duration,
+    answers: Arc<Mutex<Answers>>,
+}
+
+impl CachingResolver {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            answers: Arc::default(),
+        }
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        let known = self.answers.lock().unwrap().get(&host).cloned();
+        if let Some((_, addrs)) = known.as_ref().filter(|(at, _)| at.elapsed() < self.ttl) {
+            counter!("proxy_dns_cache_hits_total").increment(1);
+            return Box::pin(std::future::ready(Ok(addrs_of(addrs.clone()))));
+        }
+
+        let resolver = self.clone();
+        Box::pin(async move {
+            let started = Instant::now();
+            let looked_up = tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map(|addrs| addrs.collect::<Arc<[SocketAddr]>>());
+            let elapsed = started.elapsed();
+            histogram!("proxy_dns_lookup_seconds").record(elapsed.as_secs_f64());
+            match looked_up {
+                Ok(addrs) => {
+                    if elapsed >= SLOW_LOOKUP {
+                        warn!(host, "DNS lookup took {elapsed:?}");
+                    } else {
+                        debug!(host, "DNS lookup took {elapsed:?}");
+                    }
+                    resolver
+                        .answers
+                        .lock()
+                        .unwrap()
+                        .insert(host, (Instant::now(), addrs.clone()));
+                    Ok(addrs_of(addrs))
+                }
+                Err(err) => {
+                    counter!("proxy_dns_failures_total").increment(1);
+                    match known {
+                        Some((_, addrs)) => {
+                            warn!(
+                                host,
+                                "DNS lookup failed after {elapsed:?}, reusing the last answer: {err}"
+                            );
+                            Ok(addrs_of(addrs))
+                        }
+                        None => {
+                            warn!(host, "DNS lookup failed after {elapsed:?}: {err}");
+                            Err(err.into())
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn addrs_of(addrs: Arc<[SocketAddr]>) -> Addrs {
+    Box::new((0..addrs.len()).map(move |i| addrs[i]))
+}
@@ -0,0 +1,149 @@
+use axum::http::{header, HeaderMap, StatusCode};
+use tracing::debug;
+
+use crate::{admin::constant_time_eq, api_keys::API_KEY_HEADER, config::Config, peers, shadow};
+
+const REDACTED: &str = "***REDACTED***";
+
+/// Prefixes GitHub uses for its token formats (classic/fine-grained PATs,
+/// OAuth, user-to-server, server-to-server and refresh tokens).
+const TOKEN_PREFIXES: &[&str] = &["github_pat_", "ghp_", "gho_", "ghu_", "ghs_", "ghr_"];
+
+/// Whether this request's upstream exchange should be dumped: always with
+/// `DEBUG_DUMP=true`, otherwise only when the client presents the admin token
+/// in `X-Debug-Dump`.
+pub fn wanted(config: &Config, request_headers: &HeaderMap) -> bool {
+    if config.debug_dump {
+        return true;
+    }
+    let Some(admin_token) = config.admin_token.as_deref() else {
+        return false;
+    };
+    request_headers
+        .get("x-debug-dump")
+        .is_some_and(|v| constant_time_eq(v.as_bytes(), admin_token.as_bytes()))
+}
+
+pub fn request(config: &Config, request: &reqwest::Request) {
+    debug!(
+        target: "github_cors_proxy::dump",
+        "upstream request: {} {}\n{}",
+        request.method(),
+        redact(request.url().as_str(), config),
+        headers(request.headers(), config),
+    );
+}
+
+pub fn response(config: &Config, status: StatusCode, response_headers: &HeaderMap, body: &[u8]) {
+    let shown = &body[..floor_char_boundary(body, config.debug_dump_bytes)];
+    debug!(
+        target: "github_cors_proxy::dump",
+        "upstream response: {status} ({} bytes, showing {})\n{}\n\n{}",
+        body.len(),
+        shown.len(),
+        headers(response_headers, config),
+        redact(&String::from_utf8_lossy(shown), config),
+    );
+}
+
+fn headers(headers: &HeaderMap, config: &Config) -> String {
+    let mut out = String::new();
+    for (name, value) in headers {
+        let value = if is_credential(name) {
+            REDACTED.to_owned()
+        } else {
+            redact(&String::from_utf8_lossy(value.as_bytes()), config)
+        };
+        out.push_str(name.as_str());
+        out.push_str(": ");
+        out.push_str(&value);
+        out.push('\n');
+    }
+    out
+}
+
+fn is_credential(name: &header::HeaderName) -> bool {
+    name == header::AUTHORIZATION
+        || name == header::PROXY_AUTHORIZATION
+        || name == header::COOKIE
+        || name == header::SET_COOKIE
+        || name == "x-debug-dump"
+        || name == API_KEY_HEADER
+        || name == peers::SECRET_HEADER
+        || name == shadow::SECRET_HEADER
+}
+
+/// Scrubs everything secret-looking out of `text`: our own configured
+/// secrets verbatim, plus anything shaped like a GitHub token whether or not
+/// we know it.
+pub fn redact(text: &str, config: &Config) -> String {
+    let secrets = config.secrets();
+    let secrets: Vec<&str> = secrets.iter().map(String::as_str).collect();
+    redact_secrets(text, &secrets)
+}
+
+pub fn redact_secrets(text: &str, secrets: &[&str]) -> String {
+    let mut out = text.to_owned();
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        out = out.replace(secret, REDACTED);
+    }
+
+    let mut result = String::with_capacity(out.len());
+    let mut rest = out.as_str();
+    while let Some((start, prefix)) = find_token_prefix(rest) {
+        result.push_str(&rest[..start]);
+        let token_len = rest[start + prefix.len()..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len() - start - prefix.len());
+        if token_len > 0 {
+            result.push_str(REDACTED);
+        } else {
+            result.push_str(prefix);
+        }
+        rest = &rest[start + prefix.len() + token_len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn find_token_prefix(text: &str) -> Option<(usize, &'static str)> {
+    TOKEN_PREFIXES
+        .iter()
+        .filter_map(|prefix| text.find(prefix).map(|i| (i, *prefix)))
+        .min_by_key(|(i, prefix)| (*i, usize::MAX - prefix.len()))
+}
+
+pub fn floor_char_boundary(bytes: &[u8], limit: usize) -> usize {
+    if limit >= bytes.len() {
+        return bytes.len();
+    }
+    // Back off over UTF-8 continuation bytes so the cut never splits a character.
+    let mut end = limit;
+    while end > 0 && (bytes[end] & 0xC0) == 0x80 {
+        end -= 1;
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_secrets_and_token_shapes_are_redacted() {
+        let text = "shadow=s3cret webhook=hook-key token=ghp_abc123 ghp_ plain";
+        let redacted = redact_secrets(text, &["s3cret", "hook-key", ""]);
+        assert_eq!(
+            redacted,
+            "shadow=***REDACTED*** webhook=***REDACTED*** token=***REDACTED*** ghp_ plain"
+        );
+    }
+
+    #[test]
+    fn credential_headers_are_never_shown() {
+        for name in ["authorization", "x-api-key", "x-peer-secret", "x-shadow-secret", "cookie"] {
+            assert!(is_credential(&header::HeaderName::from_static(name)), "{name}");
+        }
+        assert!(!is_credential(&header::ETAG));
+    }
+}
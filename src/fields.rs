@@ -0,0 +1,205 @@
+use axum::{
+    body::{self, Body, Bytes},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use metrics::counter;
+use moka::sync::Cache;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::{content::BodyKind, error_response, freshness, signing};
+
+const PARAM: &str = "__fields=";
+const MAX_FIELDS: usize = 50;
+/// Filtered bodies kept, each until its entry's ETag changes.
+const MAX_PROJECTIONS: u64 = 1000;
+
+/// Only some fields of a JSON answer: `?__fields=name,owner.login` keeps
+/// `name` and the `login` of `owner`, from the document or, for a list,
+/// from each item in it. Fields missing from the document are left out.
+pub struct Fields {
+    tree: Tree,
+    /// The fields sorted, so the same set in any order shares a projection.
+    canonical: String,
+}
+
+/// Requested fields by name, with the ones wanted from within each; `None`
+/// for all of it.
+#[derive(Default)]
+struct Tree(BTreeMap<String, Option<Tree>>);
+
+impl Tree {
+    fn insert(&mut self, path: &str) {
+        let mut node = self;
+        let mut segments = path.split('.').peekable();
+        while let Some(segment) = segments.next() {
+            let child = node
+                .0
+                .entry(segment.to_owned())
+                .or_insert_with(|| Some(Tree::default()));
+            if segments.peek().is_none() {
+                *child = None;
+                return;
+            }
+            match child {
+                Some(tree) => node = tree,
+                // All of it is wanted already.
+                None => return,
+            }
+        }
+    }
+
+    fn pick(&self, value: &Value) -> Value {
+        match value {
+            Value::Object(object) => {
+                let picked: Map<String, Value> = object
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        let wanted = self.0.get(name)?;
+                        let picked = match wanted {
+                            Some(tree) => tree.pick(value),
+                            None => value.clone(),
+                        };
+                        Some((name.clone(), picked))
+                    })
+                    .collect();
+                Value::Object(picked)
+            }
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.pick(item)).collect()),
+            other => other.clone(),
+        }
+    }
+}
+
+/// Takes `__fields` out of a query string: GitHub has no use for it, and
+/// the full answer is cached under the key without it.
+pub fn split_query(
+    query: Option<String>,
+) -> Result<(Option<String>, Option<Fields>), &'static str> {
+    let Some(query) = query else {
+        return Ok((None, None));
+    };
+    if !query.split('&').any(|p| p.starts_with(PARAM)) {
+        return Ok((Some(query), None));
+    }
+    let mut requested = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|p| match p.strip_prefix(PARAM) {
+            Some(value) => {
+                requested = Some(value);
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let requested = requested.unwrap_or_default().replace("%2C", ",").replace("%2c", ",");
+    let mut paths: Vec<&str> = requested.split(',').filter(|p| !p.is_empty()).collect();
+    paths.sort_unstable();
+    paths.dedup();
+    let valid = |path: &&str| {
+        path.len() <= 100
+            && path.split('.').all(|segment| {
+                !segment.is_empty()
+                    && segment.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+            })
+    };
+    if paths.is_empty() || paths.len() > MAX_FIELDS || !paths.iter().all(valid) {
+        return Err("__fields takes up to 50 comma-separated field names, nested with dots");
+    }
+    let mut tree = Tree::default();
+    for path in &paths {
+        tree.insert(path);
+    }
+    let fields = Fields {
+        tree,
+        canonical: paths.join(","),
+    };
+    let rest = (!rest.is_empty()).then(|| rest.join("&"));
+    Ok((rest, Some(fields)))
+}
+
+/// Filtered bodies by cache key and fields, with the ETag of the answer
+/// each was taken from and their own.
+pub struct Projections {
+    entries: Cache<(String, String), (HeaderValue, Bytes, HeaderValue)>,
+}
+
+impl Default for Projections {
+    fn default() -> Self {
+        Self {
+            entries: Cache::new(MAX_PROJECTIONS),
+        }
+    }
+}
+
+impl Projections {
+    /// `response` cut down to `fields`, if it is a successful JSON answer.
+    /// It gets an ETag of its own, which `If-None-Match` in `request_headers`
+    /// is then compared with.
+    pub async fn filter(
+        &self,
+        key: String,
+        fields: &Fields,
+        request_headers: &HeaderMap,
+        response: Response,
+    ) -> Response {
+        // What isn't labelled as JSON is passed on without being read.
+        let content_type = response.headers().get(header::CONTENT_TYPE);
+        if response.status() != StatusCode::OK
+            || BodyKind::classify(content_type, b"{}") != BodyKind::Json
+        {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        let Ok(full) = body::to_bytes(body, usize::MAX).await else {
+            return error_response(StatusCode::BAD_GATEWAY);
+        };
+        let content_type = parts.headers.get(header::CONTENT_TYPE);
+        if BodyKind::classify(content_type, &full) != BodyKind::Json {
+            return Response::from_parts(parts, full.into());
+        }
+        // The whole answer is signed and tagged, not this part of it.
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(signing::SIGNATURE_HEADER);
+        parts.headers.remove(signing::SIGNED_HEADERS_HEADER);
+        let source = parts.headers.remove(header::ETAG);
+        counter!("proxy_field_filtered_total").increment(1);
+
+        let projection = (key, fields.canonical.clone());
+        let kept = source.as_ref().and_then(|source| {
+            let (kept_for, body, etag) = self.entries.get(&projection)?;
+            (kept_for == *source).then_some((body, etag))
+        });
+        let (picked, etag) = match kept {
+            Some(kept) => kept,
+            None => {
+                let Ok(document) = serde_json::from_slice::<Value>(&full) else {
+                    return Response::from_parts(parts, full.into());
+                };
+                let picked = Bytes::from(fields.tree.pick(&document).to_string());
+                let etag = tag(&picked);
+                if let Some(source) = source {
+                    self.entries.insert(projection, (source, picked.clone(), etag.clone()));
+                }
+                (picked, etag)
+            }
+        };
+        let unchanged = freshness::etag_matches(request_headers, Some(&etag));
+        parts.headers.insert(header::ETAG, etag);
+        if unchanged {
+            parts.status = StatusCode::NOT_MODIFIED;
+            return Response::from_parts(parts, Body::empty());
+        }
+        Response::from_parts(parts, picked.into())
+    }
+}
+
+/// A strong ETag for a projected body, from a hash of it.
+fn tag(body: &[u8]) -> HeaderValue {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    HeaderValue::try_from(format!("\"{hex}\"")).expect("hex is a valid header value")
+}
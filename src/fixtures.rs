@@ -0,0 +1,104 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use bytes::Bytes;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    cache::CachedResponse, config::Config, content::BodyKind, freshness, redact,
+    upstream::FetchError, upstream::Fetched,
+};
+
+/// Offline development against canned responses. A fixture is the body of
+/// a 200, stored under a directory laid out like the API:
+/// `repos/owner/repo.json` for `/repos/owner/repo`, and
+/// `repos/owner/repo/issues@state=open.json` for a query, which falls back
+/// to the file without one.
+///
+/// Serving (`FIXTURE_MODE`) answers from these files instead of GitHub, 404
+/// when there is none, and leaves everything else in front of upstream as
+/// it is. Recording (`RECORD_FIXTURES`) writes each real 200 to them.
+pub fn load(dir: &Path, url: &str, config: &Config) -> Result<Fetched, FetchError> {
+    let not_found = FetchError::Failed(StatusCode::NOT_FOUND);
+    let (exact, fallback) = files_for(dir, &config.github.api, url).ok_or(not_found)?;
+    let mut read = fs::read(&exact);
+    if let (Err(err), Some(fallback)) = (&read, &fallback) {
+        if err.kind() == io::ErrorKind::NotFound {
+            read = fs::read(fallback);
+        }
+    }
+    let body = match read {
+        Ok(body) => Bytes::from(body),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            debug!(url, "no fixture at {}", exact.display());
+            return Err(FetchError::Failed(StatusCode::NOT_FOUND));
+        }
+        Err(err) => {
+            warn!(url, "cannot read fixture {}: {err}", exact.display());
+            return Err(FetchError::Failed(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let ttl = freshness::ttl(&HeaderMap::new(), &config.cache).unwrap_or_default();
+    Ok(Fetched::Fresh(Arc::new(CachedResponse {
+        status: StatusCode::OK,
+        body: redact::apply(&config.redact_fields, body),
+        headers,
+        stored_at: Instant::now(),
+        ttl,
+        purged: false,
+        immutable: false,
+        kind: BodyKind::Json,
+    })))
+}
+
+/// Saves `body` as the fixture for `url`, in the background.
+pub fn record(dir: &Path, api: &str, url: &str, body: Bytes) {
+    let Some((file, _)) = files_for(dir, api, url) else {
+        return;
+    };
+    let url = url.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let written = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&file, &body));
+        match written {
+            Ok(()) => debug!(url, "recorded fixture {}", file.display()),
+            Err(err) => warn!(url, "cannot record fixture {}: {err}", file.display()),
+        }
+    });
+}
+
+/// The fixture file for `url`, and for a query, the one without it. `None`
+/// for anything outside the API or that would step out of `dir`.
+fn files_for(dir: &Path, api: &str, url: &str) -> Option<(PathBuf, Option<PathBuf>)> {
+    let path = url.strip_prefix(api)?;
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    let mut file = dir.to_path_buf();
+    for segment in path.split('/') {
+        if matches!(segment, "" | "." | "..") || segment.contains('\\') {
+            return None;
+        }
+        file.push(segment);
+    }
+    // Not `with_extension`: repository names can have dots of their own.
+    let name = file.file_name()?.to_str()?.to_owned();
+    let bare = file.with_file_name(format!("{name}.json"));
+    match query {
+        Some(query) => {
+            let query = query.replace('/', "%2F");
+            Some((file.with_file_name(format!("{name}@{query}.json")), Some(bare)))
+        }
+        None => Some((bare, None)),
+    }
+}
@@ -0,0 +1,421 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    cache::CachedResponse,
+    config::{CacheConfig, ClientCacheConfig},
+};
+
+/// How long SHA-pinned responses are kept: for as long as there is room.
+pub const IMMUTABLE_TTL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// The directives of an upstream `Cache-Control` header we act on.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Directives {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+}
+
+/// Parses `Cache-Control`, e.g. GitHub's usual `private, max-age=60, s-maxage=60`.
+///
+/// Unknown directives are ignored and malformed numbers are treated as absent,
+/// so a garbled header degrades to the default TTL rather than to an error.
+pub fn parse(value: &str) -> Directives {
+    let mut directives = Directives::default();
+    for part in value.split(',') {
+        let (name, arg) = match part.split_once('=') {
+            Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+            None => (part.trim(), None),
+        };
+        let seconds = || arg.and_then(|a| a.parse::<u64>().ok());
+        if name.eq_ignore_ascii_case("no-store") {
+            directives.no_store = true;
+        } else if name.eq_ignore_ascii_case("no-cache") {
+            directives.no_cache = true;
+        } else if name.eq_ignore_ascii_case("max-age") {
+            directives.max_age = seconds();
+        } else if name.eq_ignore_ascii_case("s-maxage") {
+            directives.s_maxage = seconds();
+        }
+    }
+    directives
+}
+
+/// How long to keep an upstream response, or `None` if it must not be cached.
+///
+/// We are a shared cache, so `s-maxage` wins over `max-age`, and either over
+/// `Expires`; whichever is used is clamped to the configured bounds.
+/// `private` is deliberately ignored: there is only ever one "user"
+/// upstream, us.
+pub fn ttl(headers: &HeaderMap, config: &CacheConfig) -> Option<Duration> {
+    let directives = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .map(parse)
+        .unwrap_or_default();
+    if directives.no_store || directives.no_cache {
+        return None;
+    }
+
+    let lifetime = match directives.s_maxage.or(directives.max_age) {
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => expires_in(headers),
+    };
+    Some(lifetime.map_or(config.ttl, |ttl| ttl.clamp(config.min_ttl, config.max_ttl)))
+}
+
+/// What is left until `Expires`, counted from the response's own `Date` so
+/// our clock and GitHub's needn't agree. An `Expires` that can't be read is
+/// already past, as RFC 9111 has it.
+fn expires_in(headers: &HeaderMap) -> Option<Duration> {
+    let date = |name| {
+        let value = headers.get(name)?.to_str().ok()?;
+        httpdate::parse_http_date(value).ok()
+    };
+    headers.get(header::EXPIRES)?;
+    let Some(expires) = date(header::EXPIRES) else {
+        return Some(Duration::ZERO);
+    };
+    let now = date(header::DATE).unwrap_or_else(SystemTime::now);
+    Some(expires.duration_since(now).unwrap_or_default())
+}
+
+/// The TTL `CACHE_TTL_RULES` or its file sets for `path`, from the first
+/// rule matching it. It replaces whatever GitHub said, bounds included,
+/// though not a `no-store`.
+pub fn rule(path: &str, config: &CacheConfig) -> Option<Duration> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    config
+        .ttl_rules
+        .iter()
+        .find(|rule| rule.pattern.matches(path))
+        .map(|rule| rule.ttl)
+}
+
+const MAX_AGE_PARAM: &str = "max_age=";
+
+/// Takes `max_age=N` out of a query string: the oldest cached answer, in
+/// seconds, this request will take, clamped to `MAX_AGE_PARAM_MIN_SECS` and
+/// `MAX_AGE_PARAM_MAX_SECS`. Like `diff_from`, it means nothing to GitHub
+/// and mustn't split the cache key; values that aren't numbers are dropped.
+pub fn split_max_age(
+    query: Option<String>,
+    config: &CacheConfig,
+) -> (Option<String>, Option<Duration>) {
+    let Some(query) = query else {
+        return (None, None);
+    };
+    if !query.split('&').any(|p| p.starts_with(MAX_AGE_PARAM)) {
+        return (Some(query), None);
+    }
+    let mut max_age = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|p| match p.strip_prefix(MAX_AGE_PARAM) {
+            Some(value) => {
+                max_age = value.parse().ok().map(|secs| {
+                    Duration::from_secs(secs)
+                        .clamp(config.max_age_param_min, config.max_age_param_max)
+                });
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let rest = (!rest.is_empty()).then(|| rest.join("&"));
+    (rest, max_age)
+}
+
+/// Whether the request's `If-None-Match` names `etag` (or is `*`), compared
+/// weakly as RFC 9110 asks for `If-None-Match`.
+pub fn etag_matches(request_headers: &HeaderMap, etag: Option<&HeaderValue>) -> bool {
+    let Some(etag) = etag.and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let etag = weak(etag);
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| tag.trim() == "*" || weak(tag) == etag)
+}
+
+/// Whether the client asked us to revalidate rather than serve from cache:
+/// `Cache-Control: no-cache` or `max-age=0`, or the legacy `Pragma: no-cache`.
+pub fn wants_revalidation(request_headers: &HeaderMap) -> bool {
+    let cache_control = request_headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .map(parse)
+        .any(|d| d.no_cache || d.max_age == Some(0));
+
+    cache_control
+        || request_headers
+            .get_all(header::PRAGMA)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.split(',').any(|p| p.trim().eq_ignore_ascii_case("no-cache")))
+}
+
+/// Sets the caching headers for serving `entry` at `age`: browsers get
+/// `max-age`, shared caches `s-maxage` (and any CDN-specific copies of it),
+/// neither outlasting what is left of the entry's freshness.
+///
+/// SHA-pinned entries are instead marked `immutable` for a year.
+///
+/// Remaining freshness is counted in the same whole seconds as the `Age`
+/// header, so a fresh miss advertises the full TTL and an entry stored 7s
+/// ago with a 10s TTL advertises `max-age=3`.
+pub fn client_headers(
+    entry: &CachedResponse,
+    age: Duration,
+    config: &ClientCacheConfig,
+    response_headers: &mut HeaderMap,
+) {
+    if entry.immutable && !entry.purged {
+        response_headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=31536000, immutable"),
+        );
+        return;
+    }
+    let remaining = if entry.purged {
+        0
+    } else {
+        entry.ttl.as_secs().saturating_sub(age.as_secs())
+    };
+    let max_age = config.max_age.map_or(remaining, |m| m.as_secs().min(remaining));
+
+    let mut cache_control = format!("public, max-age={max_age}");
+    let cdn_max_age = config.cdn_max_age.map(|m| m.as_secs().min(remaining));
+    if let Some(cdn_max_age) = cdn_max_age {
+        let _ = write!(cache_control, ", s-maxage={cdn_max_age}");
+    }
+    if let Some(swr) = config.stale_while_revalidate {
+        let _ = write!(cache_control, ", stale-while-revalidate={}", swr.as_secs());
+    }
+    let cache_control = HeaderValue::try_from(cache_control)
+        .expect("formatted cache-control is a valid header value");
+    response_headers.insert(header::CACHE_CONTROL, cache_control);
+
+    if let Some(cdn_max_age) = cdn_max_age {
+        let value = HeaderValue::try_from(format!("max-age={cdn_max_age}"))
+            .expect("formatted max-age is a valid header value");
+        for name in &config.cdn_headers {
+            response_headers.insert(name, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::TtlRule, content::BodyKind};
+    use axum::http::StatusCode;
+    use bytes::Bytes;
+    use std::time::Instant;
+
+    fn config() -> CacheConfig {
+        CacheConfig {
+            ttl: Duration::from_secs(10),
+            min_ttl: Duration::from_secs(5),
+            max_ttl: Duration::from_secs(300),
+            stale: Duration::from_secs(60),
+            stale_while_revalidate: None,
+            tti: None,
+            max_entries: 100,
+            max_bytes: None,
+            no_cache_paths: Vec::new(),
+            stream_paths: Vec::new(),
+            stream_threshold: u64::MAX,
+            diff_paths: Vec::new(),
+            ttl_rules: Vec::new(),
+            stats_ttl: Duration::from_secs(3600),
+            error_ttl: Duration::from_secs(30),
+            max_age_param_min: Duration::from_secs(10),
+            max_age_param_max: Duration::from_secs(3600),
+            disk: None,
+            redis: None,
+        }
+    }
+
+    fn upstream(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn secs(secs: u64) -> Option<Duration> {
+        Some(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn githubs_cache_control_is_parsed() {
+        let directives = parse("private, max-age=60, s-maxage=\"30\", foo=bar, max-agex=1");
+        let expected = Directives {
+            max_age: Some(60),
+            s_maxage: Some(30),
+            ..Directives::default()
+        };
+        assert_eq!(directives, expected);
+        assert_eq!(parse("max-age=soon").max_age, None);
+        assert!(parse("No-Store").no_store);
+    }
+
+    #[test]
+    fn s_maxage_wins_over_max_age_and_either_over_expires() {
+        let config = config();
+        let both = upstream(&[(header::CACHE_CONTROL, "max-age=60, s-maxage=120")]);
+        assert_eq!(ttl(&both, &config), secs(120));
+        let with_expires = upstream(&[
+            (header::CACHE_CONTROL, "max-age=60"),
+            (header::DATE, "Thu, 01 Oct 2026 00:00:00 GMT"),
+            (header::EXPIRES, "Thu, 01 Oct 2026 00:02:00 GMT"),
+        ]);
+        assert_eq!(ttl(&with_expires, &config), secs(60));
+    }
+
+    #[test]
+    fn expires_counts_from_the_responses_own_date() {
+        let headers = upstream(&[
+            (header::DATE, "Thu, 01 Oct 2026 00:00:00 GMT"),
+            (header::EXPIRES, "Thu, 01 Oct 2026 00:02:00 GMT"),
+        ]);
+        assert_eq!(ttl(&headers, &config()), secs(120));
+    }
+
+    #[test]
+    fn a_bad_or_past_expires_is_already_stale() {
+        let config = config();
+        let garbled = upstream(&[(header::EXPIRES, "0")]);
+        assert_eq!(ttl(&garbled, &config), Some(config.min_ttl));
+        let past = upstream(&[
+            (header::DATE, "Thu, 01 Oct 2026 00:02:00 GMT"),
+            (header::EXPIRES, "Thu, 01 Oct 2026 00:00:00 GMT"),
+        ]);
+        assert_eq!(ttl(&past, &config), Some(config.min_ttl));
+    }
+
+    #[test]
+    fn no_store_and_no_cache_are_never_kept_but_private_is() {
+        let config = config();
+        for value in ["no-store", "no-cache", "private, no-store, max-age=60"] {
+            let headers = upstream(&[(header::CACHE_CONTROL, value)]);
+            assert_eq!(ttl(&headers, &config), None, "{value}");
+        }
+        let private = upstream(&[(header::CACHE_CONTROL, "private, max-age=60")]);
+        assert_eq!(ttl(&private, &config), secs(60));
+    }
+
+    #[test]
+    fn upstream_lifetimes_are_clamped_and_the_default_is_not() {
+        let config = config();
+        let short = upstream(&[(header::CACHE_CONTROL, "max-age=1")]);
+        assert_eq!(ttl(&short, &config), secs(5));
+        let long = upstream(&[(header::CACHE_CONTROL, "max-age=86400")]);
+        assert_eq!(ttl(&long, &config), secs(300));
+        let at_the_bounds = upstream(&[(header::CACHE_CONTROL, "max-age=300")]);
+        assert_eq!(ttl(&at_the_bounds, &config), secs(300));
+        assert_eq!(ttl(&HeaderMap::new(), &config), secs(10));
+    }
+
+    #[test]
+    fn the_first_matching_rule_sets_the_ttl() {
+        let ttl_rule = |pattern: &str, ttl| TtlRule {
+            pattern: pattern.parse().unwrap(),
+            ttl: Duration::from_secs(ttl),
+        };
+        let config = CacheConfig {
+            ttl_rules: vec![ttl_rule("*/*/releases", 300), ttl_rule("*/*", 1)],
+            ..config()
+        };
+        assert_eq!(rule("/o/r/releases?page=2", &config), secs(300));
+        assert_eq!(rule("o/r", &config), secs(1));
+        assert_eq!(rule("search", &config), None);
+    }
+
+    #[test]
+    fn max_age_is_taken_out_of_the_query() {
+        let config = config();
+        let query = Some("page=2&max_age=30&per_page=5".to_owned());
+        assert_eq!(
+            split_max_age(query, &config),
+            (Some("page=2&per_page=5".to_owned()), secs(30))
+        );
+        let only = Some("max_age=soon".to_owned());
+        assert_eq!(split_max_age(only, &config), (None, None));
+        let none = Some("page=2".to_owned());
+        assert_eq!(split_max_age(none, &config), (Some("page=2".to_owned()), None));
+    }
+
+    #[test]
+    fn max_age_is_clamped_at_both_ends() {
+        let config = config();
+        let clamped = |value: &str| split_max_age(Some(format!("max_age={value}")), &config).1;
+        assert_eq!(clamped("0"), secs(10));
+        assert_eq!(clamped("9"), secs(10));
+        assert_eq!(clamped("10"), secs(10));
+        assert_eq!(clamped("11"), secs(11));
+        assert_eq!(clamped("3599"), secs(3599));
+        assert_eq!(clamped("3600"), secs(3600));
+        assert_eq!(clamped("3601"), secs(3600));
+        assert_eq!(clamped(&u64::MAX.to_string()), secs(3600));
+        assert_eq!(clamped("-1"), None);
+    }
+
+    fn entry(ttl: Duration) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            body: Bytes::new(),
+            headers: HeaderMap::new(),
+            stored_at: Instant::now(),
+            ttl,
+            purged: false,
+            immutable: false,
+            kind: BodyKind::Json,
+        }
+    }
+
+    fn cache_control(entry: &CachedResponse, age: Duration) -> String {
+        let config = ClientCacheConfig {
+            max_age: None,
+            cdn_max_age: None,
+            cdn_headers: Vec::new(),
+            stale_while_revalidate: None,
+        };
+        let mut headers = HeaderMap::new();
+        client_headers(entry, age, &config, &mut headers);
+        headers[header::CACHE_CONTROL].to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn a_miss_advertises_the_full_ttl() {
+        let entry = entry(Duration::from_secs(10));
+        assert_eq!(cache_control(&entry, Duration::ZERO), "public, max-age=10");
+    }
+
+    #[test]
+    fn a_hit_advertises_what_is_left_in_whole_seconds() {
+        let entry = entry(Duration::from_secs(10));
+        let age = Duration::from_millis(7_900);
+        assert_eq!(cache_control(&entry, age), "public, max-age=3");
+    }
+
+    #[test]
+    fn a_purged_entry_advertises_nothing() {
+        let entry = CachedResponse {
+            purged: true,
+            ..entry(Duration::from_secs(10))
+        };
+        assert_eq!(cache_control(&entry, Duration::ZERO), "public, max-age=0");
+    }
+}
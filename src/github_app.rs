@@ -0,0 +1,152 @@
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use metrics::counter;
+use ring::{
+    rand::SystemRandom,
+    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use serde_json::json;
+use std::{fs, path::PathBuf, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    config::{unix_now, var, Config},
+    tokens::GithubToken,
+    upstream::Upstream,
+    AppState,
+};
+
+/// Installation tokens expire an hour after they are issued; a new one is
+/// asked for well before that.
+const REFRESH_AFTER: Duration = Duration::from_secs(50 * 60);
+/// How soon a failed refresh is tried again.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Authentication as a GitHub App installation instead of a personal token:
+/// `GITHUB_APP_ID`, `GITHUB_APP_INSTALLATION_ID` and `GITHUB_APP_PRIVATE_KEY`,
+/// the path to the PEM key GitHub generated for the App. Requests go out
+/// with an installation token, exchanged for a JWT signed with the key at
+/// startup and again before each one expires.
+pub struct GithubApp {
+    pub app_id: u64,
+    pub installation_id: u64,
+    key: RsaKeyPair,
+}
+
+impl GithubApp {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(app_id) = var("GITHUB_APP_ID") else {
+            return Ok(None);
+        };
+        let app_id = app_id
+            .parse()
+            .map_err(|_| format!("GITHUB_APP_ID: {app_id:?} is not an App ID"))?;
+        let installation_id = var("GITHUB_APP_INSTALLATION_ID")
+            .ok_or("GITHUB_APP_ID requires GITHUB_APP_INSTALLATION_ID")?;
+        let installation_id = installation_id.parse().map_err(|_| {
+            format!("GITHUB_APP_INSTALLATION_ID: {installation_id:?} is not an installation ID")
+        })?;
+        let file = var("GITHUB_APP_PRIVATE_KEY")
+            .map(PathBuf::from)
+            .ok_or("GITHUB_APP_ID requires GITHUB_APP_PRIVATE_KEY")?;
+        let pem = fs::read_to_string(&file).map_err(|e| {
+            format!("GITHUB_APP_PRIVATE_KEY: cannot read {}: {e}", file.display())
+        })?;
+        let invalid = || {
+            format!(
+                "GITHUB_APP_PRIVATE_KEY: {} is not an RSA private key in PEM",
+                file.display()
+            )
+        };
+        let encoded: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = STANDARD.decode(encoded.trim()).map_err(|_| invalid())?;
+        // GitHub hands out PKCS#1; a key converted with openssl may be PKCS#8.
+        let key = if pem.contains("BEGIN RSA PRIVATE KEY") {
+            RsaKeyPair::from_der(&der)
+        } else {
+            RsaKeyPair::from_pkcs8(&der)
+        }
+        .map_err(|_| invalid())?;
+        Ok(Some(Self {
+            app_id,
+            installation_id,
+            key,
+        }))
+    }
+
+    /// A JWT that authenticates as the App itself, good for nine minutes.
+    pub fn jwt(&self) -> Result<String, String> {
+        let now = unix_now();
+        // Backdated a minute for clocks running ahead of GitHub's.
+        let claims = json!({
+            "iat": now - 60,
+            "exp": now + 9 * 60,
+            "iss": self.app_id.to_string(),
+        });
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256","typ":"JWT"}"#);
+        let message = format!("{header}.{}", URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let mut signature = vec![0; self.key.public().modulus_len()];
+        self.key
+            .sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), message.as_bytes(), &mut signature)
+            .map_err(|_| "cannot sign the GitHub App JWT".to_owned())?;
+        Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+    }
+}
+
+/// Fetches the first installation token, before anything is sent with it.
+pub async fn authenticate(upstream: &Upstream, config: &Config) -> Result<(), String> {
+    let token = &config.tokens.default;
+    let Some(app) = &token.app else {
+        return Ok(());
+    };
+    refresh(upstream, config, token, app).await?;
+    info!(
+        "Authenticated as GitHub App {} (installation {})",
+        app.app_id, app.installation_id
+    );
+    Ok(())
+}
+
+/// Replaces the installation token before each one expires, for as long as
+/// the proxy runs.
+pub async fn keep_fresh(state: AppState, shutdown: CancellationToken) {
+    let token = &state.config.tokens.default;
+    let Some(app) = &token.app else {
+        return;
+    };
+    let mut wait = REFRESH_AFTER;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(wait) => {}
+        }
+        wait = match refresh(&state.upstream, &state.config, token, app).await {
+            Ok(()) => REFRESH_AFTER,
+            Err(err) => {
+                warn!("could not refresh the GitHub App installation token: {err}");
+                RETRY_DELAY
+            }
+        };
+    }
+}
+
+async fn refresh(
+    upstream: &Upstream,
+    config: &Config,
+    token: &GithubToken,
+    app: &GithubApp,
+) -> Result<(), String> {
+    let result = upstream
+        .installation_token(config, app)
+        .await
+        .and_then(|secret| token.set_secret(&secret));
+    let outcome = if result.is_ok() { "refreshed" } else { "failed" };
+    counter!("proxy_github_app_token_refreshes_total", "outcome" => outcome).increment(1);
+    result
+}
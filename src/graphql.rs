@@ -0,0 +1,500 @@
+use axum::{
+    body::Bytes,
+    extract::{rejection::BytesRejection, DefaultBodyLimit, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+    routing::post,
+    Extension, Router,
+};
+use metrics::counter;
+use moka::future::Cache;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{sync::Arc, time::Instant};
+
+use crate::{
+    cache::{CacheStatus, CachedResponse},
+    config::{Config, GraphqlConfig},
+    fetch_failed, json_error, preflight, refused_repo, respond, service_unavailable, timed,
+    tokens::GithubToken,
+    upstream::{FetchError, Fetched},
+    AppState,
+};
+
+pub const PATH: &str = "/graphql";
+/// Room in the body for variables, on top of the query itself.
+const MAX_VARIABLES_BYTES: usize = 64 * 1024;
+
+/// GitHub's GraphQL API, for pages that would rather ask one query than walk
+/// a dozen REST endpoints. `POST /graphql` takes the same
+/// `{"query", "variables", "operationName"}` body as GitHub and answers with
+/// GitHub's response, sent with our token.
+///
+/// Only queries pass: a mutation or subscription is refused before it gets
+/// anywhere near the token. While `DENIED_REPOS` is set, so are queries for
+/// anything but `repository(owner:, name:)` with both given, checked like
+/// REST paths; `ALLOWED_REPOS` can't be combined with it at all, since any
+/// repository links to others. Results are kept for `GRAPHQL_TTL_SECS` under a
+/// hash of the query with its comments and insignificant whitespace removed,
+/// plus the variables and operation name, so the same query formatted
+/// differently shares an entry, and concurrent misses share one fetch.
+pub fn router(config: &GraphqlConfig) -> Router<AppState> {
+    if !config.enabled {
+        return Router::new();
+    }
+    Router::new()
+        .route(PATH, post(query).options(graphql_preflight))
+        .layer(DefaultBodyLimit::max(config.max_query_bytes + MAX_VARIABLES_BYTES))
+}
+
+/// GraphQL results by request hash, apart from the REST cache: they have no
+/// path to be purged or refreshed by.
+pub struct Results {
+    entries: Cache<[u8; 32], Arc<CachedResponse>>,
+}
+
+impl Results {
+    pub fn new(config: &GraphqlConfig) -> Self {
+        Self {
+            entries: Cache::builder()
+                .max_capacity(config.max_entries)
+                .time_to_live(config.ttl)
+                .build(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRequest {
+    query: String,
+    #[serde(default)]
+    variables: Option<Value>,
+    #[serde(default)]
+    operation_name: Option<String>,
+}
+
+/// How a shared miss ended when it left nothing to cache.
+enum Unstored {
+    Uncacheable(Arc<CachedResponse>),
+    Failed(FetchError),
+}
+
+async fn query(
+    headers: HeaderMap,
+    token: Option<Extension<Arc<GithubToken>>>,
+    State(state): State<AppState>,
+    body: Result<Bytes, BytesRejection>,
+) -> Response {
+    let started = Instant::now();
+    let config = &state.config;
+    let body = match body {
+        Ok(body) => body,
+        Err(rejection) => return json_error(rejection.status(), &rejection.body_text()),
+    };
+    let Ok(request) = serde_json::from_slice::<GraphqlRequest>(&body) else {
+        return json_error(StatusCode::BAD_REQUEST, r#"expected {"query": "..."}"#);
+    };
+    let max = config.graphql.max_query_bytes;
+    if request.query.len() > max {
+        let message = format!("queries are limited to {max} bytes");
+        return json_error(StatusCode::PAYLOAD_TOO_LARGE, &message);
+    }
+    let normalized = match normalize(&request.query) {
+        Ok(normalized) => normalized,
+        Err(reason) => {
+            counter!("proxy_graphql_refused_total").increment(1);
+            return json_error(StatusCode::BAD_REQUEST, reason);
+        }
+    };
+    if let Some(refused) = refused_repos(config, &normalized, request.variables.as_ref()) {
+        counter!("proxy_graphql_refused_total").increment(1);
+        return refused;
+    }
+    let key = cache_key(&normalized, &request);
+
+    let token = token.map_or_else(|| config.tokens.default.clone(), |Extension(t)| t);
+    if token.client {
+        return own_token_query(&state, &token, body, &headers, started).await;
+    }
+    if let Some(entry) = state.graphql.entries.get(&key).await {
+        return timed(respond(&entry, CacheStatus::Hit, &headers, config), None, started);
+    }
+    if let Some(reset_in) = state.upstream.quota.exhausted_for(&token, "graphql") {
+        counter!("proxy_quota_exhausted_total", "resource" => "graphql").increment(1);
+        let response = service_unavailable("GitHub's graphql rate limit is exhausted", reset_in);
+        return timed(response, None, started);
+    }
+
+    let upstream_started = Instant::now();
+    let fetched = state
+        .graphql
+        .entries
+        .try_get_with(key, async {
+            match state.upstream.graphql(config, &token, body.to_vec()).await {
+                Ok(Fetched::Fresh(entry)) => Ok(entry),
+                Ok(Fetched::Uncacheable(entry) | Fetched::Partial(entry)) => {
+                    Err(Unstored::Uncacheable(entry))
+                }
+                Ok(Fetched::NotModified { .. } | Fetched::Streamed(_)) => {
+                    Err(Unstored::Failed(FetchError::Failed(StatusCode::BAD_GATEWAY)))
+                }
+                Err(err) => Err(Unstored::Failed(err)),
+            }
+        })
+        .await;
+    let upstream_time = Some(upstream_started.elapsed());
+    let response = match fetched {
+        Ok(entry) => respond(&entry, CacheStatus::Miss, &headers, config),
+        Err(unstored) => match &*unstored {
+            Unstored::Uncacheable(entry) => respond(entry, CacheStatus::Pass, &headers, config),
+            Unstored::Failed(err) => fetch_failed(err.clone(), None, &headers, config),
+        },
+    };
+    timed(response, upstream_time, started)
+}
+
+/// A query sent with the client's own token, whose answer may be private
+/// and so is neither cached nor shared.
+async fn own_token_query(
+    state: &AppState,
+    token: &GithubToken,
+    body: Bytes,
+    headers: &HeaderMap,
+    started: Instant,
+) -> Response {
+    let config = &state.config;
+    let upstream_started = Instant::now();
+    let response = match state.upstream.graphql(config, token, body.to_vec()).await {
+        Ok(Fetched::Fresh(entry) | Fetched::Uncacheable(entry) | Fetched::Partial(entry)) => {
+            respond(&entry, CacheStatus::Bypass, headers, config)
+        }
+        Ok(Fetched::NotModified { .. } | Fetched::Streamed(_)) => {
+            fetch_failed(FetchError::Failed(StatusCode::BAD_GATEWAY), None, headers, config)
+        }
+        Err(err) => fetch_failed(err, None, headers, config),
+    };
+    timed(response, Some(upstream_started.elapsed()), started)
+}
+
+/// The usual preflight, for `POST` with a JSON body.
+async fn graphql_preflight(state: State<AppState>, headers: HeaderMap) -> Response {
+    let mut response = preflight(state, headers).await;
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("POST, OPTIONS"),
+    );
+    response
+}
+
+/// `query` without comments, commas or any whitespace that doesn't separate
+/// two names; string literals are kept as they are. Refuses documents that
+/// define anything but queries and fragments.
+fn normalize(query: &str) -> Result<String, &'static str> {
+    let mut out = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    let mut depth = 0usize;
+    // At the top level, between definitions: the next name says what it is.
+    let mut definition_start = true;
+    let mut word = String::new();
+    let mut separated = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                end_word(&mut word, depth, &mut definition_start)?;
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                separated = true;
+            }
+            '"' => {
+                end_word(&mut word, depth, &mut definition_start)?;
+                out.push('"');
+                let block = chars.peek() == Some(&'"') && {
+                    let mut ahead = chars.clone();
+                    ahead.next();
+                    ahead.peek() == Some(&'"')
+                };
+                if block {
+                    chars.next();
+                    chars.next();
+                    out.push_str("\"\"");
+                }
+                let mut quotes = 0;
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    if c == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            out.push(escaped);
+                        }
+                        quotes = 0;
+                        continue;
+                    }
+                    quotes = if c == '"' { quotes + 1 } else { 0 };
+                    if quotes == if block { 3 } else { 1 } {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err("unterminated string in query");
+                }
+                separated = false;
+            }
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {
+                end_word(&mut word, depth, &mut definition_start)?;
+                separated = true;
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let previous = out.chars().next_back();
+                if separated && previous.is_some_and(|p| p.is_ascii_alphanumeric() || p == '_') {
+                    out.push(' ');
+                }
+                word.push(c);
+                out.push(c);
+                separated = false;
+            }
+            _ => {
+                end_word(&mut word, depth, &mut definition_start)?;
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth = depth.checked_sub(1).ok_or("unbalanced braces in query")?;
+                        if depth == 0 {
+                            definition_start = true;
+                        }
+                    }
+                    _ => {}
+                }
+                out.push(c);
+                separated = false;
+            }
+        }
+    }
+    end_word(&mut word, depth, &mut definition_start)?;
+    if depth != 0 {
+        return Err("unbalanced braces in query");
+    }
+    if out.is_empty() {
+        return Err("empty query");
+    }
+    Ok(out)
+}
+
+/// Done with a name: the first at the top level decides the definition.
+fn end_word(word: &mut String, depth: usize, start: &mut bool) -> Result<(), &'static str> {
+    if word.is_empty() {
+        return Ok(());
+    }
+    if depth == 0 && *start {
+        if matches!(word.as_str(), "mutation" | "subscription") {
+            return Err("only queries can be sent through this proxy");
+        }
+        *start = false;
+    }
+    word.clear();
+    Ok(())
+}
+
+/// Top-level fields that name no repository.
+const UNSCOPED_FIELDS: &[&str] = &["rateLimit", "__typename"];
+
+/// A 403 for a query asking for a repository that isn't served here, or
+/// that can't be checked while a repository list is set.
+fn refused_repos(
+    config: &Config,
+    normalized: &str,
+    variables: Option<&Value>,
+) -> Option<Response> {
+    let repos = &config.repos;
+    if repos.allowed.patterns().is_empty() && repos.denied.patterns().is_empty() {
+        return None;
+    }
+    match repositories(normalized, variables) {
+        Ok(named) => named.iter().find_map(|path| refused_repo(config, path)),
+        Err(reason) => Some(json_error(StatusCode::FORBIDDEN, reason)),
+    }
+}
+
+enum Token<'a> {
+    Name(&'a str),
+    Variable(&'a str),
+    Str(&'a str),
+    Punct(char),
+}
+
+fn tokens(normalized: &str) -> Vec<Token<'_>> {
+    let bytes = normalized.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let name_end = |from: usize| {
+        (from..bytes.len())
+            .find(|&j| !(bytes[j].is_ascii_alphanumeric() || bytes[j] == b'_'))
+            .unwrap_or(bytes.len())
+    };
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' => i += 1,
+            b'"' => {
+                // `normalize` checked every string is closed.
+                let mut j = i + 1;
+                while j < bytes.len() && bytes[j] != b'"' {
+                    j += if bytes[j] == b'\\' { 2 } else { 1 };
+                }
+                let end = (j + 1).min(bytes.len());
+                tokens.push(Token::Str(&normalized[i..end]));
+                i = end;
+            }
+            b'$' => {
+                let end = name_end(i + 1);
+                tokens.push(Token::Variable(&normalized[i + 1..end]));
+                i = end;
+            }
+            b if b.is_ascii_alphanumeric() || b == b'_' => {
+                let end = name_end(i);
+                tokens.push(Token::Name(&normalized[i..end]));
+                i = end;
+            }
+            _ => {
+                let c = normalized[i..].chars().next().unwrap_or_default();
+                tokens.push(Token::Punct(c));
+                i += c.len_utf8();
+            }
+        }
+    }
+    tokens
+}
+
+/// Every `owner/name` a `repository` field in the query asks for, or why
+/// the query can't be checked: a top-level field other than `repository`
+/// or `UNSCOPED_FIELDS`, a fragment spread at the top level, or a
+/// `repository` whose owner and name aren't both plain strings.
+fn repositories(
+    normalized: &str,
+    variables: Option<&Value>,
+) -> Result<Vec<String>, &'static str> {
+    let tokens = tokens(normalized);
+    let mut named = Vec::new();
+    let (mut braces, mut parens) = (0usize, 0usize);
+    // Whether the definition being read is a fragment, known from its first
+    // name; a query can leave out even that.
+    let (mut fragment, mut definition_start) = (false, true);
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Punct('{') => {
+                if braces == 0 && definition_start {
+                    fragment = false;
+                }
+                definition_start = false;
+                braces += 1;
+            }
+            Token::Punct('}') => {
+                braces = braces.saturating_sub(1);
+                definition_start = braces == 0;
+            }
+            Token::Punct('(') => parens += 1,
+            Token::Punct(')') => parens = parens.saturating_sub(1),
+            Token::Punct('.') if braces == 1 && !fragment => {
+                return Err("fragments can't be spread at the top level here");
+            }
+            Token::Name(name) if braces == 0 && parens == 0 && definition_start => {
+                fragment = name == "fragment";
+                definition_start = false;
+            }
+            Token::Name(name) if braces > 0 && parens == 0 => {
+                let after = tokens.get(i + 1);
+                let alias = matches!(after, Some(Token::Punct(':')));
+                let directive = i > 0 && matches!(tokens[i - 1], Token::Punct('@'));
+                let spread = i > 0 && matches!(tokens[i - 1], Token::Punct('.'));
+                let condition = i > 0 && matches!(tokens[i - 1], Token::Name("on"));
+                if alias || directive || spread || condition || name == "on" {
+                    i += 1;
+                    continue;
+                }
+                if name == "repository" {
+                    named.push(repository_argument(&tokens[i + 1..], variables)?);
+                } else if braces == 1 && !fragment && !UNSCOPED_FIELDS.contains(&name) {
+                    return Err("only repository(owner:, name:) can be queried here");
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(named)
+}
+
+/// `owner/name` from the arguments following a `repository` field.
+fn repository_argument(
+    tokens: &[Token],
+    variables: Option<&Value>,
+) -> Result<String, &'static str> {
+    const UNCHECKABLE: &str = "repository() needs its owner and name as plain strings here";
+    if !matches!(tokens.first(), Some(Token::Punct('('))) {
+        return Err(UNCHECKABLE);
+    }
+    let (mut owner, mut name) = (None, None);
+    let mut rest = &tokens[1..];
+    while let [Token::Name(argument), Token::Punct(':'), value, tail @ ..] = rest {
+        let value = match value {
+            Token::Str(literal) if !literal.starts_with("\"\"\"") => {
+                serde_json::from_str::<String>(literal).ok()
+            }
+            Token::Variable(variable) => variables
+                .and_then(|v| v.get(variable))
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            _ => None,
+        };
+        match *argument {
+            "owner" => owner = Some(value.ok_or(UNCHECKABLE)?),
+            "name" => name = Some(value.ok_or(UNCHECKABLE)?),
+            _ => {}
+        }
+        rest = tail;
+    }
+    match (owner, name) {
+        (Some(owner), Some(name)) if !owner.contains('/') && !name.contains('/') => {
+            Ok(format!("{owner}/{name}"))
+        }
+        _ => Err(UNCHECKABLE),
+    }
+}
+
+fn cache_key(normalized: &str, request: &GraphqlRequest) -> [u8; 32] {
+    // No variables at all, `null` and `{}` all mean the same.
+    let variables = match &request.variables {
+        Some(Value::Object(object)) if object.is_empty() => Value::Null,
+        Some(variables) => sorted(variables),
+        None => Value::Null,
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(normalized.as_bytes());
+    hasher.update([0]);
+    hasher.update(variables.to_string().as_bytes());
+    hasher.update([0]);
+    hasher.update(request.operation_name.as_deref().unwrap_or_default().as_bytes());
+    hasher.finalize().into()
+}
+
+/// `value` with every object's keys in order, so variables sent in a
+/// different order hash the same.
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+            let object: Map<String, Value> =
+                entries.into_iter().map(|(k, v)| (k.clone(), sorted(v))).collect();
+            Value::Object(object)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+        other => other.clone(),
+    }
+}
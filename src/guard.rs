@@ -0,0 +1,113 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use metrics::counter;
+
+use crate::{json_error, AppState};
+
+/// Longest GitHub account name.
+const MAX_OWNER_LEN: usize = 39;
+/// Longest GitHub repository name.
+const MAX_REPO_LEN: usize = 100;
+
+/// Turns away, before anything else looks at them, URLs that could reach
+/// somewhere else on GitHub than they seem to (dot segments, encoded
+/// slashes, control characters), URLs over `MAX_URL_BYTES`, and bodies
+/// over `MAX_REQUEST_BODY_BYTES`.
+pub async fn guard_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config.server;
+    let uri = request.uri();
+    let length = uri.path().len() + uri.query().map_or(0, |q| q.len() + 1);
+    if length > config.max_url_bytes {
+        counter!("proxy_invalid_requests_total", "reason" => "url_length").increment(1);
+        let message = format!("URLs are limited to {} bytes", config.max_url_bytes);
+        return json_error(StatusCode::BAD_REQUEST, &message);
+    }
+    if let Err(message) = raw_path(uri.path()) {
+        counter!("proxy_invalid_requests_total", "reason" => "path").increment(1);
+        return json_error(StatusCode::BAD_REQUEST, message);
+    }
+    // Extractors have `DefaultBodyLimit`; this holds for any handler.
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > config.max_body_bytes) {
+        counter!("proxy_invalid_requests_total", "reason" => "body_size").increment(1);
+        let message = format!("request bodies are limited to {} bytes", config.max_body_bytes);
+        return json_error(StatusCode::PAYLOAD_TOO_LARGE, &message);
+    }
+    next.run(request).await
+}
+
+/// Checks `path` as it came, still percent-encoded: `/`, `\`, `?`, `#` and
+/// `%` mean something in the upstream URL once decoded, so they may not be
+/// hidden in an escape.
+fn raw_path(path: &str) -> Result<(), &'static str> {
+    let bytes = path.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b != b'%' {
+            continue;
+        }
+        let decoded = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or("malformed percent-encoding in the path")?;
+        if matches!(decoded, b'/' | b'\\' | b'?' | b'#' | b'%') || decoded.is_ascii_control() {
+            return Err("encoded slashes and control characters aren't allowed in the path");
+        }
+    }
+    let segments = path.strip_prefix('/').unwrap_or(path);
+    let decoded_dots = segments.replace("%2e", ".").replace("%2E", ".");
+    segments_ok(&decoded_dots)
+}
+
+/// An API path under `/repos/`, or a namespace, as handed to the proxy:
+/// decoded, without its leading slash. Batches and badges arrive here
+/// without passing `guard_middleware`.
+pub fn api_path(path: &str, repository: bool) -> Result<(), &'static str> {
+    segments_ok(path)?;
+    if !repository {
+        return Ok(());
+    }
+    let mut segments = path.split('/');
+    let owner = segments.next().unwrap_or_default();
+    let repo = segments.next().unwrap_or_default();
+    let owner_ok = (1..=MAX_OWNER_LEN).contains(&owner.len())
+        && owner
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    let repo_ok = (1..=MAX_REPO_LEN).contains(&repo.len())
+        && repo
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if !owner_ok || !repo_ok {
+        return Err("expected /owner/repo, optionally followed by an API path");
+    }
+    Ok(())
+}
+
+/// No `.` or `..` segments, no empty ones but a trailing slash, and no
+/// backslashes, fragments or control characters.
+fn segments_ok(path: &str) -> Result<(), &'static str> {
+    if path.bytes().any(|b| b == b'\\' || b == b'#' || b.is_ascii_control()) {
+        return Err("backslashes, `#` and control characters aren't allowed in the path");
+    }
+    let mut segments = path.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        if matches!(segment, "." | "..") || (segment.is_empty() && !last) {
+            return Err("the path has empty, `.` or `..` segments");
+        }
+    }
+    Ok(())
+}
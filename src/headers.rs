@@ -0,0 +1,207 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use serde::{Serialize, Serializer};
+
+/// Upstream headers relayed to clients unless `PASSTHROUGH_HEADERS` says otherwise.
+pub const DEFAULT_PASSTHROUGH: &[&str] = &[
+    "etag",
+    "last-modified",
+    "link",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    "x-ratelimit-used",
+    "x-ratelimit-resource",
+];
+
+/// Client request headers sent on to GitHub unless `FORWARD_HEADERS` says otherwise.
+pub const DEFAULT_FORWARD: &[&str] = &["if-none-match", "if-modified-since"];
+
+/// Request headers for resumable file downloads, forwarded on download
+/// routes only.
+pub const DOWNLOAD_FORWARD: &[HeaderName] = &[header::ACCEPT, header::RANGE, header::IF_RANGE];
+
+/// What a download needs relayed on top of the usual passthrough.
+pub const DOWNLOAD_PASSTHROUGH: &[HeaderName] = &[
+    header::CONTENT_TYPE,
+    header::CONTENT_DISPOSITION,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+];
+
+/// Headers that describe the upstream connection or session rather than the
+/// resource, and so are never relayed no matter what is configured.
+const NEVER_PASSTHROUGH: &[HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::SET_COOKIE,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Hop-by-hop and credential-bearing request headers. Upstream requests are
+/// made with our credentials only, so nothing here is ever forwarded.
+const NEVER_FORWARD: &[HeaderName] = &[
+    header::AUTHORIZATION,
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::COOKIE,
+    header::HOST,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Headers the proxy itself adds to responses, which browsers hide from
+/// scripts unless exposed.
+pub const PROXY_EXPOSED: &[&str] = &[
+    "x-cache",
+    "x-upstream-time",
+    "x-proxy-time",
+    "x-canonical-path",
+    "x-schema-valid",
+    "x-diff-base",
+];
+
+/// A set of headers allowed to cross between the client and GitHub.
+///
+/// Header names are case-insensitive by construction, so matching here is too.
+#[derive(Clone)]
+pub struct HeaderAllowlist {
+    names: Vec<HeaderName>,
+}
+
+impl Serialize for HeaderAllowlist {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names.iter().map(HeaderName::as_str))
+    }
+}
+
+impl HeaderAllowlist {
+    /// Upstream response headers to relay to clients.
+    pub fn response<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        Self::new(names, NEVER_PASSTHROUGH)
+    }
+
+    /// Client request headers to forward upstream.
+    pub fn request<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        Self::new(names, NEVER_FORWARD)
+    }
+
+    fn new<S: AsRef<str>>(names: &[S], forbidden: &[HeaderName]) -> Result<Self, String> {
+        let mut parsed: Vec<HeaderName> = Vec::with_capacity(names.len());
+        for name in names {
+            let name = HeaderName::from_bytes(name.as_ref().trim().as_bytes())
+                .map_err(|_| format!("invalid header name {:?}", name.as_ref()))?;
+            if forbidden.contains(&name) || is_hop_by_hop(&name) {
+                return Err(format!("header {name} cannot be passed through"));
+            }
+            if !parsed.contains(&name) {
+                parsed.push(name);
+            }
+        }
+
+        Ok(Self { names: parsed })
+    }
+
+    /// Copies the allowed headers out of `from`, keeping every value of
+    /// multi-valued headers in their original order.
+    pub fn extract(&self, from: &HeaderMap) -> HeaderMap {
+        let mut kept = HeaderMap::new();
+        for name in &self.names {
+            for value in from.get_all(name) {
+                kept.append(name.clone(), value.clone());
+            }
+        }
+        kept
+    }
+
+    /// The `access-control-expose-headers` value covering these headers
+    /// plus the proxy's own.
+    /// `extra` are proxy headers sent only in some configurations.
+    pub fn expose_value(&self, extra: &[&str]) -> HeaderValue {
+        let joined = self
+            .names
+            .iter()
+            // Content-Type is readable without being exposed.
+            .chain(
+                DOWNLOAD_PASSTHROUGH
+                    .iter()
+                    .filter(|name| **name != header::CONTENT_TYPE && !self.names.contains(name)),
+            )
+            .map(HeaderName::as_str)
+            .chain(PROXY_EXPOSED.iter().copied())
+            .chain(extra.iter().copied())
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::from_str(&joined).expect("header names are valid header values")
+    }
+}
+
+/// What every GitHub media type but the default JSON starts with.
+const GITHUB_MEDIA: &str = "application/vnd.github.";
+
+/// The GitHub media type a client asked for in `Accept`, if it isn't plain
+/// JSON: `raw+json`, `diff`, `v3.patch` or an API preview, with the
+/// `application/vnd.github.` prefix left off. Anything else, a browser's
+/// `*/*` or several types at once, is answered with JSON as always.
+pub fn media_variant(headers: &HeaderMap) -> Option<String> {
+    let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+    let media = accept.split(';').next()?.trim().to_ascii_lowercase();
+    let variant = media.strip_prefix(GITHUB_MEDIA)?;
+    let json = matches!(variant, "json" | "v3" | "v3+json");
+    (!json && is_media_variant(variant)).then(|| variant.to_owned())
+}
+
+/// Splits the media variant back off a cache key, or the upstream URL made
+/// from one, as the `Accept` to fetch it with.
+pub fn split_media_variant(key: &str) -> (&str, Option<HeaderValue>) {
+    let Some((resource, variant)) = key.split_once('#') else {
+        return (key, None);
+    };
+    let accept = is_media_variant(variant)
+        .then(|| HeaderValue::from_str(&format!("{GITHUB_MEDIA}{variant}")).ok())
+        .flatten();
+    (resource, accept)
+}
+
+fn is_media_variant(variant: &str) -> bool {
+    !variant.is_empty()
+        && variant.len() <= 64
+        && variant
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"+-.".contains(&b))
+}
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    name.as_str() == "keep-alive" || name.as_str().starts_with("proxy-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_keeps_every_value_in_order() {
+        let mut upstream = HeaderMap::new();
+        upstream.append(header::LINK, HeaderValue::from_static("<a>; rel=\"next\""));
+        upstream.append(header::ETAG, HeaderValue::from_static("\"v1\""));
+        upstream.append(header::LINK, HeaderValue::from_static("<b>; rel=\"last\""));
+        upstream.append(header::SET_COOKIE, HeaderValue::from_static("session=1"));
+        upstream.append(header::SERVER, HeaderValue::from_static("GitHub.com"));
+
+        let kept = HeaderAllowlist::response(&["Link", "ETAG"]).unwrap().extract(&upstream);
+        let links: Vec<_> = kept.get_all(header::LINK).iter().collect();
+        assert_eq!(links, ["<a>; rel=\"next\"", "<b>; rel=\"last\""]);
+        assert_eq!(kept[header::ETAG], "\"v1\"");
+        assert_eq!(kept.len(), 3);
+    }
+
+    #[test]
+    fn session_headers_cannot_be_allowed() {
+        assert!(HeaderAllowlist::response(&["set-cookie"]).is_err());
+        assert!(HeaderAllowlist::response(&["Connection"]).is_err());
+        assert!(HeaderAllowlist::request(&["authorization"]).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::json;
+use std::{sync::atomic::Ordering, time::Instant};
+use tokio::sync::Mutex;
+
+use crate::AppState;
+
+/// Probes for orchestrators, served outside the origin check.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(alive))
+        .route("/readyz", get(ready))
+}
+
+/// The last `READYZ_UPSTREAM_CHECK_SECS` check and when it was made, so
+/// probes don't each cost a request to GitHub.
+#[derive(Default)]
+pub struct UpstreamCheck {
+    last: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+/// 200 for as long as the process answers at all.
+async fn alive() -> Response {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+/// 503 while the cache is still being warmed from a peer, or, with
+/// `READYZ_UPSTREAM_CHECK_SECS`, while GitHub can't be reached or rejects a
+/// token; 200 otherwise. `status` turns to `warning`, with the reasons in
+/// `warnings`, when something needs an operator's attention soon, such as a
+/// GitHub token about to expire.
+async fn ready(State(state): State<AppState>) -> Response {
+    if !state.ready.load(Ordering::Acquire) {
+        let body = json!({ "status": "warming", "warnings": [] });
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+    }
+    if let Err(reason) = upstream_reachable(&state).await {
+        let body = json!({ "status": "unavailable", "reason": reason, "warnings": [] });
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+    }
+    let warnings = state
+        .upstream
+        .quota
+        .expiry_warnings(state.config.token_expiry_warning);
+    let status = if warnings.is_empty() { "ready" } else { "warning" };
+    Json(json!({ "status": status, "warnings": warnings })).into_response()
+}
+
+/// Probes arriving while a check is under way wait for its result.
+async fn upstream_reachable(state: &AppState) -> Result<(), String> {
+    let Some(interval) = state.config.readiness_upstream_check else {
+        return Ok(());
+    };
+    let mut last = state.upstream_check.last.lock().await;
+    if let Some((checked, result)) = last.as_ref() {
+        if checked.elapsed() < interval {
+            return result.clone();
+        }
+    }
+    let result = state.upstream.probe(&state.config).await;
+    *last = Some((Instant::now(), result.clone()));
+    result
+}
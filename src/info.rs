@@ -0,0 +1,96 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::{origin::OriginPattern, AppState};
+
+/// GitHub API namespaces the proxy answers for, by path under `/`.
+const EXAMPLES: &[&str] = &[
+    "/EduardPrigoana/repos",
+    "/EduardPrigoana/repos/releases/latest",
+    "/EduardPrigoana/repos/commits?per_page=5",
+];
+
+/// `GET /`: a short description of the service for whoever was handed its
+/// URL. Served outside the origin check and never touches the cache or GitHub.
+pub async fn index(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let user_agent = state.config.user_agent.to_str().unwrap_or_default();
+    let namespaces = state.config.api_namespaces.names();
+
+    let mut response = if wants_html(&headers) {
+        let allowed_origins = state.config.allowed_origins.patterns();
+        let upstream = &state.config.github.api;
+        Html(html(upstream, user_agent, allowed_origins, &namespaces)).into_response()
+    } else {
+        Json(json!({
+            "service": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "upstream": state.config.github.api,
+            "user_agent": user_agent,
+            "allowed_origins": state.config.allowed_origins,
+            "namespaces": namespaces,
+            "examples": EXAMPLES,
+        }))
+        .into_response()
+    };
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+fn html(
+    upstream: &str,
+    user_agent: &str,
+    allowed_origins: &[OriginPattern],
+    namespaces: &[&str],
+) -> String {
+    let list = |items: &[&str], link: bool| {
+        items
+            .iter()
+            .map(|item| match link {
+                true => format!("<li><a href=\"{0}\">{0}</a></li>", escape(item)),
+                false => format!("<li><code>{}</code></li>", escape(item)),
+            })
+            .collect::<String>()
+    };
+
+    let origins: Vec<String> = allowed_origins.iter().map(ToString::to_string).collect();
+    let origins: Vec<&str> = origins.iter().map(String::as_str).collect();
+
+    format!(
+        "<!doctype html>\n<meta charset=\"utf-8\">\n<title>{name}</title>\n\
+         <h1>{name} {version}</h1>\n\
+         <p>A caching CORS proxy in front of <code>{upstream}</code>.</p>\n\
+         <p>Upstream User-Agent: <code>{user_agent}</code></p>\n\
+         <h2>Allowed origins</h2>\n<ul>{origins}</ul>\n\
+         <h2>Namespaces</h2>\n<ul>{namespaces}</ul>\n\
+         <h2>Examples</h2>\n<ul>{examples}</ul>\n",
+        name = env!("CARGO_PKG_NAME"),
+        version = env!("CARGO_PKG_VERSION"),
+        upstream = escape(upstream),
+        user_agent = escape(user_agent),
+        origins = list(&origins, false),
+        namespaces = list(namespaces, false),
+        examples = list(EXAMPLES, true),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,114 @@
+use serde::Serialize;
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap, HashSet, VecDeque},
+    hash::BuildHasher,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::KeyQuotaConfig;
+
+/// Where origins past `USAGE_MAX_ORIGINS` are counted, together.
+const OTHER: &str = "other";
+
+/// The keys one origin brought into the cache, oldest first, as hashes.
+#[derive(Default)]
+struct OriginKeys {
+    inserted: VecDeque<(Instant, u64)>,
+    distinct: HashSet<u64>,
+}
+
+impl OriginKeys {
+    fn expire(&mut self, window: Duration) {
+        while let Some(&(at, hash)) = self.inserted.front() {
+            if at.elapsed() < window {
+                break;
+            }
+            self.inserted.pop_front();
+            self.distinct.remove(&hash);
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OriginKeyStats {
+    keys: usize,
+    over_quota: bool,
+}
+
+/// Stops one origin from filling the cache with a long tail of one-off keys:
+/// past `KEY_QUOTA_PER_ORIGIN` distinct new keys within the window, its
+/// misses are passed through uncached, while hits on what is already cached
+/// are served as usual.
+///
+/// Memory stays bounded: an origin never holds more than its quota of
+/// hashes, and at most `USAGE_MAX_ORIGINS` origins are told apart.
+pub struct KeyQuota {
+    config: KeyQuotaConfig,
+    max_origins: usize,
+    hasher: RandomState,
+    origins: Mutex<HashMap<String, OriginKeys>>,
+}
+
+impl KeyQuota {
+    pub fn new(config: KeyQuotaConfig, max_origins: usize) -> Self {
+        Self {
+            config,
+            max_origins,
+            hasher: RandomState::new(),
+            origins: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `origin` may add `key` to the cache; if so, it is counted
+    /// against its quota from now on.
+    pub fn admit(&self, origin: &str, key: &str) -> bool {
+        if self.config.per_origin == 0 {
+            return true;
+        }
+        let hash = self.hasher.hash_one(key);
+        let mut origins = self.origins.lock().unwrap();
+        let origin = match origins.contains_key(origin) || origins.len() < self.max_origins {
+            true => origin,
+            false => OTHER,
+        };
+        let keys = match origins.get_mut(origin) {
+            Some(keys) => keys,
+            None => origins.entry(origin.to_owned()).or_default(),
+        };
+        keys.expire(self.config.window);
+        if keys.distinct.contains(&hash) {
+            return true;
+        }
+        if keys.distinct.len() >= self.config.per_origin {
+            return false;
+        }
+        keys.distinct.insert(hash);
+        keys.inserted.push_back((Instant::now(), hash));
+        true
+    }
+
+    /// Distinct keys per origin over the window, or just those of `origin`.
+    pub fn snapshot(&self, origin: Option<&str>) -> BTreeMap<String, OriginKeyStats> {
+        let mut origins = self.origins.lock().unwrap();
+        origins.retain(|_, keys| {
+            keys.expire(self.config.window);
+            !keys.inserted.is_empty()
+        });
+        origins
+            .iter()
+            .filter(|(name, _)| origin.is_none_or(|o| o.eq_ignore_ascii_case(name)))
+            .map(|(name, keys)| {
+                let stats = OriginKeyStats {
+                    keys: keys.distinct.len(),
+                    over_quota: keys.distinct.len() >= self.config.per_origin,
+                };
+                (name.clone(), stats)
+            })
+            .collect()
+    }
+
+    pub fn config(&self) -> &KeyQuotaConfig {
+        &self.config
+    }
+}
@@ -0,0 +1,1663 @@
+mod access_log;
+mod admin;
+mod alerts;
+mod aliases;
+mod api_keys;
+mod badge;
+mod bans;
+mod batch;
+mod breaker;
+mod browser;
+mod cache;
+mod client_ip;
+mod compression;
+pub mod config;
+mod content;
+mod diff;
+mod fields;
+mod dns;
+mod disk;
+mod dump;
+mod fixtures;
+mod freshness;
+mod github_app;
+mod guard;
+mod graphql;
+mod headers;
+mod health;
+mod info;
+mod key_quota;
+mod namespaces;
+mod origin;
+mod otel;
+mod paginate;
+mod panics;
+mod paths;
+mod peers;
+mod prometheus;
+mod quota;
+mod ratelimit;
+mod raw;
+mod redact;
+mod redis;
+mod refresher;
+mod reporting;
+mod repos;
+mod schema;
+mod server;
+mod shadow;
+mod signing;
+mod sizes;
+mod snapshot;
+mod store;
+mod tls;
+mod tokens;
+mod upstream;
+mod usage;
+mod watch;
+mod webhook;
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Path, RawQuery, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use bytes::Bytes;
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::PrometheusHandle;
+use moka::ops::compute::Op;
+use reqwest::Client;
+use serde_json::json;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Semaphore,
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use aliases::Aliases;
+use api_keys::ApiKeys;
+use bans::{ban_middleware, Bans};
+use cache::{
+    BodyPool, CacheStatus, CachedResponse, EvictionCounters, PurgeMode, ResponseCache,
+    Revalidating, Settled, TierHits,
+};
+use client_ip::{client_ip_middleware, ClientIp};
+use config::{Config, CorsConfig};
+use content::BodyKind;
+use diff::DiffBases;
+use dns::CachingResolver;
+use health::UpstreamCheck;
+use key_quota::KeyQuota;
+use peers::{peer_middleware, Peers};
+use ratelimit::{rate_limit_middleware, RateLimiter};
+use shadow::{shadow_middleware, Shadow};
+use store::CacheStore;
+use tokens::GithubToken;
+use upstream::{FetchError, Fetched, Streamed, Upstream};
+use usage::{usage_middleware, Usage};
+use watch::Watches;
+
+/// What every request shares. Each middleware layer clones it per request,
+/// along with every layer inside it, so it is a single `Arc`.
+#[derive(Clone)]
+struct AppState(Arc<Shared>);
+
+impl Deref for AppState {
+    type Target = Shared;
+
+    fn deref(&self) -> &Shared {
+        &self.0
+    }
+}
+
+struct Shared {
+    upstream: Arc<Upstream>,
+    cache: Arc<ResponseCache>,
+    /// Bodies shared between cache entries.
+    bodies: Arc<BodyPool>,
+    evictions: Arc<EvictionCounters>,
+    tiers: Arc<TierHits>,
+    /// The optional second cache tier, on disk or in Redis.
+    store: Option<Arc<dyn CacheStore>>,
+    config: Arc<Config>,
+    bans: Arc<Bans>,
+    rate_limiter: Arc<RateLimiter>,
+    aliases: Arc<Aliases>,
+    /// The other replicas, with `PEERS`.
+    peers: Option<Arc<Peers>>,
+    /// Where to mirror traffic to, with `SHADOW_TARGET_URL`.
+    shadow: Option<Arc<Shadow>>,
+    usage: Arc<Usage>,
+    key_quota: Arc<KeyQuota>,
+    /// Who may use the proxy and how much, with `API_KEYS_FILE`.
+    api_keys: Option<Arc<ApiKeys>>,
+    watches: Arc<Watches>,
+    revalidating: Arc<Revalidating>,
+    /// Refreshes that ended without a cache entry, for those queued on them.
+    settled: Arc<Settled>,
+    diffs: Arc<DiffBases>,
+    /// `?__fields=` answers, by the entry they were cut from.
+    projections: Arc<fields::Projections>,
+    graphql: Arc<graphql::Results>,
+    raw: Arc<raw::Files>,
+    /// Gzipped bodies, with `COMPRESSION_ENABLED`.
+    compressed: Arc<compression::Compressed>,
+    /// What HTTPS listeners serve, reloaded on SIGHUP.
+    certificates: Option<Arc<tls::Certificates>>,
+    /// Where spans are exported, with `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    otel: Option<Arc<otel::Exporter>>,
+    /// Slots for proxied requests, with `MAX_IN_FLIGHT_REQUESTS`.
+    in_flight: Option<Arc<Semaphore>>,
+    upstream_check: Arc<UpstreamCheck>,
+    /// Renders everything recorded, for `GET /metrics`.
+    prometheus: PrometheusHandle,
+    /// Set once startup work such as `PEER_WARM_FROM` is done.
+    ready: Arc<AtomicBool>,
+    /// Cancelled on SIGTERM, for anything that would hold up the shutdown.
+    shutdown: CancellationToken,
+}
+
+/// The public routes for `config`, as `run` serves them on `BIND_ADDRS`
+/// but without its token check, listeners or background tasks.
+pub async fn build_router(config: Config) -> Result<Router, String> {
+    let state = app_state(config).await?;
+    Ok(routers(&state).0)
+}
+
+/// Runs the proxy as the environment configures it, until SIGTERM or Ctrl-C.
+pub async fn run() {
+    panics::install_hook();
+    let _reporting = reporting::init();
+    let log_format = config::log_format();
+    let json = log_format.as_ref().is_ok_and(|json| *json);
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(json.then(|| tracing_subscriber::fmt::layer().json()))
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .with(reporting::tracing_layer())
+        .init();
+    if let Err(err) = log_format {
+        tracing::error!("{err}");
+        reporting::flush();
+        std::process::exit(1);
+    }
+
+    // Before anything can record a metric.
+    prometheus::install();
+    let config = Config::from_env().unwrap_or_else(|err| {
+        tracing::error!("{err}");
+        reporting::flush();
+        std::process::exit(1);
+    });
+    reporting::scrub_secrets_of(&config);
+
+    let state = app_state(config).await.unwrap_or_else(|err| {
+        tracing::error!("{err}");
+        reporting::flush();
+        std::process::exit(1);
+    });
+    if let Some(dir) = &state.config.fixtures.serve {
+        warn!("Serving fixtures from {} instead of GitHub (FIXTURE_MODE)", dir.display());
+    } else if state.config.skip_token_check {
+        info!("Skipping the GitHub token check (SKIP_TOKEN_CHECK)");
+    } else {
+        // An installation token can't read `/user`; getting one at all
+        // already proved the App's credentials.
+        for token in state.config.tokens.all().into_iter().filter(|token| token.app.is_none()) {
+            if let Err(err) = state.upstream.check_token(&state.config, token).await {
+                tracing::error!("{err}");
+                reporting::flush();
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if state.config.tokens.pool.len() > 1 {
+        info!("Sharing requests among {} pooled GitHub tokens", state.config.tokens.pool.len());
+    }
+
+    let (public, admin) = routers(&state);
+
+    let acceptor = state.certificates.as_ref().map(|certificates| {
+        certificates.acceptor().unwrap_or_else(|err| {
+            tracing::error!("cannot set up TLS: {err}");
+            reporting::flush();
+            std::process::exit(1);
+        })
+    });
+    let mut listeners = Vec::new();
+    for listener in &state.config.listeners {
+        let bound = bind(listener.addr).await;
+        if listener.admin_only {
+            info!("Admin endpoints on http://{}", listener.addr);
+            listeners.push((server::Bound::Tcp(bound), admin.clone()));
+        } else if let Some(acceptor) = &acceptor {
+            info!("CORS proxy running on https://{}", listener.addr);
+            listeners.push((server::Bound::Tls(bound, acceptor.clone()), public.clone()));
+        } else {
+            info!("CORS proxy running on http://{}", listener.addr);
+            listeners.push((server::Bound::Tcp(bound), public.clone()));
+        }
+    }
+    if let Some(addr) = state.config.tls.as_ref().and_then(|tls| tls.redirect) {
+        let https_port = state
+            .config
+            .listeners
+            .iter()
+            .find(|l| !l.admin_only)
+            .map_or(443, |l| l.addr.port());
+        info!("Redirecting http://{addr} to HTTPS");
+        let redirect = tls::redirect_router(https_port);
+        listeners.push((server::Bound::Tcp(bind(addr).await), redirect));
+    }
+    if let Some(socket) = &state.config.unix_socket {
+        let bound = server::bind_unix(socket).unwrap_or_else(|err| {
+            tracing::error!("cannot listen on {}: {err}", socket.path.display());
+            reporting::flush();
+            std::process::exit(1);
+        });
+        info!("CORS proxy running on unix:{}", socket.path.display());
+        listeners.push((server::Bound::Unix(bound), public.clone()));
+    }
+
+    let allowed_origins: Vec<String> = state
+        .config
+        .allowed_origins
+        .patterns()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    info!("Allowed origins: {}", allowed_origins.join(", "));
+    if state.config.cors.allow_credentials {
+        info!("CORS: credentials allowed, so the allow-origin always names the origin");
+    }
+    info!("API namespaces: {}", state.config.api_namespaces.names().join(", "));
+    if state.config.github.enterprise {
+        let github = &state.config.github;
+        info!("GitHub Enterprise Server: API at {}, raw files at {}", github.api, github.raw);
+    }
+    info!("Upstream User-Agent: {:?}", state.config.user_agent);
+    let cache = &state.config.cache;
+    match cache.max_bytes {
+        Some(max_bytes) => info!(
+            "Cache: ttl={:?} tti={:?} max_bytes={max_bytes}",
+            cache.ttl, cache.tti
+        ),
+        None => info!(
+            "Cache: ttl={:?} tti={:?} max_entries={}",
+            cache.ttl, cache.tti, cache.max_entries
+        ),
+    }
+    if let Some(disk) = &state.config.cache.disk {
+        info!(
+            "Disk cache: {} (max {} bytes, kept {:?})",
+            disk.path.display(),
+            disk.max_bytes,
+            disk.ttl
+        );
+    }
+    if let Some(api_keys) = &state.config.api_keys {
+        info!(
+            "API keys from {}{}",
+            api_keys.file.display(),
+            if api_keys.required { ", required" } else { "" }
+        );
+    }
+    if let Some(redis) = &state.config.cache.redis {
+        info!(
+            "Redis cache: {} db {} (kept {:?}, {:?} timeout)",
+            redis.server.address, redis.server.database, redis.ttl, redis.server.timeout
+        );
+    }
+    if !state.config.trusted_proxies.is_empty() {
+        info!("Trusted proxies: {:?}", state.config.trusted_proxies);
+    }
+    info!(
+        "Connections: {:?} header timeout, {:?} idle timeout, max {} requests",
+        state.config.server.header_read_timeout,
+        state.config.server.idle_timeout,
+        state.config.server.max_requests_per_connection
+    );
+    info!(
+        "Request headers: max {} bytes, {} headers",
+        state.config.server.max_header_bytes, state.config.server.max_headers
+    );
+    if let Some(peers) = &state.config.peers {
+        info!("Sharing the cache with {} replica(s) as {}", peers.urls.len(), peers.own_url);
+    }
+    if let Some(target) = &state.config.shadow.target {
+        info!(
+            "Mirroring {}% of requests to {target}, comparing 1 in {}",
+            state.config.shadow.percent, state.config.shadow.compare_every
+        );
+    }
+    if state.config.alerts.webhook_url.is_some() {
+        let conditions: Vec<_> = state.config.alerts.conditions.iter().map(|c| c.name()).collect();
+        info!("Sending alerts on: {}", conditions.join(", "));
+    }
+    if let Some(dir) = &state.config.fixtures.record {
+        info!("Recording upstream responses as fixtures in {}", dir.display());
+    }
+    if state.config.server.h2c {
+        info!(
+            "Accepting cleartext HTTP/2, max {} concurrent streams",
+            state.config.server.http2_max_concurrent_streams
+        );
+    }
+    info!(
+        "Upstream: max {} concurrent requests, {:?} queue timeout",
+        state.config.upstream.max_concurrency, state.config.upstream.permit_timeout
+    );
+    if state.config.server.max_in_flight > 0 {
+        info!("Shedding requests beyond {} in flight", state.config.server.max_in_flight);
+    }
+    if let Some(otel) = &state.config.otel {
+        info!("Exporting traces to {}, sampling {}", otel.endpoint, otel.sample_ratio);
+    }
+    for (host, addr) in &state.config.upstream.resolve_overrides {
+        info!("Resolving {host} to {addr}");
+    }
+    if let Some(every) = state.config.upstream.keepalive {
+        info!("Keeping the upstream connection alive with a ping after {every:?} idle");
+    }
+    if let Some(signer) = &state.config.signer {
+        info!("Signing responses with Ed25519 public key {}", signer.public_key());
+    }
+    let allowed_repos = state.config.repos.allowed.patterns();
+    if !allowed_repos.is_empty() {
+        info!("Allowed repositories: {} pattern(s)", allowed_repos.len());
+    }
+    if !state.config.redact_fields.is_empty() {
+        let fields: Vec<String> = state
+            .config
+            .redact_fields
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect();
+        info!("Redacting JSON fields: {}", fields.join(", "));
+    }
+    if let Some(schemas) = &state.config.schemas {
+        info!(
+            "Validating responses against {} schema(s), 1 in {}",
+            schemas.rule_count(),
+            schemas.sample_rate()
+        );
+    }
+    let denied_repos = state.config.repos.denied.patterns();
+    if !denied_repos.is_empty() {
+        info!("Denied repositories: {} pattern(s)", denied_repos.len());
+    }
+    for (pattern, token) in &state.config.tokens.origin_rules {
+        info!("Upstream token for {pattern}: {}", token.name);
+    }
+    for (pattern, rpm) in &state.config.origin_rate_limits {
+        info!("Rate limit for {pattern}: {rpm} requests/minute");
+    }
+    if state.config.client_rate_limit > 0 {
+        info!("Rate limit per client: {} requests/minute", state.config.client_rate_limit);
+    }
+    if state.config.graphql.enabled {
+        info!(
+            "GraphQL queries up to {} bytes at {}, kept {:?}",
+            state.config.graphql.max_query_bytes,
+            graphql::PATH,
+            state.config.graphql.ttl
+        );
+    }
+    if state.config.raw.enabled {
+        info!(
+            "Raw files at {}, kept {:?}, or {:?} at a commit SHA",
+            raw::PATH,
+            state.config.raw.ttl,
+            state.config.raw.immutable_ttl
+        );
+    }
+    if state.config.badge.enabled {
+        info!("Badges at {}, kept {:?}", badge::PATH, state.config.badge.max_age);
+    }
+    if !state.config.refresh.paths.is_empty() {
+        info!(
+            "Keeping {} path(s) warm, checked every {:?}, above {}% of quota",
+            state.config.refresh.paths.len(),
+            state.config.refresh.interval,
+            state.config.refresh.min_quota_percent
+        );
+    }
+
+    let shutdown = state.shutdown.clone();
+    tokio::spawn(reload_on_hangup(state.clone()));
+    tokio::spawn(cache::sweep_bodies(state.bodies.clone()));
+    tokio::spawn(cancel_on_termination(shutdown.clone()));
+    let refresher = tokio::spawn(refresher::run(state.clone(), shutdown.clone()));
+    tokio::spawn(alerts::run(state.clone(), shutdown.clone()));
+    tokio::spawn(github_app::keep_fresh(state.clone(), shutdown.clone()));
+    tokio::spawn(prometheus::upkeep(state.prometheus.clone(), shutdown.clone()));
+    let exporting = state.otel.clone().map(|otel| tokio::spawn(otel::run(otel, shutdown.clone())));
+    let pinging = state.clone();
+    let stop_pinging = shutdown.clone();
+    tokio::spawn(async move {
+        pinging.upstream.keep_alive(&pinging.config, stop_pinging).await;
+    });
+    let warming = state.clone();
+    tokio::spawn(async move {
+        warming.upstream.warm(&warming.config).await;
+        if let Some(url) = &warming.config.warm_from {
+            snapshot::warm_from(&warming, url).await;
+        }
+        warming.ready.store(true, Ordering::Release);
+    });
+
+    let mut servers = JoinSet::new();
+    for (listener, router) in listeners {
+        let config = state.config.server.clone();
+        servers.spawn(server::serve(listener, router, config, shutdown.clone()));
+    }
+    while servers.join_next().await.is_some() {}
+    if let Some(socket) = &state.config.unix_socket {
+        let _ = std::fs::remove_file(&socket.path);
+    }
+
+    let _ = refresher.await;
+    if let Some(exporting) = exporting {
+        let _ = exporting.await;
+    }
+    info!("Upstream body sizes: {}", state.upstream.body_sizes.log_line());
+    info!("Shut down");
+}
+
+/// Everything requests are served with, for `config`. Only a GitHub App's
+/// credentials are checked on the way.
+async fn app_state(config: Config) -> Result<AppState, String> {
+    let mut client = Client::builder()
+        .dns_resolver(Arc::new(CachingResolver::new(config.upstream.dns_cache_ttl)))
+        .pool_max_idle_per_host(100)
+        .pool_idle_timeout(upstream::POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(Duration::from_secs(60))
+        // Repository moves are followed by hand, so they can be remembered.
+        .redirect(reqwest::redirect::Policy::none());
+    // An Enterprise Server may sit behind a proxy that only speaks HTTP/1.1.
+    if !config.github.enterprise {
+        client = client.http2_prior_knowledge();
+    }
+    for (host, _) in &config.upstream.resolve_overrides {
+        let addrs: Vec<_> = config
+            .upstream
+            .resolve_overrides
+            .iter()
+            .filter(|(h, _)| h == host)
+            .map(|(_, addr)| *addr)
+            .collect();
+        client = client.resolve_to_addrs(host, &addrs);
+    }
+    let client = client.build().unwrap();
+
+    let evictions = Arc::new(EvictionCounters::default());
+    let (cache, evicted) = cache::build(&config.cache, evictions.clone());
+    tokio::spawn(cache::record_evictions(evicted, evictions.clone()));
+    let store = store::open(&config.cache)?;
+
+    let bans = Bans::new(config.bans.clone());
+    let usage = Usage::new(config.usage.clone());
+    let key_quota = KeyQuota::new(config.key_quota.clone(), config.usage.max_origins);
+    let rate_limiter = RateLimiter::new(
+        config.origin_rate_limits.clone(),
+        config.client_rate_limit,
+        config.forced_refresh_per_minute,
+    );
+
+    let peers = config.peers.as_ref().map(|peers| Arc::new(Peers::new(peers)));
+    let shadow = config
+        .shadow
+        .target
+        .as_ref()
+        .map(|target| Arc::new(Shadow::new(&config.shadow, target)));
+    let upstream = Upstream::new(client, config.upstream.clone());
+    github_app::authenticate(&upstream, &config)
+        .await
+        .map_err(|err| format!("cannot authenticate as a GitHub App: {err}"))?;
+    let certificates = config.tls.as_ref().map(tls::Certificates::load).transpose()?;
+
+    let diffs = DiffBases::new(config.cache.diff_paths.clone());
+    let graphql_results = graphql::Results::new(&config.graphql);
+    let raw_files = raw::Files::new(&config.raw);
+    let compressed = compression::Compressed::new(&config.compression);
+    let otel = config.otel.as_ref().map(|otel| Arc::new(otel::Exporter::new(otel)));
+    let api_keys = match &config.api_keys {
+        Some(api_keys) => Some(Arc::new(ApiKeys::open(api_keys)?)),
+        None => None,
+    };
+    let in_flight = match config.server.max_in_flight {
+        0 => None,
+        slots => Some(Arc::new(Semaphore::new(slots))),
+    };
+    Ok(AppState(Arc::new(Shared {
+        upstream: Arc::new(upstream),
+        cache: Arc::new(cache),
+        bodies: Arc::default(),
+        evictions,
+        tiers: Arc::default(),
+        store,
+        config: Arc::new(config),
+        bans: Arc::new(bans),
+        rate_limiter: Arc::new(rate_limiter),
+        aliases: Arc::new(Aliases::new()),
+        peers,
+        shadow,
+        usage: Arc::new(usage),
+        key_quota: Arc::new(key_quota),
+        api_keys,
+        watches: Arc::default(),
+        revalidating: Arc::default(),
+        settled: Arc::default(),
+        diffs: Arc::new(diffs),
+        projections: Arc::default(),
+        graphql: Arc::new(graphql_results),
+        raw: Arc::new(raw_files),
+        compressed: Arc::new(compressed),
+        certificates,
+        otel,
+        in_flight,
+        upstream_check: Arc::default(),
+        prometheus: prometheus::install(),
+        ready: Arc::default(),
+        shutdown: CancellationToken::new(),
+    })))
+}
+
+/// The public routes, and the operator endpoints for admin-only listeners.
+fn routers(state: &AppState) -> (Router, Router) {
+    // With an admin-only listener, the operator endpoints move there entirely.
+    let admin_listener = state.config.listeners.iter().any(|l| l.admin_only);
+    let mut proxy = Router::new().route("/*path", get(proxy_handler).options(preflight));
+    if !admin_listener {
+        proxy = proxy.merge(admin::router(state.clone()));
+    }
+    let proxy = proxy
+        .merge(batch::router(&state.config.batch))
+        .merge(paginate::router(&state.config.paginate))
+        .merge(graphql::router(&state.config.graphql))
+        .merge(raw::router(&state.config.raw))
+        .merge(badge::router(&state.config.badge))
+        .merge(watch::router())
+        .layer(middleware::from_fn_with_state(state.clone(), shadow_middleware))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            browser::browser_middleware,
+        ))
+        // Inside the rate limit, so what it turns away isn't charged to a key.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            api_keys::api_key_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), usage_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), shed_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), cors_middleware))
+        .layer(DefaultBodyLimit::max(state.config.server.max_body_bytes))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            guard::guard_middleware,
+        ))
+        // Added after the origin check and rate limits so they don't apply.
+        .route("/", get(info::index))
+        .merge(webhook::router())
+        .merge(health::router())
+        .merge(signing::router());
+    let public = outer_layers(proxy, state);
+    // Snapshots first, so `/__cache/export` isn't taken for a purge of `export`.
+    let admin = snapshot::router(state.clone()).merge(admin::router(state.clone()));
+    let admin = outer_layers(admin, state);
+    (public, admin)
+}
+
+/// What every listener wraps its routes in, outermost last.
+fn outer_layers(router: Router<AppState>, state: &AppState) -> Router {
+    reporting::layer(
+        router
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                compression::compress_middleware,
+            ))
+            .layer(middleware::from_fn_with_state(state.clone(), ban_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), peer_middleware))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                shadow::mark_middleware,
+            ))
+            .layer(middleware::from_fn(access_log::log_middleware))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                client_ip_middleware,
+            ))
+            .layer(panics::layer())
+            .layer(middleware::from_fn(prometheus::track_middleware))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                origin_fallback_middleware,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                otel::trace_middleware,
+            )),
+    )
+    .with_state(state.clone())
+}
+
+/// Listens on `addr`, or exits if it can't.
+async fn bind(addr: SocketAddr) -> tokio::net::TcpListener {
+    tokio::net::TcpListener::bind(addr).await.unwrap_or_else(|err| {
+        tracing::error!("cannot listen on {addr}: {err}");
+        reporting::flush();
+        std::process::exit(1);
+    })
+}
+
+/// Starts a graceful shutdown on SIGTERM or Ctrl-C.
+async fn cancel_on_termination(shutdown: CancellationToken) {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            warn!("cannot listen for SIGTERM: {err}");
+            let _ = tokio::signal::ctrl_c().await;
+            shutdown.cancel();
+            return;
+        }
+    };
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    info!("Shutting down");
+    shutdown.cancel();
+}
+
+/// Re-reads the reloadable parts of the configuration on every SIGHUP.
+async fn reload_on_hangup(state: AppState) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            warn!("cannot listen for SIGHUP, reloading is disabled: {err}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let (Some(certificates), Some(tls)) = (&state.certificates, &state.config.tls) {
+            match certificates.reload(tls) {
+                Ok(()) => info!("Reloaded the TLS certificate"),
+                Err(err) => error!("TLS reload failed, keeping the previous certificate: {err}"),
+            }
+        }
+        if let Some(api_keys) = &state.api_keys {
+            match api_keys.reload() {
+                Ok(count) => info!("Reloaded {count} API key(s)"),
+                Err(err) => error!("API key reload failed, keeping the previous keys: {err}"),
+            }
+        }
+        let repos = &state.config.repos;
+        if let Err(err) = repos.reload() {
+            error!("reload failed, keeping previous lists: {err}");
+            continue;
+        }
+        let denied = repos.denied.patterns();
+        info!(
+            "Reloaded repository lists: {} allowed, {} denied pattern(s)",
+            repos.allowed.patterns().len(),
+            denied.len()
+        );
+
+        // Don't keep serving what was cached before a repository was denied.
+        if !denied.is_empty() {
+            if let Some(store) = &state.store {
+                store.purge_repos(&denied);
+            }
+            if let Err(err) = cache::purge_repos(&state.cache, denied, PurgeMode::Hard).await {
+                error!("cannot purge denied repositories from the cache: {err}");
+            }
+        }
+    }
+}
+
+/// Sheds what is over `MAX_IN_FLIGHT_REQUESTS` straight away, so a burst
+/// gets a quick 503 to retry rather than piling up behind GitHub. Inside
+/// the CORS layer, so browsers can read it.
+async fn shed_middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    // Long polls sit idle, and have `WATCH_MAX_WATCHERS` of their own.
+    let watching = request.uri().path().starts_with("/watch/");
+    let Some(in_flight) = state.in_flight.as_ref().filter(|_| !watching) else {
+        return next.run(request).await;
+    };
+    let Ok(_slot) = in_flight.clone().try_acquire_owned() else {
+        counter!("proxy_shed_requests_total").increment(1);
+        let retry_after = state.config.upstream.shed_retry_after;
+        return service_unavailable("the proxy is at capacity", retry_after);
+    };
+    next.run(request).await
+}
+
+/// Rejects disallowed origins, attaches the GitHub token the rest of the
+/// request is billed to, and adds `Timing-Allow-Origin` on the way out.
+async fn cors_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let origin = match origin::from_headers(request.headers(), state.config.max_origin_len) {
+        Ok(origin) => origin,
+        Err(reason) => return json_error(StatusCode::BAD_REQUEST, reason),
+    };
+
+    match origin {
+        Some(origin) if !state.config.allowed_origins.allows(origin) => {
+            if let Some(ClientIp(ip)) = request.extensions().get::<ClientIp>() {
+                warn!(client_ip = %ip, origin, "rejected disallowed origin");
+            }
+            return error_response(StatusCode::FORBIDDEN);
+        }
+        None if state.config.require_origin => {
+            return json_error(StatusCode::FORBIDDEN, "an Origin header is required");
+        }
+        _ => {}
+    }
+
+    let tokens = &state.config.tokens;
+    // A client's own token, with `CLIENT_TOKENS`; unless it sent one, the
+    // origin's, else whichever of the pool has the most quota left.
+    let own = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .filter(|_| state.config.client_tokens)
+        .and_then(GithubToken::from_client);
+    let token = match (own, tokens.for_origin(origin)) {
+        (Some(own), _) => Arc::new(own),
+        (None, Some(token)) => token.clone(),
+        (None, None) => {
+            let resource = quota::resource_of(request.uri().path().trim_start_matches('/'));
+            let best = state.upstream.quota.best_of(&tokens.pool, resource);
+            best.unwrap_or(&tokens.default).clone()
+        }
+    };
+    request.extensions_mut().insert(token.clone());
+    let mut response = next.run(request).await;
+    // A cached answer would otherwise show the quota left when it was fetched.
+    state.upstream.quota.refresh_headers(&token, response.headers_mut());
+
+    // Resource Timing is gated like the response itself, so mirror whatever
+    // allow-origin the response ended up with.
+    if state.config.timing_allow_origin {
+        if let Some(allowed) = response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .cloned()
+        {
+            response.headers_mut().insert("timing-allow-origin", allowed);
+        }
+    }
+    response
+}
+
+/// Gives every error, and any other response that came without one, the
+/// allow-origin the request is entitled to: its own origin if that passed
+/// the allowlist, `*` if it sent none (unless `REQUIRE_ORIGIN` or
+/// credentials), and nothing at all for a disallowed or malformed one, so
+/// such sites can't read why they were turned away. With
+/// `CORS_ALLOW_CREDENTIALS`, a named origin is told credentials are fine.
+/// Outermost, so rejections by any middleware and caught panics go through
+/// it too.
+async fn origin_fallback_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let credentials = state.config.cors.allow_credentials;
+    let allow_origin = match origin::from_headers(request.headers(), state.config.max_origin_len) {
+        Ok(Some(origin)) if state.config.allowed_origins.allows(origin) => {
+            request.headers().get(header::ORIGIN).cloned()
+        }
+        Ok(None) if !state.config.require_origin && !credentials => {
+            Some(HeaderValue::from_static("*"))
+        }
+        _ => None,
+    };
+    let mut response = next.run(request).await;
+    let status = response.status();
+    let headers = response.headers_mut();
+    if status.is_client_error() || status.is_server_error() {
+        headers.remove(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+    }
+    if !headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN) {
+        if let Some(allow_origin) = allow_origin {
+            if allow_origin != "*" {
+                headers.append(header::VARY, HeaderValue::from_static("origin"));
+            }
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+    }
+    let named = headers
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_some_and(|origin| origin != "*");
+    if credentials && named {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+    response
+}
+
+async fn proxy_handler(
+    Path(path): Path<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<Arc<GithubToken>>>,
+    State(state): State<AppState>,
+) -> Response {
+    let token = token.map_or_else(|| state.config.tokens.default.clone(), |Extension(t)| t);
+    // As in the API; no account can be called `repos`.
+    let path = match path.strip_prefix("repos/") {
+        Some(repo_path) => repo_path.to_owned(),
+        None => path,
+    };
+    let repository = state.config.api_namespaces.of(&path).is_none();
+    if let Err(message) = guard::api_path(&path, repository) {
+        counter!("proxy_invalid_requests_total", "reason" => "path").increment(1);
+        return json_error(StatusCode::BAD_REQUEST, message);
+    }
+    let Some(moved) = state.aliases.resolve(&path) else {
+        return proxy(state, path, query, headers, client_ip, token, false).await;
+    };
+
+    // Served under the repository's new name, but the old one must pass too.
+    if let Some(refused) = refused_repo(&state.config, &path) {
+        return refused;
+    }
+    let canonical = HeaderValue::try_from(format!("/{moved}")).ok();
+    let mut response = proxy(state, moved, query, headers, client_ip, token, true).await;
+    if let Some(canonical) = canonical {
+        response.headers_mut().insert("x-canonical-path", canonical);
+    }
+    response
+}
+
+/// A 403 if the path's repository isn't served here.
+fn refused_repo(config: &Config, path: &str) -> Option<Response> {
+    if config.repos.is_denied(path) {
+        counter!("proxy_repo_denied_total").increment(1);
+        return Some(json_error(StatusCode::FORBIDDEN, "repository is blocked on this proxy"));
+    }
+    if !config.repos.permits(path) {
+        return Some(json_error(StatusCode::FORBIDDEN, "repository is not served by this proxy"));
+    }
+    None
+}
+
+/// The longest `X-Request-Deadline-Ms` is taken at its word.
+const MAX_CLIENT_DEADLINE: Duration = Duration::from_secs(3600);
+
+/// When the client stops waiting, from `X-Request-Deadline-Ms`.
+fn client_deadline(headers: &HeaderMap, started: Instant) -> Option<Instant> {
+    let millis: u64 = headers
+        .get("x-request-deadline-ms")?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(started + Duration::from_millis(millis).min(MAX_CLIENT_DEADLINE))
+}
+
+/// Gives up on `fetch` at `deadline`, dropping it, and with it any request
+/// to GitHub still in flight.
+async fn within<T>(
+    deadline: Option<Instant>,
+    fetch: impl Future<Output = Result<T, FetchError>>,
+) -> Result<T, FetchError> {
+    let Some(deadline) = deadline else {
+        return fetch.await;
+    };
+    match tokio::time::timeout_at(deadline.into(), fetch).await {
+        Ok(result) => result,
+        Err(_) => {
+            counter!("proxy_client_deadlines_exceeded_total").increment(1);
+            Err(FetchError::TimedOut)
+        }
+    }
+}
+
+/// Counts requests whose client went away before they were answered. The
+/// handler is simply dropped then, and everything it awaited with it: an
+/// upstream request is cancelled rather than left running.
+struct Abandoned(bool);
+
+impl Drop for Abandoned {
+    fn drop(&mut self) {
+        if self.0 {
+            counter!("proxy_requests_abandoned_total").increment(1);
+        }
+    }
+}
+
+async fn proxy(
+    state: AppState,
+    path: String,
+    query: Option<String>,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Arc<GithubToken>,
+    aliased: bool,
+) -> Response {
+    let (query, fields) = match fields::split_query(query) {
+        Ok(split) => split,
+        Err(message) => return json_error(StatusCode::BAD_REQUEST, message),
+    };
+    let key = match &query {
+        Some(query) => format!("{path}?{query}"),
+        None => path.clone(),
+    };
+    // A projection has an ETag of its own, which the full answer's is never
+    // compared with.
+    let (headers, conditional) = match &fields {
+        Some(_) => {
+            let mut full = headers.clone();
+            full.remove(header::IF_NONE_MATCH);
+            (full, headers)
+        }
+        None => (headers, HeaderMap::new()),
+    };
+    let mut abandoned = Abandoned(true);
+    let projections = state.projections.clone();
+    let response = fetch_or_serve(state, path, query, headers, client_ip, token, aliased).await;
+    abandoned.0 = false;
+    match fields {
+        Some(fields) => projections.filter(key, &fields, &conditional, response).await,
+        None => response,
+    }
+}
+
+async fn fetch_or_serve(
+    state: AppState,
+    path: String,
+    query: Option<String>,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Arc<GithubToken>,
+    aliased: bool,
+) -> Response {
+    let started = Instant::now();
+    let deadline = client_deadline(&headers, started);
+    let (query, diff_from) = diff::split_query(query);
+    let Shared {
+        upstream,
+        cache,
+        config,
+        rate_limiter,
+        ..
+    } = &*state;
+    let (query, max_age) = freshness::split_max_age(query, &config.cache);
+
+    // The repository lists have nothing to say about other namespaces.
+    let namespace = config.api_namespaces.of(&path);
+    if namespace.is_none() {
+        if let Some(refused) = refused_repo(config, &path) {
+            return refused;
+        }
+    }
+
+    // Files can be large and are often asked for in ranges; none are kept.
+    let download = paths::is_download(&path);
+    // What a client's own token can see may be private: never kept.
+    let mut bypass = download
+        || token.client
+        || paths::any_match(&config.cache.no_cache_paths, &path)
+        || paths::any_match(&config.cache.stream_paths, &path);
+
+    // A hard refresh in the browser. Honoured, but rationed per client so it
+    // can't be used to push every request through to GitHub.
+    let force_refresh = !bypass
+        && freshness::wants_revalidation(&headers)
+        && client_ip.is_some_and(|Extension(ClientIp(ip))| rate_limiter.allow_forced_refresh(ip));
+
+    let cache_key = match &query {
+        Some(q) => {
+            let mut key = path;
+            key.reserve_exact(1 + q.len());
+            key.push('?');
+            key.push_str(q);
+            key
+        }
+        None => path,
+    };
+    // Raw, diff and patch media types are other answers for the same path,
+    // kept apart from its JSON under `#<variant>`.
+    let cache_key: Arc<str> = match headers::media_variant(&headers).filter(|_| !download) {
+        Some(variant) => format!("{cache_key}#{variant}").into(),
+        None => cache_key.into(),
+    };
+
+    let cached = if bypass {
+        None
+    } else {
+        lookup(&state, &cache_key).await
+    };
+    // `?max_age=`: an entry older than this client takes is refreshed early,
+    // rationed like a hard refresh; over budget, it is served as usual.
+    let too_old = !force_refresh
+        && max_age.is_some_and(|max_age| {
+            cached
+                .as_ref()
+                .is_some_and(|entry| entry.is_fresh() && entry.stored_at.elapsed() > max_age)
+        })
+        && client_ip.is_some_and(|Extension(ClientIp(ip))| rate_limiter.allow_forced_refresh(ip));
+    let fetched_after = if force_refresh {
+        Some(started)
+    } else if too_old {
+        max_age.and_then(|max_age| started.checked_sub(max_age))
+    } else {
+        None
+    };
+    if fetched_after.is_none() {
+        if let Some(entry) = cached.as_ref().filter(|e| e.is_fresh()) {
+            let response = respond(entry, CacheStatus::Hit, &headers, config);
+            let response = diffed(&state, &cache_key, diff_from.as_ref(), entry, response);
+            return timed(response, None, started);
+        }
+    }
+    // Something new to the cache, from an origin that has added its share.
+    if cached.is_none() && !bypass {
+        let origin = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok());
+        if origin.is_some_and(|origin| !state.key_quota.admit(origin, &cache_key)) {
+            counter!("proxy_key_quota_passed_total").increment(1);
+            bypass = true;
+        }
+    }
+    let stale = cached;
+
+    // Another replica owns this key, so its cache (or its fetch) serves us
+    // too. A request forwarded here in turn is never forwarded on.
+    if let Some(peers) = state.peers.as_ref().filter(|_| !bypass) {
+        if !headers.contains_key(peers::SECRET_HEADER) {
+            let mut forwarded = config.forward_headers.extract(&headers);
+            if let Some(origin) = headers.get(header::ORIGIN) {
+                forwarded.insert(header::ORIGIN, origin.clone());
+            }
+            if let (_, Some(accept)) = headers::split_media_variant(&cache_key) {
+                forwarded.insert(header::ACCEPT, accept);
+            }
+            if fetched_after.is_some() {
+                forwarded.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            }
+            let client = client_ip.map(|Extension(ClientIp(ip))| ip);
+            let peer_started = Instant::now();
+            if let Some(response) = peers.forward(&cache_key, forwarded, client).await {
+                return timed(response, Some(peer_started.elapsed()), started);
+            }
+        }
+    }
+
+    let url = config.api_namespaces.upstream_url(&config.github, &cache_key);
+
+    // GitHub keeps a quota per resource (search has its own, far smaller
+    // one); once ours is spent, asking again only earns a 403 until it resets.
+    let resource = quota::resource_of(&url[config.github.api.len()..]);
+    if let Some(reset_in) = upstream.quota.exhausted_for(&token, resource) {
+        counter!("proxy_quota_exhausted_total", "resource" => resource).increment(1);
+        if let Some(entry) = &stale {
+            let response = respond(entry, CacheStatus::Stale, &headers, config);
+            return timed(response, None, started);
+        }
+        let reason = format!("GitHub's {resource} rate limit is exhausted");
+        return timed(service_unavailable(&reason, reset_in), None, started);
+    }
+
+    let mut forwarded = config.forward_headers.extract(&headers);
+    if download {
+        for name in headers::DOWNLOAD_FORWARD {
+            if let Some(value) = headers.get(name) {
+                forwarded.insert(name, value.clone());
+            }
+        }
+    }
+    let dump = dump::wanted(config, &headers);
+
+    // Plain requests share one upstream fetch per key, and so do those whose
+    // only validator is an `If-None-Match`, which `respond` checks against
+    // whatever they are served. Others can't: GitHub may answer them with a
+    // 304 that means nothing to anyone else.
+    let shared = forwarded.keys().all(|name| name == header::IF_NONE_MATCH);
+    if shared && !bypass {
+        // Just past its freshness, an entry is served as it is while one
+        // request's background task refreshes it.
+        let window = config.cache.stale_while_revalidate;
+        let revalidatable = stale.as_ref().filter(|entry| {
+            fetched_after.is_none() && window.is_some_and(|w| entry.is_stale_within(w))
+        });
+        if let Some(entry) = revalidatable {
+            if let Some(claim) = state.revalidating.claim(&cache_key) {
+                let state = state.clone();
+                let key = cache_key.clone();
+                tokio::spawn(async move {
+                    let _claim = claim;
+                    let refreshed = refresh(&state, &token, key.clone(), &url, false, None).await;
+                    if refreshed.is_err() {
+                        warn!(path = %key, "background revalidation failed");
+                    }
+                });
+            }
+            counter!("proxy_stale_while_revalidate_total").increment(1);
+            let response = respond(entry, CacheStatus::Stale, &headers, config);
+            return timed(response, None, started);
+        }
+        // A soft-purged entry is revalidated by one request while the rest
+        // are served what it held.
+        let purged = stale.as_ref().filter(|entry| entry.purged && !force_refresh);
+        let _claim = match purged {
+            Some(entry) => match state.revalidating.claim(&cache_key) {
+                Some(claim) => Some(claim),
+                None => {
+                    let response = respond(entry, CacheStatus::Stale, &headers, config);
+                    return timed(response, None, started);
+                }
+            },
+            None => None,
+        };
+        let upstream_started = Instant::now();
+        let refreshing = refresh(&state, &token, cache_key.clone(), &url, dump, fetched_after);
+        let refreshed = within(deadline, refreshing).await;
+        let upstream_time = upstream_started.elapsed();
+        let response = match refreshed {
+            Ok((entry, cache_status)) => {
+                // A refresh the client forced, whatever the cache held.
+                let cache_status = match cache_status {
+                    CacheStatus::Pass => CacheStatus::Pass,
+                    _ if fetched_after.is_some() => CacheStatus::Bypass,
+                    status => status,
+                };
+                let response = respond(&entry, cache_status, &headers, config);
+                diffed(&state, &cache_key, diff_from.as_ref(), &entry, response)
+            }
+            Err(FetchError::Moved(location)) if !aliased => {
+                return follow_move(state, token, &location, &cache_key, headers, client_ip).await;
+            }
+            Err(err) => fetch_failed(err, stale.as_deref(), &headers, config),
+        };
+        return timed(response, Some(upstream_time), started);
+    }
+
+    let passed = if token.client {
+        CacheStatus::Bypass
+    } else {
+        CacheStatus::Pass
+    };
+    let upstream_started = Instant::now();
+    let fetched = within(deadline, upstream.fetch(config, &token, &url, forwarded, dump)).await;
+    let upstream_time = upstream_started.elapsed();
+    let response = match fetched {
+        Ok(Fetched::Uncacheable(entry)) => respond(&entry, CacheStatus::Pass, &headers, config),
+        Ok(Fetched::Partial(entry)) => respond(&entry, CacheStatus::Pass, &headers, config),
+        Ok(Fetched::Streamed(streamed)) => respond_streamed(streamed, &headers, config),
+        Ok(Fetched::Fresh(entry)) if bypass => respond(&entry, passed, &headers, config),
+        Ok(Fetched::Fresh(entry)) => {
+            let entry = state.bodies.intern(entry);
+            if let Some(replaced) = &stale {
+                state.diffs.remember(&cache_key, replaced, &entry);
+            }
+            if let Some(store) = &state.store {
+                store.store(cache_key.clone(), entry.clone());
+            }
+            cache.insert(cache_key, entry.clone()).await;
+            respond(&entry, CacheStatus::Miss, &headers, config)
+        }
+        Ok(Fetched::NotModified { headers: upstream_headers, .. }) => {
+            let entry = CachedResponse {
+                status: StatusCode::NOT_MODIFIED,
+                body: Bytes::new(),
+                headers: upstream_headers,
+                stored_at: Instant::now(),
+                ttl: Duration::ZERO,
+                purged: false,
+                immutable: false,
+                kind: BodyKind::Json,
+            };
+            let status = if bypass {
+                passed
+            } else {
+                CacheStatus::Revalidated
+            };
+            respond(&entry, status, &headers, config)
+        }
+        Err(FetchError::Moved(location)) if !aliased => {
+            return follow_move(state, token, &location, &cache_key, headers, client_ip).await;
+        }
+        Err(err) => fetch_failed(err, stale.as_deref(), &headers, config),
+    };
+    timed(response, Some(upstream_time), started)
+}
+
+/// GitHub redirected: the repository was renamed or transferred. Learns
+/// its new name and serves the request from there, as later requests for
+/// the old name will be.
+async fn follow_move(
+    state: AppState,
+    token: Arc<GithubToken>,
+    location: &str,
+    cache_key: &str,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+) -> Response {
+    // The client's `Accept` picks the variant again.
+    let (cache_key, _) = headers::split_media_variant(cache_key);
+    let (path, query) = match cache_key.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_owned())),
+        None => (cache_key, None),
+    };
+    let Some((old_repo, _)) = aliases::split_repo(path) else {
+        return error_response(StatusCode::BAD_GATEWAY);
+    };
+    let Some(new_repo) = state.upstream.moved_to(&state.config, &token, location).await else {
+        warn!(path, location, "GitHub redirected somewhere the proxy won't follow");
+        return error_response(StatusCode::BAD_GATEWAY);
+    };
+    info!(from = old_repo, to = new_repo, "repository moved");
+    state.aliases.record(old_repo, &new_repo);
+
+    let token = Some(Extension(token));
+    let path = Path(path.to_owned());
+    Box::pin(proxy_handler(path, RawQuery(query), headers, client_ip, token, State(state))).await
+}
+
+/// Reports where the time went: `X-Upstream-Time` for waiting on GitHub
+/// (absent when we didn't have to) and `X-Proxy-Time` for the whole request.
+/// The same durations feed the latency histograms.
+fn timed(mut response: Response, upstream_time: Option<Duration>, started: Instant) -> Response {
+    let response_headers = response.headers_mut();
+    if let Some(upstream_time) = upstream_time {
+        histogram!("proxy_upstream_duration_seconds").record(upstream_time.as_secs_f64());
+        response_headers.insert("x-upstream-time", millis(upstream_time));
+    }
+    let total = started.elapsed();
+    histogram!("proxy_request_duration_seconds").record(total.as_secs_f64());
+    response_headers.insert("x-proxy-time", millis(total));
+    response
+}
+
+fn millis(duration: Duration) -> HeaderValue {
+    HeaderValue::try_from(format!("{}ms", duration.as_millis()))
+        .expect("formatted duration is a valid header value")
+}
+
+/// Refreshes `key` from upstream, coalescing with concurrent refreshes of the
+/// same key, and revalidating with the held entry's ETag when there is one.
+///
+/// An entry somebody else refreshed while we queued is reused as-is, unless
+/// `fetched_after` is set: a forced refresh only accepts data fetched after
+/// the client asked for it, and `?max_age=` nothing older than it allows.
+///
+/// So is a failure, or an answer that can't be cached, with the requests
+/// that queued behind it.
+///
+/// The result is shared whichever token fetched it: the data is the same.
+async fn refresh(
+    state: &AppState,
+    token: &GithubToken,
+    key: Arc<str>,
+    url: &str,
+    dump: bool,
+    fetched_after: Option<Instant>,
+) -> Result<(Arc<CachedResponse>, CacheStatus), FetchError> {
+    let Shared { upstream, config, .. } = &**state;
+    let queued = Instant::now();
+    let mut cache_status = CacheStatus::Miss;
+    let mut uncacheable = None;
+
+    let result = state
+        .cache
+        .entry(key.clone())
+        .and_try_compute_with(|current| async {
+            let current = current.map(|entry| entry.into_value());
+            if let Some(held) = &current {
+                let usable = match fetched_after {
+                    Some(after) => held.stored_at >= after,
+                    None => held.is_fresh(),
+                };
+                if usable {
+                    counter!("proxy_coalesced_requests_total").increment(1);
+                    cache_status = CacheStatus::Hit;
+                    return Ok(Op::Nop);
+                }
+            }
+            // What we queued behind failed, or can't be cached: so would ours.
+            if let Some(outcome) = state.settled.since(&key, queued) {
+                counter!("proxy_coalesced_requests_total").increment(1);
+                uncacheable = Some(outcome?);
+                return Ok(Op::Nop);
+            }
+
+            let mut validators = HeaderMap::new();
+            if let Some(etag) = current.as_ref().and_then(|c| c.headers.get(header::ETAG)) {
+                validators.insert(header::IF_NONE_MATCH, etag.clone());
+            }
+
+            let fetched = upstream.fetch(config, token, url, validators, dump).await;
+            let fetched = fetched.inspect_err(|err| state.settled.record(&key, Err(err.clone())))?;
+            match fetched {
+                Fetched::Fresh(entry) => {
+                    let entry = state.bodies.intern(entry);
+                    if let Some(replaced) = &current {
+                        state.diffs.remember(&key, replaced, &entry);
+                    }
+                    Ok(Op::Put(entry))
+                }
+                // Only ever partial if asked for a range, which this never is.
+                Fetched::Uncacheable(entry) | Fetched::Partial(entry) => {
+                    state.settled.record(&key, Ok(entry.clone()));
+                    uncacheable = Some(entry);
+                    // Whatever we held is now known to be outdated.
+                    Ok(if current.is_some() { Op::Remove } else { Op::Nop })
+                }
+                // Too large to keep, but those queued on it need the body.
+                Fetched::Streamed(streamed) => {
+                    let entry = streamed.collect().await?;
+                    state.settled.record(&key, Ok(entry.clone()));
+                    uncacheable = Some(entry);
+                    Ok(if current.is_some() { Op::Remove } else { Op::Nop })
+                }
+                Fetched::NotModified { headers, ttl } => match current {
+                    Some(held) => {
+                        cache_status = CacheStatus::Revalidated;
+                        let ttl = ttl.unwrap_or(held.ttl);
+                        Ok(Op::Put(Arc::new(held.revalidated(&headers, ttl))))
+                    }
+                    None => Err(FetchError::Failed(StatusCode::BAD_GATEWAY)),
+                },
+            }
+        })
+        .await?;
+
+    if let Some(entry) = uncacheable {
+        if let Some(store) = &state.store {
+            store.remove(&key);
+        }
+        return Ok((entry, CacheStatus::Pass));
+    }
+    let entry = match result.into_entry() {
+        Some(entry) => entry.into_value(),
+        None => return Err(FetchError::Failed(StatusCode::BAD_GATEWAY)),
+    };
+    if let Some(store) = &state.store {
+        if matches!(cache_status, CacheStatus::Miss | CacheStatus::Revalidated) {
+            store.store(key, entry.clone());
+        }
+    }
+    Ok((entry, cache_status))
+}
+
+/// The entry for `key` from memory or, failing that, from the second tier,
+/// in which case it is promoted back into memory for the next request.
+async fn lookup(state: &AppState, key: &Arc<str>) -> Option<Arc<CachedResponse>> {
+    let mut span = otel::Span::start("cache lookup", otel::Kind::Internal);
+    if let Some(entry) = state.cache.get(key).await {
+        state.tiers.memory_hit();
+        span.set("proxy.cache.tier", "memory");
+        return Some(entry);
+    }
+    let Some(store) = &state.store else {
+        state.tiers.miss();
+        return None;
+    };
+    match store.get(key).await {
+        Some(entry) => {
+            state.tiers.store_hit(store.name());
+            span.set("proxy.cache.tier", store.name());
+            let entry = state.bodies.intern(entry);
+            state.cache.insert(key.clone(), entry.clone()).await;
+            Some(entry)
+        }
+        None => {
+            state.tiers.miss();
+            None
+        }
+    }
+}
+
+/// `response` with its body swapped for a JSON Patch from the version
+/// tagged `diff_from`, when there is one worth sending.
+fn diffed(
+    state: &AppState,
+    key: &Arc<str>,
+    diff_from: Option<&HeaderValue>,
+    entry: &CachedResponse,
+    response: Response,
+) -> Response {
+    let Some(base) = diff_from.filter(|_| entry.status == StatusCode::OK) else {
+        return response;
+    };
+    let Some(patch) = state.diffs.patch(key, base, entry) else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    // The signature was over the full body.
+    parts.headers.remove(signing::SIGNATURE_HEADER);
+    parts.headers.remove(signing::SIGNED_HEADERS_HEADER);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json-patch+json"),
+    );
+    parts.headers.insert("x-diff-base", base.clone());
+    Response::from_parts(parts, patch.into())
+}
+
+/// Falls back to a stale copy when upstream couldn't be asked at all.
+fn fetch_failed(
+    err: FetchError,
+    stale: Option<&CachedResponse>,
+    headers: &HeaderMap,
+    config: &Config,
+) -> Response {
+    match (err, stale) {
+        (FetchError::Saturated, Some(stale)) => {
+            respond(stale, CacheStatus::Stale, headers, config)
+        }
+        (FetchError::Saturated, None) => {
+            service_unavailable("upstream is at capacity", config.upstream.shed_retry_after)
+        }
+        (FetchError::Pending | FetchError::TimedOut, Some(stale)) => {
+            respond(stale, CacheStatus::Stale, headers, config)
+        }
+        (FetchError::TimedOut, None) => {
+            json_error(StatusCode::GATEWAY_TIMEOUT, "GitHub did not answer in time")
+        }
+        (FetchError::Pending, None) => still_computing(config.upstream.stats_retry_delay),
+        (FetchError::Failed(status), _) => error_response(status),
+        (FetchError::Unreachable, _) => {
+            json_error(StatusCode::BAD_GATEWAY, "GitHub could not be reached")
+        }
+        (FetchError::CircuitOpen(_), Some(stale)) => {
+            respond(stale, CacheStatus::Stale, headers, config)
+        }
+        (FetchError::CircuitOpen(wait), None) => {
+            service_unavailable("GitHub is failing; requests to it are paused", wait)
+        }
+        (FetchError::RateLimited(_), Some(stale)) => {
+            respond(stale, CacheStatus::Stale, headers, config)
+        }
+        (FetchError::RateLimited(wait), None) => {
+            service_unavailable("GitHub's rate limit is exhausted", wait)
+        }
+        // Redirected again after following one move; not chased further.
+        (FetchError::Moved(_), _) => error_response(StatusCode::BAD_GATEWAY),
+        (FetchError::Upstream { status, message }, _) => {
+            let mut body = json!({
+                "error": format!("GitHub responded with {status}"),
+                "upstream_status": status.as_u16(),
+            });
+            if let Some(message) = message {
+                body["github_message"] = message.into();
+            }
+            json_body(StatusCode::BAD_GATEWAY, body)
+        }
+    }
+}
+
+/// Builds every proxied response, so `X-Cache` and the caching headers that
+/// depend on it are decided in exactly one place. A client whose
+/// `If-None-Match` names the entry's ETag gets a 304 instead.
+fn respond(
+    entry: &CachedResponse,
+    cache_status: CacheStatus,
+    headers: &HeaderMap,
+    config: &Config,
+) -> Response {
+    let mut response = cors_response(entry, headers, config);
+    let response_headers = response.headers_mut();
+    if matches!(cache_status, CacheStatus::Pass | CacheStatus::Bypass) {
+        // Came straight from GitHub and must not be kept anywhere else either.
+        response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    } else {
+        let age = entry.stored_at.elapsed();
+        freshness::client_headers(entry, age, &config.client_cache, response_headers);
+        // Paired with `max-age` this lets downstream caches work out how much
+        // freshness is left; stale entries will (correctly) exceed it.
+        response_headers.insert(header::AGE, HeaderValue::from(age.as_secs()));
+    }
+    response_headers.insert("x-cache", cache_status.header_value());
+    let etag = entry.headers.get(header::ETAG);
+    if entry.status == StatusCode::OK && freshness::etag_matches(headers, etag) {
+        let (mut parts, _) = response.into_parts();
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+    if let Some(signer) = &config.signer {
+        signer.sign(response_headers, &entry.body);
+    }
+    response
+}
+
+/// A `Streamed` body on its way to the client, with the headers `respond`
+/// would give it for a pass.
+fn respond_streamed(streamed: Streamed, headers: &HeaderMap, config: &Config) -> Response {
+    let head = CachedResponse {
+        status: streamed.status,
+        body: Bytes::new(),
+        headers: streamed.headers.clone(),
+        stored_at: Instant::now(),
+        ttl: Duration::ZERO,
+        purged: false,
+        immutable: false,
+        kind: BodyKind::Binary,
+    };
+    let (mut parts, _) = cors_response(&head, headers, config).into_parts();
+    parts.headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    parts.headers.insert("x-cache", CacheStatus::Pass.header_value());
+    Response::from_parts(parts, streamed.into_body())
+}
+
+async fn preflight(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let cors = &state.config.cors;
+    let mut response_headers = HeaderMap::new();
+    if let Some(origin) = allow_origin(&headers, cors) {
+        response_headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+    response_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, cors.allow_methods.clone());
+    response_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, cors.allow_headers.clone());
+    let max_age = HeaderValue::from(cors.max_age.as_secs());
+    response_headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age);
+    (StatusCode::OK, response_headers).into_response()
+}
+
+/// The request's own origin, or `*` when it sent none, which credentials
+/// rule out.
+fn allow_origin(headers: &HeaderMap, cors: &CorsConfig) -> Option<HeaderValue> {
+    // Reflecting the client's own value is a cheap refcount bump, unlike
+    // re-parsing it into a new HeaderValue.
+    match headers.get(header::ORIGIN).filter(|v| v.to_str().is_ok()) {
+        Some(origin) => Some(origin.clone()),
+        None if cors.allow_credentials => None,
+        None => Some(HeaderValue::from_static("*")),
+    }
+}
+
+#[inline(always)]
+fn cors_response(entry: &CachedResponse, headers: &HeaderMap, config: &Config) -> Response {
+    let mut response_headers = HeaderMap::with_capacity(4 + entry.headers.len());
+    if let Some(origin) = allow_origin(headers, &config.cors) {
+        response_headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+    // The allow-origin above is per origin, so shared caches must key on it.
+    // So is the body, by `Accept` media type.
+    response_headers.insert(header::VARY, HeaderValue::from_static("origin, accept"));
+    if !entry.headers.contains_key(header::CONTENT_TYPE) {
+        response_headers.insert(header::CONTENT_TYPE, entry.kind.default_content_type());
+    }
+    response_headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        config.expose_headers.clone(),
+    );
+    for (name, value) in &entry.headers {
+        response_headers.append(name, value.clone());
+    }
+    (entry.status, response_headers, entry.body.clone()).into_response()
+}
+
+/// A bare status. Like every response built here, it gets its allow-origin
+/// from `origin_fallback_middleware`.
+#[inline(always)]
+fn error_response(status: StatusCode) -> Response {
+    status.into_response()
+}
+
+/// An error that tells the client why, as `{"error": "..."}`.
+fn json_error(status: StatusCode, message: &str) -> Response {
+    json_body(status, json!({ "error": message }))
+}
+
+fn json_body(status: StatusCode, body: serde_json::Value) -> Response {
+    (status, Json(body)).into_response()
+}
+
+/// GitHub's 202, passed on with what it means and when to ask again.
+fn still_computing(retry_after: Duration) -> Response {
+    let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let body = json!({
+        "error": "GitHub is still computing these statistics",
+        "retry_after": retry_after,
+    });
+    let mut response = json_body(StatusCode::ACCEPTED, body);
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+/// The one way to build a proxy-generated 503, so none goes out without a
+/// reason and a `Retry-After` of at least a second.
+fn service_unavailable(reason: &str, retry_after: Duration) -> Response {
+    let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let body = json!({ "error": reason, "retry_after": retry_after });
+    let mut response = (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
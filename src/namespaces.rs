@@ -0,0 +1,81 @@
+use serde::{Serialize, Serializer};
+
+use crate::config::GithubUrls;
+
+/// Top-level parts of the REST API that `API_NAMESPACES` may open up next to
+/// `/repos`. All are names GitHub reserves, so no account can own them and a
+/// path starting with one can't be meant as a repository: `/users/octocat`
+/// is never the `octocat` repository of a `users` account.
+///
+/// Read-only and public data only. `/user`, answered for whoever owns our
+/// token, is deliberately not among them.
+const KNOWN: [&str; 10] = [
+    "users",
+    "orgs",
+    "search",
+    "rate_limit",
+    "gists",
+    "licenses",
+    "emojis",
+    "gitignore",
+    "events",
+    "meta",
+];
+
+/// The API namespaces proxied besides repositories, from `API_NAMESPACES`.
+/// Requests for them are served at the same paths as in the API
+/// (`/users/octocat`, `/search/repositories?q=...`) and cached like any
+/// other; everything else is a repository path, as always.
+pub struct Namespaces {
+    enabled: Vec<&'static str>,
+}
+
+impl Namespaces {
+    pub fn parse(names: &[String]) -> Result<Self, String> {
+        let enabled = names
+            .iter()
+            .map(|name| {
+                let name = name.trim().trim_matches('/').to_ascii_lowercase();
+                KNOWN.into_iter().find(|known| *known == name).ok_or_else(|| {
+                    format!("API_NAMESPACES: {name:?} is not one of {}", KNOWN.join(", "))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { enabled })
+    }
+
+    /// The namespace `path` (a cache key, so without the leading slash) is
+    /// in, if it is one of ours.
+    pub fn of(&self, path: &str) -> Option<&'static str> {
+        let first = path.split(['/', '?', '#']).next()?;
+        self.enabled
+            .iter()
+            .copied()
+            .find(|name| first.eq_ignore_ascii_case(name))
+    }
+
+    /// Every namespace served, repositories first.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = vec!["repos"];
+        names.extend(&self.enabled);
+        names
+    }
+
+    /// Where `key` is fetched from.
+    pub fn upstream_url(&self, github: &GithubUrls, key: &str) -> String {
+        let prefix = match self.of(key) {
+            Some(_) => &github.api,
+            None => &github.repos,
+        };
+        let mut url = String::with_capacity(prefix.len() + key.len());
+        url.push_str(prefix);
+        url.push_str(key);
+        url
+    }
+}
+
+impl Serialize for Namespaces {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.enabled)
+    }
+}
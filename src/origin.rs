@@ -0,0 +1,186 @@
+use axum::http::{header, HeaderMap};
+use serde::{Serialize, Serializer};
+use std::{collections::HashSet, fmt, str::FromStr};
+
+/// An origin as written in configuration.
+///
+/// Accepted forms:
+/// - `https://app.example.com` — that exact origin
+/// - `app.example.com` — that host over either http or https
+/// - `*.example.com` — any subdomain, either scheme
+/// - `https://*.example.com` — any subdomain, https only
+#[derive(Clone, Debug)]
+pub struct OriginPattern {
+    scheme: Option<String>,
+    host: HostPattern,
+}
+
+#[derive(Clone, Debug)]
+enum HostPattern {
+    Exact(String),
+    /// Stored with its leading dot, e.g. `.example.com`.
+    Subdomain(String),
+}
+
+impl OriginPattern {
+    pub fn matches(&self, origin: &str) -> bool {
+        let Some((scheme, host)) = origin.split_once("://") else {
+            return false;
+        };
+        let scheme_ok = match &self.scheme {
+            Some(expected) => scheme.eq_ignore_ascii_case(expected),
+            None => scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http"),
+        };
+        if !scheme_ok {
+            return false;
+        }
+
+        match &self.host {
+            HostPattern::Exact(expected) => host.eq_ignore_ascii_case(expected),
+            HostPattern::Subdomain(suffix) => {
+                host.len() > suffix.len()
+                    && host.is_char_boundary(host.len() - suffix.len())
+                    && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+        }
+    }
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, host) = match s.split_once("://") {
+            Some((scheme, host)) => (Some(scheme.to_ascii_lowercase()), host),
+            None => (None, s),
+        };
+        let host = host.trim_end_matches('/').to_ascii_lowercase();
+
+        if host.is_empty() || host.contains(['/', ' ', ',']) {
+            return Err(format!("invalid origin pattern {s:?}"));
+        }
+
+        let host = match host.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') && suffix.len() > 1 => {
+                HostPattern::Subdomain(suffix.to_owned())
+            }
+            Some(_) => return Err(format!("invalid origin pattern {s:?}")),
+            None if host.contains('*') => return Err(format!("invalid origin pattern {s:?}")),
+            None => HostPattern::Exact(host),
+        };
+
+        Ok(Self { scheme, host })
+    }
+}
+
+impl fmt::Display for OriginPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}://")?;
+        }
+        match &self.host {
+            HostPattern::Exact(host) => f.write_str(host),
+            HostPattern::Subdomain(suffix) => write!(f, "*{suffix}"),
+        }
+    }
+}
+
+impl Serialize for OriginPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// `ALLOWED_ORIGINS`, compiled for the per-request check: exact origins are
+/// one hash lookup, and only wildcard patterns are tried one by one.
+#[derive(Clone, Debug)]
+pub struct OriginAllowlist {
+    /// As configured, for display.
+    patterns: Vec<OriginPattern>,
+    /// Lowercase `scheme://host` of every pattern without a wildcard.
+    exact: HashSet<String>,
+    wildcards: Vec<OriginPattern>,
+}
+
+impl OriginAllowlist {
+    pub fn new(patterns: Vec<OriginPattern>) -> Self {
+        let mut exact = HashSet::new();
+        let mut wildcards = Vec::new();
+        for pattern in &patterns {
+            match (&pattern.scheme, &pattern.host) {
+                (Some(scheme), HostPattern::Exact(host)) => {
+                    exact.insert(format!("{scheme}://{host}"));
+                }
+                (None, HostPattern::Exact(host)) => {
+                    exact.insert(format!("https://{host}"));
+                    exact.insert(format!("http://{host}"));
+                }
+                (_, HostPattern::Subdomain(_)) => wildcards.push(pattern.clone()),
+            }
+        }
+        Self {
+            patterns,
+            exact,
+            wildcards,
+        }
+    }
+
+    pub fn allows(&self, origin: &str) -> bool {
+        let found = if origin.bytes().any(|b| b.is_ascii_uppercase()) {
+            self.exact.contains(&origin.to_ascii_lowercase())
+        } else {
+            self.exact.contains(origin)
+        };
+        found || self.wildcards.iter().any(|pattern| pattern.matches(origin))
+    }
+
+    pub fn patterns(&self) -> &[OriginPattern] {
+        &self.patterns
+    }
+}
+
+impl Serialize for OriginAllowlist {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+/// The request's `Origin`, if it sent one, or why it can't be used: sent
+/// more than once, not a single well-formed origin, or overlong. Nothing that
+/// fails here is ever matched against an allowlist or reflected back.
+pub fn from_headers(headers: &HeaderMap, max_len: usize) -> Result<Option<&str>, &'static str> {
+    let mut values = headers.get_all(header::ORIGIN).iter();
+    let Some(value) = values.next() else {
+        return Ok(None);
+    };
+    if values.next().is_some() {
+        return Err("multiple origin headers");
+    }
+    // Anything this long is garbage, and we'd be echoing it back.
+    if value.len() > max_len {
+        return Err("origin header is too long");
+    }
+    match value.to_str() {
+        Ok(origin) if is_well_formed(origin) => Ok(Some(origin)),
+        _ => Err("origin header is malformed"),
+    }
+}
+
+/// `scheme://host[:port]` and nothing else, or the opaque `null`. Rules out
+/// lists, whitespace, paths and userinfo.
+fn is_well_formed(origin: &str) -> bool {
+    if origin == "null" {
+        return true;
+    }
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let mut scheme = scheme.bytes();
+    scheme.next().is_some_and(|b| b.is_ascii_alphabetic())
+        && scheme.all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+        && !authority.is_empty()
+        && !authority.starts_with(':')
+        && authority
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._:[]".contains(&b))
+}
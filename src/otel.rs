@@ -0,0 +1,332 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use metrics::counter;
+use reqwest::Client;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::{config::OtelConfig, AppState};
+
+/// Spans waiting for the next export; any more are dropped.
+const MAX_QUEUED_SPANS: usize = 4096;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+tokio::task_local! {
+    /// The trace of the request being handled, for spans started within it.
+    static CURRENT: Context;
+}
+
+#[derive(Clone)]
+struct Context {
+    trace_id: [u8; 16],
+    /// The request's own span, parent to the rest.
+    span_id: [u8; 8],
+    sampled: bool,
+    exporter: Arc<Exporter>,
+}
+
+/// Sends finished spans to the collector at `OTEL_EXPORTER_OTLP_ENDPOINT`,
+/// in batches, as OTLP over HTTP with JSON.
+pub struct Exporter {
+    config: OtelConfig,
+    client: Client,
+    queued: Mutex<Vec<Value>>,
+}
+
+impl Exporter {
+    pub fn new(config: &OtelConfig) -> Self {
+        Self {
+            config: config.clone(),
+            client: Client::new(),
+            queued: Mutex::default(),
+        }
+    }
+
+    fn push(&self, span: Value) {
+        let mut queued = self.queued.lock().unwrap();
+        if queued.len() >= MAX_QUEUED_SPANS {
+            counter!("proxy_otel_spans_dropped_total").increment(1);
+            return;
+        }
+        queued.push(span);
+    }
+
+    async fn flush(&self) {
+        let spans = std::mem::take(&mut *self.queued.lock().unwrap());
+        if spans.is_empty() {
+            return;
+        }
+        let count = spans.len() as u64;
+        let service = attribute("service.name", &self.config.service_name);
+        let scope = json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") });
+        let body = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [service] },
+                "scopeSpans": [{ "scope": scope, "spans": spans }],
+            }],
+        });
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.config.headers {
+            headers.insert(name, value.clone());
+        }
+        let sent = self
+            .client
+            .post(&self.config.endpoint)
+            .timeout(EXPORT_TIMEOUT)
+            .headers(headers)
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await;
+        match sent {
+            Ok(response) if response.status().is_success() => {
+                counter!("proxy_otel_spans_exported_total").increment(count);
+            }
+            Ok(response) => {
+                counter!("proxy_otel_spans_dropped_total").increment(count);
+                warn!(status = %response.status(), "the trace collector refused {count} span(s)");
+            }
+            Err(err) => {
+                counter!("proxy_otel_spans_dropped_total").increment(count);
+                warn!("cannot export {count} span(s): {err}");
+            }
+        }
+    }
+}
+
+/// Exports every `OTEL_BSP_SCHEDULE_DELAY`, and once more at shutdown.
+pub async fn run(exporter: Arc<Exporter>, shutdown: CancellationToken) {
+    let mut ticker = tokio::time::interval(exporter.config.schedule_delay);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => exporter.flush().await,
+        }
+    }
+    exporter.flush().await;
+}
+
+/// Traces each request as a server span, continuing the trace of an
+/// incoming `traceparent` or else starting one, sampled at
+/// `OTEL_TRACES_SAMPLER_ARG`.
+pub async fn trace_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(exporter) = &state.otel else {
+        return next.run(request).await;
+    };
+    let parent = request
+        .headers()
+        .get("traceparent")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_traceparent);
+    let (trace_id, parent_id, sampled) = match parent {
+        Some((trace_id, parent_id, sampled)) => (trace_id, Some(parent_id), sampled),
+        None => {
+            let trace_id: [u8; 16] = random();
+            let sampled = sampled(&trace_id, exporter.config.sample_ratio);
+            (trace_id, None, sampled)
+        }
+    };
+    let context = Context {
+        trace_id,
+        span_id: random(),
+        sampled,
+        exporter: exporter.clone(),
+    };
+    let mut span = Span {
+        inner: sampled.then(|| Inner {
+            context: context.clone(),
+            span_id: context.span_id,
+            parent_id,
+            name: request.method().to_string(),
+            kind: Kind::Server,
+            start: SystemTime::now(),
+            attributes: vec![
+                attribute("http.request.method", request.method().as_str()),
+                attribute("url.path", request.uri().path()),
+            ],
+            failed: false,
+        }),
+    };
+    let response = CURRENT.scope(context, next.run(request)).await;
+    span.set_int("http.response.status_code", response.status().as_u16().into());
+    if let Some(cache) = response.headers().get("x-cache").and_then(|v| v.to_str().ok()) {
+        span.set("proxy.cache", cache);
+    }
+    if response.status().is_server_error() {
+        span.fail();
+    }
+    response
+}
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// A span within the trace of the request being handled, exported when
+/// dropped; inert outside a sampled request.
+pub struct Span {
+    inner: Option<Inner>,
+}
+
+struct Inner {
+    context: Context,
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    name: String,
+    kind: Kind,
+    start: SystemTime,
+    attributes: Vec<Value>,
+    failed: bool,
+}
+
+impl Span {
+    pub fn start(name: &str, kind: Kind) -> Self {
+        let context = CURRENT.try_with(Context::clone).ok();
+        Self {
+            inner: context.filter(|c| c.sampled).map(|context| Inner {
+                span_id: random(),
+                parent_id: Some(context.span_id),
+                context,
+                name: name.to_owned(),
+                kind,
+                start: SystemTime::now(),
+                attributes: Vec::new(),
+                failed: false,
+            }),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        if let Some(inner) = &mut self.inner {
+            inner.attributes.push(attribute(key, value));
+        }
+    }
+
+    pub fn set_int(&mut self, key: &str, value: i64) {
+        if let Some(inner) = &mut self.inner {
+            inner
+                .attributes
+                .push(json!({ "key": key, "value": { "intValue": value.to_string() } }));
+        }
+    }
+
+    pub fn fail(&mut self) {
+        if let Some(inner) = &mut self.inner {
+            inner.failed = true;
+        }
+    }
+
+    /// The `traceparent` to send on, naming this span as the parent of
+    /// whatever is done with it; unsampled requests still pass the trace on.
+    pub fn traceparent(&self) -> Option<HeaderValue> {
+        if let Some(inner) = &self.inner {
+            return format_traceparent(&inner.context.trace_id, &inner.span_id, true);
+        }
+        let context = CURRENT.try_with(Context::clone).ok()?;
+        format_traceparent(&context.trace_id, &context.span_id, context.sampled)
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(inner) = self.inner.take() else {
+            return;
+        };
+        let nanos = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos())
+                .to_string()
+        };
+        let mut span = json!({
+            "traceId": hex(&inner.context.trace_id),
+            "spanId": hex(&inner.span_id),
+            "name": inner.name,
+            "kind": inner.kind as u8,
+            "startTimeUnixNano": nanos(inner.start),
+            "endTimeUnixNano": nanos(SystemTime::now()),
+            "attributes": inner.attributes,
+            "status": { "code": if inner.failed { 2 } else { 0 } },
+        });
+        if let Some(parent_id) = inner.parent_id {
+            span["parentSpanId"] = hex(&parent_id).into();
+        }
+        inner.context.exporter.push(span);
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// `00-<trace id>-<parent id>-<flags>`, as W3C Trace Context has it.
+fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], bool)> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if version != "00" || parts.next().is_some() {
+        return None;
+    }
+    let trace_id: [u8; 16] = unhex(trace_id)?;
+    let parent_id: [u8; 8] = unhex(parent_id)?;
+    let [flags]: [u8; 1] = unhex(flags)?;
+    // All zeros is invalid, for either id.
+    if trace_id == [0; 16] || parent_id == [0; 8] {
+        return None;
+    }
+    Some((trace_id, parent_id, flags & 1 == 1))
+}
+
+fn format_traceparent(
+    trace_id: &[u8; 16],
+    span_id: &[u8; 8],
+    sampled: bool,
+) -> Option<HeaderValue> {
+    let flags = if sampled { "01" } else { "00" };
+    HeaderValue::try_from(format!("00-{}-{}-{flags}", hex(trace_id), hex(span_id))).ok()
+}
+
+/// Whether a new trace is kept, decided by its id so every service that
+/// samples by ratio makes the same call.
+fn sampled(trace_id: &[u8; 16], ratio: f64) -> bool {
+    let low = u64::from_be_bytes(trace_id[8..].try_into().expect("eight bytes"));
+    (low as f64) < ratio * u64::MAX as f64
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    let _ = SystemRandom::new().fill(&mut bytes);
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let lowercase = |b: u8| b.is_ascii_digit() || (b'a'..=b'f').contains(&b);
+    if hex.len() != N * 2 || !hex.bytes().all(lowercase) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
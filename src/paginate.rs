@@ -0,0 +1,126 @@
+use axum::{
+    body,
+    extract::{Path, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use metrics::{counter, histogram};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::{
+    client_ip::ClientIp, config::PaginateConfig, content::BodyKind, json_body, json_error, paths,
+    proxy_handler, ratelimit::too_many_requests, tokens::GithubToken, AppState,
+};
+
+/// Every page of a list at once: `GET /__all/owner/repo/releases` follows
+/// GitHub's `Link: rel="next"` for up to `PAGINATE_MAX_PAGES` pages and
+/// answers with one JSON array, instead of leaving the page to walk the
+/// links a round trip at a time. Each page goes through the cache like any
+/// other request and is charged to the origin's rate limit as one; a list
+/// longer than the cap is cut short, marked by `X-Pages-Truncated`.
+pub fn router(config: &PaginateConfig) -> Router<AppState> {
+    if config.max_pages == 0 {
+        return Router::new();
+    }
+    Router::new().route("/__all/*path", get(all_pages))
+}
+
+async fn all_pages(
+    Path(path): Path<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    token: Option<Extension<Arc<GithubToken>>>,
+    State(state): State<AppState>,
+) -> Response {
+    if paths::is_download(&path) {
+        return json_error(StatusCode::BAD_REQUEST, "downloads can't be paginated");
+    }
+    // As in `/__batch`: only the origin is passed on, so every page shares
+    // cache entries and coalesced fetches with plain requests for it.
+    let mut sub_headers = HeaderMap::new();
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        sub_headers.insert(header::ORIGIN, origin.clone());
+    }
+    let origin = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok());
+    counter!("proxy_paginated_requests_total").increment(1);
+
+    let max_pages = state.config.paginate.max_pages;
+    let mut items = Vec::new();
+    let mut pages = 0;
+    let mut next = Some(query);
+    while let Some(query) = next.take() {
+        if pages == max_pages {
+            next = Some(query);
+            break;
+        }
+        // The first page was charged on the way in.
+        let charged = origin.filter(|_| pages > 0);
+        if let Some(Err(retry_after)) = charged.and_then(|o| state.rate_limiter.check_origin(o)) {
+            counter!("proxy_rate_limited_total", "scope" => "origin").increment(1);
+            return too_many_requests(retry_after);
+        }
+        let response = proxy_handler(
+            Path(path.clone()),
+            RawQuery(query),
+            sub_headers.clone(),
+            client_ip,
+            token.clone(),
+            State(state.clone()),
+        )
+        .await;
+        pages += 1;
+        if !response.status().is_success() {
+            return response;
+        }
+        // GitHub's links often name the repository by id rather than by
+        // name; the query is all that changes from page to page.
+        next = response
+            .headers()
+            .get(header::LINK)
+            .and_then(|v| v.to_str().ok())
+            .and_then(next_query)
+            .map(Some);
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let Ok(body) = body::to_bytes(response.into_body(), usize::MAX).await else {
+            return json_error(StatusCode::BAD_GATEWAY, "response was cut short");
+        };
+        if BodyKind::classify(content_type.as_ref(), &body) != BodyKind::Json {
+            return json_error(StatusCode::BAD_REQUEST, "only JSON lists can be paginated");
+        }
+        match serde_json::from_slice(&body) {
+            Ok(Value::Array(page)) => items.extend(page),
+            _ => return json_error(StatusCode::BAD_REQUEST, "only JSON lists can be paginated"),
+        }
+    }
+    histogram!("proxy_paginated_pages").record(pages as f64);
+
+    let mut response = json_body(StatusCode::OK, Value::Array(items));
+    let response_headers = response.headers_mut();
+    response_headers.insert("x-pages", HeaderValue::from(pages));
+    if next.is_some() {
+        counter!("proxy_paginated_truncated_total").increment(1);
+        response_headers.insert("x-pages-truncated", HeaderValue::from_static("true"));
+    }
+    response_headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("x-pages, x-pages-truncated"),
+    );
+    response
+}
+
+/// The query of the `rel="next"` URL in a `Link` header.
+fn next_query(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        let next = params
+            .split(';')
+            .any(|param| param.trim().replace(' ', "") == r#"rel="next""#);
+        let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+        let (_, query) = url.split_once('?')?;
+        next.then(|| query.to_owned())
+    })
+}
@@ -0,0 +1,99 @@
+use axum::{http::StatusCode, response::Response};
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    panic::PanicHookInfo,
+};
+use tower_http::catch_panic::CatchPanicLayer;
+use tracing::error;
+
+use crate::json_error;
+
+/// The log target of panic reports, which error reporting skips since it
+/// catches panics itself.
+pub const TARGET: &str = "github_cors_proxy::panic";
+
+/// Replaces the default hook, which writes straight to stderr, with one that
+/// logs the panic and, under `RUST_BACKTRACE=1`, where it happened.
+pub fn install_hook() {
+    std::panic::set_hook(Box::new(|info: &PanicHookInfo<'_>| {
+        let location = info
+            .location()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            error!(target: TARGET, %location, "panic: {}\n{backtrace}", message(info.payload()));
+        } else {
+            error!(target: TARGET, %location, "panic: {}", message(info.payload()));
+        }
+    }));
+}
+
+/// Turns a panic anywhere below it into a 500 the browser can read, instead
+/// of a dropped connection it reports as a network error.
+pub fn layer() -> CatchPanicLayer<fn(Box<dyn Any + Send>) -> Response> {
+    CatchPanicLayer::custom(internal_error as fn(Box<dyn Any + Send>) -> Response)
+}
+
+fn internal_error(_payload: Box<dyn Any + Send>) -> Response {
+    // The hook has logged the payload already.
+    json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(non-string payload)")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app_state, config::Config, outer_layers};
+    use axum::{
+        body::{self, Body},
+        extract::connect_info::MockConnectInfo,
+        http::{header, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    async fn boom() -> &'static str {
+        panic!("boom")
+    }
+
+    #[tokio::test]
+    async fn a_panic_is_a_readable_500_and_serving_goes_on() {
+        std::env::set_var("GITHUB_TOKEN", "test-token");
+        let state = app_state(Config::from_env().unwrap()).await.unwrap();
+        let routes = Router::new()
+            .route("/boom", get(boom))
+            .route("/fine", get(|| async { "fine" }));
+        let client: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let router = outer_layers(routes, &state).layer(MockConnectInfo(client));
+        let get = |path: &str| {
+            Request::get(path)
+                .header(header::ORIGIN, "https://prigoana.com")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(get("/boom")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://prigoana.com");
+        assert!(headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("application/json"));
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "internal error");
+
+        for _ in 0..3 {
+            let response = router.clone().oneshot(get("/fine")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
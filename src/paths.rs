@@ -0,0 +1,215 @@
+use serde::{Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// A glob over request paths (without the leading slash or query string),
+/// used wherever configuration needs to pick out a class of endpoints.
+///
+/// `*` matches any run of characters, slashes included, so `*/actions/*`
+/// covers every repository's actions endpoints, and `*/*` matches
+/// `o/r/releases` as well as `o/r`. There is no other wildcard: the rest of
+/// the pattern must match the whole path exactly, a leading slash aside.
+/// Matching ignores ASCII case, as GitHub does for owner and repository
+/// names.
+#[derive(Clone, Debug)]
+pub struct PathPattern {
+    pattern: String,
+}
+
+impl PathPattern {
+    pub fn matches(&self, path: &str) -> bool {
+        glob(self.pattern.as_bytes(), path.trim_start_matches('/').as_bytes())
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = s.trim().trim_start_matches('/');
+        if pattern.is_empty() {
+            return Err(format!("invalid path pattern {s:?}"));
+        }
+        Ok(Self {
+            pattern: pattern.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl Serialize for PathPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Whether any of `patterns` matches `path`.
+pub fn any_match(patterns: &[PathPattern], path: &str) -> bool {
+    patterns.iter().any(|p| p.matches(path))
+}
+
+/// Release assets and source archives: the endpoints that serve files,
+/// which are passed straight through with their range headers.
+pub fn is_download(path: &str) -> bool {
+    let mut segments = path.trim_start_matches('/').split('/').skip(2);
+    matches!(
+        (segments.next(), segments.next()),
+        (Some("releases"), Some("assets")) | (Some("tarball" | "zipball"), _)
+    )
+}
+
+/// Broad kinds of endpoint, by API path (`repos/...`, `search/...`), for
+/// rules and reporting that go by what is asked for rather than a pattern.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PathClass {
+    /// A repository itself, `repos/{owner}/{repo}`.
+    Repo,
+    Releases,
+    Contents,
+    /// GitHub's repository statistics, which it computes on demand and
+    /// answers with a 202 until they are ready.
+    Stats,
+    Search,
+    Other,
+}
+
+impl PathClass {
+    pub const ALL: [Self; 6] = [
+        Self::Repo,
+        Self::Releases,
+        Self::Contents,
+        Self::Stats,
+        Self::Search,
+        Self::Other,
+    ];
+
+    pub fn of(api_path: &str) -> Self {
+        let path = api_path.split(['?', '#']).next().unwrap_or_default();
+        let mut segments = path.trim_start_matches('/').split('/');
+        match segments.next() {
+            Some("search") => return Self::Search,
+            Some("repos") => {}
+            _ => return Self::Other,
+        }
+        let (_owner, _repo) = (segments.next(), segments.next());
+        match segments.next() {
+            None | Some("") => Self::Repo,
+            Some("releases") => Self::Releases,
+            Some("contents" | "readme") => Self::Contents,
+            Some("stats") => Self::Stats,
+            Some(_) => Self::Other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Repo => "repo",
+            Self::Releases => "releases",
+            Self::Contents => "contents",
+            Self::Stats => "stats",
+            Self::Search => "search",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Requests pinned to a full commit SHA, whose answer can never change: a
+/// commit or git object asked for by SHA, or anything at `?ref=<sha>`.
+/// Branch names that merely look like hex are shorter than 40 characters.
+pub fn is_immutable(key: &str) -> bool {
+    let (path, query) = match key.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (key, None),
+    };
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let pinned_path = match segments.as_slice() {
+        [_, _, "commits", sha] | [_, _, "git", "blobs" | "trees" | "commits" | "tags", sha] => {
+            is_sha(sha)
+        }
+        _ => false,
+    };
+    pinned_path
+        || query.is_some_and(|query| {
+            query
+                .split('&')
+                .any(|param| param.strip_prefix("ref=").is_some_and(is_sha))
+        })
+}
+
+pub fn is_sha(s: &str) -> bool {
+    s.len() == 40 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Iterative wildcard match with single-star backtracking: linear in
+/// practice and immune to pathological patterns.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t].to_ascii_lowercase() {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "0123456789abcdef0123456789ABCDEF01234567";
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        pattern.parse::<PathPattern>().unwrap().matches(path)
+    }
+
+    #[test]
+    fn a_star_matches_across_slashes() {
+        assert!(matches("*/*", "o/r"));
+        assert!(matches("*/*", "o/r/releases/latest"));
+        assert!(matches("*/actions/*", "/o/r/actions/runs"));
+        assert!(!matches("*/actions/*", "o/r/actions"));
+    }
+
+    #[test]
+    fn the_rest_of_a_pattern_matches_the_whole_path() {
+        assert!(matches("/O/R/Releases", "o/r/releases"));
+        assert!(!matches("o/r", "o/r/releases"));
+        assert!(!matches("o/r/releases", "o/r"));
+        assert!(!matches("o/?", "o/r"));
+        assert!("/".parse::<PathPattern>().is_err());
+    }
+
+    #[test]
+    fn only_a_full_sha_pins_a_request() {
+        assert!(is_sha(SHA));
+        assert!(!is_sha(&SHA[..39]));
+        assert!(!is_sha(&SHA.replace('0', "g")));
+
+        assert!(is_immutable(&format!("o/r/commits/{SHA}")));
+        assert!(is_immutable(&format!("/o/r/git/trees/{SHA}")));
+        assert!(is_immutable(&format!("o/r/contents/README.md?ref={SHA}")));
+        assert!(is_immutable(&format!("o/r/readme?page=1&ref={SHA}")));
+        assert!(!is_immutable("o/r/commits/main"));
+        assert!(!is_immutable("o/r/commits/deadbeef"));
+        assert!(!is_immutable("o/r/contents/README.md?ref=main"));
+        assert!(!is_immutable(&format!("o/r/commits/{SHA}/comments")));
+        assert!(!is_immutable(&format!("o/r/branches/{SHA}")));
+    }
+}
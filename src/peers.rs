@@ -0,0 +1,180 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use metrics::counter;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::{
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{
+    admin::constant_time_eq, client_ip::ClientIp, config::PeerConfig, error_response, AppState,
+};
+
+/// Carries the shared secret on requests between replicas; its presence is
+/// also what stops a forwarded request from being forwarded again.
+pub const SECRET_HEADER: HeaderName = HeaderName::from_static("x-peer-secret");
+/// The client a forwarded request is really for.
+const CLIENT_HEADER: HeaderName = HeaderName::from_static("x-peer-client");
+
+/// How long a replica that couldn't be reached is left out of the ring.
+const DOWN_FOR: Duration = Duration::from_secs(10);
+
+/// Marks a request forwarded by another replica.
+#[derive(Clone, Copy)]
+pub struct FromPeer;
+
+struct Peer {
+    url: String,
+    down_until: Mutex<Option<Instant>>,
+}
+
+/// Replicas sharing one cache between them: every key belongs to one of them
+/// by rendezvous hashing, and the others forward their misses for it to that
+/// owner instead of asking GitHub themselves. A replica that can't be
+/// reached is skipped for a while, its keys spread over the rest.
+pub struct Peers {
+    peers: Vec<Peer>,
+    own_url: String,
+    secret: HeaderValue,
+    timeout: Duration,
+    client: Client,
+}
+
+impl Peers {
+    pub fn new(config: &PeerConfig) -> Self {
+        let mut secret =
+            HeaderValue::from_str(&config.secret).expect("PEER_SECRET is a valid header value");
+        secret.set_sensitive(true);
+        Self {
+            peers: config
+                .urls
+                .iter()
+                .map(|url| Peer {
+                    url: url.clone(),
+                    down_until: Mutex::new(None),
+                })
+                .collect(),
+            own_url: config.own_url.clone(),
+            secret,
+            timeout: config.timeout,
+            client: Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("peer client builds"),
+        }
+    }
+
+    /// The replica responsible for `key`, unless it is this one.
+    fn owner(&self, key: &str) -> Option<&Peer> {
+        let now = Instant::now();
+        let owner = self
+            .peers
+            .iter()
+            .filter(|peer| {
+                peer.url == self.own_url
+                    || peer.down_until.lock().unwrap().is_none_or(|until| until <= now)
+            })
+            .max_by_key(|peer| score(&peer.url, key))?;
+        (owner.url != self.own_url).then_some(owner)
+    }
+
+    /// Asks `key`'s owner for it, if that's another replica and it answers.
+    pub async fn forward(
+        &self,
+        key: &str,
+        forwarded: HeaderMap,
+        client: Option<IpAddr>,
+    ) -> Option<Response> {
+        let owner = self.owner(key)?;
+        let mut request = self
+            .client
+            .get(format!("{}/{key}", owner.url))
+            .timeout(self.timeout)
+            .headers(forwarded)
+            .header(SECRET_HEADER, self.secret.clone());
+        if let Some(client) = client {
+            request = request.header(CLIENT_HEADER, client.to_string());
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) => return owner.unreachable(err),
+        };
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(err) => return owner.unreachable(err),
+        };
+        counter!("proxy_peer_forwards_total").increment(1);
+
+        // Built bare, so the owner's headers are the only ones it has.
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        for (name, value) in &headers {
+            if !is_hop_by_hop(name) {
+                response.headers_mut().append(name, value.clone());
+            }
+        }
+        Some(response)
+    }
+}
+
+impl Peer {
+    fn unreachable(&self, err: reqwest::Error) -> Option<Response> {
+        warn!(peer = self.url, "peer unreachable, asking GitHub directly: {err}");
+        counter!("proxy_peer_failures_total").increment(1);
+        *self.down_until.lock().unwrap() = Some(Instant::now() + DOWN_FOR);
+        None
+    }
+}
+
+/// Rendezvous hashing: every replica agrees on the highest-scoring peer for
+/// a key, and losing one only moves the keys it owned.
+fn score(peer: &str, key: &str) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(peer.as_bytes())
+        .chain_update([0])
+        .chain_update(key.as_bytes())
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    name == header::CONNECTION || name == header::TRANSFER_ENCODING || name == "keep-alive"
+}
+
+/// Recognises requests forwarded by another replica: anything carrying the
+/// peer header must carry the right secret. For those the client is the one
+/// the forwarding replica names, and nothing is forwarded again.
+pub async fn peer_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(presented) = request.headers().get(SECRET_HEADER) else {
+        return next.run(request).await;
+    };
+    let expected = state.config.peers.as_ref().map(|peers| peers.secret.as_bytes());
+    if !expected.is_some_and(|expected| constant_time_eq(presented.as_bytes(), expected)) {
+        counter!("proxy_peer_rejected_total").increment(1);
+        return error_response(StatusCode::UNAUTHORIZED);
+    }
+    let client = request
+        .headers()
+        .get(CLIENT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    if let Some(client) = client {
+        request.extensions_mut().insert(ClientIp(client));
+    }
+    request.extensions_mut().insert(FromPeer);
+    next.run(request).await
+}
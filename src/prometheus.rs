@@ -0,0 +1,91 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, gauge};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::{sync::OnceLock, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+use crate::{cache, AppState};
+
+/// How often histograms are trimmed to their recent window.
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+const SECONDS_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+/// Powers of four, from 256 bytes to 64 MiB.
+const BYTES_BUCKETS: [f64; 10] = [
+    256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+    67108864.0,
+];
+
+/// Installs the recorder behind every `counter!`, `gauge!` and `histogram!`
+/// in the proxy, before any of them is reached. Durations and sizes become
+/// Prometheus histograms; anything else measured becomes a summary.
+///
+/// Only the first call installs it; later ones, from more routers built in
+/// the same process, get the same handle.
+pub fn install() -> PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), &SECONDS_BUCKETS)
+                .and_then(|b| {
+                    b.set_buckets_for_metric(Matcher::Suffix("_bytes".into()), &BYTES_BUCKETS)
+                })
+                .and_then(PrometheusBuilder::install_recorder)
+                .expect("the metrics recorder is installed once, with valid buckets")
+        })
+        .clone()
+}
+
+pub async fn upkeep(handle: PrometheusHandle, shutdown: CancellationToken) {
+    let mut ticker = tokio::time::interval(UPKEEP_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => handle.run_upkeep(),
+        }
+    }
+}
+
+/// `GET /metrics`, in the Prometheus text format. The cache's size is taken
+/// on the way.
+pub async fn render(State(state): State<AppState>) -> Response {
+    state.cache.run_pending_tasks().await;
+    gauge!("proxy_cache_entries").set(state.cache.entry_count() as f64);
+    let bytes = cache::memory_bytes(&state.cache, &state.config.cache);
+    gauge!("proxy_cache_bytes").set(bytes as f64);
+    let content_type = HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8");
+    ([(header::CONTENT_TYPE, content_type)], state.prometheus.render()).into_response()
+}
+
+/// Counts every response by status, and the requests still waiting for one.
+pub async fn track_middleware(request: Request, next: Next) -> Response {
+    let in_flight = InFlight::start();
+    let response = next.run(request).await;
+    drop(in_flight);
+    let status = response.status().as_u16().to_string();
+    counter!("proxy_requests_total", "status" => status).increment(1);
+    response
+}
+
+/// Counted out again however the request ends, disconnects included.
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        gauge!("proxy_requests_in_flight").increment(1.0);
+        Self
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        gauge!("proxy_requests_in_flight").decrement(1.0);
+    }
+}
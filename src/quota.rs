@@ -0,0 +1,325 @@
+use axum::http::{HeaderMap, HeaderValue};
+use metrics::{counter, gauge};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::tokens::{GithubToken, Tokens};
+
+/// The `X-RateLimit-*` headers of the last upstream response for one token
+/// and resource.
+#[derive(Clone, Serialize)]
+pub struct Observation {
+    limit: Option<u64>,
+    remaining: Option<u64>,
+    used: Option<u64>,
+    /// Unix time at which the window resets.
+    reset: Option<u64>,
+    /// Unix time the headers were seen.
+    observed_at: u64,
+}
+
+impl Observation {
+    /// Out of quota until a reset that hasn't happened yet.
+    fn exhausted(&self) -> bool {
+        self.remaining == Some(0) && self.reset.is_some_and(|reset| reset > self.observed_at)
+    }
+}
+
+#[derive(Serialize)]
+pub struct TokenQuota {
+    name: String,
+    /// The last few characters of the secret, enough to tell tokens apart.
+    suffix: String,
+    exhausted: bool,
+    resources: BTreeMap<String, ResourceQuota>,
+}
+
+#[derive(Serialize)]
+struct ResourceQuota {
+    #[serde(flatten)]
+    observation: Observation,
+    exhausted: bool,
+}
+
+/// The rate-limit resource GitHub bills a request for, going by its API
+/// path. Search has its own, much smaller, per-minute quota.
+pub fn resource_of(path: &str) -> &'static str {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    if path == "search/code" {
+        "code_search"
+    } else if path.starts_with("search/") {
+        "search"
+    } else if path == "graphql" {
+        "graphql"
+    } else {
+        "core"
+    }
+}
+
+/// GitHub's view of our quota, collected passively from the rate-limit
+/// headers on every upstream response, per token since each has its own.
+/// Expiry dates, which GitHub reports for fine-grained tokens, are picked up
+/// the same way.
+#[derive(Default)]
+pub struct QuotaTracker {
+    observed: Mutex<BTreeMap<(String, String), Observation>>,
+    /// Unix time each token expires at, by name.
+    expirations: Mutex<BTreeMap<String, u64>>,
+}
+
+impl QuotaTracker {
+    pub fn record(&self, token: &GithubToken, headers: &HeaderMap) {
+        counter!("proxy_upstream_requests_total", "token" => token.name.clone()).increment(1);
+        // Its quota is the client's to track, and one client's says nothing
+        // about the next.
+        if token.client {
+            return;
+        }
+
+        let expiration = headers
+            .get("github-authentication-token-expiration")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_expiration);
+        if let Some(expires_at) = expiration {
+            let days = expires_at.saturating_sub(now()) as f64 / SECS_PER_DAY as f64;
+            gauge!("proxy_github_token_days_remaining", "token" => token.name.clone()).set(days);
+            self.expirations
+                .lock()
+                .unwrap()
+                .insert(token.name.clone(), expires_at);
+        }
+
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        let Some(remaining) = number("x-ratelimit-remaining") else {
+            return;
+        };
+        let resource = headers
+            .get("x-ratelimit-resource")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("core")
+            .to_owned();
+        gauge!(
+            "proxy_github_ratelimit_remaining",
+            "token" => token.name.clone(),
+            "resource" => resource.clone()
+        )
+        .set(remaining as f64);
+        let observation = Observation {
+            limit: number("x-ratelimit-limit"),
+            remaining: Some(remaining),
+            used: number("x-ratelimit-used"),
+            reset: number("x-ratelimit-reset"),
+            observed_at: now(),
+        };
+        self.observed
+            .lock()
+            .unwrap()
+            .insert((token.name.clone(), resource), observation);
+    }
+
+    /// Brings the rate-limit headers of an answer, which may have been kept
+    /// since long ago, up to date with the last seen for `token` and the
+    /// same resource. Only headers already there are changed.
+    pub fn refresh_headers(&self, token: &GithubToken, headers: &mut HeaderMap) {
+        if !headers.contains_key("x-ratelimit-remaining") {
+            return;
+        }
+        let resource = headers
+            .get("x-ratelimit-resource")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("core")
+            .to_owned();
+        let observed = self.observed.lock().unwrap();
+        let Some(observation) = observed.get(&(token.name.clone(), resource)) else {
+            return;
+        };
+        let current = [
+            ("x-ratelimit-limit", observation.limit),
+            ("x-ratelimit-remaining", observation.remaining),
+            ("x-ratelimit-used", observation.used),
+            ("x-ratelimit-reset", observation.reset),
+        ];
+        for (name, value) in current {
+            if let Some(value) = value.filter(|_| headers.contains_key(name)) {
+                headers.insert(name, HeaderValue::from(value));
+            }
+        }
+    }
+
+    /// How long until `token` expires, as last reported by GitHub; zero
+    /// once it has.
+    pub fn expires_in(&self, token: &GithubToken) -> Option<Duration> {
+        let expires_at = *self.expirations.lock().unwrap().get(&token.name)?;
+        Some(Duration::from_secs(expires_at.saturating_sub(now())))
+    }
+
+    /// A warning for each token expiring within `horizon`, or already expired.
+    pub fn expiry_warnings(&self, horizon: Duration) -> Vec<String> {
+        let now = now();
+        self.expirations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now + horizon.as_secs())
+            .map(|(name, expires_at)| match expires_at.checked_sub(now) {
+                Some(left) => format!(
+                    "the {name} GitHub token expires in {} day(s)",
+                    left / SECS_PER_DAY
+                ),
+                None => format!("the {name} GitHub token has expired"),
+            })
+            .collect()
+    }
+
+    /// How long until `token` has `resource` quota again, while GitHub says
+    /// it has none left.
+    pub fn exhausted_for(&self, token: &GithubToken, resource: &str) -> Option<Duration> {
+        let observed = self.observed.lock().unwrap();
+        let observation = observed.get(&(token.name.clone(), resource.to_owned()))?;
+        let now = now();
+        let reset = observation.reset?;
+        (observation.remaining == Some(0) && reset > now)
+            .then(|| Duration::from_secs(reset - now))
+    }
+
+    /// What `token` has left of `resource` quota, and its limit, in a window
+    /// that hasn't reset since.
+    pub fn left(&self, token: &GithubToken, resource: &str) -> Option<(u64, u64)> {
+        let observed = self.observed.lock().unwrap();
+        let observation = observed.get(&(token.name.clone(), resource.to_owned()))?;
+        if observation.reset.is_none_or(|reset| reset <= now()) {
+            return None;
+        }
+        Some((observation.remaining?, observation.limit?))
+    }
+
+    /// Whichever of `tokens` has the most `resource` quota left, the first
+    /// on a tie. A token not seen yet, or whose window has reset since, is
+    /// taken to have all of it.
+    pub fn best_of<'a>(
+        &self,
+        tokens: impl IntoIterator<Item = &'a Arc<GithubToken>>,
+        resource: &str,
+    ) -> Option<&'a Arc<GithubToken>> {
+        let observed = self.observed.lock().unwrap();
+        let now = now();
+        let remaining = |token: &GithubToken| {
+            observed
+                .get(&(token.name.clone(), resource.to_owned()))
+                .filter(|observation| observation.reset.is_some_and(|reset| reset > now))
+                .and_then(|observation| observation.remaining)
+                .unwrap_or(u64::MAX)
+        };
+        let tokens: Vec<_> = tokens.into_iter().collect();
+        // `max_by_key` keeps the last of equals.
+        tokens.into_iter().rev().max_by_key(|token| remaining(token))
+    }
+
+    /// The token closest to running out of core quota, and what it has
+    /// left, going by windows that haven't reset since.
+    pub fn lowest_core_remaining(&self) -> Option<(String, u64)> {
+        let now = now();
+        self.observed
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((_, resource), observation)| {
+                resource == "core" && observation.reset.is_some_and(|reset| reset > now)
+            })
+            .filter_map(|((name, _), observation)| Some((name.clone(), observation.remaining?)))
+            .min_by_key(|(_, remaining)| *remaining)
+    }
+
+    pub fn snapshot(&self, tokens: &Tokens) -> Vec<TokenQuota> {
+        let observed = self.observed.lock().unwrap();
+        tokens
+            .all()
+            .into_iter()
+            .map(|token| {
+                let resources: BTreeMap<String, ResourceQuota> = observed
+                    .iter()
+                    .filter(|((name, _), _)| *name == token.name)
+                    .map(|((_, resource), observation)| {
+                        let quota = ResourceQuota {
+                            observation: observation.clone(),
+                            exhausted: observation.exhausted(),
+                        };
+                        (resource.clone(), quota)
+                    })
+                    .collect();
+                TokenQuota {
+                    name: token.name.clone(),
+                    suffix: suffix(&token.secret()),
+                    exhausted: resources.values().any(|quota| quota.exhausted),
+                    resources,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Shows the last four characters, but only of secrets long enough that
+/// this gives nothing useful away.
+fn suffix(secret: &str) -> String {
+    match secret.char_indices().rev().nth(3) {
+        Some((start, _)) if secret.len() > 16 => format!("…{}", &secret[start..]),
+        _ => "…".to_owned(),
+    }
+}
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Parses GitHub's `2024-05-01 12:00:00 UTC` (or `... -0700`) into Unix time.
+fn parse_expiration(value: &str) -> Option<u64> {
+    let mut parts = value.split_whitespace();
+    let (date, time, zone) = (parts.next()?, parts.next()?, parts.next()?);
+    let numbers = |s: &str, sep: char| -> Option<Vec<i64>> {
+        s.split(sep).map(|n| n.parse().ok()).collect()
+    };
+    let (date, time) = (numbers(date, '-')?, numbers(time, ':')?);
+    let ([year, month, day], [hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+    let offset = match zone {
+        "UTC" | "GMT" | "Z" => 0,
+        _ => {
+            let sign = match zone.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let digits: i64 = zone.get(1..5)?.parse().ok()?;
+            sign * ((digits / 100) * 3600 + (digits % 100) * 60)
+        }
+    };
+    let days = days_from_civil(*year, *month, *day);
+    let local = days * SECS_PER_DAY as i64 + hour * 3600 + minute * 60 + second;
+    u64::try_from(local - offset).ok()
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+/// algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
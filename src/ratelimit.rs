@@ -0,0 +1,232 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use metrics::counter;
+use moka::sync::Cache;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    batch, client_ip::ClientIp, error_response, origin::OriginPattern, peers::FromPeer,
+    shadow::FromShadow, AppState,
+};
+
+/// A classic token bucket refilled continuously at `rpm / 60` tokens per
+/// second, holding at most a minute's worth of budget.
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(rpm: u32) -> Self {
+        Self {
+            tokens: rpm as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes one token, or reports how long until one is available.
+    fn try_take(&mut self, rpm: u32) -> Result<(), Duration> {
+        let capacity = rpm as f64;
+        let per_sec = capacity / 60.0;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(capacity);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+        }
+    }
+}
+
+struct OriginBucket {
+    rpm: u32,
+    bucket: Mutex<TokenBucket>,
+    allowed: AtomicU64,
+    limited: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct OriginStats {
+    rpm: u32,
+    allowed: u64,
+    limited: u64,
+}
+
+pub struct RateLimiter {
+    origin_rules: Vec<(OriginPattern, u32)>,
+    client_rpm: u32,
+    /// By the address `ClientIp` settled on, so clients behind our trusted
+    /// proxies each get their own.
+    clients: Cache<IpAddr, Arc<Mutex<TokenBucket>>>,
+    forced_refresh_rpm: u32,
+    forced_refresh: Cache<IpAddr, Arc<Mutex<TokenBucket>>>,
+    /// One bucket per concrete origin string, even when several origins share
+    /// a pattern, so one site can't spend another's budget.
+    origins: Cache<String, Arc<OriginBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        origin_rules: Vec<(OriginPattern, u32)>,
+        client_rpm: u32,
+        forced_refresh_rpm: u32,
+    ) -> Self {
+        Self {
+            origin_rules,
+            client_rpm,
+            // Idle for a minute, a bucket is full again anyway.
+            clients: Cache::builder()
+                .time_to_idle(Duration::from_secs(60))
+                .max_capacity(100_000)
+                .build(),
+            forced_refresh_rpm,
+            forced_refresh: Cache::builder()
+                .time_to_idle(Duration::from_secs(60))
+                .max_capacity(100_000)
+                .build(),
+            origins: Cache::builder()
+                .time_to_idle(Duration::from_secs(3600))
+                .max_capacity(10_000)
+                .build(),
+        }
+    }
+
+    /// Charges a request to `origin`'s budget. `None` means the origin has no
+    /// budget of its own and only the other limits apply.
+    pub fn check_origin(&self, origin: &str) -> Option<Result<(), Duration>> {
+        let entry = match self.origins.get(origin) {
+            Some(entry) => entry,
+            None => {
+                let rpm = self
+                    .origin_rules
+                    .iter()
+                    .find(|(pattern, _)| pattern.matches(origin))
+                    .map(|(_, rpm)| *rpm)?;
+                self.origins.get_with_by_ref(origin, || {
+                    Arc::new(OriginBucket {
+                        rpm,
+                        bucket: Mutex::new(TokenBucket::full(rpm)),
+                        allowed: AtomicU64::new(0),
+                        limited: AtomicU64::new(0),
+                    })
+                })
+            }
+        };
+
+        let result = entry.bucket.lock().unwrap().try_take(entry.rpm);
+        match result {
+            Ok(()) => entry.allowed.fetch_add(1, Ordering::Relaxed),
+            Err(_) => entry.limited.fetch_add(1, Ordering::Relaxed),
+        };
+        Some(result)
+    }
+
+    /// Charges a request to `client`'s budget, if `CLIENT_RATE_LIMIT_RPM`
+    /// sets one.
+    pub fn check_client(&self, client: IpAddr) -> Result<(), Duration> {
+        if self.client_rpm == 0 {
+            return Ok(());
+        }
+        let bucket = self
+            .clients
+            .get_with(client, || Arc::new(Mutex::new(TokenBucket::full(self.client_rpm))));
+        let result = bucket.lock().unwrap().try_take(self.client_rpm);
+        result
+    }
+
+    /// Whether `client` may force another cache bypass right now. Over budget
+    /// they're simply served from cache as usual.
+    pub fn allow_forced_refresh(&self, client: IpAddr) -> bool {
+        if self.forced_refresh_rpm == 0 {
+            return false;
+        }
+        let bucket = self.forced_refresh.get_with(client, || {
+            Arc::new(Mutex::new(TokenBucket::full(self.forced_refresh_rpm)))
+        });
+        let result = bucket.lock().unwrap().try_take(self.forced_refresh_rpm);
+        result.is_ok()
+    }
+
+    pub fn origin_stats(&self) -> BTreeMap<String, OriginStats> {
+        self.origins
+            .iter()
+            .map(|(origin, entry)| {
+                (
+                    origin.as_ref().clone(),
+                    OriginStats {
+                        rpm: entry.rpm,
+                        allowed: entry.allowed.load(Ordering::Relaxed),
+                        limited: entry.limited.load(Ordering::Relaxed),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Runs after the origin check, so only allowlisted origins ever get a bucket.
+pub async fn rate_limit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Forwarded misses were charged by the replica the client came to, and
+    // mirrored traffic was charged where it really happened.
+    let extensions = request.extensions();
+    if request.uri().path() == batch::PATH
+        || extensions.get::<FromPeer>().is_some()
+        || extensions.get::<FromShadow>().is_some()
+    {
+        return next.run(request).await;
+    }
+    let origin = request
+        .headers()
+        .get("origin")
+        .and_then(|v| v.to_str().ok());
+
+    if let Some(origin) = origin {
+        if let Some(Err(retry_after)) = state.rate_limiter.check_origin(origin) {
+            counter!("proxy_rate_limited_total", "scope" => "origin").increment(1);
+            return too_many_requests(retry_after);
+        }
+    }
+    // Even within its origin's budget, no one client may spend all of it.
+    if let Some(ClientIp(ip)) = extensions.get::<ClientIp>() {
+        if let Err(retry_after) = state.rate_limiter.check_client(*ip) {
+            counter!("proxy_rate_limited_total", "scope" => "client").increment(1);
+            return too_many_requests(retry_after);
+        }
+    }
+
+    next.run(request).await
+}
+
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    let mut response = error_response(StatusCode::TOO_MANY_REQUESTS);
+    let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let headers = response.headers_mut();
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    // Readable from the page, so it can back off for as long as asked.
+    headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("retry-after"),
+    );
+    response
+}
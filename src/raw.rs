@@ -0,0 +1,149 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use moka::future::Cache;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cache::{CacheStatus, CachedResponse, EntryExpiry},
+    config::RawConfig,
+    fetch_failed, paths, refused_repo, respond, respond_streamed, timed,
+    tokens::GithubToken,
+    upstream::{FetchError, Fetched},
+    AppState,
+};
+
+pub const PATH: &str = "/raw/:owner/:repo/:ref/*path";
+
+/// Files as they are in a repository, for pages that want a README or a
+/// JSON config rather than the API's description of it:
+/// `GET /raw/owner/repo/ref/path` answers with the file from
+/// raw.githubusercontent.com, under the type it was served with there. None
+/// of it counts against our API rate limit.
+///
+/// Files are kept for `RAW_TTL_SECS`, or `RAW_IMMUTABLE_TTL_SECS` at a full
+/// commit SHA, where they can never change.
+pub fn router(config: &RawConfig) -> Router<AppState> {
+    if !config.enabled {
+        return Router::new();
+    }
+    Router::new().route(PATH, get(raw_file))
+}
+
+/// Files by `owner/repo/ref/path`, apart from the REST cache whose keys are
+/// API paths.
+pub struct Files {
+    entries: Cache<Arc<str>, Arc<CachedResponse>>,
+}
+
+impl Files {
+    pub fn new(config: &RawConfig) -> Self {
+        Self {
+            entries: Cache::builder()
+                .max_capacity(config.max_entries)
+                .expire_after(EntryExpiry {
+                    stale: Duration::ZERO,
+                })
+                .build(),
+        }
+    }
+}
+
+/// How a shared miss ended when it left nothing to cache.
+enum Unstored {
+    Uncacheable(Arc<CachedResponse>),
+    Failed(FetchError),
+}
+
+async fn raw_file(
+    Path((owner, repo, git_ref, path)): Path<(String, String, String, String)>,
+    headers: HeaderMap,
+    token: Option<Extension<Arc<GithubToken>>>,
+    State(state): State<AppState>,
+) -> Response {
+    let started = Instant::now();
+    let config = &state.config;
+    if let Some(refused) = refused_repo(config, &format!("{owner}/{repo}")) {
+        return refused;
+    }
+    let key: Arc<str> = format!("{owner}/{repo}/{git_ref}/{path}").into();
+    let url = format!("{}{key}", config.github.raw);
+    let token = token.map_or_else(|| config.tokens.default.clone(), |Extension(t)| t);
+    let upstream_started = Instant::now();
+
+    // What a client's own token can see may be private: never kept.
+    if token.client {
+        let fetched = state.upstream.fetch(config, &token, &url, HeaderMap::new(), false);
+        let response = match fetched.await {
+            Ok(Fetched::Streamed(streamed)) => respond_streamed(streamed, &headers, config),
+            Ok(Fetched::Fresh(entry) | Fetched::Uncacheable(entry) | Fetched::Partial(entry)) => {
+                respond(&entry, CacheStatus::Bypass, &headers, config)
+            }
+            Ok(Fetched::NotModified { .. }) => {
+                fetch_failed(FetchError::Failed(StatusCode::BAD_GATEWAY), None, &headers, config)
+            }
+            Err(err) => fetch_failed(err, None, &headers, config),
+        };
+        return timed(response, Some(upstream_started.elapsed()), started);
+    }
+
+    if let Some(entry) = state.raw.entries.get(&key).await {
+        return timed(respond(&entry, CacheStatus::Hit, &headers, config), None, started);
+    }
+    let immutable = paths::is_sha(&git_ref);
+    let fetched = state
+        .raw
+        .entries
+        .try_get_with(key, async {
+            let fetched = state.upstream.fetch(config, &token, &url, HeaderMap::new(), false);
+            match fetched.await {
+                Ok(Fetched::Fresh(entry)) if entry.status == StatusCode::OK => {
+                    Ok(kept(&entry, &config.raw, immutable))
+                }
+                // A 404 keeps what `CACHE_ERROR_TTL_SECS` gave it.
+                Ok(Fetched::Fresh(entry)) => Ok(entry),
+                Ok(Fetched::Uncacheable(entry) | Fetched::Partial(entry)) => {
+                    Err(Unstored::Uncacheable(entry))
+                }
+                Ok(Fetched::Streamed(streamed)) => match streamed.collect().await {
+                    Ok(entry) => Err(Unstored::Uncacheable(entry)),
+                    Err(err) => Err(Unstored::Failed(err)),
+                },
+                Ok(Fetched::NotModified { .. }) => {
+                    Err(Unstored::Failed(FetchError::Failed(StatusCode::BAD_GATEWAY)))
+                }
+                Err(err) => Err(Unstored::Failed(err)),
+            }
+        })
+        .await;
+    let upstream_time = Some(upstream_started.elapsed());
+    let response = match fetched {
+        Ok(entry) => respond(&entry, CacheStatus::Miss, &headers, config),
+        Err(unstored) => match &*unstored {
+            Unstored::Uncacheable(entry) => respond(entry, CacheStatus::Pass, &headers, config),
+            Unstored::Failed(err) => fetch_failed(err.clone(), None, &headers, config),
+        },
+    };
+    timed(response, upstream_time, started)
+}
+
+/// `entry`, kept for as long as files are at its ref.
+fn kept(entry: &CachedResponse, config: &RawConfig, immutable: bool) -> Arc<CachedResponse> {
+    Arc::new(CachedResponse {
+        status: entry.status,
+        body: entry.body.clone(),
+        headers: entry.headers.clone(),
+        stored_at: entry.stored_at,
+        ttl: if immutable { config.immutable_ttl } else { config.ttl },
+        purged: false,
+        immutable,
+        kind: entry.kind,
+    })
+}
@@ -0,0 +1,181 @@
+use bytes::Bytes;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::{fmt, str::FromStr};
+
+/// A dotted path to JSON fields removed from response bodies, such as
+/// `commit.author.email` or `*.email`.
+///
+/// Each segment names an object key, `*` standing for any key. Arrays are
+/// looked through: a rule applies to every element, at any level, so
+/// `commit.author.email` works on a single commit and on a list of them.
+#[derive(Clone, Debug)]
+pub struct RedactRule {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Key(String),
+    Any,
+}
+
+impl Segment {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Segment::Key(expected) => expected == key,
+            Segment::Any => true,
+        }
+    }
+}
+
+impl FromStr for RedactRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let segments = s
+            .trim()
+            .split('.')
+            .map(|segment| match segment {
+                "" => Err(format!("invalid redaction path {s:?}")),
+                "*" => Ok(Segment::Any),
+                key => Ok(Segment::Key(key.to_owned())),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { segments })
+    }
+}
+
+impl fmt::Display for RedactRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            match segment {
+                Segment::Key(key) => f.write_str(key)?,
+                Segment::Any => f.write_str("*")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for RedactRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Removes every field matched by `rules` from a JSON body.
+///
+/// Bodies that aren't JSON, or in which nothing matched, come back exactly as
+/// they went in; with no rules the body isn't even parsed.
+pub fn apply(rules: &[RedactRule], body: Bytes) -> Bytes {
+    if rules.is_empty() {
+        return body;
+    }
+    let Ok(mut document) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+
+    let mut removed = false;
+    for rule in rules {
+        removed |= remove(&mut document, &rule.segments);
+    }
+    if !removed {
+        return body;
+    }
+    match serde_json::to_vec(&document) {
+        Ok(redacted) => Bytes::from(redacted),
+        Err(_) => body,
+    }
+}
+
+fn remove(value: &mut Value, segments: &[Segment]) -> bool {
+    match value {
+        Value::Array(items) => items
+            .iter_mut()
+            .fold(false, |removed, item| remove(item, segments) | removed),
+        Value::Object(fields) => remove_from_object(fields, segments),
+        _ => false,
+    }
+}
+
+fn remove_from_object(fields: &mut Map<String, Value>, segments: &[Segment]) -> bool {
+    let Some((first, rest)) = segments.split_first() else {
+        return false;
+    };
+
+    if rest.is_empty() {
+        let before = fields.len();
+        fields.retain(|key, _| !first.matches(key));
+        return fields.len() != before;
+    }
+
+    fields
+        .iter_mut()
+        .filter(|(key, _)| first.matches(key))
+        .fold(false, |removed, (_, child)| remove(child, rest) | removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(paths: &[&str]) -> Vec<RedactRule> {
+        paths.iter().map(|path| path.parse().unwrap()).collect()
+    }
+
+    fn redacted(paths: &[&str], body: &'static str) -> Value {
+        serde_json::from_slice(&apply(&rules(paths), Bytes::from_static(body.as_bytes()))).unwrap()
+    }
+
+    #[test]
+    fn nested_arrays_of_objects_are_looked_through() {
+        let commits = r#"[
+            {"sha": "a", "commit": {"author": {"name": "A", "email": "a@example.com"}},
+             "files": [{"name": "x", "email": "x@example.com"}]},
+            {"sha": "b", "commit": {"author": {"name": "B", "email": "b@example.com"}},
+             "files": [[{"email": "y@example.com", "name": "y"}]]}
+        ]"#;
+        let value = redacted(&["commit.author.email", "files.email"], commits);
+        assert_eq!(
+            value,
+            serde_json::json!([
+                {"sha": "a", "commit": {"author": {"name": "A"}}, "files": [{"name": "x"}]},
+                {"sha": "b", "commit": {"author": {"name": "B"}}, "files": [[{"name": "y"}]]}
+            ])
+        );
+    }
+
+    #[test]
+    fn a_wildcard_matches_any_key() {
+        let repo = r#"{"owner": {"email": "o@example.com", "login": "o"},
+                       "organization": {"email": "org@example.com"}, "email": "top"}"#;
+        let value = redacted(&["*.email"], repo);
+        assert_eq!(
+            value,
+            serde_json::json!({"owner": {"login": "o"}, "organization": {}, "email": "top"})
+        );
+    }
+
+    #[test]
+    fn unmatched_bodies_pass_through_byte_for_byte() {
+        let body = Bytes::from_static(b"{ \"b\": 1,  \"a\": [ {\"c\": 2.50} ] }");
+        let passed = apply(&rules(&["permissions", "a.email"]), body.clone());
+        assert_eq!(passed, body);
+        assert_eq!(passed.as_ptr(), body.as_ptr());
+
+        let not_json = Bytes::from_static(b"permissions: none");
+        assert_eq!(apply(&rules(&["permissions"]), not_json.clone()), not_json);
+        assert_eq!(apply(&[], body.clone()).as_ptr(), body.as_ptr());
+    }
+
+    #[test]
+    fn empty_segments_are_refused() {
+        assert!("commit..email".parse::<RedactRule>().is_err());
+        assert!(".email".parse::<RedactRule>().is_err());
+        assert_eq!("*.email".parse::<RedactRule>().unwrap().to_string(), "*.email");
+    }
+}
@@ -0,0 +1,320 @@
+use futures_util::future::BoxFuture;
+use metrics::counter;
+use serde_json::{json, Value};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    cache::CachedResponse,
+    config::{RedisCacheConfig, RedisConfig},
+    repos::RepoPattern,
+    store::{self, CacheStore},
+};
+
+/// Connections kept open between commands.
+const MAX_IDLE: usize = 8;
+/// Keys asked for per `SCAN` when purging.
+const SCAN_COUNT: &str = "500";
+
+/// A small pool of connections to one Redis server. A command that fails or
+/// outlasts the configured timeout is counted and given up on.
+pub struct Redis {
+    config: RedisConfig,
+    /// What the server is used for, as `proxy_redis_errors_total` labels it.
+    purpose: &'static str,
+    idle: Mutex<Vec<Connection>>,
+    errors: AtomicU64,
+}
+
+impl Redis {
+    pub fn new(config: &RedisConfig, purpose: &'static str) -> Self {
+        Self {
+            config: config.clone(),
+            purpose,
+            idle: Mutex::default(),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends one command on an idle connection, or a new one.
+    pub async fn command(&self, args: &[&[u8]]) -> Result<Reply, String> {
+        let idle = self.idle.lock().unwrap().pop();
+        let exchange = async {
+            let mut connection = match idle {
+                Some(connection) => connection,
+                None => Connection::open(&self.config).await?,
+            };
+            let reply = connection.call(args).await?;
+            Ok((connection, reply))
+        };
+        let result = match tokio::time::timeout(self.config.timeout, exchange).await {
+            Ok(result) => result,
+            Err(_) => Err("timed out".to_owned()),
+        };
+        match result {
+            Ok((connection, reply)) => {
+                let mut idle = self.idle.lock().unwrap();
+                if idle.len() < MAX_IDLE {
+                    idle.push(connection);
+                }
+                Ok(reply)
+            }
+            Err(err) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                counter!("proxy_redis_errors_total", "purpose" => self.purpose).increment(1);
+                Err(err)
+            }
+        }
+    }
+
+    /// `key` under the configured prefix.
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.config.key_prefix)
+    }
+
+    pub fn stats(&self) -> Value {
+        json!({
+            "address": self.config.address,
+            "errors": self.errors.load(Ordering::Relaxed),
+            "idle_connections": self.idle.lock().unwrap().len(),
+        })
+    }
+}
+
+/// Entries in Redis under `CACHE_REDIS_PREFIX`, each stored like a disk
+/// cache file and expiring after `CACHE_REDIS_TTL_SECS`: kept across
+/// restarts, and shared by every replica pointed at the same database.
+/// A command that fails or outlasts `CACHE_REDIS_TIMEOUT_MS` is a miss.
+pub struct RedisCache {
+    redis: Redis,
+    ttl: Duration,
+}
+
+impl RedisCache {
+    pub fn new(config: &RedisCacheConfig) -> Arc<Self> {
+        Arc::new(Self {
+            redis: Redis::new(&config.server, "cache"),
+            ttl: config.ttl,
+        })
+    }
+
+    /// Deletes every key under the prefix whose cache key `doomed` picks.
+    async fn delete_where(&self, doomed: impl Fn(&str) -> bool) -> Result<usize, String> {
+        let prefix = &self.redis.config.key_prefix;
+        let pattern = format!("{prefix}*");
+        let mut cursor = b"0".to_vec();
+        let mut deleted = 0;
+        loop {
+            let args: [&[u8]; 6] = [
+                b"SCAN",
+                &cursor,
+                b"MATCH",
+                pattern.as_bytes(),
+                b"COUNT",
+                SCAN_COUNT.as_bytes(),
+            ];
+            let Reply::Array(mut page) = self.redis.command(&args).await? else {
+                return Err("unexpected reply to SCAN".into());
+            };
+            let (Some(Reply::Array(keys)), Some(Reply::Bulk(Some(next)))) = (page.pop(), page.pop())
+            else {
+                return Err("unexpected reply to SCAN".into());
+            };
+            let keys: Vec<Vec<u8>> = keys
+                .into_iter()
+                .filter_map(|key| match key {
+                    Reply::Bulk(Some(key)) => Some(key),
+                    _ => None,
+                })
+                .filter(|key| {
+                    std::str::from_utf8(key)
+                        .ok()
+                        .and_then(|key| key.strip_prefix(prefix.as_str()))
+                        .is_some_and(&doomed)
+                })
+                .collect();
+            if !keys.is_empty() {
+                let mut args: Vec<&[u8]> = vec![b"DEL"];
+                args.extend(keys.iter().map(Vec::as_slice));
+                self.redis.command(&args).await?;
+                deleted += keys.len();
+            }
+            if next == b"0" {
+                return Ok(deleted);
+            }
+            cursor = next;
+        }
+    }
+}
+
+impl CacheStore for Arc<RedisCache> {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<Arc<CachedResponse>>> {
+        Box::pin(async move {
+            let stored = match self.redis.command(&[b"GET", self.redis.key(key).as_bytes()]).await {
+                Ok(Reply::Bulk(stored)) => stored?,
+                Ok(_) => return None,
+                Err(err) => {
+                    debug!(key, "Redis cache lookup failed: {err}");
+                    return None;
+                }
+            };
+            match store::decode(&stored, key) {
+                Some((entry, _)) => Some(Arc::new(entry)),
+                None => {
+                    warn!(key, "discarding unreadable Redis cache entry");
+                    self.remove(key);
+                    None
+                }
+            }
+        })
+    }
+
+    fn store(&self, key: Arc<str>, entry: Arc<CachedResponse>) {
+        let redis = self.clone();
+        tokio::spawn(async move {
+            let contents = store::encode(&key, &entry, store::fetched_at(&entry));
+            let (stored_key, ttl) = (redis.redis.key(&key), redis.ttl.as_millis().to_string());
+            let args: [&[u8]; 5] = [
+                b"SET",
+                stored_key.as_bytes(),
+                &contents,
+                b"PX",
+                ttl.as_bytes(),
+            ];
+            if let Err(err) = redis.redis.command(&args).await {
+                debug!(%key, "cannot write Redis cache entry: {err}");
+            }
+        });
+    }
+
+    fn remove(&self, key: &str) {
+        let (redis, key) = (self.clone(), self.redis.key(key));
+        tokio::spawn(async move {
+            if let Err(err) = redis.redis.command(&[b"DEL", key.as_bytes()]).await {
+                debug!(key, "cannot delete Redis cache entry: {err}");
+            }
+        });
+    }
+
+    fn purge_repos(&self, repos: &[RepoPattern]) {
+        let (redis, repos) = (self.clone(), repos.to_vec());
+        tokio::spawn(async move {
+            match redis.delete_where(|key| store::in_repos(key, &repos)).await {
+                Ok(count) => debug!(count, "purged Redis cache entries"),
+                Err(err) => warn!("cannot purge the Redis cache: {err}"),
+            }
+        });
+    }
+
+    fn clear(&self) {
+        let redis = self.clone();
+        tokio::spawn(async move {
+            match redis.delete_where(|_| true).await {
+                Ok(count) => debug!(count, "cleared the Redis cache"),
+                Err(err) => warn!("cannot clear the Redis cache: {err}"),
+            }
+        });
+    }
+
+    fn stats(&self) -> Value {
+        let mut stats = self.redis.stats();
+        stats["backend"] = "redis".into();
+        stats
+    }
+}
+
+/// The replies this client needs apart: status replies are only ever
+/// checked for not being errors.
+pub enum Reply {
+    Simple,
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+/// One connection speaking RESP2, a command at a time.
+struct Connection(BufStream<TcpStream>);
+
+impl Connection {
+    async fn open(config: &RedisConfig) -> Result<Self, String> {
+        let stream = TcpStream::connect(&config.address)
+            .await
+            .map_err(|e| format!("cannot connect to {}: {e}", config.address))?;
+        let _ = stream.set_nodelay(true);
+        let mut connection = Self(BufStream::new(stream));
+        if let Some(password) = &config.password {
+            let mut auth: Vec<&[u8]> = vec![b"AUTH"];
+            auth.extend(config.username.as_deref().map(str::as_bytes));
+            auth.push(password.as_bytes());
+            connection.call(&auth).await?;
+        }
+        if config.database != 0 {
+            let database = config.database.to_string();
+            connection.call(&[b"SELECT", database.as_bytes()]).await?;
+        }
+        Ok(connection)
+    }
+
+    async fn call(&mut self, args: &[&[u8]]) -> Result<Reply, String> {
+        let mut command = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            command.extend_from_slice(arg);
+            command.extend_from_slice(b"\r\n");
+        }
+        let io = |e: std::io::Error| e.to_string();
+        self.0.write_all(&command).await.map_err(io)?;
+        self.0.flush().await.map_err(io)?;
+        self.read().await
+    }
+
+    fn read(&mut self) -> BoxFuture<'_, Result<Reply, String>> {
+        Box::pin(async move {
+            let io = |e: std::io::Error| e.to_string();
+            let mut line = Vec::new();
+            self.0.read_until(b'\n', &mut line).await.map_err(io)?;
+            let Some(line) = line.strip_suffix(b"\r\n") else {
+                return Err("the connection was closed".into());
+            };
+            let (&kind, rest) = line.split_first().ok_or("empty reply")?;
+            let rest = String::from_utf8_lossy(rest);
+            let number = || rest.parse::<i64>().map_err(|_| format!("malformed reply {rest:?}"));
+            match kind {
+                b'+' => Ok(Reply::Simple),
+                b':' => Ok(Reply::Integer(number()?)),
+                b'-' => Err(format!("Redis answered {rest}")),
+                b'$' => {
+                    let Ok(length) = usize::try_from(number()?) else {
+                        return Ok(Reply::Bulk(None));
+                    };
+                    let mut data = vec![0; length + 2];
+                    self.0.read_exact(&mut data).await.map_err(io)?;
+                    data.truncate(length);
+                    Ok(Reply::Bulk(Some(data)))
+                }
+                b'*' => {
+                    let mut items = Vec::new();
+                    for _ in 0..number()?.max(0) {
+                        items.push(self.read().await?);
+                    }
+                    Ok(Reply::Array(items))
+                }
+                _ => Err(format!("malformed reply {:?}", kind as char)),
+            }
+        })
+    }
+}
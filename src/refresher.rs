@@ -0,0 +1,149 @@
+use metrics::counter;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{sync::Semaphore, task::JoinSet, time::MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{quota, refresh, tokens::GithubToken, AppState};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct PathState {
+    running: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// Keeps `REFRESH_PATHS` permanently warm: every interval, each path about
+/// to go stale before the next tick is re-fetched (conditionally, when an
+/// ETag is held) so visitors never wait on a miss for it.
+///
+/// Refreshes are spread out by random jitter and capped in concurrency; a
+/// failing path backs off exponentially on its own. Each goes out on the
+/// pooled token with the most quota left, and waits while even that one is
+/// below `REFRESH_MIN_QUOTA_PERCENT`, leaving the rest to visitors.
+pub async fn run(state: AppState, shutdown: CancellationToken) {
+    let config = &state.config.refresh;
+    if config.paths.is_empty() {
+        return;
+    }
+
+    let paths: Vec<(Arc<str>, Arc<Mutex<PathState>>)> = config
+        .paths
+        .iter()
+        .map(|path| (path.clone(), Arc::default()))
+        .collect();
+    let permits = Arc::new(Semaphore::new(config.concurrency));
+    let mut tasks = JoinSet::new();
+
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        while tasks.try_join_next().is_some() {}
+
+        for (key, path_state) in &paths {
+            let remaining = match state.cache.get(key).await {
+                Some(entry) => entry.ttl.saturating_sub(entry.stored_at.elapsed()),
+                None => Duration::ZERO,
+            };
+            if remaining > config.interval {
+                continue;
+            }
+            let Some(token) = token_with_budget(&state, key) else {
+                counter!("proxy_refresh_skipped_total", "reason" => "quota").increment(1);
+                debug!(path = %key, "quota is low, not refreshing");
+                continue;
+            };
+            {
+                let mut path_state = path_state.lock().unwrap();
+                if path_state.running || path_state.retry_at.is_some_and(|at| at > Instant::now()) {
+                    continue;
+                }
+                path_state.running = true;
+            }
+
+            // Land somewhere in the first half of what's left, so paths that
+            // share a TTL don't all go upstream in the same instant.
+            let jitter = jitter(remaining.min(config.interval) / 2);
+            let (state, key, path_state) = (state.clone(), key.clone(), path_state.clone());
+            let token = token.clone();
+            let (permits, shutdown) = (permits.clone(), shutdown.clone());
+            tasks.spawn(async move {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = tokio::time::sleep(jitter) => {}
+                }
+                let Ok(_permit) = permits.acquire().await else {
+                    return;
+                };
+                refresh_one(&state, &token, &key, &path_state).await;
+            });
+        }
+    }
+
+    tasks.shutdown().await;
+}
+
+/// The token to refresh `key` with, unless none has the quota to spare.
+fn token_with_budget<'a>(state: &'a AppState, key: &str) -> Option<&'a Arc<GithubToken>> {
+    let tokens = &state.config.tokens;
+    let resource = quota::resource_of(key);
+    let quota = &state.upstream.quota;
+    let token = quota.best_of(&tokens.pool, resource).unwrap_or(&tokens.default);
+    match quota.left(token, resource) {
+        Some((left, limit)) if left * 100 < limit * state.config.refresh.min_quota_percent => None,
+        _ => Some(token),
+    }
+}
+
+async fn refresh_one(
+    state: &AppState,
+    token: &GithubToken,
+    key: &Arc<str>,
+    path_state: &Mutex<PathState>,
+) {
+    let url = state.config.api_namespaces.upstream_url(&state.config.github, key);
+    let interval = state.config.refresh.interval;
+    let result = refresh(state, token, key.clone(), &url, false, Some(Instant::now())).await;
+
+    let mut path_state = path_state.lock().unwrap();
+    path_state.running = false;
+    match result {
+        Ok(_) => {
+            debug!(path = %key, "background refresh done");
+            path_state.failures = 0;
+            path_state.retry_at = None;
+        }
+        Err(_) => {
+            path_state.failures += 1;
+            let backoff = interval
+                .saturating_mul(2u32.saturating_pow(path_state.failures))
+                .min(MAX_BACKOFF);
+            warn!(
+                path = %key,
+                failures = path_state.failures,
+                "background refresh failed, retrying in {backoff:?}"
+            );
+            path_state.retry_at = Some(Instant::now() + backoff);
+        }
+    }
+}
+
+/// A random duration below `max`, from std's randomly seeded hasher.
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    match max.as_millis() as u64 {
+        0 => Duration::ZERO,
+        millis => Duration::from_millis(random % millis),
+    }
+}
@@ -0,0 +1,219 @@
+//! Error reporting to Sentry, built with `--features sentry` and switched on
+//! by `SENTRY_DSN`. Without the feature everything here compiles to nothing.
+//!
+//! What gets reported: panics, every `error!` log (startup and config errors
+//! among them, with `warn!`/`info!` lines along as breadcrumbs), and upstream
+//! failures, rate-limited so an outage is one event a minute rather than one
+//! per request. Each request runs with its path and origin as tags, and every
+//! string of an event is scrubbed of tokens before it leaves.
+
+use axum::{http::StatusCode, Router};
+
+#[cfg(feature = "sentry")]
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "sentry")]
+use crate::config::{var, Config};
+
+/// Keeps the Sentry client alive; dropping it flushes pending events.
+pub struct Guard {
+    #[cfg(feature = "sentry")]
+    _client: Option<sentry::ClientInitGuard>,
+}
+
+/// Sets up the client from `SENTRY_DSN` (and `SENTRY_ENVIRONMENT`). Called
+/// before anything else so config errors are caught too.
+#[cfg(feature = "sentry")]
+pub fn init() -> Guard {
+    let Some(dsn) = var("SENTRY_DSN") else {
+        return Guard { _client: None };
+    };
+    let mut options = sentry::ClientOptions::default();
+    options.release = sentry::release_name!();
+    options.environment = var("SENTRY_ENVIRONMENT").map(Into::into);
+    options.before_send = Some(std::sync::Arc::new(scrub));
+    Guard {
+        _client: Some(sentry::init((dsn, options))),
+    }
+}
+
+#[cfg(not(feature = "sentry"))]
+#[inline(always)]
+pub fn init() -> Guard {
+    Guard {}
+}
+
+/// Sends whatever is queued before the process exits without unwinding,
+/// which skips the flush on dropping the `Guard`.
+#[cfg(feature = "sentry")]
+pub fn flush() {
+    if let Some(client) = sentry::Hub::current().client() {
+        client.flush(Some(Duration::from_secs(2)));
+    }
+}
+
+#[cfg(not(feature = "sentry"))]
+#[inline(always)]
+pub fn flush() {}
+
+/// The tracing layer that turns `error!` into events, or nothing.
+#[cfg(feature = "sentry")]
+pub fn tracing_layer<S>() -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    use sentry::integrations::tracing::{default_event_filter, EventFilter};
+
+    // Panics are captured by the panic integration, with a proper stack trace.
+    sentry::integrations::tracing::layer().event_filter(|metadata| {
+        if metadata.target() == crate::panics::TARGET {
+            EventFilter::Ignore
+        } else {
+            default_event_filter(metadata)
+        }
+    })
+}
+
+#[cfg(not(feature = "sentry"))]
+#[inline(always)]
+pub fn tracing_layer() -> tracing_subscriber::layer::Identity {
+    tracing_subscriber::layer::Identity::new()
+}
+
+/// Our own secrets, to scrub verbatim on top of anything token-shaped. Set
+/// once the config is loaded; events from before then get the shape check only.
+#[cfg(feature = "sentry")]
+static SECRETS: OnceLock<Vec<String>> = OnceLock::new();
+
+#[cfg(feature = "sentry")]
+pub fn scrub_secrets_of(config: &Config) {
+    let _ = SECRETS.set(config.secrets());
+}
+
+#[cfg(not(feature = "sentry"))]
+#[inline(always)]
+pub fn scrub_secrets_of(_config: &crate::config::Config) {}
+
+/// Rewrites every string in the event, wherever in it a token might have
+/// ended up: messages, exception values, breadcrumbs, request headers.
+#[cfg(feature = "sentry")]
+fn scrub(event: sentry::protocol::Event<'static>) -> Option<sentry::protocol::Event<'static>> {
+    fn walk(value: &mut serde_json::Value, secrets: &[&str]) {
+        match value {
+            serde_json::Value::String(text) => *text = crate::dump::redact_secrets(text, secrets),
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|v| walk(v, secrets)),
+            serde_json::Value::Object(fields) => fields.values_mut().for_each(|v| walk(v, secrets)),
+            _ => {}
+        }
+    }
+
+    let secrets: Vec<&str> = SECRETS
+        .get()
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    let mut value = serde_json::to_value(&event).ok()?;
+    walk(&mut value, &secrets);
+    // Drop the event rather than risk sending it unscrubbed.
+    serde_json::from_value(value).ok()
+}
+
+/// Runs each request on its own hub tagged with its path and origin, so a
+/// panic or upstream failure inside it is reported with that context.
+#[cfg(feature = "sentry")]
+pub fn layer<S: Clone + Send + Sync + 'static>(router: Router<S>) -> Router<S> {
+    use axum::{extract::Request, http::header, middleware::Next};
+    use sentry::{Hub, SentryFutureExt};
+
+    async fn with_hub(request: Request, next: Next) -> axum::response::Response {
+        if sentry::Hub::current()
+            .client()
+            .is_none_or(|c| !c.is_enabled())
+        {
+            return next.run(request).await;
+        }
+        let hub = std::sync::Arc::new(Hub::new_from_top(Hub::current()));
+        hub.configure_scope(|scope| {
+            scope.set_tag("path", request.uri().path());
+            if let Some(origin) = request.headers().get(header::ORIGIN) {
+                scope.set_tag("origin", String::from_utf8_lossy(origin.as_bytes()));
+            }
+        });
+        next.run(request).bind_hub(hub).await
+    }
+
+    router.layer(axum::middleware::from_fn(with_hub))
+}
+
+#[cfg(not(feature = "sentry"))]
+#[inline(always)]
+pub fn layer<S: Clone + Send + Sync + 'static>(router: Router<S>) -> Router<S> {
+    router
+}
+
+/// How often an upstream failure event may be sent, at most.
+#[cfg(feature = "sentry")]
+const FAILURE_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "sentry")]
+struct FailureWindow {
+    last_report: Option<Instant>,
+    unreported: u64,
+}
+
+#[cfg(feature = "sentry")]
+static FAILURES: Mutex<FailureWindow> = Mutex::new(FailureWindow {
+    last_report: None,
+    unreported: 0,
+});
+
+/// Notes that a request to GitHub failed (`status` is GitHub's, or the 502 we
+/// answered with when it couldn't be reached). The first failure is reported
+/// at once; the rest are counted and folded into one event a minute.
+#[cfg(feature = "sentry")]
+pub fn upstream_failure(status: StatusCode, url: &str, github_request_id: Option<&str>) {
+    if sentry::Hub::current()
+        .client()
+        .is_none_or(|c| !c.is_enabled())
+    {
+        return;
+    }
+    let failures = {
+        let mut window = FAILURES.lock().unwrap();
+        window.unreported += 1;
+        if window
+            .last_report
+            .is_some_and(|at| at.elapsed() < FAILURE_REPORT_INTERVAL)
+        {
+            return;
+        }
+        window.last_report = Some(Instant::now());
+        std::mem::take(&mut window.unreported)
+    };
+
+    sentry::with_scope(
+        |scope| {
+            scope.set_fingerprint(Some(&["upstream-failure"]));
+            scope.set_tag("upstream_status", status.as_u16());
+            scope.set_extra("url", url.into());
+            scope.set_extra("failures", failures.into());
+            if let Some(id) = github_request_id {
+                scope.set_tag("github_request_id", id);
+            }
+        },
+        || {
+            sentry::capture_message(
+                &format!("upstream failed with {status} ({failures} since the last report)"),
+                sentry::Level::Error,
+            )
+        },
+    );
+}
+
+#[cfg(not(feature = "sentry"))]
+#[inline(always)]
+pub fn upstream_failure(_status: StatusCode, _url: &str, _github_request_id: Option<&str>) {}
@@ -0,0 +1,178 @@
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use crate::config::{parse_list, unix_now, var};
+
+/// `owner` (every repository of that owner) or `owner/repo`, compared
+/// ASCII case-insensitively as GitHub compares names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepoPattern {
+    owner: String,
+    repo: Option<String>,
+}
+
+impl RepoPattern {
+    /// Matches against the first two segments of a request path.
+    pub fn matches(&self, path: &str) -> bool {
+        let mut segments = path.trim_start_matches('/').split('/');
+        let owner = segments.next().unwrap_or_default();
+        if !owner.eq_ignore_ascii_case(&self.owner) {
+            return false;
+        }
+        match &self.repo {
+            Some(repo) => segments
+                .next()
+                .is_some_and(|r| r.eq_ignore_ascii_case(repo)),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for RepoPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = |name: &str| {
+            !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        };
+        let (owner, repo) = match s.trim().split_once('/') {
+            Some((owner, repo)) => (owner, Some(repo)),
+            None => (s.trim(), None),
+        };
+        if !valid(owner) || repo.is_some_and(|r| !valid(r)) {
+            return Err(format!("invalid repository pattern {s:?}"));
+        }
+        Ok(Self {
+            owner: owner.to_ascii_lowercase(),
+            repo: repo.map(str::to_ascii_lowercase),
+        })
+    }
+}
+
+impl fmt::Display for RepoPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.repo {
+            Some(repo) => write!(f, "{}/{repo}", self.owner),
+            None => f.write_str(&self.owner),
+        }
+    }
+}
+
+impl Serialize for RepoPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A list of repository patterns from `<NAME>` plus, optionally, a file named
+/// by `<NAME>_FILE` that is re-read on reload (one pattern per line or
+/// comma-separated, `#` starts a comment).
+pub struct RepoList {
+    name: &'static str,
+    fixed: Vec<RepoPattern>,
+    file: Option<PathBuf>,
+    current: RwLock<Arc<[RepoPattern]>>,
+}
+
+impl RepoList {
+    pub fn from_env(name: &'static str) -> Result<Self, String> {
+        let list = Self {
+            name,
+            fixed: parse_list(name)?,
+            file: var(&format!("{name}_FILE")).map(PathBuf::from),
+            current: RwLock::new(Arc::from([])),
+        };
+        list.reload()?;
+        Ok(list)
+    }
+
+    /// Re-reads the file, keeping the previous patterns if it can't be used.
+    pub fn reload(&self) -> Result<(), String> {
+        let mut patterns = self.fixed.clone();
+        if let Some(file) = &self.file {
+            let contents = fs::read_to_string(file)
+                .map_err(|e| format!("{}_FILE: cannot read {}: {e}", self.name, file.display()))?;
+            for line in contents.lines() {
+                let line = line.split('#').next().unwrap_or_default();
+                for item in line.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+                    patterns.push(
+                        item.parse()
+                            .map_err(|e| format!("{}_FILE: {e}", self.name))?,
+                    );
+                }
+            }
+        }
+        *self.current.write().unwrap() = patterns.into();
+        Ok(())
+    }
+
+    pub fn patterns(&self) -> Arc<[RepoPattern]> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl Serialize for RepoList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("patterns", &*self.patterns())?;
+        map.serialize_entry("file", &self.file)?;
+        map.end()
+    }
+}
+
+/// Which repositories the proxy may be used for. The denylist is checked
+/// first; an empty allowlist leaves it open to every other repository.
+#[derive(Serialize)]
+pub struct RepoAccess {
+    pub allowed: RepoList,
+    pub denied: RepoList,
+    /// Successful reloads since startup.
+    reloads: AtomicU64,
+    /// Unix time of the last of them.
+    reloaded_at: Mutex<Option<u64>>,
+}
+
+impl RepoAccess {
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            allowed: RepoList::from_env("ALLOWED_REPOS")?,
+            denied: RepoList::from_env("DENIED_REPOS")?,
+            reloads: AtomicU64::new(0),
+            reloaded_at: Mutex::default(),
+        })
+    }
+
+    pub fn is_denied(&self, path: &str) -> bool {
+        self.denied.patterns().iter().any(|p| p.matches(path))
+    }
+
+    pub fn permits(&self, path: &str) -> bool {
+        let allowed = self.allowed.patterns();
+        allowed.is_empty() || allowed.iter().any(|p| p.matches(path))
+    }
+
+    /// Reloads both lists; if either fails, neither changes.
+    pub fn reload(&self) -> Result<(), String> {
+        let (allowed, denied) = (self.allowed.patterns(), self.denied.patterns());
+        let result = self.allowed.reload().and_then(|()| self.denied.reload());
+        if result.is_err() {
+            *self.allowed.current.write().unwrap() = allowed;
+            *self.denied.current.write().unwrap() = denied;
+        } else {
+            self.reloads.fetch_add(1, Ordering::Relaxed);
+            *self.reloaded_at.lock().unwrap() = Some(unix_now());
+        }
+        result
+    }
+}
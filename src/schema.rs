@@ -0,0 +1,213 @@
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    config::{flag, list, parse, var},
+    paths::PathPattern,
+};
+
+/// How many failing JSON pointers are collected, and so logged, per response.
+pub const MAX_VIOLATIONS: usize = 5;
+
+/// Contract checks on upstream bodies, so a field GitHub renamed or dropped
+/// shows up in the logs and metrics rather than in user bug reports.
+///
+/// `SCHEMA_PATHS` maps path patterns to JSON Schema files in `SCHEMA_DIR`
+/// (`*/*=repo.json,*/*/releases=releases.json`), first match wins. Only a
+/// subset of the standard is understood: `type`, `enum`, `properties`,
+/// `required` and `items`; other keywords are ignored. A mismatch never
+/// stops a response from being served.
+pub struct Schemas {
+    rules: Vec<(PathPattern, String, Value)>,
+    /// One in this many matching responses is checked.
+    sample_rate: u64,
+    seen: AtomicU64,
+    /// Mark failing responses with `X-Schema-Valid: false`.
+    pub mark_invalid: bool,
+}
+
+/// What a sampled check found.
+pub struct Violations {
+    /// The schema's file name.
+    pub schema: String,
+    /// The first `MAX_VIOLATIONS` failing locations, as JSON pointers.
+    pub pointers: Vec<String>,
+}
+
+/// The rules, not the schemas themselves.
+impl Serialize for Schemas {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let rules: Vec<(&PathPattern, &str)> = self
+            .rules
+            .iter()
+            .map(|(pattern, file, _)| (pattern, file.as_str()))
+            .collect();
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("rules", &rules)?;
+        map.serialize_entry("sample_rate", &self.sample_rate)?;
+        map.serialize_entry("mark_invalid", &self.mark_invalid)?;
+        map.end()
+    }
+}
+
+impl Schemas {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(dir) = var("SCHEMA_DIR").map(PathBuf::from) else {
+            return Ok(None);
+        };
+        let mut rules = Vec::new();
+        for entry in list("SCHEMA_PATHS") {
+            let (pattern, file) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("SCHEMA_PATHS: expected pattern=file, got {entry:?}"))?;
+            let pattern: PathPattern = pattern.parse().map_err(|e| format!("SCHEMA_PATHS: {e}"))?;
+            let file = file.trim();
+            let path = dir.join(file);
+            let contents = fs::read(&path)
+                .map_err(|e| format!("SCHEMA_DIR: cannot read {}: {e}", path.display()))?;
+            let schema = serde_json::from_slice(&contents)
+                .map_err(|e| format!("SCHEMA_DIR: {} is not JSON: {e}", path.display()))?;
+            rules.push((pattern, file.to_owned(), schema));
+        }
+        if rules.is_empty() {
+            return Err("SCHEMA_DIR requires SCHEMA_PATHS".into());
+        }
+        let sample_rate = parse("SCHEMA_SAMPLE_RATE", 10)?;
+        if sample_rate == 0 {
+            return Err("SCHEMA_SAMPLE_RATE must be at least 1".into());
+        }
+        Ok(Some(Self {
+            rules,
+            sample_rate,
+            seen: AtomicU64::new(0),
+            mark_invalid: flag("SCHEMA_VALID_HEADER")?,
+        }))
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn sample_rate(&self) -> u64 {
+        self.sample_rate
+    }
+
+    /// Checks `body` against the schema for `path`, if it has one and this
+    /// response is sampled. `None` also for bodies that aren't JSON.
+    pub fn check(&self, path: &str, body: &[u8]) -> Option<Violations> {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let (_, name, schema) = self.rules.iter().find(|(p, ..)| p.matches(path))?;
+        if !self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.sample_rate) {
+            return None;
+        }
+        let document: Value = serde_json::from_slice(body).ok()?;
+        let mut pointers = Vec::new();
+        validate(schema, &document, &mut String::new(), &mut pointers);
+        (!pointers.is_empty()).then(|| Violations {
+            schema: name.clone(),
+            pointers,
+        })
+    }
+}
+
+/// Appends where `value` breaks `schema` to `failed`, up to `MAX_VIOLATIONS`.
+fn validate(schema: &Value, value: &Value, pointer: &mut String, failed: &mut Vec<String>) {
+    if failed.len() >= MAX_VIOLATIONS {
+        return;
+    }
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    if !type_matches(schema.get("type"), value) {
+        failed.push(location(pointer));
+        return;
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            failed.push(location(pointer));
+            return;
+        }
+    }
+    match value {
+        Value::Object(fields) => validate_object(schema, fields, pointer, failed),
+        Value::Array(items) => {
+            let Some(item_schema) = schema.get("items") else {
+                return;
+            };
+            for (i, item) in items.iter().enumerate() {
+                let len = pointer.len();
+                pointer.push('/');
+                pointer.push_str(&i.to_string());
+                validate(item_schema, item, pointer, failed);
+                pointer.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    fields: &Map<String, Value>,
+    pointer: &mut String,
+    failed: &mut Vec<String>,
+) {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !fields.contains_key(name) && failed.len() < MAX_VIOLATIONS {
+                failed.push(format!("{pointer}/{}", escape(name)));
+            }
+        }
+    }
+    let Some(Value::Object(properties)) = schema.get("properties") else {
+        return;
+    };
+    for (name, property) in properties {
+        if let Some(field) = fields.get(name) {
+            let len = pointer.len();
+            pointer.push('/');
+            pointer.push_str(&escape(name));
+            validate(property, field, pointer, failed);
+            pointer.truncate(len);
+        }
+    }
+}
+
+/// Whether `value` is of the schema's `type`, which may be a list of them.
+/// No `type` accepts anything.
+fn type_matches(expected: Option<&Value>, value: &Value) -> bool {
+    let is = |name: &str| match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    };
+    match expected {
+        Some(Value::String(name)) => is(name),
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).any(is),
+        _ => true,
+    }
+}
+
+/// The document root is the empty pointer, which would vanish in a log line.
+fn location(pointer: &str) -> String {
+    if pointer.is_empty() {
+        "(root)".to_owned()
+    } else {
+        pointer.to_owned()
+    }
+}
+
+/// RFC 6901 escaping for one reference token.
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
@@ -0,0 +1,304 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderValue, Version},
+    Router,
+};
+use hyper::{body::Incoming, service::service_fn};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use std::{
+    convert::Infallible,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use crate::config::{ServerConfig, UnixSocketConfig};
+
+/// Connections on a Unix socket come from this machine; they are given as
+/// from localhost, which `TRUSTED_PROXIES` can then name.
+const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// A listener to accept connections on.
+pub enum Bound {
+    Tcp(TcpListener),
+    Tls(TcpListener, TlsAcceptor),
+    Unix(UnixListener),
+}
+
+/// Binds the `LISTEN_UNIX` socket with its mode, in place of one a previous
+/// run left behind; anything there that isn't a socket is left alone.
+pub fn bind_unix(config: &UnixSocketConfig) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(&config.path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&config.path)?,
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "not a socket")),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let listener = UnixListener::bind(&config.path)?;
+    fs::set_permissions(&config.path, fs::Permissions::from_mode(config.mode))?;
+    Ok(listener)
+}
+
+/// Like `axum::serve`, but with the server-side limits it doesn't expose: a
+/// deadline and size bounds for request headers, an idle timeout for
+/// kept-alive connections and a cap on requests per connection. Stops accepting on `shutdown` and
+/// returns once every open connection has finished its requests, or once
+/// `SHUTDOWN_DRAIN_SECS` have gone by without them all finishing.
+pub async fn serve(
+    listener: Bound,
+    router: Router,
+    config: ServerConfig,
+    shutdown: CancellationToken,
+) {
+    let connections = TaskTracker::new();
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = accept(&listener, &connections, &router, &config, &shutdown) => accepted,
+        };
+        match accepted {
+            Ok(()) => {}
+            // Typically running out of file descriptors; give it a moment.
+            Err(err) => {
+                warn!("accept failed: {err}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+
+    connections.close();
+    if tokio::time::timeout(config.drain_timeout, connections.wait()).await.is_err() {
+        warn!(
+            "{} connection(s) still busy after {:?}, dropping them",
+            connections.len(),
+            config.drain_timeout
+        );
+    }
+}
+
+/// Takes the next connection off `listener` and serves it on `connections`.
+async fn accept(
+    listener: &Bound,
+    connections: &TaskTracker,
+    router: &Router,
+    config: &ServerConfig,
+    shutdown: &CancellationToken,
+) -> io::Result<()> {
+    let (router, config, shutdown) = (router.clone(), config.clone(), shutdown.clone());
+    let h2c = config.h2c;
+    match listener {
+        Bound::Tcp(listener) => {
+            let (stream, peer) = listener.accept().await?;
+            connections.spawn(serve_connection(stream, peer, h2c, router, config, shutdown));
+        }
+        // The handshake is the client's to finish, on the connection's own
+        // task and within the time it has for its headers.
+        Bound::Tls(listener, acceptor) => {
+            let (stream, peer) = listener.accept().await?;
+            let handshake = acceptor.accept(stream);
+            connections.spawn(async move {
+                match tokio::time::timeout(config.header_read_timeout, handshake).await {
+                    Ok(Ok(stream)) => {
+                        serve_connection(stream, peer, true, router, config, shutdown).await;
+                    }
+                    Ok(Err(err)) => debug!(%peer, "TLS handshake failed: {err}"),
+                    Err(_) => debug!(%peer, "TLS handshake timed out"),
+                }
+            });
+        }
+        Bound::Unix(listener) => {
+            let (stream, _) = listener.accept().await?;
+            let served = serve_connection(stream, UNIX_PEER, h2c, router, config, shutdown);
+            connections.spawn(served);
+        }
+    }
+    Ok(())
+}
+
+/// When a connection last did anything, and how much it has done.
+struct Activity {
+    in_flight: AtomicUsize,
+    served: AtomicU64,
+    last: Mutex<Instant>,
+}
+
+impl Activity {
+    fn idle_for(&self) -> Duration {
+        if self.in_flight.load(Ordering::Acquire) > 0 {
+            return Duration::ZERO;
+        }
+        self.last.lock().unwrap().elapsed()
+    }
+}
+
+async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    stream: S,
+    peer: SocketAddr,
+    http2: bool,
+    router: Router,
+    config: ServerConfig,
+    shutdown: CancellationToken,
+) {
+    let activity = Arc::new(Activity {
+        in_flight: AtomicUsize::new(0),
+        served: AtomicU64::new(0),
+        last: Mutex::new(Instant::now()),
+    });
+    let max_requests = config.max_requests_per_connection;
+    let exhausted = move |served: u64| max_requests > 0 && served >= max_requests;
+
+    let service = {
+        let activity = activity.clone();
+        service_fn(move |mut request: Request<Incoming>| {
+            let (router, activity) = (router.clone(), activity.clone());
+            request.extensions_mut().insert(ConnectInfo(peer));
+            activity.in_flight.fetch_add(1, Ordering::AcqRel);
+            let served = activity.served.fetch_add(1, Ordering::AcqRel) + 1;
+            let http1 = request.version() <= Version::HTTP_11;
+            async move {
+                let mut response = router.oneshot(request).await?;
+                if http1 && exhausted(served) {
+                    response
+                        .headers_mut()
+                        .insert(header::CONNECTION, HeaderValue::from_static("close"));
+                }
+                *activity.last.lock().unwrap() = Instant::now();
+                activity.in_flight.fetch_sub(1, Ordering::AcqRel);
+                Ok::<_, Infallible>(response)
+            }
+        })
+    };
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(config.header_read_timeout)
+        .max_buf_size(config.max_header_bytes)
+        .max_headers(config.max_headers)
+        .keep_alive(true);
+    // Without TLS there's no ALPN to negotiate HTTP/2 with; the only way to
+    // speak it is for the client to start with its preface.
+    if http2 {
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(config.http2_max_concurrent_streams)
+            .max_header_list_size(config.max_header_bytes as u32);
+    } else {
+        builder = builder.http1_only();
+    }
+    // Not `serve_connection_with_upgrades`: it would take HTTP/2 whatever
+    // `http1_only` says, and nothing served here upgrades.
+    let connection = builder.serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    let mut checks = tokio::time::interval(config.idle_timeout / 4);
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            result = connection.as_mut() => {
+                if let Err(err) = result {
+                    debug!(%peer, "connection ended with an error: {err}");
+                }
+                break;
+            }
+            _ = shutdown.cancelled(), if !closing => {
+                connection.as_mut().graceful_shutdown();
+                closing = true;
+            }
+            _ = checks.tick(), if !closing => {
+                let idle = activity.idle_for() >= config.idle_timeout;
+                if idle || exhausted(activity.served.load(Ordering::Acquire)) {
+                    connection.as_mut().graceful_shutdown();
+                    closing = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::sync::Barrier;
+
+    fn config(h2c: bool) -> ServerConfig {
+        ServerConfig {
+            header_read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(60),
+            max_requests_per_connection: 0,
+            max_header_bytes: 16 * 1024,
+            max_headers: 100,
+            max_url_bytes: 8 * 1024,
+            max_body_bytes: 1024 * 1024,
+            h2c,
+            http2_max_concurrent_streams: 100,
+            max_in_flight: 0,
+            drain_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn h2c_multiplexes_requests_on_one_connection() {
+        const REQUESTS: usize = 12;
+        // Every request waits for all the others, so none can be answered
+        // unless they are all in flight at once.
+        let barrier = Arc::new(Barrier::new(REQUESTS));
+        let router = Router::new().route(
+            "/",
+            get(move |ConnectInfo(peer): ConnectInfo<SocketAddr>| async move {
+                barrier.wait().await;
+                peer.port().to_string()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let shutdown = CancellationToken::new();
+        let server = serve(Bound::Tcp(listener), router, config(true), shutdown.clone());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+        let requests = (0..REQUESTS).map(|_| async {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.version(), Version::HTTP_2);
+            response.text().await.unwrap()
+        });
+        let all = futures_util::future::join_all(requests);
+        let ports = tokio::time::timeout(Duration::from_secs(5), all).await.unwrap();
+        assert!(ports.iter().all(|port| *port == ports[0]));
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn without_h2c_a_cleartext_preface_is_refused() {
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let shutdown = CancellationToken::new();
+        tokio::spawn(serve(Bound::Tcp(listener), router, config(false), shutdown.clone()));
+
+        let h2 = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+        assert!(h2.get(&url).send().await.is_err());
+        let http1 = reqwest::Client::new().get(&url).send().await.unwrap();
+        assert_eq!(http1.version(), Version::HTTP_11);
+        shutdown.cancel();
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    body::{self, Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use metrics::counter;
+use reqwest::Client;
+use serde_json::Value;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::{
+    admin::constant_time_eq, config::ShadowConfig, error_response, peers::FromPeer, AppState,
+};
+
+/// Carries `SHADOW_SECRET` on mirrored requests, marking them as such.
+pub const SECRET_HEADER: HeaderName = HeaderName::from_static("x-shadow-secret");
+
+/// How long a mirrored request may take before it is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Bodies larger than this are mirrored but not compared.
+const MAX_COMPARE_BYTES: u64 = 1024 * 1024;
+/// How many differing locations one comparison logs.
+const MAX_DIFFERENCES: usize = 5;
+
+/// Marks a request mirrored here by another instance's shadow mode.
+#[derive(Clone, Copy)]
+pub struct FromShadow;
+
+/// Shadow mode, for trying out a new version against production traffic:
+/// `SHADOW_PERCENT` of proxied requests are sent again to
+/// `SHADOW_TARGET_URL` once the client has its answer, and every
+/// `SHADOW_COMPARE_EVERY`th of those has its status and JSON body compared
+/// with ours, differences logged.
+///
+/// Mirroring never holds up a client: at most `SHADOW_CONCURRENCY` mirrored
+/// requests run at once and any more are skipped. The target, configured
+/// with the same `SHADOW_SECRET`, leaves them out of its rate limits and
+/// usage accounting.
+pub struct Shadow {
+    target: String,
+    secret: HeaderValue,
+    percent: u64,
+    compare_every: u64,
+    permits: Arc<Semaphore>,
+    seen: AtomicU64,
+    mirrored: AtomicU64,
+    client: Client,
+}
+
+impl Shadow {
+    pub fn new(config: &ShadowConfig, target: &str) -> Self {
+        let secret = config.secret.as_deref().expect("SHADOW_TARGET_URL requires SHADOW_SECRET");
+        let mut secret =
+            HeaderValue::from_str(secret).expect("SHADOW_SECRET is a valid header value");
+        secret.set_sensitive(true);
+        Self {
+            target: target.to_owned(),
+            secret,
+            percent: config.percent,
+            compare_every: config.compare_every,
+            permits: Arc::new(Semaphore::new(config.concurrency)),
+            seen: AtomicU64::new(0),
+            mirrored: AtomicU64::new(0),
+            client: Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(TIMEOUT)
+                .build()
+                .expect("shadow client builds"),
+        }
+    }
+
+    /// Whether this request is one of the `SHADOW_PERCENT`, spread evenly
+    /// over every hundred.
+    fn sampled(&self) -> bool {
+        self.seen.fetch_add(1, Ordering::Relaxed) % 100 < self.percent
+    }
+
+    /// Sends the request to the target and, given our own answer, compares
+    /// the two.
+    async fn mirror(&self, path: String, headers: HeaderMap, ours: Option<(StatusCode, Bytes)>) {
+        let url = format!("{}{path}", self.target);
+        let sent = self
+            .client
+            .get(&url)
+            .headers(headers)
+            .header(SECRET_HEADER, self.secret.clone())
+            .send()
+            .await;
+        let theirs = match sent {
+            Ok(theirs) => theirs,
+            Err(err) => {
+                debug!(path, "shadow request failed: {err}");
+                counter!("proxy_shadow_failures_total").increment(1);
+                return;
+            }
+        };
+        counter!("proxy_shadow_requests_total").increment(1);
+        let Some((status, body)) = ours else {
+            return;
+        };
+
+        let their_status = theirs.status();
+        let Ok(their_body) = theirs.bytes().await else {
+            counter!("proxy_shadow_failures_total").increment(1);
+            return;
+        };
+        if their_status != status {
+            counter!("proxy_shadow_mismatches_total", "kind" => "status").increment(1);
+            warn!(path, ours = %status, theirs = %their_status, "shadow status differs");
+            return;
+        }
+        let (Ok(ours), Ok(theirs)) = (
+            serde_json::from_slice::<Value>(&body),
+            serde_json::from_slice::<Value>(&their_body),
+        ) else {
+            return;
+        };
+        let mut differences = Vec::new();
+        diff(&ours, &theirs, &mut String::new(), &mut differences);
+        if !differences.is_empty() {
+            counter!("proxy_shadow_mismatches_total", "kind" => "body").increment(1);
+            warn!(path, "shadow body differs at {}", differences.join(", "));
+        }
+    }
+}
+
+/// Mirrors a share of proxied GETs to the shadow target, after the client
+/// has been answered. Requests that are themselves mirrored or forwarded by
+/// a replica aren't mirrored again.
+pub async fn shadow_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(shadow) = state.shadow.clone() else {
+        return next.run(request).await;
+    };
+    let extensions = request.extensions();
+    let eligible = request.method() == Method::GET
+        && !request.uri().path().starts_with("/__")
+        && !request.uri().path().starts_with("/watch/")
+        && extensions.get::<FromShadow>().is_none()
+        && extensions.get::<FromPeer>().is_none();
+    if !eligible || !shadow.sampled() {
+        return next.run(request).await;
+    }
+
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| request.uri().path().to_owned(), |p| p.as_str().to_owned());
+    let mut headers = state.config.forward_headers.extract(request.headers());
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        headers.insert(header::ORIGIN, origin.clone());
+    }
+    let response = next.run(request).await;
+
+    let Ok(permit) = shadow.permits.clone().try_acquire_owned() else {
+        counter!("proxy_shadow_skipped_total").increment(1);
+        return response;
+    };
+    let mirrored = shadow.mirrored.fetch_add(1, Ordering::Relaxed);
+    let compare = mirrored.is_multiple_of(shadow.compare_every)
+        && response
+            .body()
+            .size_hint()
+            .upper()
+            .is_some_and(|len| len <= MAX_COMPARE_BYTES);
+    // Proxied bodies are already in memory, so this doesn't wait on anything.
+    let (response, ours) = if compare {
+        let (parts, body) = response.into_parts();
+        match body::to_bytes(body, MAX_COMPARE_BYTES as usize).await {
+            Ok(bytes) => {
+                let ours = Some((parts.status, bytes.clone()));
+                (Response::from_parts(parts, Body::from(bytes)), ours)
+            }
+            Err(_) => return error_response(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    } else {
+        (response, None)
+    };
+
+    tokio::spawn(async move {
+        let _permit = permit;
+        shadow.mirror(path, headers, ours).await;
+    });
+    response
+}
+
+/// Recognises requests mirrored here by another instance, which must carry
+/// this instance's `SHADOW_SECRET`.
+pub async fn mark_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(presented) = request.headers().get(SECRET_HEADER) else {
+        return next.run(request).await;
+    };
+    let expected = state.config.shadow.secret.as_deref();
+    if !expected.is_some_and(|expected| constant_time_eq(presented.as_bytes(), expected.as_bytes()))
+    {
+        return error_response(StatusCode::UNAUTHORIZED);
+    }
+    request.extensions_mut().insert(FromShadow);
+    next.run(request).await
+}
+
+/// Appends the JSON pointers at which `ours` and `theirs` differ to
+/// `differences`, up to `MAX_DIFFERENCES`.
+fn diff(ours: &Value, theirs: &Value, pointer: &mut String, differences: &mut Vec<String>) {
+    if differences.len() >= MAX_DIFFERENCES || ours == theirs {
+        return;
+    }
+    match (ours, theirs) {
+        (Value::Object(ours), Value::Object(theirs)) => {
+            for key in ours.keys().chain(theirs.keys().filter(|k| !ours.contains_key(*k))) {
+                let len = pointer.len();
+                pointer.push('/');
+                pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                match (ours.get(key), theirs.get(key)) {
+                    (Some(a), Some(b)) => diff(a, b, pointer, differences),
+                    _ if differences.len() < MAX_DIFFERENCES => differences.push(pointer.clone()),
+                    _ => {}
+                }
+                pointer.truncate(len);
+            }
+        }
+        (Value::Array(ours), Value::Array(theirs)) if ours.len() == theirs.len() => {
+            for (i, (a, b)) in ours.iter().zip(theirs).enumerate() {
+                let len = pointer.len();
+                pointer.push('/');
+                pointer.push_str(&i.to_string());
+                diff(a, b, pointer, differences);
+                pointer.truncate(len);
+            }
+        }
+        _ if pointer.is_empty() => differences.push("(root)".to_owned()),
+        _ => differences.push(pointer.clone()),
+    }
+}
@@ -0,0 +1,201 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde_json::json;
+use std::{fs, path::PathBuf};
+
+use crate::{config::var, error_response, AppState};
+
+pub const SIGNATURE_HEADER: &str = "x-proxy-signature";
+pub const SIGNED_HEADERS_HEADER: &str = "x-proxy-signed-headers";
+/// Covered by the signature whenever the response carries them.
+const SIGNED: [HeaderName; 2] = [header::CONTENT_TYPE, header::ETAG];
+
+/// Signs proxied responses with the Ed25519 key in `RESPONSE_SIGNING_KEY`
+/// (PKCS#8, PEM or DER, as `openssl genpkey -algorithm ed25519` writes it),
+/// so consumers that keep responses can later check they came from us
+/// unmodified.
+///
+/// `X-Proxy-Signature` is the base64 signature over, for each header named
+/// in `X-Proxy-Signed-Headers` in that order, `name: value\n`, then a blank
+/// line, then the body. With the public key from `GET /__signing-key`:
+///
+/// ```text
+/// printf 'content-type: %s\netag: %s\n\n' "$type" "$etag" > message
+/// cat body >> message
+/// base64 -d <<< "$signature" > signature.bin
+/// openssl pkeyutl -verify -pubin -inkey public.pem -rawin \
+///     -in message -sigfile signature.bin
+/// ```
+///
+/// Signing is done per response. Range responses, being parts of a download
+/// rather than a whole body, are never signed.
+pub struct Signer {
+    file: PathBuf,
+    key: Ed25519KeyPair,
+    public_key: String,
+}
+
+impl Signer {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(file) = var("RESPONSE_SIGNING_KEY").map(PathBuf::from) else {
+            return Ok(None);
+        };
+        let contents = fs::read(&file).map_err(|e| {
+            format!("RESPONSE_SIGNING_KEY: cannot read {}: {e}", file.display())
+        })?;
+        let der = match std::str::from_utf8(&contents) {
+            Ok(pem) if pem.contains("-----BEGIN") => {
+                let encoded: String = pem
+                    .lines()
+                    .filter(|line| !line.starts_with("-----"))
+                    .collect();
+                STANDARD.decode(encoded.trim()).map_err(|_| {
+                    format!("RESPONSE_SIGNING_KEY: {} is not valid PEM", file.display())
+                })?
+            }
+            _ => contents,
+        };
+        let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(|_| {
+            format!(
+                "RESPONSE_SIGNING_KEY: {} is not a PKCS#8 Ed25519 private key",
+                file.display()
+            )
+        })?;
+        let public_key = STANDARD.encode(key.public_key().as_ref());
+        Ok(Some(Self {
+            file,
+            key,
+            public_key,
+        }))
+    }
+
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Adds the signature headers for `body` as served with `headers`.
+    pub fn sign(&self, headers: &mut HeaderMap, body: &[u8]) {
+        if headers.contains_key(header::CONTENT_RANGE) {
+            return;
+        }
+        let signed: Vec<(&HeaderName, &HeaderValue)> = SIGNED
+            .iter()
+            .filter_map(|name| Some((name, headers.get(name)?)))
+            .collect();
+        let mut message = Vec::with_capacity(body.len() + 128);
+        for (name, value) in &signed {
+            message.extend_from_slice(name.as_str().as_bytes());
+            message.extend_from_slice(b": ");
+            message.extend_from_slice(value.as_bytes());
+            message.push(b'\n');
+        }
+        message.push(b'\n');
+        message.extend_from_slice(body);
+
+        let names = signed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let signature = STANDARD.encode(self.key.sign(&message));
+        headers.insert(
+            SIGNED_HEADERS_HEADER,
+            HeaderValue::from_str(&names).expect("header names are valid header values"),
+        );
+        headers.insert(
+            SIGNATURE_HEADER,
+            HeaderValue::from_str(&signature).expect("base64 is a valid header value"),
+        );
+    }
+}
+
+/// The public half only.
+impl Serialize for Signer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("key_file", &self.file)?;
+        map.serialize_entry("public_key", &self.public_key)?;
+        map.end()
+    }
+}
+
+/// `GET /__signing-key`, public like the signatures it checks.
+pub fn router() -> Router<AppState> {
+    Router::new().route("/__signing-key", get(signing_key))
+}
+
+async fn signing_key(State(state): State<AppState>) -> Response {
+    let Some(signer) = &state.config.signer else {
+        return error_response(StatusCode::NOT_FOUND);
+    };
+    Json(json!({ "algorithm": "ed25519", "public_key": signer.public_key() })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{UnparsedPublicKey, ED25519},
+    };
+
+    fn signer() -> Signer {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = STANDARD.encode(key.public_key().as_ref());
+        Signer {
+            file: PathBuf::new(),
+            key,
+            public_key,
+        }
+    }
+
+    /// What a consumer checks the signature against, as the docs above
+    /// tell them to build it.
+    fn message(headers: &HeaderMap, body: &[u8]) -> Vec<u8> {
+        let names = headers[SIGNED_HEADERS_HEADER].to_str().unwrap();
+        let mut message = Vec::new();
+        for name in names.split(", ") {
+            message.extend_from_slice(format!("{name}: ").as_bytes());
+            message.extend_from_slice(headers[name].as_bytes());
+            message.push(b'\n');
+        }
+        message.push(b'\n');
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn a_signature_verifies_with_the_public_key() {
+        let signer = signer();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(header::ETAG, HeaderValue::from_static("\"abc\""));
+        let body = br#"{"full_name":"o/r"}"#;
+        signer.sign(&mut headers, body);
+        assert_eq!(headers[SIGNED_HEADERS_HEADER], "content-type, etag");
+
+        let public_key = STANDARD.decode(signer.public_key()).unwrap();
+        let public_key = UnparsedPublicKey::new(&ED25519, public_key);
+        let signature = STANDARD.decode(&headers[SIGNATURE_HEADER]).unwrap();
+        assert!(public_key.verify(&message(&headers, body), &signature).is_ok());
+        let tampered = message(&headers, br#"{"full_name":"o/x"}"#);
+        assert!(public_key.verify(&tampered, &signature).is_err());
+    }
+
+    #[test]
+    fn a_range_is_never_signed() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_RANGE, HeaderValue::from_static("bytes 0-1/10"));
+        signer().sign(&mut headers, b"ab");
+        assert!(!headers.contains_key(SIGNATURE_HEADER));
+    }
+}
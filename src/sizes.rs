@@ -0,0 +1,144 @@
+use metrics::histogram;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{cache::ResponseCache, paths::PathClass};
+
+/// Bucket 0 holds empty bodies, bucket `i` sizes below `2^i`; the last one
+/// everything from 2 GiB up.
+const BUCKETS: usize = 33;
+
+/// Body sizes at power-of-two resolution, so percentiles come out within a
+/// factor of two; the minimum and maximum are exact.
+pub struct SizeHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct SizeSummary {
+    count: u64,
+    min: u64,
+    max: u64,
+    p50: u64,
+    p90: u64,
+    p99: u64,
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl SizeHistogram {
+    pub fn record(&self, len: u64) {
+        let bucket = (u64::BITS - len.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.min.fetch_min(len, Ordering::Relaxed);
+        self.max.fetch_max(len, Ordering::Relaxed);
+    }
+
+    /// Each percentile is the top of the bucket it falls in, capped by the
+    /// largest size seen.
+    pub fn summary(&self) -> Option<SizeSummary> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        if count == 0 {
+            return None;
+        }
+        let max = self.max.load(Ordering::Relaxed);
+        let percentile = |p: u64| {
+            let rank = (count * p).div_ceil(100);
+            let mut seen = 0;
+            for (bucket, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    return ((1u64 << bucket) - 1).min(max);
+                }
+            }
+            max
+        };
+        Some(SizeSummary {
+            count,
+            min: self.min.load(Ordering::Relaxed),
+            max,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+impl SizeSummary {
+    /// Exact, from every size there is.
+    pub fn of(mut sizes: Vec<u64>) -> Option<Self> {
+        sizes.sort_unstable();
+        let count = sizes.len() as u64;
+        let percentile = |p: u64| sizes[((count * p).div_ceil(100) as usize).max(1) - 1];
+        Some(Self {
+            count,
+            min: *sizes.first()?,
+            max: *sizes.last()?,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+/// Sizes of upstream bodies, by kind of endpoint, for tuning the cache: also
+/// recorded as `proxy_upstream_body_bytes`, next to the
+/// `proxy_cache_entry_bytes` of what is stored.
+#[derive(Default)]
+pub struct BodySizes {
+    classes: [SizeHistogram; PathClass::ALL.len()],
+}
+
+impl BodySizes {
+    pub fn record(&self, class: PathClass, len: usize) {
+        histogram!("proxy_upstream_body_bytes", "class" => class.name()).record(len as f64);
+        let index = PathClass::ALL.iter().position(|c| *c == class).unwrap_or_default();
+        self.classes[index].record(len as u64);
+    }
+
+    pub fn summary(&self) -> BTreeMap<&'static str, SizeSummary> {
+        PathClass::ALL
+            .iter()
+            .zip(&self.classes)
+            .filter_map(|(class, sizes)| Some((class.name(), sizes.summary()?)))
+            .collect()
+    }
+
+    /// One line for the log: each class's median, p99 and largest body.
+    pub fn log_line(&self) -> String {
+        let summary = self.summary();
+        if summary.is_empty() {
+            return "none fetched".to_owned();
+        }
+        summary
+            .iter()
+            .map(|(class, s)| {
+                format!("{class} n={} p50={} p99={} max={}", s.count, s.p50, s.p99, s.max)
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// The bodies held in `cache` right now.
+pub fn cached(cache: &ResponseCache) -> Option<SizeSummary> {
+    SizeSummary::of(cache.iter().map(|(_, entry)| entry.body.len() as u64).collect())
+}
@@ -0,0 +1,329 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hyper::body::{Body as _, Frame, SizeHint};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{
+    admin::require_admin,
+    cache::CachedResponse,
+    content::BodyKind,
+    error_response, AppState,
+};
+
+/// Starts every snapshot, so a stray body isn't taken for one.
+const MAGIC: &[u8] = b"REPOS-CACHE-SNAPSHOT-1\n";
+/// Entries with a larger body are left out of an import.
+const MAX_ENTRY_BYTES: usize = 16 * 1024 * 1024;
+/// Bound on one record's metadata.
+const MAX_META_BYTES: usize = 64 * 1024;
+/// How long `PEER_WARM_FROM` is waited on before starting cold.
+const WARM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Moving a warm cache between replicas: `GET /__cache/export` streams
+/// every live entry, `POST /__cache/import` loads such a stream. Served on
+/// admin-only listeners alone, behind `ADMIN_TOKEN`.
+///
+/// A snapshot is `MAGIC` followed by one record per entry: a big-endian
+/// `u32` length and that many bytes of JSON metadata (key, status, remaining
+/// TTL, headers), then a `u32` length and the body.
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/__cache/export", get(export))
+        .route("/__cache/import", post(import))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    key: String,
+    status: u16,
+    ttl_ms: u64,
+    #[serde(default)]
+    immutable: bool,
+    headers: Vec<(String, String)>,
+}
+
+/// How an import went.
+#[derive(Default, Serialize)]
+pub struct Imported {
+    imported: u64,
+    expired: u64,
+    oversized: u64,
+}
+
+async fn export(State(state): State<AppState>) -> Response {
+    let entries: Vec<_> = state
+        .cache
+        .iter()
+        .filter(|(_, entry)| entry.is_fresh())
+        .collect();
+    counter!("proxy_snapshot_exported_entries_total").increment(entries.len() as u64);
+    let mut response = Body::new(Snapshot {
+        magic: true,
+        entries: entries.into_iter(),
+    })
+    .into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    response
+}
+
+/// Encodes entries one at a time as the client reads them.
+struct Snapshot {
+    magic: bool,
+    entries: std::vec::IntoIter<(Arc<Arc<str>>, Arc<CachedResponse>)>,
+}
+
+impl hyper::body::Body for Snapshot {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if std::mem::take(&mut self.magic) {
+            return Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(MAGIC)))));
+        }
+        Poll::Ready(
+            self.entries
+                .next()
+                .map(|(key, entry)| Ok(Frame::data(encode(&key, &entry)))),
+        )
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+fn encode(key: &str, entry: &CachedResponse) -> Bytes {
+    let meta = Meta {
+        key: key.to_owned(),
+        status: entry.status.as_u16(),
+        ttl_ms: entry.ttl.saturating_sub(entry.stored_at.elapsed()).as_millis() as u64,
+        immutable: entry.immutable,
+        headers: entry
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+            .collect(),
+    };
+    let meta = serde_json::to_vec(&meta).expect("snapshot metadata serializes");
+    let mut record = BytesMut::with_capacity(8 + meta.len() + entry.body.len());
+    record.put_u32(meta.len() as u32);
+    record.put_slice(&meta);
+    record.put_u32(entry.body.len() as u32);
+    record.put_slice(&entry.body);
+    record.freeze()
+}
+
+async fn import(State(state): State<AppState>, request: Request) -> Response {
+    let mut body = request.into_body();
+    let mut decoder = Decoder::default();
+    let mut imported = Imported::default();
+    loop {
+        let frame = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await;
+        let chunk = match frame {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(chunk) => chunk,
+                Err(_) => continue,
+            },
+            Some(Err(_)) => return error_response(StatusCode::BAD_REQUEST),
+            None => break,
+        };
+        if let Err(err) = decoder.feed(&chunk, &state, &mut imported).await {
+            warn!("rejected cache snapshot: {err}");
+            return error_response(StatusCode::BAD_REQUEST);
+        }
+    }
+    if !decoder.finished() {
+        return error_response(StatusCode::BAD_REQUEST);
+    }
+    counter!("proxy_snapshot_imported_entries_total").increment(imported.imported);
+    info!(imported = imported.imported, "imported cache snapshot");
+    Json(json!(imported)).into_response()
+}
+
+/// Imports `PEER_WARM_FROM`'s cache, giving up after `WARM_TIMEOUT`. Any
+/// failure only means starting cold.
+pub async fn warm_from(state: &AppState, url: &str) {
+    let Some(admin_token) = state.config.admin_token.as_deref() else {
+        return;
+    };
+    let started = Instant::now();
+    let warming = async {
+        let mut response = reqwest::Client::new()
+            .get(format!("{url}/__cache/export"))
+            .bearer_auth(admin_token)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| err.to_string())?;
+        let mut decoder = Decoder::default();
+        let mut imported = Imported::default();
+        while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+            decoder.feed(&chunk, state, &mut imported).await?;
+        }
+        if !decoder.finished() {
+            return Err("snapshot was cut short".to_owned());
+        }
+        Ok::<_, String>(imported)
+    };
+    match tokio::time::timeout(WARM_TIMEOUT, warming).await {
+        Ok(Ok(imported)) => {
+            counter!("proxy_snapshot_imported_entries_total").increment(imported.imported);
+            info!(
+                from = url,
+                imported = imported.imported,
+                "warmed the cache in {:?}",
+                started.elapsed()
+            );
+        }
+        Ok(Err(err)) => warn!(from = url, "cannot warm the cache, starting cold: {err}"),
+        Err(_) => warn!(from = url, "warming the cache timed out, starting cold"),
+    }
+}
+
+/// Parses a snapshot as it arrives, in whatever chunks.
+#[derive(Default)]
+struct Decoder {
+    buffer: BytesMut,
+    past_magic: bool,
+    /// What is left of an oversized body being skipped.
+    skipping: usize,
+}
+
+enum Record {
+    Entry(Meta, Bytes),
+    /// Too large to import; its body is being skipped.
+    Oversized,
+}
+
+impl Decoder {
+    /// Whether the stream ended between records, as a complete one does.
+    fn finished(&self) -> bool {
+        self.past_magic && self.buffer.is_empty() && self.skipping == 0
+    }
+
+    async fn feed(
+        &mut self,
+        mut chunk: &[u8],
+        state: &AppState,
+        imported: &mut Imported,
+    ) -> Result<(), String> {
+        let skipped = self.skipping.min(chunk.len());
+        self.skipping -= skipped;
+        chunk = &chunk[skipped..];
+        self.buffer.extend_from_slice(chunk);
+        if !self.past_magic {
+            if self.buffer.len() < MAGIC.len() {
+                return Ok(());
+            }
+            if &self.buffer[..MAGIC.len()] != MAGIC {
+                return Err("not a cache snapshot".into());
+            }
+            self.buffer.advance(MAGIC.len());
+            self.past_magic = true;
+        }
+        while let Some(record) = self.next_record()? {
+            let Record::Entry(meta, body) = record else {
+                imported.oversized += 1;
+                continue;
+            };
+            let Ok(status) = StatusCode::from_u16(meta.status) else {
+                imported.expired += 1;
+                continue;
+            };
+            if meta.ttl_ms == 0 {
+                imported.expired += 1;
+                continue;
+            }
+            let headers = headers(&meta.headers);
+            let entry = state.bodies.intern(Arc::new(CachedResponse {
+                status,
+                kind: BodyKind::classify(headers.get(header::CONTENT_TYPE), &body),
+                body,
+                headers,
+                stored_at: Instant::now(),
+                ttl: Duration::from_millis(meta.ttl_ms),
+                purged: false,
+                immutable: meta.immutable,
+            }));
+            state.cache.insert(meta.key.into(), entry).await;
+            imported.imported += 1;
+        }
+        Ok(())
+    }
+
+    /// The next complete record, if the buffer holds one.
+    fn next_record(&mut self) -> Result<Option<Record>, String> {
+        if self.skipping > 0 {
+            return Ok(None);
+        }
+        let length = |buffer: &[u8], at: usize| {
+            buffer
+                .get(at..at + 4)
+                .map(|b| u32::from_be_bytes(b.try_into().expect("four bytes")) as usize)
+        };
+        let Some(meta_len) = length(&self.buffer, 0) else {
+            return Ok(None);
+        };
+        if meta_len > MAX_META_BYTES {
+            return Err("record metadata is too large".into());
+        }
+        let Some(body_len) = length(&self.buffer, 4 + meta_len) else {
+            return Ok(None);
+        };
+        if body_len > MAX_ENTRY_BYTES {
+            self.buffer.advance(8 + meta_len);
+            let buffered = body_len.min(self.buffer.len());
+            self.buffer.advance(buffered);
+            self.skipping = body_len - buffered;
+            return Ok(Some(Record::Oversized));
+        }
+        let record_len = 8 + meta_len + body_len;
+        if self.buffer.len() < record_len {
+            return Ok(None);
+        }
+        let mut record = self.buffer.split_to(record_len).freeze();
+        record.advance(4);
+        let meta: Meta = serde_json::from_slice(&record.split_to(meta_len))
+            .map_err(|err| format!("invalid record metadata: {err}"))?;
+        record.advance(4);
+        Ok(Some(Record::Entry(meta, record)))
+    }
+}
+
+fn headers(pairs: &[(String, String)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    headers
+}
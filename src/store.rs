@@ -0,0 +1,169 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    cache::CachedResponse, config::CacheConfig, content::BodyKind, disk::DiskCache,
+    redis::RedisCache, repos::RepoPattern,
+};
+
+/// The second cache tier, behind memory: asked when memory misses, and
+/// written through whenever an upstream answer is kept. Everything but
+/// `get` happens in the background; a store that fails counts as a miss.
+pub trait CacheStore: Send + Sync {
+    /// What `/__stats` and the `tier` label of lookups call it.
+    fn name(&self) -> &'static str;
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<Arc<CachedResponse>>>;
+    fn store(&self, key: Arc<str>, entry: Arc<CachedResponse>);
+    fn remove(&self, key: &str);
+    /// Deletes every entry for a repository matching one of `repos`.
+    fn purge_repos(&self, repos: &[RepoPattern]);
+    fn clear(&self);
+    fn stats(&self) -> Value;
+}
+
+/// The store `CACHE_DISK_PATH` or `CACHE_REDIS_URL` asks for, if either.
+pub fn open(config: &CacheConfig) -> Result<Option<Arc<dyn CacheStore>>, String> {
+    if let Some(disk) = &config.disk {
+        return Ok(Some(Arc::new(DiskCache::open(disk)?)));
+    }
+    if let Some(redis) = &config.redis {
+        return Ok(Some(Arc::new(RedisCache::new(redis))));
+    }
+    Ok(None)
+}
+
+/// Everything about an entry but its body, stored as a JSON line with the
+/// body following verbatim.
+#[derive(Serialize, Deserialize)]
+pub struct Meta {
+    pub key: String,
+    /// Unix milliseconds.
+    pub stored_at: u64,
+    pub ttl_ms: u64,
+    /// Entries written before statuses were kept are all 200s.
+    #[serde(default = "ok")]
+    pub status: u16,
+    #[serde(default)]
+    pub immutable: bool,
+    pub headers: Vec<(String, String)>,
+    pub body_len: usize,
+}
+
+/// `entry` as stored, with the Unix milliseconds it was fetched at.
+pub fn encode(key: &str, entry: &CachedResponse, stored_at: u64) -> Vec<u8> {
+    let meta = Meta {
+        key: key.to_owned(),
+        stored_at,
+        ttl_ms: entry.ttl.as_millis() as u64,
+        status: entry.status.as_u16(),
+        immutable: entry.immutable,
+        headers: entry
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned()))
+            })
+            .collect(),
+        body_len: entry.body.len(),
+    };
+    let mut contents = serde_json::to_vec(&meta).expect("metadata serializes");
+    contents.push(b'\n');
+    contents.extend_from_slice(&entry.body);
+    contents
+}
+
+/// An entry back from what `encode` made, checking it is the one asked for
+/// and complete, with the Unix milliseconds it was fetched at.
+pub fn decode(contents: &[u8], key: &str) -> Option<(CachedResponse, u64)> {
+    let newline = contents.iter().position(|&b| b == b'\n')?;
+    let meta: Meta = serde_json::from_slice(&contents[..newline]).ok()?;
+    let body = &contents[newline + 1..];
+    if meta.key != key || body.len() != meta.body_len {
+        return None;
+    }
+
+    let mut headers = HeaderMap::with_capacity(meta.headers.len());
+    for (name, value) in &meta.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+        headers.append(name, HeaderValue::from_str(value).ok()?);
+    }
+
+    // Back onto the monotonic clock; an entry older than it treats as stale.
+    let age = Duration::from_millis(unix_millis(SystemTime::now()).saturating_sub(meta.stored_at));
+    let (stored_at, ttl) = match Instant::now().checked_sub(age) {
+        Some(stored_at) => (stored_at, Duration::from_millis(meta.ttl_ms)),
+        None => (Instant::now(), Duration::ZERO),
+    };
+    let kind = BodyKind::classify(headers.get(header::CONTENT_TYPE), body);
+    let entry = CachedResponse {
+        status: StatusCode::from_u16(meta.status).ok()?,
+        body: Bytes::copy_from_slice(body),
+        headers,
+        stored_at,
+        ttl,
+        purged: false,
+        immutable: meta.immutable,
+        kind,
+    };
+    Some((entry, meta.stored_at))
+}
+
+/// When `entry` was fetched, in Unix milliseconds.
+pub fn fetched_at(entry: &CachedResponse) -> u64 {
+    unix_millis(SystemTime::now() - entry.stored_at.elapsed())
+}
+
+/// Whether `key` is for a repository matching one of `repos`.
+pub fn in_repos(key: &str, repos: &[RepoPattern]) -> bool {
+    let path = key.split(['?', '#']).next().unwrap_or_default();
+    repos.iter().any(|p| p.matches(path))
+}
+
+fn ok() -> u16 {
+    StatusCode::OK.as_u16()
+}
+
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_valued_headers_survive_storage_in_order() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::LINK, HeaderValue::from_static("<a>; rel=\"next\""));
+        headers.append(header::ETAG, HeaderValue::from_static("\"v1\""));
+        headers.append(header::LINK, HeaderValue::from_static("<b>; rel=\"last\""));
+        let entry = CachedResponse {
+            status: StatusCode::OK,
+            body: Bytes::from_static(b"{}\n[]"),
+            headers,
+            stored_at: Instant::now(),
+            ttl: Duration::from_secs(60),
+            purged: false,
+            immutable: false,
+            kind: BodyKind::Json,
+        };
+
+        let stored = encode("o/r", &entry, fetched_at(&entry));
+        let (decoded, _) = decode(&stored, "o/r").unwrap();
+        let links: Vec<_> = decoded.headers.get_all(header::LINK).iter().collect();
+        assert_eq!(links, ["<a>; rel=\"next\"", "<b>; rel=\"last\""]);
+        assert_eq!(decoded.headers[header::ETAG], "\"v1\"");
+        assert_eq!(decoded.headers.len(), 3);
+        assert_eq!(decoded.body, entry.body);
+        assert!(decode(&stored, "o/other").is_none());
+    }
+}
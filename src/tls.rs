@@ -0,0 +1,103 @@
+use axum::{
+    http::{header, uri::Authority, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use std::sync::{Arc, RwLock};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{config::TlsConfig, json_error};
+
+/// The certificate served on HTTPS listeners, swapped for a fresh copy of
+/// `TLS_CERT` and `TLS_KEY` on SIGHUP without dropping a connection.
+#[derive(Debug)]
+pub struct Certificates {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+impl Certificates {
+    pub fn load(config: &TlsConfig) -> Result<Arc<Self>, String> {
+        Ok(Arc::new(Self {
+            current: RwLock::new(certified_key(config)?),
+        }))
+    }
+
+    /// Reads the files again; on error the certificate in use stays.
+    pub fn reload(&self, config: &TlsConfig) -> Result<(), String> {
+        let key = certified_key(config)?;
+        *self.current.write().unwrap() = key;
+        Ok(())
+    }
+
+    /// What HTTPS connections are accepted with. HTTP/2 is offered through
+    /// ALPN, as every browser expects of HTTPS.
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor, String> {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn certified_key(config: &TlsConfig) -> Result<Arc<CertifiedKey>, String> {
+    let cert = config.cert.display();
+    let chain = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("TLS_CERT {cert}: {e}"))?;
+    if chain.is_empty() {
+        return Err(format!("TLS_CERT {cert}: no certificates"));
+    }
+    let key_path = config.key.display();
+    let key = PrivateKeyDer::from_pem_file(&config.key)
+        .map_err(|e| format!("TLS_KEY {key_path}: {e}"))?;
+    let key =
+        ring::sign::any_supported_type(&key).map_err(|e| format!("TLS_KEY {key_path}: {e}"))?;
+    let certified = CertifiedKey::new(chain, key);
+    certified
+        .keys_match()
+        .map_err(|e| format!("TLS_CERT and TLS_KEY don't match: {e}"))?;
+    Ok(Arc::new(certified))
+}
+
+/// Everything on the `TLS_REDIRECT_ADDR` listener: a permanent redirect to
+/// the same URL on HTTPS, at `https_port`.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |uri: Uri, headers: HeaderMap| async move {
+        redirect(&uri, &headers, https_port)
+    })
+}
+
+fn redirect(uri: &Uri, headers: &HeaderMap, https_port: u16) -> Response {
+    let authority = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok());
+    let Some(authority) = authority else {
+        return json_error(StatusCode::BAD_REQUEST, "a Host header is required");
+    };
+    let host = authority.host();
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let location = match https_port {
+        443 => format!("https://{host}{path}"),
+        port => format!("https://{host}:{port}{path}"),
+    };
+    // 308 rather than 301, so a POST is still a POST.
+    Redirect::permanent(&location).into_response()
+}
@@ -0,0 +1,260 @@
+use axum::http::HeaderValue;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    config::{fingerprint, list, var},
+    github_app::GithubApp,
+    origin::OriginPattern,
+};
+
+/// A GitHub credential upstream requests are billed against.
+pub struct GithubToken {
+    /// `default` for `GITHUB_TOKEN`, `pool-<n>` for the n-th of
+    /// `GITHUB_TOKENS`, `app` for a GitHub App, otherwise the suffix of its
+    /// variable.
+    pub name: String,
+    /// Replaced as it expires when this is a GitHub App's.
+    credential: RwLock<Credential>,
+    pub app: Option<GithubApp>,
+    /// A client's own, sent with its request; nothing about it is kept.
+    pub client: bool,
+}
+
+struct Credential {
+    secret: String,
+    /// `Bearer <secret>`, built once and marked sensitive.
+    authorization: HeaderValue,
+}
+
+impl Credential {
+    fn new(variable: &str, secret: String) -> Result<Self, String> {
+        let mut authorization = HeaderValue::from_str(&format!("Bearer {secret}"))
+            .map_err(|_| format!("{variable} contains characters not allowed in a header"))?;
+        authorization.set_sensitive(true);
+        Ok(Self {
+            secret,
+            authorization,
+        })
+    }
+}
+
+impl GithubToken {
+    pub fn secret(&self) -> String {
+        self.credential.read().unwrap().secret.clone()
+    }
+
+    pub fn authorization(&self) -> HeaderValue {
+        self.credential.read().unwrap().authorization.clone()
+    }
+
+    /// Swaps in a new installation token for a GitHub App.
+    pub fn set_secret(&self, secret: &str) -> Result<(), String> {
+        let credential = Credential::new("the installation token", secret.to_owned())?;
+        *self.credential.write().unwrap() = credential;
+        Ok(())
+    }
+
+    /// The kind of token, going by GitHub's documented prefixes.
+    pub fn kind(&self) -> &'static str {
+        const KINDS: &[(&str, &str)] = &[
+            ("github_pat_", "fine-grained personal access token"),
+            ("ghp_", "classic personal access token"),
+            ("gho_", "OAuth token"),
+            ("ghu_", "GitHub App user token"),
+            ("ghs_", "GitHub App installation token"),
+        ];
+        let secret = self.secret();
+        KINDS
+            .iter()
+            .find(|(prefix, _)| secret.starts_with(prefix))
+            .map_or("unrecognised token", |(_, kind)| kind)
+    }
+
+    /// A client's own token from its `Authorization`, with `CLIENT_TOKENS`:
+    /// `Bearer <token>`, or GitHub's older `token <token>`.
+    pub fn from_client(authorization: &HeaderValue) -> Option<Self> {
+        let (scheme, secret) = authorization.to_str().ok()?.split_once(' ')?;
+        let secret = secret.trim();
+        let known = scheme.eq_ignore_ascii_case("bearer") || scheme.eq_ignore_ascii_case("token");
+        if !known || secret.is_empty() {
+            return None;
+        }
+        let mut token = Self::new("Authorization", "client".into(), secret.to_owned()).ok()?;
+        token.client = true;
+        Some(token)
+    }
+
+    fn from_var(variable: &str, name: String) -> Result<Self, String> {
+        let secret =
+            var(variable).ok_or_else(|| format!("{variable} environment variable must be set"))?;
+        Self::new(variable, name, secret)
+    }
+
+    fn new(variable: &str, name: String, secret: String) -> Result<Self, String> {
+        Ok(Self {
+            name,
+            credential: RwLock::new(Credential::new(variable, secret)?),
+            app: None,
+            client: false,
+        })
+    }
+
+    /// Without a secret until `github_app::authenticate` fetches one.
+    fn app(app: GithubApp) -> Self {
+        Self {
+            name: "app".into(),
+            credential: RwLock::new(Credential {
+                secret: String::new(),
+                authorization: HeaderValue::from_static("Bearer unauthenticated"),
+            }),
+            app: Some(app),
+            client: false,
+        }
+    }
+}
+
+/// Which token serves which origins: `GITHUB_TOKEN` by default, and
+/// `ORIGIN_TOKENS="pattern=name,..."` routing origin groups to the token in
+/// `GITHUB_TOKEN_<NAME>`, so each project spends its own quota.
+///
+/// `GITHUB_TOKENS`, a list, replaces `GITHUB_TOKEN` with a pool: each request
+/// without a token of its own goes out with whichever has the most quota
+/// left, and one GitHub rate-limits is retried with the next.
+///
+/// With `GITHUB_APP_ID` the proxy authenticates as a GitHub App instead, and
+/// the App's installation token takes the place of `GITHUB_TOKEN`.
+pub struct Tokens {
+    /// The first of the pool, for background work that needs just one.
+    pub default: Arc<GithubToken>,
+    pub pool: Vec<Arc<GithubToken>>,
+    /// First matching pattern wins.
+    pub origin_rules: Vec<(OriginPattern, Arc<GithubToken>)>,
+}
+
+impl Tokens {
+    pub fn from_env() -> Result<Self, String> {
+        let pooled = list("GITHUB_TOKENS");
+        let pool = if let Some(app) = GithubApp::from_env()? {
+            if var("GITHUB_TOKEN").is_some() || !pooled.is_empty() {
+                return Err("GITHUB_APP_ID replaces GITHUB_TOKEN and GITHUB_TOKENS; set only \
+                     one of them"
+                    .into());
+            }
+            vec![Arc::new(GithubToken::app(app))]
+        } else if pooled.is_empty() {
+            vec![Arc::new(GithubToken::from_var("GITHUB_TOKEN", "default".into())?)]
+        } else if var("GITHUB_TOKEN").is_some() {
+            return Err("set either GITHUB_TOKEN or GITHUB_TOKENS, not both".into());
+        } else {
+            pooled
+                .into_iter()
+                .enumerate()
+                .map(|(i, secret)| {
+                    let name = format!("pool-{}", i + 1);
+                    GithubToken::new("GITHUB_TOKENS", name, secret).map(Arc::new)
+                })
+                .collect::<Result<_, _>>()?
+        };
+        let default = pool[0].clone();
+
+        let mut named: Vec<Arc<GithubToken>> = Vec::new();
+        let mut origin_rules = Vec::new();
+        for entry in list("ORIGIN_TOKENS") {
+            let (pattern, name) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("ORIGIN_TOKENS: expected origin=name, got {entry:?}"))?;
+            let name = name.trim().to_ascii_lowercase();
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+                return Err(format!("ORIGIN_TOKENS: invalid token name in {entry:?}"));
+            }
+
+            let token = match named.iter().find(|t| t.name == name) {
+                Some(token) => token.clone(),
+                None => {
+                    let variable = format!("GITHUB_TOKEN_{}", name.to_ascii_uppercase());
+                    let token = Arc::new(GithubToken::from_var(&variable, name)?);
+                    named.push(token.clone());
+                    token
+                }
+            };
+            let pattern: OriginPattern = pattern
+                .trim()
+                .parse()
+                .map_err(|e| format!("ORIGIN_TOKENS: {e}"))?;
+            origin_rules.push((pattern, token));
+        }
+
+        Ok(Self {
+            default,
+            pool,
+            origin_rules,
+        })
+    }
+
+    /// A stand-in for `FIXTURE_MODE`, where nothing is sent to GitHub.
+    pub fn offline() -> Self {
+        let default = Arc::new(GithubToken {
+            name: "default".into(),
+            credential: RwLock::new(Credential {
+                secret: String::new(),
+                authorization: HeaderValue::from_static("Bearer fixtures"),
+            }),
+            app: None,
+            client: false,
+        });
+        Self {
+            default: default.clone(),
+            pool: vec![default],
+            origin_rules: Vec::new(),
+        }
+    }
+
+    /// The token `ORIGIN_TOKENS` gives `origin`, if any; the rest share the
+    /// pool.
+    pub fn for_origin(&self, origin: Option<&str>) -> Option<&Arc<GithubToken>> {
+        let origin = origin?;
+        self.origin_rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(origin))
+            .map(|(_, token)| token)
+    }
+
+    /// Every distinct token, the pool first.
+    pub fn all(&self) -> Vec<&GithubToken> {
+        let mut tokens: Vec<&GithubToken> = self.pool.iter().map(|t| &**t).collect();
+        for (_, token) in &self.origin_rules {
+            if !tokens.iter().any(|t| t.name == token.name) {
+                tokens.push(token);
+            }
+        }
+        tokens
+    }
+}
+
+/// Shown by `GET /__config` as a fingerprint, never the secret itself.
+impl Serialize for GithubToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("name", &self.name)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("fingerprint", &fingerprint(&self.secret()))?;
+        map.end()
+    }
+}
+
+impl Serialize for Tokens {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let rules: Vec<(&OriginPattern, &str)> = self
+            .origin_rules
+            .iter()
+            .map(|(pattern, token)| (pattern, token.name.as_str()))
+            .collect();
+        let pool: Vec<&str> = self.pool.iter().map(|token| token.name.as_str()).collect();
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("tokens", &self.all())?;
+        map.serialize_entry("pool", &pool)?;
+        map.serialize_entry("origin_rules", &rules)?;
+        map.end()
+    }
+}
@@ -0,0 +1,883 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+};
+use futures_util::stream;
+use metrics::{counter, histogram};
+use reqwest::Client;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::Semaphore, time::MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{
+    alerts::Outcomes,
+    breaker::CircuitBreaker,
+    cache::CachedResponse,
+    config::{self, Config, GithubUrls, UpstreamConfig},
+    content::BodyKind,
+    dump, fixtures, freshness,
+    github_app::GithubApp,
+    headers::{self, DOWNLOAD_PASSTHROUGH},
+    otel,
+    paths::{self, PathClass},
+    quota::{self, QuotaTracker},
+    redact, reporting,
+    sizes::BodySizes,
+    tokens::GithubToken,
+};
+
+/// How long a pooled connection may sit idle before it is closed.
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+pub enum Fetched {
+    Fresh(Arc<CachedResponse>),
+    /// A full response GitHub asked us not to store.
+    Uncacheable(Arc<CachedResponse>),
+    /// A 206 for a ranged download, never stored.
+    Partial(Arc<CachedResponse>),
+    /// GitHub confirmed the validators we sent; only the relayable headers of
+    /// the 304 and the freshness it grants are kept.
+    NotModified {
+        headers: HeaderMap,
+        ttl: Option<Duration>,
+    },
+    /// A body passed on as it arrives, never stored.
+    Streamed(Streamed),
+}
+
+/// A successful response whose body is piped to the client instead of held
+/// in memory: downloads, anything declaring more than
+/// `STREAM_THRESHOLD_BYTES`, and paths in `STREAM_PATHS`. Not signed, and
+/// never redacted: with `REDACT_FIELDS`, JSON is always read whole.
+pub struct Streamed {
+    pub status: StatusCode,
+    /// What is relayed, with the body's type and, if known, length.
+    pub headers: HeaderMap,
+    response: reqwest::Response,
+}
+
+impl Streamed {
+    pub fn into_body(self) -> Body {
+        Body::from_stream(stream::unfold(self.response, |mut response| async move {
+            let chunk = response.chunk().await.transpose()?;
+            Some((chunk, response))
+        }))
+    }
+
+    /// The whole body after all, for a fetch shared between requests.
+    pub async fn collect(self) -> Result<Arc<CachedResponse>, FetchError> {
+        let body = self
+            .response
+            .bytes()
+            .await
+            .map_err(|_| FetchError::Failed(StatusCode::BAD_GATEWAY))?;
+        let mut headers = self.headers;
+        headers.remove(header::CONTENT_LENGTH);
+        Ok(Arc::new(CachedResponse {
+            status: self.status,
+            kind: BodyKind::classify(headers.get(header::CONTENT_TYPE), &body),
+            body,
+            headers,
+            stored_at: Instant::now(),
+            ttl: Duration::ZERO,
+            purged: false,
+            immutable: false,
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub enum FetchError {
+    /// The status the client should see.
+    Failed(StatusCode),
+    /// GitHub itself failed (5xx); `message` is what it said about it.
+    Upstream {
+        status: StatusCode,
+        message: Option<String>,
+    },
+    /// No upstream slot freed up in time; the request was never sent.
+    Saturated,
+    /// GitHub didn't answer within `UPSTREAM_TIMEOUT_SECS`, or before the
+    /// client's own deadline.
+    TimedOut,
+    /// GitHub couldn't be reached at all, or the connection broke before it
+    /// answered.
+    Unreachable,
+    /// GitHub answered 202: it is still computing the statistics asked for,
+    /// and kept doing so through every retry.
+    Pending,
+    /// GitHub redirected, as it does for renamed and transferred
+    /// repositories; carries the `Location`.
+    Moved(String),
+    /// The circuit breaker is open after GitHub kept failing; carries how
+    /// long until it lets a request through again. Nothing was sent.
+    CircuitOpen(Duration),
+    /// GitHub refused the request for going over a rate limit, with every
+    /// token that could have been tried; carries how long it asked us to wait.
+    RateLimited(Duration),
+}
+
+/// How long to wait when GitHub rate-limits us without saying until when.
+const RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// The GitHub side of the proxy: a pooled client plus a cap on how many
+/// requests may be in flight at once, since GitHub's secondary rate limits
+/// punish bursts of concurrency rather than volume.
+pub struct Upstream {
+    client: Client,
+    permits: Semaphore,
+    config: UpstreamConfig,
+    pub quota: QuotaTracker,
+    pub outcomes: Outcomes,
+    pub breaker: CircuitBreaker,
+    pub body_sizes: BodySizes,
+    /// Whether GitHub was reached since the last keepalive tick.
+    used: AtomicBool,
+}
+
+impl Upstream {
+    pub fn new(client: Client, config: UpstreamConfig) -> Self {
+        Self {
+            client,
+            permits: Semaphore::new(config.max_concurrency),
+            breaker: CircuitBreaker::new(
+                config.circuit_breaker_failures,
+                config.circuit_breaker_cooldown,
+            ),
+            config,
+            quota: QuotaTracker::default(),
+            outcomes: Outcomes::default(),
+            body_sizes: BodySizes::default(),
+            used: AtomicBool::new(false),
+        }
+    }
+
+    /// Opens a connection to GitHub before the first request needs one, so
+    /// it doesn't pay for DNS, TLS and HTTP/2 setup on top of a cold cache.
+    pub async fn warm(&self, config: &Config) {
+        if config.fixtures.serve.is_some() {
+            return;
+        }
+        let started = Instant::now();
+        match self.ping(config, &config.tokens.default).await {
+            Ok(_) => info!("Upstream connection ready in {:?}", started.elapsed()),
+            Err(err) => warn!("could not open an upstream connection ahead of time: {err}"),
+        }
+    }
+
+    /// With `UPSTREAM_KEEPALIVE_SECS`, pings GitHub whenever a whole interval
+    /// went by without a request, so a pooled connection outlives quiet
+    /// periods. The ping is `GET /rate_limit`, which costs no quota.
+    pub async fn keep_alive(&self, config: &Config, shutdown: CancellationToken) {
+        let Some(interval) = config.upstream.keepalive else {
+            return;
+        };
+        if config.fixtures.serve.is_some() {
+            return;
+        }
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            if self.used.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+            counter!("proxy_upstream_keepalives_total").increment(1);
+            match self.ping(config, &config.tokens.default).await {
+                Ok(_) => debug!("upstream keepalive sent"),
+                Err(err) => warn!("upstream keepalive failed: {err}"),
+            }
+        }
+    }
+
+    /// Whether GitHub answers, and takes each of our tokens, for `/readyz`.
+    pub async fn probe(&self, config: &Config) -> Result<(), String> {
+        if config.fixtures.serve.is_some() {
+            return Ok(());
+        }
+        for token in config.tokens.all() {
+            match self.ping(config, token).await {
+                Ok(status) if status.is_success() => {}
+                // Rate limiting is off on this Enterprise Server.
+                Ok(StatusCode::NOT_FOUND) if config.github.enterprise => {}
+                Ok(StatusCode::UNAUTHORIZED) => {
+                    return Err(format!("GitHub rejects the {} token", token.name));
+                }
+                Ok(status) => return Err(format!("GitHub answered {status}")),
+                Err(err) => return Err(format!("GitHub is unreachable: {err}")),
+            }
+        }
+        Ok(())
+    }
+
+    async fn ping(
+        &self,
+        config: &Config,
+        token: &GithubToken,
+    ) -> Result<StatusCode, reqwest::Error> {
+        let response = self
+            .client
+            .get(&config.github.rate_limit)
+            .timeout(self.config.timeout)
+            .header(header::USER_AGENT, config.user_agent.clone())
+            .header(header::AUTHORIZATION, token.authorization())
+            .send()
+            .await?;
+        self.quota.record(token, response.headers());
+        let status = response.status();
+        response.bytes().await?;
+        Ok(status)
+    }
+
+    /// Performs an upstream GET, billed to `token`. A 202 is asked again up
+    /// to `STATS_RETRIES` times, since GitHub usually has the statistics
+    /// ready a moment later. A rate-limited request by a token from the pool
+    /// is sent again with whichever other one has the most quota left.
+    /// Failures that tend to pass, a lost connection, a 5xx or a rate limit
+    /// lifting within `UPSTREAM_RETRY_MAX_DELAY_MS`, are tried again up to
+    /// `UPSTREAM_RETRIES` times, backing off in between.
+    pub async fn fetch(
+        &self,
+        config: &Config,
+        token: &GithubToken,
+        url: &str,
+        forwarded: HeaderMap,
+        dump: bool,
+    ) -> Result<Fetched, FetchError> {
+        let mut token = token;
+        let mut attempt = 0;
+        let mut retries = 0;
+        let mut tried = vec![token.name.clone()];
+        loop {
+            let once = self.fetch_once(config, token, url, forwarded.clone(), dump);
+            match self.guarded(once).await {
+                Err(FetchError::Pending) if attempt < self.config.stats_retries => {
+                    attempt += 1;
+                    counter!("proxy_upstream_stats_retries_total").increment(1);
+                    tokio::time::sleep(self.config.stats_retry_delay).await;
+                }
+                Err(FetchError::RateLimited(wait)) => {
+                    let pool = &config.tokens.pool;
+                    if !pool.iter().any(|pooled| pooled.name == token.name) {
+                        return Err(FetchError::RateLimited(wait));
+                    }
+                    let path = url.strip_prefix(&config.github.api).unwrap_or(url);
+                    let resource = quota::resource_of(path);
+                    let untried = pool.iter().filter(|pooled| {
+                        !tried.contains(&pooled.name)
+                            && self.quota.exhausted_for(pooled, resource).is_none()
+                    });
+                    let Some(next) = self.quota.best_of(untried, resource) else {
+                        if retries < self.config.retries && wait <= self.config.retry_max_delay {
+                            retries += 1;
+                            counter!("proxy_upstream_retries_total", "reason" => "rate_limited")
+                                .increment(1);
+                            tokio::time::sleep(wait).await;
+                            continue;
+                        }
+                        return Err(FetchError::RateLimited(wait));
+                    };
+                    counter!("proxy_token_rotations_total").increment(1);
+                    info!(url, from = token.name, to = next.name, "token rate-limited, rotating");
+                    tried.push(next.name.clone());
+                    token = next.as_ref();
+                }
+                Err(FetchError::Unreachable | FetchError::Upstream { .. })
+                    if retries < self.config.retries =>
+                {
+                    let delay = backoff(&self.config, retries);
+                    retries += 1;
+                    counter!("proxy_upstream_retries_total", "reason" => "failed").increment(1);
+                    debug!(url, retry = retries, "retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends `attempt` unless the circuit breaker is open, and tells the
+    /// breaker how it went.
+    async fn guarded<T>(
+        &self,
+        attempt: impl Future<Output = Result<T, FetchError>>,
+    ) -> Result<T, FetchError> {
+        self.breaker.allow().map_err(FetchError::CircuitOpen)?;
+        let result = attempt.await;
+        match &result {
+            Err(FetchError::Unreachable | FetchError::TimedOut | FetchError::Upstream { .. }) => {
+                self.breaker.failed();
+            }
+            // Never sent.
+            Err(FetchError::Saturated) => {}
+            _ => self.breaker.succeeded(),
+        }
+        result
+    }
+
+    async fn fetch_once(
+        &self,
+        config: &Config,
+        token: &GithubToken,
+        url: &str,
+        forwarded: HeaderMap,
+        dump: bool,
+    ) -> Result<Fetched, FetchError> {
+        let (url, accept) = headers::split_media_variant(url);
+        if let Some(dir) = &config.fixtures.serve {
+            return fixtures::load(dir, url, config);
+        }
+        // Downloads redirect elsewhere, and the follow-up needs the client's
+        // download headers again.
+        let redirect_headers = forwarded.clone();
+        let mut span = otel::Span::start("GET", otel::Kind::Client);
+        span.set("http.request.method", "GET");
+        span.set("url.full", url);
+        if let Some(host) = reqwest::Url::parse(url).ok().as_ref().and_then(|u| u.host_str()) {
+            span.set("server.address", host);
+        }
+        let mut request = self
+            .client
+            .get(url)
+            .timeout(self.config.timeout)
+            .headers(forwarded)
+            .header(header::USER_AGENT, config.user_agent.clone())
+            .header(header::AUTHORIZATION, token.authorization());
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        if let Some(traceparent) = span.traceparent() {
+            request = request.header("traceparent", traceparent);
+        }
+        let request = request
+            .build()
+            .map_err(|_| FetchError::Failed(StatusCode::BAD_REQUEST))?;
+
+        let waiting = Instant::now();
+        let _permit = tokio::time::timeout(self.config.permit_timeout, self.permits.acquire())
+            .await
+            .map_err(|_| FetchError::Saturated)?
+            .expect("upstream semaphore is never closed");
+        histogram!("proxy_upstream_permit_wait_seconds").record(waiting.elapsed().as_secs_f64());
+
+        if dump {
+            dump::request(config, &request);
+        }
+
+        let unreachable = |err: reqwest::Error| {
+            self.outcomes.failed();
+            if err.is_timeout() {
+                counter!("proxy_upstream_timeouts_total").increment(1);
+                return FetchError::TimedOut;
+            }
+            reporting::upstream_failure(StatusCode::BAD_GATEWAY, url, None);
+            FetchError::Unreachable
+        };
+        let mut response = self.client.execute(request).await.map_err(|err| {
+            span.fail();
+            unreachable(err)
+        })?;
+        span.set_int("http.response.status_code", response.status().as_u16().into());
+        if response.status().is_server_error() {
+            span.fail();
+        }
+        self.used.store(true, Ordering::Relaxed);
+        self.quota.record(token, response.headers());
+        match response.status() {
+            // A client's token being refused says nothing about ours.
+            StatusCode::UNAUTHORIZED if !token.client => self.outcomes.unauthorized(),
+            status if status.is_server_error() => self.outcomes.failed(),
+            _ => self.outcomes.succeeded(),
+        }
+        if let Some(wait) = rate_limited(response.status(), response.headers()) {
+            counter!("proxy_upstream_rate_limited_total", "token" => token.name.clone())
+                .increment(1);
+            warn!(url, token = token.name, "GitHub rate-limited the request");
+            return Err(FetchError::RateLimited(wait));
+        }
+        let mut download = false;
+        if response.status().is_redirection() && response.status() != StatusCode::NOT_MODIFIED {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if location.starts_with(&config.github.api) {
+                return Err(FetchError::Moved(location.to_owned()));
+            }
+            if !is_download_location(location, &config.github) {
+                warn!(url, location, "not following a redirect away from GitHub");
+                return Err(FetchError::Failed(StatusCode::BAD_GATEWAY));
+            }
+            // The URL is signed; our token is not for this host.
+            response = self
+                .client
+                .get(location)
+                .timeout(self.config.timeout)
+                .headers(redirect_headers)
+                .header(header::USER_AGENT, config.user_agent.clone())
+                .send()
+                .await
+                .map_err(unreachable)?;
+            download = true;
+        }
+        let status = response.status();
+        let failed = status.is_client_error() || status.is_server_error();
+        let raw_headers = (dump || failed).then(|| response.headers().clone());
+
+        let mut headers = config.passthrough_headers.extract(response.headers());
+        if download {
+            for name in DOWNLOAD_PASSTHROUGH {
+                if let Some(value) = response.headers().get(name) {
+                    headers.insert(name, value.clone());
+                }
+            }
+        }
+        let mut ttl = freshness::ttl(response.headers(), &config.cache);
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified { headers, ttl });
+        }
+        // Empty, and not worth keeping: the data it stands for is on its way.
+        if status == StatusCode::ACCEPTED {
+            return Err(FetchError::Pending);
+        }
+        let length = response.content_length();
+        let large = length.is_some_and(|length| length > config.cache.stream_threshold);
+        let github = &config.github;
+        let path = url.strip_prefix(&github.repos).or(url.strip_prefix(&github.api));
+        let stream_path = path.is_some_and(|p| paths::any_match(&config.cache.stream_paths, p));
+        // Redaction needs the whole document; asked with the most JSON-like
+        // body there is, could this one be JSON?
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let redacted = !config.redact_fields.is_empty()
+            && BodyKind::classify(content_type.as_ref(), b"{}") == BodyKind::Json;
+        if status.is_success() && (download || large || stream_path) && !redacted {
+            if let Some(content_type) = content_type {
+                headers.insert(header::CONTENT_TYPE, content_type);
+            }
+            if let Some(length) = length {
+                headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+            }
+            counter!("proxy_streamed_responses_total").increment(1);
+            return Ok(Fetched::Streamed(Streamed {
+                status,
+                headers,
+                response,
+            }));
+        }
+        // Read whole all the same, but too large to keep.
+        if large {
+            ttl = None;
+        }
+        let class = PathClass::of(url.strip_prefix(&github.api).unwrap_or_default());
+        // Expensive for GitHub to compute, and slow to change.
+        if class == PathClass::Stats && status == StatusCode::OK {
+            ttl = ttl.map(|_| config.cache.stats_ttl);
+        }
+        if let Some(rule) = path.and_then(|p| freshness::rule(p, &config.cache)) {
+            ttl = ttl.map(|_| rule).filter(|rule| !rule.is_zero());
+        }
+        // Kept, so requests for a path that doesn't exist can't spend our
+        // quota one after another, but not for long.
+        if status.is_client_error() {
+            let error_ttl = config.cache.error_ttl;
+            ttl = ttl.map(|ttl| ttl.min(error_ttl)).filter(|_| !error_ttl.is_zero());
+        }
+        // Pinned to a commit, so it can never change.
+        let immutable = status == StatusCode::OK
+            && !download
+            && ttl.is_some()
+            && url.strip_prefix(&github.repos).is_some_and(paths::is_immutable);
+        if immutable {
+            ttl = Some(freshness::IMMUTABLE_TTL);
+        }
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|_| FetchError::Failed(StatusCode::INTERNAL_SERVER_ERROR))?;
+        self.body_sizes.record(class, body.len());
+        let kind = BodyKind::classify(content_type.as_ref(), &body);
+        // Anything but JSON is relayed as it came, under its own type.
+        if let Some(content_type) = content_type.filter(|_| kind != BodyKind::Json) {
+            headers.insert(header::CONTENT_TYPE, content_type);
+        }
+
+        if let Some(raw_headers) = &raw_headers {
+            if dump {
+                dump::response(config, status, raw_headers, &body);
+            }
+            if failed {
+                let message = log_error(config, status, url, raw_headers, &body);
+                if status.is_server_error() {
+                    return Err(FetchError::Upstream { status, message });
+                }
+            }
+        }
+        // Checked as GitHub sent it: redaction may remove required fields.
+        let json = kind == BodyKind::Json;
+        let checked = json && status == StatusCode::OK;
+        if let Some(schemas) = config.schemas.as_ref().filter(|_| checked) {
+            let path = url.strip_prefix(&github.repos).filter(|_| !download);
+            if let Some(violations) = path.and_then(|path| schemas.check(path, &body)) {
+                counter!("proxy_schema_violations_total", "schema" => violations.schema.clone())
+                    .increment(1);
+                warn!(
+                    url,
+                    schema = violations.schema,
+                    "response does not match its schema at {}",
+                    violations.pointers.join(", ")
+                );
+                if schemas.mark_invalid {
+                    headers.insert("x-schema-valid", HeaderValue::from_static("false"));
+                }
+            }
+        }
+        let body = if json {
+            redact::apply(&config.redact_fields, body)
+        } else {
+            body
+        };
+        if let Some(dir) = &config.fixtures.record {
+            if status == StatusCode::OK && !download {
+                fixtures::record(dir, &config.github.api, url, body.clone());
+            }
+        }
+
+        let entry = Arc::new(CachedResponse {
+            status,
+            body,
+            headers,
+            stored_at: Instant::now(),
+            ttl: ttl.unwrap_or_default(),
+            purged: false,
+            immutable,
+            kind,
+        });
+        Ok(match ttl {
+            _ if status == StatusCode::PARTIAL_CONTENT => Fetched::Partial(entry),
+            Some(_) => Fetched::Fresh(entry),
+            None => Fetched::Uncacheable(entry),
+        })
+    }
+
+    /// POSTs a GraphQL request `body` to GitHub, under the same cap on
+    /// concurrency as everything else. The answer is kept for
+    /// `GRAPHQL_TTL_SECS` if it is a 200 without errors.
+    pub async fn graphql(
+        &self,
+        config: &Config,
+        token: &GithubToken,
+        body: Vec<u8>,
+    ) -> Result<Fetched, FetchError> {
+        self.guarded(self.graphql_once(config, token, body)).await
+    }
+
+    async fn graphql_once(
+        &self,
+        config: &Config,
+        token: &GithubToken,
+        body: Vec<u8>,
+    ) -> Result<Fetched, FetchError> {
+        if let Some(dir) = &config.fixtures.serve {
+            return fixtures::load(dir, &config.github.graphql, config);
+        }
+        let _permit = tokio::time::timeout(self.config.permit_timeout, self.permits.acquire())
+            .await
+            .map_err(|_| FetchError::Saturated)?
+            .expect("upstream semaphore is never closed");
+        let response = self
+            .client
+            .post(&config.github.graphql)
+            .timeout(self.config.timeout)
+            .header(header::USER_AGENT, config.user_agent.clone())
+            .header(header::AUTHORIZATION, token.authorization())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| {
+                self.outcomes.failed();
+                match err.is_timeout() {
+                    true => FetchError::TimedOut,
+                    false => FetchError::Unreachable,
+                }
+            })?;
+        self.used.store(true, Ordering::Relaxed);
+        self.quota.record(token, response.headers());
+        let status = response.status();
+        match status {
+            StatusCode::UNAUTHORIZED if !token.client => self.outcomes.unauthorized(),
+            status if status.is_server_error() => self.outcomes.failed(),
+            _ => self.outcomes.succeeded(),
+        }
+        let raw_headers = status.is_server_error().then(|| response.headers().clone());
+        let headers = config.passthrough_headers.extract(response.headers());
+        let body = response
+            .bytes()
+            .await
+            .map_err(|_| FetchError::Failed(StatusCode::INTERNAL_SERVER_ERROR))?;
+        if let Some(raw_headers) = &raw_headers {
+            let message = log_error(config, status, &config.github.graphql, raw_headers, &body);
+            return Err(FetchError::Upstream { status, message });
+        }
+        // Partial data with errors is still a 200; only a clean one is kept.
+        let clean = status == StatusCode::OK
+            && serde_json::from_slice::<serde_json::Value>(&body)
+                .is_ok_and(|value| value.get("errors").is_none());
+        let entry = Arc::new(CachedResponse {
+            status,
+            body,
+            headers,
+            stored_at: Instant::now(),
+            ttl: config.graphql.ttl,
+            purged: false,
+            immutable: false,
+            kind: BodyKind::Json,
+        });
+        Ok(if clean {
+            Fetched::Fresh(entry)
+        } else {
+            Fetched::Uncacheable(entry)
+        })
+    }
+
+    /// The `owner/repo` a repository redirect points at. GitHub usually
+    /// redirects to `/repositories/<id>`, so the name is looked up by id.
+    /// Anything pointing away from the API is ignored.
+    pub async fn moved_to(
+        &self,
+        config: &Config,
+        token: &GithubToken,
+        location: &str,
+    ) -> Option<String> {
+        let path = location.strip_prefix(&config.github.api)?;
+        let mut segments = path.split(['/', '?']);
+        match segments.next()? {
+            "repos" => Some(format!("{}/{}", segments.next()?, segments.next()?)),
+            "repositories" => {
+                let id: u64 = segments.next()?.parse().ok()?;
+                let url = format!("{}repositories/{id}", config.github.api);
+                let (Fetched::Fresh(entry) | Fetched::Uncacheable(entry)) =
+                    self.fetch(config, token, &url, HeaderMap::new(), false).await.ok()?
+                else {
+                    return None;
+                };
+                let repo: serde_json::Value = serde_json::from_slice(&entry.body).ok()?;
+                repo["full_name"].as_str().map(str::to_owned)
+            }
+            _ => None,
+        }
+    }
+
+    /// Exchanges a JWT for a new installation token of `app`.
+    pub async fn installation_token(
+        &self,
+        config: &Config,
+        app: &GithubApp,
+    ) -> Result<String, String> {
+        let url = format!(
+            "{}app/installations/{}/access_tokens",
+            config.github.api, app.installation_id
+        );
+        let response = self
+            .client
+            .post(url)
+            .timeout(self.config.timeout)
+            .header(header::USER_AGENT, config.user_agent.clone())
+            .header(header::AUTHORIZATION, format!("Bearer {}", app.jwt()?))
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .map_err(|err| format!("GitHub is unreachable: {err}"))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| format!("GitHub is unreachable: {err}"))?;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        if !status.is_success() {
+            let message = body["message"].as_str().unwrap_or_default();
+            return Err(format!("GitHub answered {status} for an installation token: {message}"));
+        }
+        body["token"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| "GitHub sent no installation token".to_owned())
+    }
+
+    /// Asks GitHub who `token` belongs to and logs the answer. Only a token
+    /// GitHub rejects outright is an error; anything inconclusive, such as
+    /// GitHub being unreachable, is logged and let through.
+    pub async fn check_token(&self, config: &Config, token: &GithubToken) -> Result<(), String> {
+        let response = self
+            .client
+            .get(&config.github.user)
+            .timeout(Duration::from_secs(10))
+            .header(header::USER_AGENT, config.user_agent.clone())
+            .header(header::AUTHORIZATION, token.authorization())
+            .send()
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(token = token.name, "could not verify the GitHub token: {err}");
+                return Ok(());
+            }
+        };
+        self.quota.record(token, response.headers());
+
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED {
+            return Err(format!(
+                "GitHub rejected the {} token ({}) with 401 Bad credentials; check that it is \
+                 copied correctly and has not expired or been revoked",
+                token.name,
+                token.kind()
+            ));
+        }
+        let limit = response
+            .headers()
+            .get("x-ratelimit-limit")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("unknown")
+            .to_owned();
+        if !status.is_success() {
+            // Installation tokens, for one, can't read /user.
+            warn!(
+                token = token.name,
+                "could not verify the GitHub token ({}): /user answered {status}",
+                token.kind()
+            );
+            return Ok(());
+        }
+
+        let login = response
+            .bytes()
+            .await
+            .ok()
+            .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+            .and_then(|user| user["login"].as_str().map(str::to_owned))
+            .unwrap_or_else(|| "unknown".to_owned());
+        info!(
+            token = token.name,
+            "GitHub token OK: {} for {login}, {limit} requests/hour",
+            token.kind()
+        );
+
+        // Only fine-grained tokens report one; others may or may not expire.
+        match self.quota.expires_in(token) {
+            None => info!(token = token.name, "GitHub reports no expiry for this token"),
+            Some(left) => {
+                let days = left.as_secs() / (24 * 60 * 60);
+                if left <= config.token_expiry_warning {
+                    warn!(token = token.name, "GitHub token expires in {days} day(s)");
+                } else {
+                    info!(token = token.name, "GitHub token expires in {days} day(s)");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where GitHub sends release asset and archive downloads.
+const DOWNLOAD_HOSTS: &[&str] = &[
+    "objects.githubusercontent.com",
+    "release-assets.githubusercontent.com",
+    "github-releases.githubusercontent.com",
+    "codeload.github.com",
+];
+
+/// Exponential, with equal jitter: between half and all of the doubled
+/// delay, so retries after a shared failure don't arrive together.
+fn backoff(config: &UpstreamConfig, retry: u32) -> Duration {
+    let doubled = config.retry_base_delay.saturating_mul(1 << retry.min(16));
+    let delay = doubled.min(config.retry_max_delay);
+    let mut random = [0; 4];
+    let _ = SystemRandom::new().fill(&mut random);
+    let fraction = f64::from(u32::from_le_bytes(random)) / f64::from(u32::MAX);
+    delay / 2 + delay.mul_f64(fraction / 2.0)
+}
+
+/// How long GitHub asks us to wait, when `status` is its refusal for going
+/// over a primary or secondary rate limit rather than any other 403.
+fn rate_limited(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let number = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+    };
+    if let Some(secs) = number("retry-after") {
+        return Some(Duration::from_secs(secs));
+    }
+    (number("x-ratelimit-remaining") == Some(0)).then(|| {
+        number("x-ratelimit-reset").map_or(RATE_LIMIT_WAIT, |reset| {
+            Duration::from_secs(reset.saturating_sub(config::unix_now()))
+        })
+    })
+}
+
+/// On GHES, downloads come from its own host or a subdomain of it
+/// (`codeload.<host>` with subdomain isolation).
+fn is_download_location(location: &str, github: &GithubUrls) -> bool {
+    let Ok(url) = reqwest::Url::parse(location) else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    if github.enterprise {
+        let own = github.host().unwrap_or_default();
+        return host == own || host.strip_suffix(own.as_str()).is_some_and(|s| s.ends_with('.'));
+    }
+    url.scheme() == "https" && DOWNLOAD_HOSTS.contains(&host)
+}
+
+/// How much of an upstream error body is logged.
+const ERROR_BODY_LOG_BYTES: usize = 512;
+
+/// Logs an upstream error with the start of its body, which usually says
+/// exactly what went wrong, and returns GitHub's `message` from it.
+fn log_error(
+    config: &Config,
+    status: StatusCode,
+    url: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Option<String> {
+    let request_id = headers
+        .get("x-github-request-id")
+        .and_then(|v| v.to_str().ok());
+    if status.is_server_error() {
+        reporting::upstream_failure(status, url, request_id);
+    }
+    let shown = &body[..dump::floor_char_boundary(body, ERROR_BODY_LOG_BYTES)];
+    warn!(
+        %status,
+        github_request_id = request_id.unwrap_or("-"),
+        "upstream error: {}",
+        dump::redact(&String::from_utf8_lossy(shown), config)
+    );
+
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|error| error["message"].as_str().map(str::to_owned))
+}
@@ -0,0 +1,209 @@
+use axum::{
+    body::HttpBody,
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    api_keys::KeyName, config::UsageConfig, peers::FromPeer, shadow::FromShadow, AppState,
+};
+
+/// Where requests without an `Origin` are counted.
+const NO_ORIGIN: &str = "(none)";
+/// Where origins past `USAGE_MAX_ORIGINS` are counted.
+const OTHER: &str = "other";
+
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct Counters {
+    requests: u64,
+    cache_hits: u64,
+    upstream_calls: u64,
+    bytes_served: u64,
+    errors: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.requests += other.requests;
+        self.cache_hits += other.cache_hits;
+        self.upstream_calls += other.upstream_calls;
+        self.bytes_served += other.bytes_served;
+        self.errors += other.errors;
+    }
+}
+
+#[derive(Serialize)]
+pub struct Bucket {
+    /// Unix time of the start of the hour.
+    hour: u64,
+    #[serde(flatten)]
+    counters: Counters,
+}
+
+#[derive(Serialize)]
+pub struct OriginUsage {
+    total: Counters,
+    hours: Vec<Bucket>,
+}
+
+/// Hourly buckets by name, an origin or an API key.
+type Series = BTreeMap<String, VecDeque<Bucket>>;
+
+/// Who the proxy's traffic is for, per origin and per API key in hourly
+/// buckets over the last `USAGE_WINDOW_HOURS`. At most `USAGE_MAX_ORIGINS`
+/// origins are told apart; keys are only as many as `API_KEYS_FILE` lists.
+pub struct Usage {
+    config: UsageConfig,
+    origins: Mutex<Series>,
+    keys: Mutex<Series>,
+}
+
+impl Usage {
+    pub fn new(config: UsageConfig) -> Self {
+        Self {
+            config,
+            origins: Mutex::new(BTreeMap::new()),
+            keys: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn record(&self, origin: Option<&str>, key: Option<&str>, counters: Counters) {
+        let hour = current_hour();
+        let mut origins = self.origins.lock().unwrap();
+        let name = match origin {
+            None => NO_ORIGIN,
+            Some(origin) if origins.contains_key(origin) => origin,
+            Some(origin) if origins.len() < self.config.max_origins => origin,
+            Some(_) => OTHER,
+        };
+        self.add(&mut origins, name, hour, counters);
+        drop(origins);
+        if let Some(key) = key {
+            self.add(&mut self.keys.lock().unwrap(), key, hour, counters);
+        }
+    }
+
+    fn add(&self, series: &mut Series, name: &str, hour: u64, counters: Counters) {
+        let buckets = match series.get_mut(name) {
+            Some(buckets) => buckets,
+            None => series.entry(name.to_owned()).or_default(),
+        };
+        match buckets.back_mut() {
+            Some(bucket) if bucket.hour == hour => bucket.counters.add(&counters),
+            _ => buckets.push_back(Bucket { hour, counters }),
+        }
+        self.expire(series, hour);
+    }
+
+    /// Drops buckets that have left the window, and names left with none.
+    fn expire(&self, series: &mut Series, hour: u64) {
+        let oldest = hour.saturating_sub((self.config.window_hours - 1) * 3600);
+        series.retain(|_, buckets| {
+            while buckets.front().is_some_and(|b| b.hour < oldest) {
+                buckets.pop_front();
+            }
+            !buckets.is_empty()
+        });
+    }
+
+    /// Per-origin usage over the window, or just that of `origin`.
+    pub fn snapshot(&self, origin: Option<&str>) -> BTreeMap<String, OriginUsage> {
+        self.summarize(&self.origins, |name| {
+            origin.is_none_or(|o| o.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// Per-API-key usage over the window, or just that of the key `key` names.
+    pub fn key_snapshot(&self, key: Option<&str>) -> BTreeMap<String, OriginUsage> {
+        self.summarize(&self.keys, |name| key.is_none_or(|k| k == name))
+    }
+
+    fn summarize(
+        &self,
+        series: &Mutex<Series>,
+        wanted: impl Fn(&str) -> bool,
+    ) -> BTreeMap<String, OriginUsage> {
+        let mut series = series.lock().unwrap();
+        self.expire(&mut series, current_hour());
+        series
+            .iter()
+            .filter(|(name, _)| wanted(name))
+            .map(|(name, buckets)| {
+                let mut total = Counters::default();
+                let hours = buckets
+                    .iter()
+                    .map(|bucket| {
+                        total.add(&bucket.counters);
+                        Bucket {
+                            hour: bucket.hour,
+                            counters: bucket.counters,
+                        }
+                    })
+                    .collect();
+                (name.clone(), OriginUsage { total, hours })
+            })
+            .collect()
+    }
+
+    pub fn window_hours(&self) -> u64 {
+        self.config.window_hours
+    }
+}
+
+/// Counts every proxied request against its origin, and its API key if it
+/// had one, once the response is known: where it came from (cache or
+/// GitHub), its size, and whether it failed.
+pub async fn usage_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let extensions = request.extensions();
+    if request.uri().path().starts_with("/__")
+        || extensions.get::<FromPeer>().is_some()
+        || extensions.get::<FromShadow>().is_some()
+    {
+        return next.run(request).await;
+    }
+    // Only reached with a single well-formed origin, if any.
+    let origin = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    let response = next.run(request).await;
+
+    let cache = response
+        .headers()
+        .get("x-cache")
+        .map(|v| v.as_bytes())
+        .unwrap_or_default();
+    let counters = Counters {
+        requests: 1,
+        cache_hits: u64::from(cache == b"HIT" || cache == b"STALE"),
+        upstream_calls: u64::from(matches!(cache, b"MISS" | b"REVALIDATED" | b"BYPASS" | b"PASS")),
+        bytes_served: response.body().size_hint().exact().unwrap_or_default(),
+        errors: u64::from(
+            response.status().is_client_error() || response.status().is_server_error(),
+        ),
+    };
+    let key = response.extensions().get::<KeyName>().map(|KeyName(name)| name.clone());
+    state.usage.record(origin.as_deref(), key.as_deref(), counters);
+    response
+}
+
+fn current_hour() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    now - now % 3600
+}
@@ -0,0 +1,204 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+    routing::get,
+    Router,
+};
+use metrics::{counter, gauge};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{sync::watch, time::MissedTickBehavior};
+use tracing::debug;
+
+use crate::{
+    cache::{CacheStatus, CachedResponse},
+    fetch_failed, json_error, refresh, refused_repo, respond, service_unavailable, AppState,
+};
+
+/// Long polling for dashboards: `GET /watch/{owner}/{repo}` with the ETag
+/// last seen in `If-None-Match` is held open until the repository's data
+/// changes (200 with the new body) or `?timeout=` seconds pass (304).
+///
+/// However many clients watch a repository, one loop re-checks it, on the
+/// `REFRESH_INTERVAL_SECS` cadence and through the cache like any request.
+pub fn router() -> Router<AppState> {
+    Router::new().route("/watch/:owner/:repo", get(watch))
+}
+
+/// The refresh loops, one per watched repository, and how many requests are
+/// parked on them.
+#[derive(Default)]
+pub struct Watches {
+    loops: Mutex<HashMap<Arc<str>, watch::Sender<Arc<CachedResponse>>>>,
+    parked: AtomicUsize,
+}
+
+/// Holds one of the `WATCH_MAX_WATCHERS` slots.
+struct Parked<'a>(&'a Watches);
+
+impl Drop for Parked<'_> {
+    fn drop(&mut self) {
+        let parked = self.0.parked.fetch_sub(1, Ordering::Relaxed) - 1;
+        gauge!("proxy_watchers").set(parked as f64);
+    }
+}
+
+impl Watches {
+    fn park(&self, max: usize) -> Option<Parked<'_>> {
+        let parked = self
+            .parked
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < max).then_some(n + 1))
+            .ok()?;
+        gauge!("proxy_watchers").set((parked + 1) as f64);
+        Some(Parked(self))
+    }
+}
+
+#[derive(Deserialize)]
+struct WatchQuery {
+    timeout: Option<u64>,
+}
+
+async fn watch(
+    Path((owner, repo)): Path<(String, String)>,
+    query: Option<Query<WatchQuery>>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let config = &state.config;
+    let Some(Query(query)) = query else {
+        return json_error(StatusCode::BAD_REQUEST, "timeout must be a number of seconds");
+    };
+    let timeout = query
+        .timeout
+        .map_or(config.watch.max_timeout, Duration::from_secs)
+        .min(config.watch.max_timeout);
+
+    let key: Arc<str> = format!("{owner}/{repo}").into();
+    if let Some(refused) = refused_repo(config, &key) {
+        return refused;
+    }
+    let url = format!("{}{key}", config.github.repos);
+    let token = &config.tokens.default;
+    let (entry, cache_status) = match refresh(&state, token, key.clone(), &url, false, None).await {
+        Ok(refreshed) => refreshed,
+        Err(err) => return fetch_failed(err, None, &headers, config),
+    };
+
+    let seen = headers.get(header::IF_NONE_MATCH);
+    if seen != Some(&tag(&entry)) {
+        return changed(&entry, cache_status, &headers, &state);
+    }
+    let Some(_parked) = state.watches.park(config.watch.max_watchers) else {
+        counter!("proxy_watch_rejected_total").increment(1);
+        return service_unavailable("too many watchers", config.refresh.interval);
+    };
+
+    let mut updates = subscribe(&state, key, entry.clone());
+    let changed_entry = tokio::select! {
+        updated = updates.wait_for(|current| Some(&tag(current)) != seen) => {
+            updated.ok().map(|entry| entry.clone())
+        }
+        _ = tokio::time::sleep(timeout) => None,
+        // Answer rather than hold up a graceful shutdown.
+        _ = state.shutdown.cancelled() => None,
+    };
+    match changed_entry {
+        Some(entry) => changed(&entry, CacheStatus::Hit, &headers, &state),
+        None => unchanged(&entry, &headers, &state),
+    }
+}
+
+/// A receiver for `key`'s loop, starting it if nobody watches it yet.
+fn subscribe(
+    state: &AppState,
+    key: Arc<str>,
+    current: Arc<CachedResponse>,
+) -> watch::Receiver<Arc<CachedResponse>> {
+    let mut loops = state.watches.loops.lock().unwrap();
+    if let Some(sender) = loops.get(&key) {
+        return sender.subscribe();
+    }
+    let (sender, receiver) = watch::channel(current);
+    loops.insert(key.clone(), sender.clone());
+    tokio::spawn(refresh_loop(state.clone(), key, sender));
+    receiver
+}
+
+/// Re-checks `key` every refresh interval until its last watcher is gone,
+/// waking them all when the data changed.
+async fn refresh_loop(state: AppState, key: Arc<str>, sender: watch::Sender<Arc<CachedResponse>>) {
+    let url = format!("{}{key}", state.config.github.repos);
+    let mut ticker = tokio::time::interval(state.config.refresh.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = state.shutdown.cancelled() => break,
+        }
+        {
+            // Subscribing happens under this lock too, so no watcher can
+            // join a loop that is about to end.
+            let mut loops = state.watches.loops.lock().unwrap();
+            if sender.receiver_count() == 0 {
+                loops.remove(&key);
+                return;
+            }
+        }
+
+        let token = &state.config.tokens.default;
+        match refresh(&state, token, key.clone(), &url, false, None).await {
+            Ok((entry, _)) => {
+                sender.send_if_modified(|current| {
+                    let modified = tag(current) != tag(&entry);
+                    if modified {
+                        *current = entry;
+                    }
+                    modified
+                });
+            }
+            Err(_) => debug!(path = %key, "watch refresh failed, retrying next interval"),
+        }
+    }
+    state.watches.loops.lock().unwrap().remove(&key);
+}
+
+/// What `If-None-Match` is compared with: GitHub's ETag, or a hash of the
+/// body when it sent none.
+fn tag(entry: &CachedResponse) -> HeaderValue {
+    if let Some(etag) = entry.headers.get(header::ETAG) {
+        return etag.clone();
+    }
+    let digest = Sha256::digest(&entry.body);
+    let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    HeaderValue::try_from(format!("\"{hex}\"")).expect("hex is a valid header value")
+}
+
+fn changed(
+    entry: &CachedResponse,
+    cache_status: CacheStatus,
+    headers: &HeaderMap,
+    state: &AppState,
+) -> Response {
+    let mut response = respond(entry, cache_status, headers, &state.config);
+    response.headers_mut().insert(header::ETAG, tag(entry));
+    response
+}
+
+fn unchanged(entry: &CachedResponse, headers: &HeaderMap, state: &AppState) -> Response {
+    let mut response = changed(entry, CacheStatus::Hit, headers, state);
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+    *response.body_mut() = Body::empty();
+    response
+}
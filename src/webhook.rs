@@ -0,0 +1,129 @@
+use axum::{
+    extract::{DefaultBodyLimit, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use metrics::counter;
+use serde_json::Value;
+use sha2::Sha256;
+use tracing::{error, info};
+
+use crate::{admin::PurgeParams, cache, error_response, repos::RepoPattern, AppState};
+
+pub const PATH: &str = "/__webhook/github";
+
+/// GitHub's own cap on a delivery; pushes of many commits come close.
+const MAX_BODY_BYTES: usize = 25 * 1024 * 1024;
+
+/// Push-based invalidation: GitHub tells us when a repository changed and we
+/// drop what we cached for it, instead of waiting out the TTL.
+///
+/// Authenticated by the delivery signature rather than `ADMIN_TOKEN`, since
+/// GitHub can't send a bearer token. Without `WEBHOOK_SECRET` it answers 404.
+///
+/// Purges are soft unless the webhook's URL says `?mode=hard`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(PATH, post(github))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+}
+
+async fn github(
+    Query(params): Query<PurgeParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(secret) = state.config.webhook_secret.as_deref() else {
+        return error_response(StatusCode::NOT_FOUND);
+    };
+    let signature = headers
+        .get("x-hub-signature-256")
+        .and_then(|v| v.to_str().ok());
+    if !signature.is_some_and(|signature| verify(secret, signature, &body)) {
+        return error_response(StatusCode::UNAUTHORIZED);
+    }
+
+    let event = headers
+        .get("x-github-event")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !matches!(event, "push" | "release" | "repository") {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    counter!("proxy_webhook_events_total", "event" => event.to_owned()).increment(1);
+
+    let Ok(payload) = serde_json::from_slice::<Value>(&body) else {
+        return error_response(StatusCode::BAD_REQUEST);
+    };
+    let repos = affected_repos(&payload);
+    if repos.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST);
+    }
+
+    let names: Vec<String> = repos.iter().map(|repo| repo.to_string()).collect();
+    if let Some(store) = &state.store {
+        store.purge_repos(&repos);
+    }
+    // A soft purge walks the whole cache; GitHub only waits ten seconds.
+    let (cache, event) = (state.cache.clone(), event.to_owned());
+    tokio::spawn(async move {
+        match cache::purge_repos(&cache, repos, params.mode).await {
+            Ok(()) => info!(event, "purged cached entries for {}", names.join(", ")),
+            Err(err) => error!(event, "cannot purge {}: {err}", names.join(", ")),
+        }
+    });
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The repository an event is about, plus its previous name when it was
+/// just renamed or transferred, since entries may be cached under either.
+fn affected_repos(payload: &Value) -> Vec<RepoPattern> {
+    let repository = &payload["repository"];
+    let mut names: Vec<String> = repository["full_name"]
+        .as_str()
+        .map(str::to_owned)
+        .into_iter()
+        .collect();
+
+    let owner = repository["owner"]["login"].as_str();
+    let changes = &payload["changes"];
+    if let (Some(owner), Some(old)) = (owner, changes["repository"]["name"]["from"].as_str()) {
+        names.push(format!("{owner}/{old}"));
+    }
+    if let (Some(name), Some(old_owner)) = (
+        repository["name"].as_str(),
+        changes["owner"]["from"]["user"]["login"]
+            .as_str()
+            .or(changes["owner"]["from"]["organization"]["login"].as_str()),
+    ) {
+        names.push(format!("{old_owner}/{name}"));
+    }
+
+    names.iter().filter_map(|name| name.parse().ok()).collect()
+}
+
+/// Checks `sha256=<hex>` against the HMAC of the raw body, in constant time.
+fn verify(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let Some(expected) = signature.strip_prefix("sha256=").and_then(decode_hex) else {
+        return false;
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
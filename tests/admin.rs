@@ -0,0 +1,107 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use common::{github, header, proxy, send};
+use std::sync::atomic::Ordering;
+
+const TOKEN: &str = "admin-token";
+
+#[tokio::test]
+async fn without_a_token_the_admin_routes_do_not_exist() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let response = send(&proxy, common::get("/__stats", &[])).await;
+    assert_eq!(response.status(), 404);
+    let authorized = [("authorization", "Bearer admin-token")];
+    let response = send(&proxy, common::get("/__stats", &authorized)).await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn admin_routes_want_the_token() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("ADMIN_TOKEN", TOKEN)]).await;
+
+    let response = send(&proxy, common::get("/__stats", &[])).await;
+    assert_eq!(response.status(), 401);
+    let wrong = [("authorization", "Bearer admin-tokem")];
+    let response = send(&proxy, common::get("/__stats", &wrong)).await;
+    assert_eq!(response.status(), 401);
+    let right = [("authorization", "Bearer admin-token")];
+    let response = send(&proxy, common::get("/__stats", &right)).await;
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn a_purge_needs_the_token_and_empties_the_cache() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[("ADMIN_TOKEN", TOKEN)]).await;
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+
+    let purge = |headers: &[(&str, &str)]| {
+        let mut request = Request::delete("/__cache/o/r");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+    let response = send(&proxy, purge(&[])).await;
+    assert_eq!(response.status(), 401);
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(header(&response, "x-cache"), Some("HIT"));
+
+    let response = send(&proxy, purge(&[("authorization", "Bearer admin-token")])).await;
+    assert!(response.status().is_success());
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(header(&response, "x-cache"), Some("MISS"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn an_entry_is_purged_by_its_key_alone() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("ADMIN_TOKEN", TOKEN)]).await;
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+    send(&proxy, common::get("/repos/o/r?page=2", &[])).await;
+    let authorized = [("authorization", "Bearer admin-token")];
+
+    let response = send(&proxy, common::get("/admin/cache/stats", &authorized)).await;
+    assert_eq!(response.status(), 200);
+    let purge = || {
+        Request::delete("/admin/cache/o/r?mode=hard")
+            .header("authorization", "Bearer admin-token")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = send(&proxy, purge()).await;
+    assert_eq!(response.status(), 204);
+    let response = send(&proxy, purge()).await;
+    assert_eq!(response.status(), 404);
+
+    let response = send(&proxy, common::get("/repos/o/r?page=2", &[])).await;
+    assert_eq!(header(&response, "x-cache"), Some("HIT"));
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(header(&response, "x-cache"), Some("MISS"));
+}
+
+#[tokio::test]
+async fn metrics_are_open_to_scrapers_unless_told_otherwise() {
+    let (github, _) = github().await;
+    let scrape = || Request::get("/metrics").body(Body::empty()).unwrap();
+
+    let open = proxy(&github, &[]).await;
+    let response = send(&open, scrape()).await;
+    assert_eq!(response.status(), 200);
+    assert!(header(&response, "content-type").is_some_and(|t| t.starts_with("text/plain")));
+
+    let vars = [("ADMIN_TOKEN", TOKEN), ("METRICS_REQUIRE_ADMIN", "true")];
+    let guarded = proxy(&github, &vars).await;
+    let response = send(&guarded, scrape()).await;
+    assert_eq!(response.status(), 401);
+    let right = Request::get("/metrics")
+        .header("authorization", "Bearer admin-token")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&guarded, right).await.status(), 200);
+}
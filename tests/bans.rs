@@ -0,0 +1,54 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use common::{github, proxy, send, ORIGIN};
+use std::fs;
+
+/// Two `Origin`s: a 400, which counts towards `BAN_MAX_INVALID`.
+fn invalid(key: Option<&str>) -> Request<Body> {
+    let mut request = Request::get("/repos/o/r").header("origin", ORIGIN).header("origin", ORIGIN);
+    if let Some(key) = key {
+        request = request.header("x-api-key", key);
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn a_key_is_banned_rather_than_the_address_it_shares() {
+    let (github, _) = github().await;
+    let keys = std::env::temp_dir().join(format!("bans-test-keys-{}.json", std::process::id()));
+    fs::write(&keys, r#"[{"name": "blog", "key": "blog-secret"}]"#).unwrap();
+    let vars = [
+        ("API_KEYS_FILE", keys.to_str().unwrap()),
+        ("BAN_MAX_INVALID", "1"),
+        ("ADMIN_TOKEN", "admin-token"),
+    ];
+    let proxy = proxy(&github, &vars).await;
+    fs::remove_file(&keys).unwrap();
+
+    for _ in 0..2 {
+        assert_eq!(send(&proxy, invalid(Some("blog-secret"))).await.status(), 400);
+    }
+    let with_key = [("x-api-key", "blog-secret")];
+    assert_eq!(send(&proxy, common::get("/repos/o/r", &with_key)).await.status(), 403);
+    // The same address, without the key, is still served.
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(response.status(), 200);
+
+    let admin = [("authorization", "Bearer admin-token")];
+    let bans = common::body(send(&proxy, common::get("/__bans", &admin)).await).await;
+    let bans: serde_json::Value = serde_json::from_slice(&bans).unwrap();
+    assert_eq!(bans[0]["client"], "key:blog");
+    assert_eq!(bans.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn without_a_key_the_address_is_banned() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("BAN_MAX_INVALID", "1")]).await;
+
+    for _ in 0..2 {
+        assert_eq!(send(&proxy, invalid(None)).await.status(), 400);
+    }
+    assert_eq!(send(&proxy, common::get("/repos/o/r", &[])).await.status(), 403);
+}
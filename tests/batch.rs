@@ -0,0 +1,89 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use common::{github, proxy, send, ORIGIN};
+use serde_json::Value;
+use std::sync::atomic::Ordering;
+
+async fn json(response: axum::response::Response) -> Value {
+    serde_json::from_slice(&common::body(response).await).unwrap()
+}
+
+#[tokio::test]
+async fn each_path_is_charged_to_the_client() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("CLIENT_RATE_LIMIT_RPM", "2")]).await;
+
+    let batch = "/__batch?paths=o/r,o/r?page=2,o/r?page=3";
+    let response = send(&proxy, common::get(batch, &[])).await;
+    assert_eq!(response.status(), 200);
+    let results = json(response).await;
+    assert_eq!(results["o/r"]["status"], 200);
+    assert_eq!(results["o/r?page=2"]["status"], 200);
+    assert_eq!(results["o/r?page=3"]["status"], 429);
+    assert_eq!(results["o/r?page=3"]["body"]["error"], "rate limited");
+
+    let after = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(after.status(), 429);
+}
+
+#[tokio::test]
+async fn repeated_paths_are_fetched_once() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let response = send(&proxy, common::get("/__batch?paths=o/r,/o/r,%20o/r", &[])).await;
+    assert_eq!(response.status(), 200);
+    let results = json(response).await;
+    assert_eq!(results.as_object().unwrap().len(), 1);
+    assert_eq!(results["o/r"]["body"]["full_name"], "o/r");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn a_batch_over_the_limit_is_refused_whole() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[("BATCH_MAX_PATHS", "2")]).await;
+
+    let within = send(&proxy, common::get("/__batch?paths=o/r,o/r?page=2", &[])).await;
+    assert_eq!(within.status(), 200);
+    let over = "/__batch?paths=o/r,o/r?page=2,o/r?page=3";
+    let response = send(&proxy, common::get(over, &[])).await;
+    assert_eq!(response.status(), 400);
+    assert_eq!(json(response).await["error"], "at most 2 paths per batch");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn downloads_and_failures_answer_for_themselves() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let request = Request::post("/__batch")
+        .header("origin", ORIGIN)
+        .header("content-type", "application/json")
+        .body(Body::from(r#"["o/r", "o/r/tarball/main", "o/missing"]"#))
+        .unwrap();
+    let response = send(&proxy, request).await;
+    assert_eq!(response.status(), 200);
+    let results = json(response).await;
+    assert_eq!(results["o/r"]["status"], 200);
+    assert_eq!(results["o/r/tarball/main"]["status"], 400);
+    assert_eq!(results["o/r/tarball/main"]["body"]["error"], "downloads can't be batched");
+    assert_eq!(results["o/missing"]["status"], 404);
+}
+
+#[tokio::test]
+async fn a_warm_batch_never_reaches_github() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+    let batch = "/__batch?paths=o/r,o/r?page=2";
+
+    send(&proxy, common::get(batch, &[])).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    let response = send(&proxy, common::get(batch, &[])).await;
+    let results = json(response).await;
+    assert_eq!(results["o/r"]["status"], 200);
+    assert_eq!(results["o/r?page=2"]["status"], 200);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
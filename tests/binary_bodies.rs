@@ -0,0 +1,65 @@
+mod common;
+
+use axum::{http::header, routing, Router};
+use common::{header, proxy, send, serve};
+
+/// A PNG's signature and header, never valid UTF-8.
+const PNG: &[u8] =
+    b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\x1f\x15\xc4\x89";
+/// "café crème", in ISO-8859-1.
+const LATIN_1: &[u8] = b"caf\xe9 cr\xe8me";
+
+async fn github() -> String {
+    let routes = Router::new()
+        .route(
+            "/api/v3/repos/o/r/contents/logo.png",
+            routing::get(|| async { ([(header::CONTENT_TYPE, "image/png")], PNG) }),
+        )
+        .route(
+            "/api/v3/repos/o/r/contents/notes.txt",
+            routing::get(|| async {
+                ([(header::CONTENT_TYPE, "text/plain; charset=iso-8859-1")], LATIN_1)
+            }),
+        );
+    serve(routes).await
+}
+
+/// `body`, ungzipped if it came gzipped.
+fn decoded(encoding: Option<&str>, body: Vec<u8>) -> Vec<u8> {
+    match encoding {
+        // The 10-byte gzip header and 8-byte trailer around raw deflate.
+        Some("gzip") => {
+            miniz_oxide::inflate::decompress_to_vec(&body[10..body.len() - 8]).unwrap()
+        }
+        None => body,
+        Some(other) => panic!("unexpected encoding {other}"),
+    }
+}
+
+#[tokio::test]
+async fn bodies_pass_through_byte_for_byte() {
+    let vars = [("COMPRESSION_ENABLED", "1"), ("COMPRESSION_MIN_BYTES", "1")];
+    let proxy = proxy(&github().await, &vars).await;
+
+    for (path, expected) in [
+        ("/repos/o/r/contents/logo.png", PNG),
+        ("/repos/o/r/contents/notes.txt", LATIN_1),
+    ] {
+        // A miss, then hits with and without gzip.
+        for accept in ["identity", "gzip", "identity"] {
+            let request = common::get(path, &[("accept-encoding", accept)]);
+            let response = send(&proxy, request).await;
+            assert_eq!(response.status(), 200, "{path}");
+            let encoding = header(&response, "content-encoding").map(str::to_owned);
+            let content_type = header(&response, "content-type").map(str::to_owned);
+            let body = decoded(encoding.as_deref(), common::body(response).await);
+            assert_eq!(body, expected, "{path} with {accept}");
+            // Text is worth gzipping, an image isn't.
+            let text = path.ends_with(".txt");
+            let gzipped = text && accept == "gzip";
+            assert_eq!(encoding.as_deref(), gzipped.then_some("gzip"), "{path} with {accept}");
+            let charset = text.then_some("text/plain; charset=iso-8859-1");
+            assert_eq!(content_type.as_deref(), Some(charset.unwrap_or("image/png")));
+        }
+    }
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::{github, header, proxy, send};
+use std::sync::atomic::Ordering;
+
+#[tokio::test]
+async fn a_first_request_misses_and_the_next_hits() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let first = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(first.status(), 200);
+    assert_eq!(header(&first, "x-cache"), Some("MISS"));
+    assert_eq!(common::body(first).await, br#"{"full_name":"o/r"}"#);
+
+    let second = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(second.status(), 200);
+    assert_eq!(header(&second, "x-cache"), Some("HIT"));
+    assert_eq!(common::body(second).await, br#"{"full_name":"o/r"}"#);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn x_cache_is_exposed_to_scripts() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    let exposed = header(&response, "access-control-expose-headers").unwrap_or_default();
+    assert!(exposed.split(',').any(|name| name.trim().eq_ignore_ascii_case("x-cache")));
+}
+
+#[tokio::test]
+async fn a_client_token_bypasses_the_cache() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[("CLIENT_TOKENS", "1")]).await;
+    let token = [("authorization", "Bearer client-token")];
+
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+    let response = send(&proxy, common::get("/repos/o/r", &token)).await;
+    assert_eq!(header(&response, "x-cache"), Some("BYPASS"));
+    assert_eq!(header(&response, "cache-control"), Some("no-store"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn a_hard_refresh_bypasses_the_cache_and_refills_it() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+    let refresh = [("cache-control", "no-cache")];
+    let refreshed = send(&proxy, common::get("/repos/o/r", &refresh)).await;
+    assert_eq!(header(&refreshed, "x-cache"), Some("BYPASS"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let next = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(header(&next, "x-cache"), Some("HIT"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use axum::{routing::get, Router};
+use common::{proxy, send, serve};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Notify, time::timeout};
+
+/// Signals when GitHub's side of a request is dropped, as it is once the
+/// proxy's request to it is cancelled and the connection closes.
+struct Hanging(Arc<Notify>);
+
+impl Drop for Hanging {
+    fn drop(&mut self) {
+        self.0.notify_one();
+    }
+}
+
+/// GitHub taking a request for `o/r` and never answering it: notified on
+/// the first of the pair when a request arrives, on the second when it is
+/// given up on.
+async fn hanging_github() -> (String, Arc<Notify>, Arc<Notify>) {
+    let (arrived, dropped) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+    let (on_arrival, on_drop) = (arrived.clone(), dropped.clone());
+    let routes = Router::new().route(
+        "/api/v3/repos/o/r",
+        get(move || async move {
+            let _hanging = Hanging(on_drop);
+            on_arrival.notify_one();
+            std::future::pending::<()>().await;
+        }),
+    );
+    (serve(routes).await, arrived, dropped)
+}
+
+#[tokio::test]
+async fn a_client_going_away_cancels_the_request_to_github() {
+    let (github, arrived, dropped) = hanging_github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let request = send(&proxy, common::get("/repos/o/r", &[]));
+    tokio::select! {
+        _ = request => panic!("GitHub never answered"),
+        _ = arrived.notified() => {}
+    }
+    // The request was dropped with the select, as a disconnect drops it.
+    let cancelled = timeout(Duration::from_secs(5), dropped.notified()).await;
+    assert!(cancelled.is_ok(), "the request to GitHub outlived its client");
+}
+
+#[tokio::test]
+async fn a_client_deadline_answers_in_time_and_cancels_the_request() {
+    let (github, _, dropped) = hanging_github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let deadline = [("x-request-deadline-ms", "200")];
+    let answered = timeout(
+        Duration::from_secs(5),
+        send(&proxy, common::get("/repos/o/r", &deadline)),
+    )
+    .await;
+    let response = answered.expect("the deadline was not kept");
+    assert_eq!(response.status(), 504);
+    let cancelled = timeout(Duration::from_secs(5), dropped.notified()).await;
+    assert!(cancelled.is_ok(), "the request to GitHub outlived the deadline");
+}
@@ -0,0 +1,100 @@
+//! What the integration tests share: a stand-in for GitHub, and the proxy's
+//! router pointed at it.
+
+#![allow(dead_code)]
+
+use axum::{
+    body::{self, Body},
+    extract::connect_info::MockConnectInfo,
+    http::Request,
+    response::Response,
+    routing, Json, Router,
+};
+use github_cors_proxy::{build_router, config::Config};
+use serde_json::json;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+/// The origin the proxy allows when `ALLOWED_ORIGINS` is unset.
+pub const ORIGIN: &str = "https://prigoana.com";
+
+pub const CLIENT: &str = "192.0.2.1:40000";
+
+/// `Config::from_env` reads the process's environment, which the tests of a
+/// binary share; they take turns.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Serves `routes` on a loopback port, returning its base URL.
+pub async fn serve(routes: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, routes).await.unwrap() });
+    format!("http://{address}")
+}
+
+/// GitHub with one repository, `o/r`, counting the requests it gets.
+pub async fn github() -> (String, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    let routes = Router::new().route(
+        "/api/v3/repos/o/r",
+        routing::get(move || async move {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Json(json!({ "full_name": "o/r" }))
+        }),
+    );
+    (serve(routes).await, calls)
+}
+
+/// The proxy as `vars` configure it, on top of GitHub at `github`, with
+/// every request coming from `CLIENT`. The GitHub stand-in's routes live
+/// under `/api/v3/`, as GHES puts them.
+pub async fn proxy(github: &str, vars: &[(&str, &str)]) -> Router {
+    let config = config(github, vars).unwrap();
+    let client: SocketAddr = CLIENT.parse().unwrap();
+    build_router(config).await.unwrap().layer(MockConnectInfo(client))
+}
+
+/// The configuration `vars` make, on top of GitHub at `github`.
+pub fn config(github: &str, vars: &[(&str, &str)]) -> Result<Config, String> {
+    let _turn = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let defaults = [("GITHUB_API_BASE", github), ("GITHUB_TOKEN", "test-token")];
+    let vars = defaults.into_iter().chain(vars.iter().copied());
+    let vars: Vec<_> = vars.collect();
+    for (name, value) in &vars {
+        std::env::set_var(name, value);
+    }
+    let config = Config::from_env();
+    for (name, _) in &vars {
+        std::env::remove_var(name);
+    }
+    config
+}
+
+/// A GET of `path` from `ORIGIN`, with `headers` besides.
+pub fn get(path: &str, headers: &[(&str, &str)]) -> Request<Body> {
+    let mut request = Request::get(path).header("origin", ORIGIN);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+pub async fn send(proxy: &Router, request: Request<Body>) -> Response {
+    proxy.clone().oneshot(request).await.unwrap()
+}
+
+pub async fn body(response: Response) -> Vec<u8> {
+    body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+}
+
+pub fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers().get(name).and_then(|v| v.to_str().ok())
+}
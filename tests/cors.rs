@@ -0,0 +1,167 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request, StatusCode},
+    routing, Router,
+};
+use common::{github, header, proxy, send, serve, ORIGIN};
+use std::{
+    sync::atomic::Ordering,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[tokio::test]
+async fn an_allowed_origin_is_reflected() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(header(&response, "access-control-allow-origin"), Some(ORIGIN));
+    let vary = header(&response, "vary").unwrap_or_default();
+    assert!(vary.split(',').any(|name| name.trim() == "origin"));
+}
+
+#[tokio::test]
+async fn a_subdomain_of_an_allowed_wildcard_is_allowed() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let origin = "https://blog.prigoana.com";
+    let request = Request::get("/repos/o/r").header("origin", origin);
+    let response = send(&proxy, request.body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(header(&response, "access-control-allow-origin"), Some(origin));
+}
+
+#[tokio::test]
+async fn another_origin_is_refused_before_github_is_asked() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let request = Request::get("/repos/o/r").header("origin", "https://evil.example");
+    let response = send(&proxy, request.body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), 403);
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn a_preflight_lists_what_may_be_sent() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let request = Request::options("/repos/o/r")
+        .header("origin", ORIGIN)
+        .header("access-control-request-method", "GET")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&proxy, request).await;
+    assert!(response.status().is_success());
+    assert_eq!(header(&response, "access-control-allow-origin"), Some(ORIGIN));
+    let methods = header(&response, "access-control-allow-methods").unwrap_or_default();
+    assert!(methods.contains("GET"));
+    assert!(header(&response, "access-control-max-age").is_some());
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn a_required_origin_refuses_requests_without_one() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("REQUIRE_ORIGIN", "1")]).await;
+
+    let request = Request::get("/repos/o/r").body(Body::empty()).unwrap();
+    let response = send(&proxy, request).await;
+    assert_eq!(response.status(), 403);
+}
+
+#[tokio::test]
+async fn several_origin_headers_are_refused_and_none_reflected() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let request = Request::get("/repos/o/r")
+        .header("origin", ORIGIN)
+        .header("origin", "https://evil.example")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&proxy, request).await;
+    assert_eq!(response.status(), 400);
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn an_origin_that_isnt_utf8_is_refused_and_not_reflected() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &[]).await;
+
+    let origin = HeaderValue::from_bytes(b"https://\xffprigoana.com").unwrap();
+    let request = Request::get("/repos/o/r").header("origin", origin);
+    let response = send(&proxy, request.body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), 400);
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+}
+
+/// GitHub with a repository of each way to fail.
+async fn failing_github() -> String {
+    let reset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 600;
+    let routes = Router::new()
+        .route(
+            "/api/v3/repos/o/missing",
+            routing::get(|| async { (StatusCode::NOT_FOUND, r#"{"message":"Not Found"}"#) }),
+        )
+        .route(
+            "/api/v3/repos/o/broken",
+            routing::get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "oops") }),
+        )
+        .route(
+            "/api/v3/repos/o/limited",
+            routing::get(move || async move {
+                let headers = [
+                    ("x-ratelimit-remaining", "0".to_owned()),
+                    ("x-ratelimit-reset", reset.to_string()),
+                ];
+                (StatusCode::FORBIDDEN, headers, r#"{"message":"API rate limit exceeded"}"#)
+            }),
+        );
+    serve(routes).await
+}
+
+#[tokio::test]
+async fn every_error_is_readable_by_an_allowed_origin() {
+    let proxy = proxy(&failing_github().await, &[]).await;
+
+    for (path, status) in [
+        ("/repos/o/missing", 404),
+        ("/repos/o/broken", 502),
+        ("/repos/o/limited", 503),
+    ] {
+        let response = send(&proxy, common::get(path, &[])).await;
+        assert_eq!(response.status(), status, "{path}");
+        assert_eq!(header(&response, "access-control-allow-origin"), Some(ORIGIN), "{path}");
+    }
+}
+
+#[tokio::test]
+async fn a_rate_limited_request_is_readable_by_an_allowed_origin() {
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("CLIENT_RATE_LIMIT_RPM", "1")]).await;
+
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(response.status(), 429);
+    assert_eq!(header(&response, "access-control-allow-origin"), Some(ORIGIN));
+}
+
+#[tokio::test]
+async fn an_unreachable_github_is_readable_by_an_allowed_origin() {
+    // Nothing listens on port 1.
+    let proxy = proxy("http://127.0.0.1:1", &[]).await;
+
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(response.status(), 502);
+    assert_eq!(header(&response, "access-control-allow-origin"), Some(ORIGIN));
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing, Router,
+};
+use common::{proxy, send};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+/// GitHub whose archive redirects to a file host that never answers.
+async fn github() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let location = format!("{base}/codeload/o/r/tar.gz/main");
+    let routes = Router::new()
+        .route(
+            "/api/v3/repos/o/r/tarball/main",
+            routing::get(move || async move {
+                (StatusCode::FOUND, [(header::LOCATION, location)]).into_response()
+            }),
+        )
+        .route(
+            "/codeload/o/r/tar.gz/main",
+            routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                "never"
+            }),
+        );
+    tokio::spawn(async move { axum::serve(listener, routes).await.unwrap() });
+    base
+}
+
+#[tokio::test]
+async fn a_stalled_download_redirect_times_out() {
+    let github = github().await;
+    let vars = [("UPSTREAM_TIMEOUT_SECS", "1"), ("UPSTREAM_RETRIES", "0")];
+    let proxy = proxy(&github, &vars).await;
+
+    let started = Instant::now();
+    let response = send(&proxy, common::get("/repos/o/r/tarball/main", &[])).await;
+    assert_eq!(response.status(), 504);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
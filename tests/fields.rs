@@ -0,0 +1,51 @@
+mod common;
+
+use axum::{http::header, routing, Router};
+use common::{header, proxy, send, serve};
+
+/// GitHub answering `o/r` with an ETag and a charset on its JSON type.
+async fn github() -> String {
+    let routes = Router::new().route(
+        "/api/v3/repos/o/r",
+        routing::get(|| async {
+            (
+                [
+                    (header::CONTENT_TYPE, "application/json; charset=utf-8"),
+                    (header::ETAG, "\"full\""),
+                ],
+                r#"{"name":"r","owner":{"login":"o","id":1},"size":10}"#,
+            )
+        }),
+    );
+    serve(routes).await
+}
+
+#[tokio::test]
+async fn a_projection_has_its_own_etag() {
+    let proxy = proxy(&github().await, &[]).await;
+
+    let response = send(&proxy, common::get("/repos/o/r?__fields=name,owner.login", &[])).await;
+    assert_eq!(response.status(), 200);
+    let etag = header(&response, "etag").unwrap().to_owned();
+    assert_ne!(etag, "\"full\"");
+    assert_eq!(common::body(response).await, br#"{"name":"r","owner":{"login":"o"}}"#);
+
+    let full = [("if-none-match", "\"full\"")];
+    let response = send(&proxy, common::get("/repos/o/r?__fields=name,owner.login", &full)).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(header(&response, "etag"), Some(etag.as_str()));
+
+    let own = [("if-none-match", etag.as_str())];
+    let response = send(&proxy, common::get("/repos/o/r?__fields=owner.login,name", &own)).await;
+    assert_eq!(response.status(), 304);
+}
+
+#[tokio::test]
+async fn other_projections_are_tagged_differently() {
+    let proxy = proxy(&github().await, &[]).await;
+
+    let name = send(&proxy, common::get("/repos/o/r?__fields=name", &[])).await;
+    let size = send(&proxy, common::get("/repos/o/r?__fields=size", &[])).await;
+    assert_ne!(header(&name, "etag"), header(&size, "etag"));
+    assert_eq!(common::body(size).await, br#"{"size":10}"#);
+}
@@ -0,0 +1,57 @@
+//! The proxy as `run` serves it, since hyper itself enforces the header
+//! limits. The only test in its binary: `run` reads the environment as it
+//! goes.
+
+mod common;
+
+use common::{github, ORIGIN};
+use std::{net::TcpListener, time::Duration};
+
+#[tokio::test]
+async fn an_oversized_header_block_gets_a_431() {
+    let (github, _) = github().await;
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    for (name, value) in [
+        ("GITHUB_API_BASE", github.as_str()),
+        ("GITHUB_TOKEN", "test-token"),
+        ("SKIP_TOKEN_CHECK", "1"),
+        ("BIND_ADDRS", &addr.to_string()),
+        ("HTTP_MAX_HEADER_BYTES", "8192"),
+    ] {
+        std::env::set_var(name, value);
+    }
+    tokio::spawn(github_cors_proxy::run());
+
+    let url = format!("http://{addr}/repos/o/r");
+    let client = reqwest::Client::new();
+    let get = |padding: usize| {
+        client
+            .get(&url)
+            .header("origin", ORIGIN)
+            .header("x-padding", "a".repeat(padding))
+            .send()
+    };
+    let mut within = None;
+    for _ in 0..50 {
+        match get(4 * 1024).await {
+            Ok(response) => {
+                within = Some(response);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    let within = within.expect("the proxy never started listening");
+    assert_eq!(within.status(), 200);
+
+    let oversized = get(16 * 1024).await.unwrap();
+    assert_eq!(oversized.status(), 431);
+    let after = get(0).await.unwrap();
+    assert_eq!(after.status(), 200);
+
+    // Within the header limit, but too long an origin to be echoed back.
+    let long_origin = format!("https://{}.com", "a".repeat(300));
+    let response = client.get(&url).header("origin", long_origin).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+    assert!(response.headers().get("access-control-allow-origin").is_none());
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use axum::{routing, Json, Router};
+use common::{header, proxy, send, serve};
+use serde_json::json;
+
+const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+const FOREVER: &str = "public, max-age=31536000, immutable";
+
+async fn github() -> String {
+    let routes = Router::new()
+        .route(
+            "/api/v3/repos/o/r/commits/:reference",
+            routing::get(|| async { Json(json!({ "sha": SHA })) }),
+        )
+        .route(
+            "/api/v3/repos/o/r/contents/:file",
+            routing::get(|| async { Json(json!({ "name": "README.md" })) }),
+        );
+    serve(routes).await
+}
+
+#[tokio::test]
+async fn sha_pinned_requests_are_kept_for_good() {
+    let proxy = proxy(&github().await, &[]).await;
+
+    for path in [
+        format!("/repos/o/r/commits/{SHA}"),
+        format!("/repos/o/r/contents/README.md?ref={SHA}"),
+    ] {
+        let response = send(&proxy, common::get(&path, &[])).await;
+        assert_eq!(response.status(), 200, "{path}");
+        assert_eq!(header(&response, "cache-control"), Some(FOREVER), "{path}");
+    }
+}
+
+#[tokio::test]
+async fn branch_names_get_the_usual_ttl() {
+    let proxy = proxy(&github().await, &[]).await;
+
+    for path in [
+        "/repos/o/r/commits/main",
+        "/repos/o/r/commits/deadbeef",
+        "/repos/o/r/contents/README.md?ref=main",
+    ] {
+        let response = send(&proxy, common::get(path, &[])).await;
+        assert_eq!(response.status(), 200, "{path}");
+        assert_eq!(header(&response, "cache-control"), Some("public, max-age=10"), "{path}");
+    }
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{github, header, proxy, send};
+use std::{sync::atomic::Ordering, time::Duration};
+
+const BOUNDS: [(&str, &str); 2] =
+    [("MAX_AGE_PARAM_MIN_SECS", "1"), ("MAX_AGE_PARAM_MAX_SECS", "2")];
+
+#[tokio::test]
+async fn max_age_below_the_floor_still_hits_a_younger_entry() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &BOUNDS).await;
+
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+    let response = send(&proxy, common::get("/repos/o/r?max_age=0", &[])).await;
+    assert_eq!(header(&response, "x-cache"), Some("HIT"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let response = send(&proxy, common::get("/repos/o/r?max_age=0", &[])).await;
+    assert_eq!(response.status(), 200);
+    assert_ne!(header(&response, "x-cache"), Some("HIT"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn max_age_above_the_ceiling_is_cut_down_to_it() {
+    let (github, calls) = github().await;
+    let proxy = proxy(&github, &BOUNDS).await;
+
+    send(&proxy, common::get("/repos/o/r", &[])).await;
+    let response = send(&proxy, common::get("/repos/o/r?max_age=3600", &[])).await;
+    assert_eq!(header(&response, "x-cache"), Some("HIT"));
+
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    let response = send(&proxy, common::get("/repos/o/r?max_age=3600", &[])).await;
+    assert_eq!(response.status(), 200);
+    assert_ne!(header(&response, "x-cache"), Some("HIT"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
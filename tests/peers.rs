@@ -0,0 +1,39 @@
+mod common;
+
+use axum::http::header;
+use common::{github, proxy, send};
+use std::sync::atomic::Ordering;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn a_forwarded_miss_has_one_content_type() {
+    let (github, calls) = github().await;
+    let listeners = [
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+    ];
+    let urls = listeners
+        .iter()
+        .map(|listener| format!("http://{}", listener.local_addr().unwrap()))
+        .collect::<Vec<_>>();
+    let peers = urls.join(",");
+    let mut replicas = Vec::new();
+    for (listener, url) in listeners.into_iter().zip(&urls) {
+        let vars = [("PEERS", peers.as_str()), ("PEER_SELF", url), ("PEER_SECRET", "s3cret")];
+        let replica = proxy(&github, &vars).await;
+        let served = replica.clone();
+        tokio::spawn(async move { axum::serve(listener, served).await.unwrap() });
+        replicas.push(replica);
+    }
+
+    // Whichever replica doesn't own the key forwards to the one that does.
+    for replica in &replicas {
+        let response = send(replica, common::get("/repos/o/r", &[])).await;
+        assert_eq!(response.status(), 200);
+        let types: Vec<_> = response.headers().get_all(header::CONTENT_TYPE).iter().collect();
+        assert_eq!(types.len(), 1, "{types:?}");
+        assert!(types[0].to_str().unwrap().starts_with("application/json"));
+        assert_eq!(common::body(response).await, br#"{"full_name":"o/r"}"#);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
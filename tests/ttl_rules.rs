@@ -0,0 +1,65 @@
+mod common;
+
+use common::{github, header, proxy, send};
+use std::path::PathBuf;
+
+/// `contents` in a rules file of its own, for `name`.
+fn rules_file(name: &str, contents: &str) -> PathBuf {
+    let file = std::env::temp_dir().join(format!("ttl-rules-{name}-{}", std::process::id()));
+    std::fs::write(&file, contents).unwrap();
+    file
+}
+
+#[tokio::test]
+async fn a_rules_file_sets_the_ttl_of_matching_paths() {
+    let rules = r#"
+        # First match wins.
+        [[rule]]
+        path = '*/*'  # repositories
+        ttl_secs = 300
+
+        [[rule]]
+        path = "*/*/releases"
+        ttl_secs = 5
+    "#;
+    let file = rules_file("file", rules);
+    let (github, _) = github().await;
+    let proxy = proxy(&github, &[("CACHE_TTL_RULES_FILE", file.to_str().unwrap())]).await;
+    std::fs::remove_file(&file).unwrap();
+
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(header(&response, "cache-control"), Some("public, max-age=300"));
+}
+
+#[tokio::test]
+async fn env_rules_come_before_the_file() {
+    let file = rules_file("env", "rule = [{ path = \"*/*\", ttl_secs = 300 }]\n");
+    let (github, _) = github().await;
+    let vars = [
+        ("CACHE_TTL_RULES", "o/*=42"),
+        ("CACHE_TTL_RULES_FILE", file.to_str().unwrap()),
+    ];
+    let proxy = proxy(&github, &vars).await;
+    std::fs::remove_file(&file).unwrap();
+
+    let response = send(&proxy, common::get("/repos/o/r", &[])).await;
+    assert_eq!(header(&response, "cache-control"), Some("public, max-age=42"));
+}
+
+#[tokio::test]
+async fn a_malformed_rules_file_is_refused() {
+    let (github, _) = github().await;
+    for (name, contents) in [
+        ("syntax", "*/*/releases = 300\n"),
+        ("field", "[[rule]]\npath = \"*/*\"\nttl = 300\n"),
+        ("missing", "[[rule]]\npath = \"*/*\"\n"),
+        ("secs", "[[rule]]\npath = \"*/*\"\nttl_secs = \"5m\"\n"),
+        ("pattern", "[[rule]]\npath = \"/\"\nttl_secs = 5\n"),
+    ] {
+        let file = rules_file(name, contents);
+        let config = common::config(&github, &[("CACHE_TTL_RULES_FILE", file.to_str().unwrap())]);
+        std::fs::remove_file(&file).unwrap();
+        let err = config.err().unwrap_or_else(|| panic!("{contents:?} was accepted"));
+        assert!(err.starts_with("CACHE_TTL_RULES_FILE: "), "{err}");
+    }
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use common::{github, proxy, send};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+const SECRET: &str = "webhook-secret";
+
+/// A signed `push` delivery for `o/r`, padded out to about `size` bytes.
+fn delivery(size: usize) -> Request<Body> {
+    let padding = "x".repeat(size);
+    let body = json!({ "repository": { "full_name": "o/r" }, "padding": padding }).to_string();
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    let signature: String =
+        mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect();
+    Request::post("/__webhook/github")
+        .header("x-github-event", "push")
+        .header("x-hub-signature-256", format!("sha256={signature}"))
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn deliveries_larger_than_other_bodies_are_taken() {
+    let (github, _) = github().await;
+    let vars = [("WEBHOOK_SECRET", SECRET), ("MAX_REQUEST_BODY_BYTES", "1024")];
+    let proxy = proxy(&github, &vars).await;
+
+    let response = send(&proxy, delivery(10 * 1024 * 1024)).await;
+    assert_eq!(response.status(), 204);
+    let response = send(&proxy, delivery(26 * 1024 * 1024)).await;
+    assert_eq!(response.status(), 413);
+}